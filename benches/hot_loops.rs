@@ -0,0 +1,73 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use hackattic::challenges::mini_miner::{self, Block, Midstate};
+use hackattic::utils::{unpack, zip};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+// The loops the solvers spend their time in, each on input shaped like the challenge's
+
+fn zip_crypto(c: &mut Criterion) {
+    // 12 byte ZipCrypto header and a short secret.txt, like brute_force_zip's archive
+    let encrypted: Vec<u8> = (0..12 + 32).map(|i| (i * 37 + 11) as u8).collect();
+    let mut group = c.benchmark_group("zip_crypto");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("verify_password", |b| {
+        b.iter(|| zip::verify_zip_crypto_password(black_box(&encrypted), "a1b2c", 0xdeadbeef))
+    });
+    group.finish();
+}
+
+fn crc32(c: &mut Criterion) {
+    let data = vec![0xa5u8; 64 * 1024];
+    let mut group = c.benchmark_group("crc32");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("64KiB", |b| b.iter(|| zip::crc32(black_box(&data))));
+    group.finish();
+}
+
+fn mining(c: &mut Criterion) {
+    let block: Block = serde_json::from_value(json!({
+        "data": [["0a5d8a5e6b1cf3b2", -57], ["a5e8d4f2c6b3e1d0", 92]],
+        "nonce": null,
+    }))
+    .unwrap();
+    let midstate = Midstate::new(&block);
+    let mut group = c.benchmark_group("mining");
+    group.throughput(Throughput::Elements(1));
+    // what the search does per nonce
+    group.bench_function("midstate", |b| {
+        let mut nonce = 0u64;
+        b.iter(|| {
+            nonce += 1;
+            midstate.hash(black_box(nonce))
+        })
+    });
+    // serializing and hashing the whole block, what the midstate saves
+    group.bench_function("full_block", |b| {
+        let mut nonce = 0u64;
+        b.iter(|| {
+            nonce += 1;
+            Sha256::digest(mini_miner::serialize_block(&block, black_box(nonce)).as_bytes())
+        })
+    });
+    group.finish();
+}
+
+fn unpacker(c: &mut Criterion) {
+    let format = "<i I h 2x f d >d";
+    let bytes: Vec<u8> = (0..32).collect();
+    let values = unpack::unpack(format, &bytes).unwrap();
+    let mut group = c.benchmark_group("unpack");
+    group.bench_function("help_me_unpack", |b| {
+        b.iter(|| unpack::unpack(black_box(format), black_box(&bytes)))
+    });
+    group.bench_function("pack", |b| {
+        b.iter(|| unpack::pack(black_box(format), black_box(&values)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, zip_crypto, crc32, mining, unpacker);
+criterion_main!(benches);
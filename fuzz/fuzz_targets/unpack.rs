@@ -0,0 +1,21 @@
+#![no_main]
+
+use hackattic::utils::unpack;
+use libfuzzer_sys::fuzz_target;
+
+// The first byte is the format's length, the format follows and the rest is the buffer
+fuzz_target!(|data: &[u8]| {
+    let Some((&len, rest)) = data.split_first() else {
+        return;
+    };
+    let (format, bytes) = rest.split_at((len as usize).min(rest.len()));
+    let Ok(format) = std::str::from_utf8(format) else {
+        return;
+    };
+
+    let _ = unpack::annotated_dump(format, bytes);
+    if let Ok(values) = unpack::unpack(format, bytes) {
+        // what was unpacked always packs back
+        unpack::pack(format, &values).expect("unpacked values pack back");
+    }
+});
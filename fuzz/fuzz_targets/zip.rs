@@ -0,0 +1,17 @@
+#![no_main]
+
+use hackattic::utils::zip;
+use libfuzzer_sys::fuzz_target;
+
+// The downloaded archive as brute_force_zip reads it: every entry, then the ZipCrypto check
+// and decryption on each entry's data
+fuzz_target!(|data: &[u8]| {
+    zip::check_if_zip(data);
+    let Ok(files) = zip::extract_all_files(data) else {
+        return;
+    };
+    for (_, content, crc32) in files {
+        zip::verify_zip_crypto_password(&content, "abcd", crc32);
+        zip::decrypt_zip_crypto_content(&content, "abcd");
+    }
+});
@@ -1,40 +1,292 @@
-use std::io::Read;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use base64::{Engine, engine::general_purpose};
+use anyhow::{Context, bail};
+use base64::engine::general_purpose;
 use flate2::read::GzDecoder;
-use regex::Regex;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use tracing::{info, info_span, warn};
 
-pub fn run() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("backup_restore");
+use crate::utils::docker;
+use crate::utils::pg_dump::{self, DumpFormat};
 
-    let problem = client.get_problem();
-    let b64 = problem["dump"].as_str().unwrap();
+const DEFAULT_TABLE: &str = "criminal_records";
+const POSTGRES_READY_TIMEOUT: Duration = Duration::from_secs(60);
 
-    let buf = general_purpose::STANDARD
-        .decode(b64)
-        .expect("expect base64");
+#[derive(Debug, Clone, Deserialize)]
+pub struct Problem {
+    /// base64 pg_dump output, plain or gzipped SQL or a custom or tar archive
+    pub dump: String,
+}
 
-    let mut d = GzDecoder::new(&buf[..]);
-    let mut s = String::new();
-    d.read_to_string(&mut s).expect("Failed to decompress");
+#[derive(Debug, Clone, Serialize)]
+pub struct Solution {
+    pub alive_ssns: Vec<String>,
+}
 
-    let re = Regex::new(r"COPY .+;\n([\s\S]*)\\\.").unwrap();
-    let extracted_text = re.captures(&s).unwrap().get(1).unwrap().as_str();
+/// The columns of a row that matter, picked by name
+#[derive(Debug, Deserialize)]
+struct CriminalRecord {
+    ssn: Option<String>,
+    status: Option<String>,
+}
 
-    let mut socials: Vec<String> = Vec::new();
-    for line in extracted_text.lines() {
-        let columns: Vec<&str> = line.split('\t').collect();
+/// The dump as a plain SQL script: base64 decoded, gunzipped when it's compressed, and custom
+/// or tar archives converted with pg_restore
+fn dump_reader(b64: &str) -> Result<Box<dyn BufRead + '_>, String> {
+    let decoder = base64::read::DecoderReader::new(b64.as_bytes(), &general_purpose::STANDARD);
+    let mut reader = BufReader::new(decoder);
+    let head = reader
+        .fill_buf()
+        .map_err(|e| format!("Invalid base64 dump: {}", e))?;
+    let mut reader: Box<dyn BufRead> = if head.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+
+    let head = reader
+        .fill_buf()
+        .map_err(|e| format!("Failed to decompress the dump: {}", e))?;
+    let format = DumpFormat::detect(head);
+    if format == DumpFormat::Plain {
+        return Ok(reader);
+    }
+
+    info!(
+        "The dump is a {:?} format archive, converting it with pg_restore",
+        format
+    );
+    let mut archive = Vec::new();
+    reader
+        .read_to_end(&mut archive)
+        .map_err(|e| format!("Failed to decompress the dump: {}", e))?;
+    let sql = pg_dump::archive_to_sql(&archive, format)?;
+    Ok(Box::new(Cursor::new(sql)))
+}
 
-        let status = columns[columns.len() - 1];
-        if status == "alive" {
-            socials.push(columns[3].to_string());
+/// Restores the dump into a throwaway Postgres container and asks it for the alive SSNs, an
+/// oracle for the hand-rolled COPY parser
+fn query_postgres(b64: &str, table: &str) -> Result<Vec<String>, String> {
+    docker::preflight(pg_dump::POSTGRES_IMAGE)?;
+
+    let container = format!("hackattic-postgres-{}", std::process::id());
+    let started = docker::run(&[
+        "run",
+        "-d",
+        "--rm",
+        "--name",
+        &container,
+        "-e",
+        "POSTGRES_HOST_AUTH_METHOD=trust",
+        pg_dump::POSTGRES_IMAGE,
+    ])?;
+    if !started.status.success() {
+        return Err(docker::explain_error(
+            &String::from_utf8_lossy(&started.stderr),
+            pg_dump::POSTGRES_IMAGE,
+        ));
+    }
+
+    let result = restore_and_query(&container, b64, table);
+    let _ = docker::run(&["rm", "-f", &container]);
+    result
+}
+
+fn restore_and_query(container: &str, b64: &str, table: &str) -> Result<Vec<String>, String> {
+    // Checked over TCP, the entrypoint's temporary init server only listens on the socket
+    let started = Instant::now();
+    loop {
+        let ready = docker::run(&[
+            "exec",
+            container,
+            "pg_isready",
+            "-h",
+            "127.0.0.1",
+            "-U",
+            "postgres",
+        ])?;
+        if ready.status.success() {
+            break;
         }
+        if started.elapsed() > POSTGRES_READY_TIMEOUT {
+            return Err(format!(
+                "Postgres wasn't ready after {}s",
+                POSTGRES_READY_TIMEOUT.as_secs()
+            ));
+        }
+        thread::sleep(Duration::from_millis(500));
     }
+    info!(
+        "Postgres ready after {:.1}s, restoring the dump",
+        started.elapsed().as_secs_f64()
+    );
+
+    // Errors like missing roles are expected, everything else in the dump still applies
+    let mut psql = Command::new("docker")
+        .args([
+            "exec",
+            "-i",
+            container,
+            "psql",
+            "-h",
+            "127.0.0.1",
+            "-U",
+            "postgres",
+            "-q",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run psql: {}", e))?;
+    let mut stdin = psql.stdin.take().expect("stdin is piped");
+    let copied = dump_reader(b64)
+        .and_then(|mut reader| std::io::copy(&mut reader, &mut stdin).map_err(|e| e.to_string()));
+    drop(stdin);
+    let output = psql
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run psql: {}", e))?;
+    copied.map_err(|e| format!("Failed to feed the dump to psql: {}", e))?;
+    let errors = String::from_utf8_lossy(&output.stderr);
+    let errors: Vec<&str> = errors
+        .lines()
+        .filter(|line| line.contains("ERROR"))
+        .collect();
+    if !errors.is_empty() {
+        info!(
+            "psql reported {} errors while restoring, first: {}",
+            errors.len(),
+            errors[0]
+        );
+    }
+
+    let query = format!("SELECT ssn FROM {} WHERE status = 'alive'", table);
+    let output = docker::run(&[
+        "exec",
+        container,
+        "psql",
+        "-h",
+        "127.0.0.1",
+        "-U",
+        "postgres",
+        "-At",
+        "-c",
+        &query,
+    ])?;
+    if !output.status.success() {
+        return Err(format!(
+            "Query {:?} failed: {}",
+            query,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
 
-    let solution = json!({
-        "alive_ssns": socials
-    });
+/// Prints how the parser's SSNs differ from Postgres's, returns whether they agree
+fn compare(parsed: &[String], oracle: &[String]) -> bool {
+    let parsed_set: HashSet<&String> = parsed.iter().collect();
+    let oracle_set: HashSet<&String> = oracle.iter().collect();
+    let missing: Vec<_> = oracle_set.difference(&parsed_set).collect();
+    let extra: Vec<_> = parsed_set.difference(&oracle_set).collect();
+
+    if missing.is_empty() && extra.is_empty() {
+        info!(
+            "Postgres agrees with the parser: {} alive SSNs",
+            oracle.len()
+        );
+        return true;
+    }
+    warn!(
+        "Parser and Postgres disagree: {} SSNs only in Postgres {:?}, {} only in the parser {:?}",
+        missing.len(),
+        missing,
+        extra.len(),
+        extra
+    );
+    false
+}
+
+/// The alive SSNs in `table`, and the table's name as the dump spells it
+fn alive_ssns(problem: &Problem, table: &str) -> anyhow::Result<(String, Vec<String>)> {
+    let _span = info_span!("solve", table).entered();
+    // base64 -> gunzip -> lines, rows are handled as they're decompressed so the whole dump
+    // never sits in memory
+    let reader = dump_reader(&problem.dump).map_err(anyhow::Error::msg)?;
+
+    let mut rows = 0;
+    let mut socials: Vec<String> = Vec::new();
+    let headers = pg_dump::for_each_record(reader, table, |record: CriminalRecord| {
+        rows += 1;
+        if record.status.as_deref() == Some("alive")
+            && let Some(ssn) = record.ssn
+        {
+            socials.push(ssn);
+        }
+    })
+    .map_err(anyhow::Error::msg)
+    .context("Failed to parse the dump")?;
+    let Some(header) = headers.iter().find(|header| header.is_table(table)) else {
+        let tables: Vec<&str> = headers.iter().map(|header| header.table.as_str()).collect();
+        bail!(
+            "No COPY block for {} in the dump, found {:?}",
+            table,
+            tables
+        );
+    };
+    // Missing Option fields deserialize to None, so a renamed column would go unnoticed
+    for column in ["ssn", "status"] {
+        if header.column(column).is_none() {
+            bail!(
+                "{} has no {} column, its columns are {:?}",
+                header.table,
+                column,
+                header.columns
+            );
+        }
+    }
+    info!("{}: {} rows", header.table, rows);
+    Ok((header.table.clone(), socials))
+}
+
+/// Parses the criminal records out of the dump. Custom and tar archives still go through
+/// pg_restore, everything else is parsed in memory
+pub fn solve(problem: &Problem) -> anyhow::Result<Solution> {
+    let (_, alive_ssns) = alive_ssns(problem, DEFAULT_TABLE)?;
+    Ok(Solution { alive_ssns })
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("backup_restore")?;
+
+    let problem = client.get_problem()?;
+    let problem: Problem = serde_json::from_value(problem.clone())
+        .with_context(|| format!("Unexpected problem: {}", problem))?;
+
+    let table = crate::utils::args::get("--table", "BACKUP_TABLE")
+        .unwrap_or_else(|| DEFAULT_TABLE.to_string());
+    let (table, mut socials) = alive_ssns(&problem, &table)?;
+
+    if crate::utils::args::is_set("--postgres", "BACKUP_POSTGRES") {
+        let oracle = query_postgres(&problem.dump, &table)
+            .map_err(anyhow::Error::msg)
+            .context("Postgres check failed")?;
+        // Postgres is the reference, its answer is submitted when they disagree
+        if !compare(&socials, &oracle) {
+            socials = oracle;
+        }
+    }
 
-    client.submit_solution(solution);
+    let solution = Solution {
+        alive_ssns: socials,
+    };
+    client.submit_solution(serde_json::to_value(&solution)?)?;
+    Ok(())
 }
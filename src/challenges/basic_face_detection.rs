@@ -1,107 +1,803 @@
 use std::fs;
+use std::path::Path;
+use std::time::Instant;
 
-use opencv::core::{MatTraitConst, Rect};
+use anyhow::{Context, bail};
+use opencv::core::{
+    AccessFlag, MatTraitConst, Point, Rect, Rect2f, UMat, UMatTraitConst, UMatUsageFlags,
+};
 use opencv::{
     core::{Mat, Scalar, Size, Vector},
-    imgcodecs, imgproc,
-    objdetect::CascadeClassifier,
-    prelude::CascadeClassifierTrait,
+    dnn, imgcodecs, imgproc,
+    objdetect::{CascadeClassifier, FaceDetectorYN, FaceDetectorYNTrait},
+    prelude::{CascadeClassifierTrait, CascadeClassifierTraitConst},
 };
 use serde_json::json;
+use tracing::{info, warn};
+
+use crate::utils::data_dir::DataDir;
+
+// Files in the challenge's data and cache directories
+const CASCADE_FILE: &str = "haarcascade_frontalface_alt2.xml";
+const CASCADE_URL: &str = "https://raw.githubusercontent.com/opencv/opencv/4.x/data/haarcascades/haarcascade_frontalface_alt2.xml";
+const IMAGE_FILE: &str = "image.jpeg";
+const OUTPUT_IMAGE_FILE: &str = "output.jpg";
+const GRID_SIZE: i32 = 8;
+
+const YUNET_MODEL_FILE: &str = "face_detection_yunet_2023mar.onnx";
+const YUNET_MODEL_URL: &str = "https://github.com/opencv/opencv_zoo/raw/main/models/face_detection_yunet/face_detection_yunet_2023mar.onnx";
+const DEFAULT_SCORE_THRESHOLD: f32 = 0.6;
+const YUNET_NMS_THRESHOLD: f32 = 0.3;
+const YUNET_TOP_K: i32 = 5000;
+
+const PROFILE_CASCADE_FILE: &str = "haarcascade_profileface.xml";
+// Cascades of the ensemble, the profile one only finds faces turned to one side so it also
+// runs on the mirrored image
+const ENSEMBLE_CASCADES: &[(&str, &str)] = &[
+    (CASCADE_FILE, CASCADE_URL),
+    (
+        "haarcascade_frontalface_default.xml",
+        "https://raw.githubusercontent.com/opencv/opencv/4.x/data/haarcascades/haarcascade_frontalface_default.xml",
+    ),
+    (
+        PROFILE_CASCADE_FILE,
+        "https://raw.githubusercontent.com/opencv/opencv/4.x/data/haarcascades/haarcascade_profileface.xml",
+    ),
+];
+// Detections overlapping more than this are the same face
+const NMS_IOU_THRESHOLD: f64 = 0.3;
+
+// Submissions allowed after a rejected one
+const DEFAULT_RETRIES: usize = 4;
+// (scale factor, min neighbors) combinations tried after a rejection
+const HAAR_SWEEP: &[(f64, i32)] = &[
+    (1.05, 3),
+    (1.05, 5),
+    (1.1, 3),
+    (1.1, 7),
+    (1.2, 3),
+    (1.2, 5),
+    (1.3, 4),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Detector {
+    Haar,
+    Dnn,
+    // every cascade of ENSEMBLE_CASCADES, merged with non-maximum suppression
+    Ensemble,
+}
+
+/// A detected face and the detector's confidence in it (YuNet score or Haar level weight)
+#[derive(Clone, Copy)]
+struct Face {
+    rect: Rect,
+    confidence: f64,
+}
 
-const CASCADE_PATH: &str = "data/haarcascade_frontalface_alt2.xml";
-const IMAGE_PATH: &str = "data/image.jpeg";
-const OUTPUT_IMAGE_PATH: &str = "data/output.jpg";
-
-pub fn run() {
-    // --- 1. Download Image and Save ---
-    let client = crate::utils::hackattic_client::HackatticClient::new("basic_face_detection");
-    let problem = client.get_problem();
-    let image_url = problem["image_url"].as_str().unwrap();
-    let image_bytes = client.download_file(image_url);
-    fs::write(IMAGE_PATH, image_bytes).unwrap();
-
-    // --- 2. Load Again and Pre-process Image ---
-    println!("Loading image from: {}", IMAGE_PATH);
-    let original_img = match imgcodecs::imread(IMAGE_PATH, imgcodecs::IMREAD_COLOR) {
-        Ok(m) => m,
-        Err(_) => {
-            eprintln!("Error: Could not read image at path: {}", IMAGE_PATH);
-            return;
+/// Haar cascade `detect_multi_scale` tuning
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct HaarParams {
+    scale_factor: f64,
+    min_neighbors: i32,
+    min_size: Size,
+    // Size::default() means no upper bound
+    max_size: Size,
+}
+
+impl HaarParams {
+    /// Defaults (1.1 / 5 / 30px / unbounded) overridden by `--scale-factor`, `--min-neighbors`,
+    /// `--min-size` and `--max-size`
+    fn from_args() -> anyhow::Result<Self> {
+        let get = crate::utils::args::get;
+        Ok(Self {
+            scale_factor: get("--scale-factor", "FACE_SCALE_FACTOR")
+                .map(|value| value.parse().context("Invalid --scale-factor"))
+                .transpose()?
+                .unwrap_or(1.1),
+            min_neighbors: get("--min-neighbors", "FACE_MIN_NEIGHBORS")
+                .map(|value| value.parse().context("Invalid --min-neighbors"))
+                .transpose()?
+                .unwrap_or(5),
+            min_size: get("--min-size", "FACE_MIN_SIZE")
+                .map(|value| parse_size(&value).context("Invalid --min-size"))
+                .transpose()?
+                .unwrap_or(Size::new(30, 30)),
+            max_size: get("--max-size", "FACE_MAX_SIZE")
+                .map(|value| parse_size(&value).context("Invalid --max-size"))
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+// "30" for a square or "40x30" for width x height
+fn parse_size(value: &str) -> Option<Size> {
+    match value.split_once('x') {
+        Some((width, height)) => Some(Size::new(width.parse().ok()?, height.parse().ok()?)),
+        None => {
+            let side = value.parse().ok()?;
+            Some(Size::new(side, side))
         }
+    }
+}
+
+// The path of a file in the challenge's data directory
+fn data_path(file: &str) -> Result<String, String> {
+    DataDir::challenge("basic_face_detection")
+        .file(file)
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+// The path of a file in the challenge's cache directory, for models and the problem image
+fn cache_path(file: &str) -> Result<String, String> {
+    DataDir::cache("basic_face_detection")
+        .file(file)
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+// Where the models were kept before the data and cache directories, relative to the working
+// directory
+const LEGACY_DIR: &str = "data";
+
+// Downloads the model unless it's already there, returns its path. One left in the old ./data
+// directory is copied over instead
+fn ensure_model(file: &str, url: &str) -> Result<String, String> {
+    let path = cache_path(file)?;
+    if Path::new(&path).exists() {
+        return Ok(path);
+    }
+
+    let legacy = Path::new(LEGACY_DIR).join(file);
+    if legacy.exists() {
+        info!("Copying {} to {}", legacy.display(), path);
+        fs::copy(&legacy, &path)
+            .map_err(|e| format!("Failed to copy {} to {}: {}", legacy.display(), path, e))?;
+        return Ok(path);
+    }
+
+    info!("Downloading model to: {}", path);
+    let model = reqwest::blocking::get(url)
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.bytes())
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    fs::write(&path, model).map_err(|e| format!("Failed to save {}: {}", path, e))?;
+    Ok(path)
+}
+
+/// Downloads every cascade and the YuNet model and checks OpenCV loads them, so the detectors
+/// can run offline. A model that doesn't load is removed, the next run downloads it again
+pub fn prefetch() -> Result<Vec<String>, String> {
+    let invalid = |path: &str, e: String| {
+        let _ = fs::remove_file(path);
+        format!("{} doesn't load ({}), removed it", path, e)
     };
 
-    let mut gray_img = Mat::default();
+    let mut ready = Vec::new();
+    for (file, url) in ENSEMBLE_CASCADES {
+        let path = ensure_model(file, url)?;
+        match CascadeClassifier::new(&path).and_then(|cascade| cascade.empty()) {
+            Ok(false) => ready.push(path),
+            Ok(true) => return Err(invalid(&path, "empty cascade".to_string())),
+            Err(e) => return Err(invalid(&path, e.to_string())),
+        }
+    }
+
+    let path = ensure_model(YUNET_MODEL_FILE, YUNET_MODEL_URL)?;
+    FaceDetectorYN::create(
+        &path,
+        "",
+        Size::new(320, 320),
+        DEFAULT_SCORE_THRESHOLD,
+        YUNET_NMS_THRESHOLD,
+        YUNET_TOP_K,
+        dnn::DNN_BACKEND_DEFAULT,
+        dnn::DNN_TARGET_CPU,
+    )
+    .map_err(|e| invalid(&path, e.to_string()))?;
+    ready.push(path);
+    Ok(ready)
+}
+
+// The [row, col] tile holding the center of a face
+fn face_tile(face: &Rect, image_size: Size) -> [i32; 2] {
+    let center_x = face.x + face.width / 2;
+    let center_y = face.y + face.height / 2;
+
+    let row = (center_y * GRID_SIZE / image_size.height).clamp(0, GRID_SIZE - 1);
+    let col = (center_x * GRID_SIZE / image_size.width).clamp(0, GRID_SIZE - 1);
+    [row, col]
+}
+
+// Maps every face to its tile, without duplicates
+fn face_tiles(faces: &[Face], image_size: Size) -> Vec<[i32; 2]> {
+    let mut tiles = Vec::new();
+    for face in faces {
+        let tile = face_tile(&face.rect, image_size);
+        if !tiles.contains(&tile) {
+            tiles.push(tile);
+        }
+    }
+    tiles.sort();
+    tiles
+}
+
+// Draws the tile grid, the detected faces and their [row,col] tile and confidence
+fn draw_debug_image(image: &Mat, faces: &[Face]) -> opencv::Result<Mat> {
+    let mut debug_img = image.clone();
+    let size = image.size()?;
+    let green = Scalar::new(0.0, 255.0, 0.0, 0.0);
+    let yellow = Scalar::new(0.0, 255.0, 255.0, 0.0);
+
+    for i in 1..GRID_SIZE {
+        let x = i * size.width / GRID_SIZE;
+        let y = i * size.height / GRID_SIZE;
+        imgproc::line(
+            &mut debug_img,
+            Point::new(x, 0),
+            Point::new(x, size.height),
+            yellow,
+            1,
+            imgproc::LINE_8,
+            0,
+        )?;
+        imgproc::line(
+            &mut debug_img,
+            Point::new(0, y),
+            Point::new(size.width, y),
+            yellow,
+            1,
+            imgproc::LINE_8,
+            0,
+        )?;
+    }
+
+    for face in faces {
+        imgproc::rectangle(&mut debug_img, face.rect, green, 2, imgproc::LINE_8, 0)?;
+
+        let [row, col] = face_tile(&face.rect, size);
+        let label = format!("[{},{}] {:.2}", row, col, face.confidence);
+        // above the rectangle, or inside it when the face touches the top edge
+        let label_y = if face.rect.y > 15 {
+            face.rect.y - 5
+        } else {
+            face.rect.y + 15
+        };
+        imgproc::put_text(
+            &mut debug_img,
+            &label,
+            Point::new(face.rect.x, label_y),
+            imgproc::FONT_HERSHEY_SIMPLEX,
+            0.5,
+            green,
+            1,
+            imgproc::LINE_AA,
+            false,
+        )?;
+    }
+
+    Ok(debug_img)
+}
+
+// Enables OpenCL for the T-API (UMat) work when `--gpu` is given, OpenCV would otherwise
+// pick it up on its own whenever a device is present
+fn setup_gpu(gpu: bool) -> anyhow::Result<()> {
+    let opencl = gpu && opencv::core::have_opencl().unwrap_or(false);
+    opencv::core::set_use_opencl(opencl).context("Failed to configure OpenCL")?;
+    if gpu && !opencl {
+        info!("OpenCL not available, running the Haar cascade on the CPU");
+    }
+    Ok(())
+}
+
+// (backend, target) pairs to run YuNet on, best first, the CPU always last as the fallback
+fn dnn_targets(gpu: bool) -> Vec<(i32, i32, &'static str)> {
+    let mut targets = Vec::new();
+    if gpu {
+        if opencv::core::get_cuda_enabled_device_count().unwrap_or(0) > 0 {
+            targets.push((dnn::DNN_BACKEND_CUDA, dnn::DNN_TARGET_CUDA, "CUDA"));
+        }
+        if opencv::core::have_opencl().unwrap_or(false) {
+            targets.push((dnn::DNN_BACKEND_OPENCV, dnn::DNN_TARGET_OPENCL, "OpenCL"));
+        }
+    }
+    targets.push((dnn::DNN_BACKEND_DEFAULT, dnn::DNN_TARGET_CPU, "CPU"));
+    targets
+}
+
+// Detects faces with OpenCV's YuNet DNN model, keeping those scoring at least `score_threshold`
+fn detect_dnn(image: &Mat, score_threshold: f32, gpu: bool) -> Result<Vec<Face>, String> {
+    let model_path = ensure_model(YUNET_MODEL_FILE, YUNET_MODEL_URL)?;
+
+    let mut last_error = String::new();
+    for (backend, target, name) in dnn_targets(gpu) {
+        match run_yunet(&model_path, image, score_threshold, backend, target) {
+            Ok(faces) => return Ok(faces),
+            Err(e) => {
+                warn!("YuNet on {} failed: {}", name, e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+fn run_yunet(
+    model_path: &str,
+    image: &Mat,
+    score_threshold: f32,
+    backend: i32,
+    target: i32,
+) -> Result<Vec<Face>, String> {
+    let mut detector = FaceDetectorYN::create(
+        model_path,
+        "",
+        image.size().map_err(|e| e.to_string())?,
+        score_threshold,
+        YUNET_NMS_THRESHOLD,
+        YUNET_TOP_K,
+        backend,
+        target,
+    )
+    .map_err(|e| format!("Failed to load YuNet model: {}", e))?;
+
+    let mut detections = Mat::default();
+    detector
+        .detect(image, &mut detections)
+        .map_err(|e| format!("YuNet detection failed: {}", e))?;
+
+    // one row per face: x, y, w, h, 5 landmarks (x, y), score
+    let mut faces = Vec::new();
+    for row in 0..detections.rows() {
+        let value = |col| detections.at_2d::<f32>(row, col).map(|value| *value);
+        let rect = Rect2f::new(
+            value(0).map_err(|e| e.to_string())?,
+            value(1).map_err(|e| e.to_string())?,
+            value(2).map_err(|e| e.to_string())?,
+            value(3).map_err(|e| e.to_string())?,
+        );
+        let score = value(14).map_err(|e| e.to_string())?;
+        info!("YuNet face at {:?} with score {:.2}", rect, score);
+        faces.push(Face {
+            rect: Rect::new(
+                rect.x as i32,
+                rect.y as i32,
+                rect.width as i32,
+                rect.height as i32,
+            ),
+            confidence: f64::from(score),
+        });
+    }
+
+    Ok(faces)
+}
+
+// Detects faces with the Haar cascade, downloading it when missing
+fn detect_haar(gray_img: &UMat, params: &HaarParams) -> anyhow::Result<Vec<Face>> {
+    let path = ensure_model(CASCADE_FILE, CASCADE_URL).map_err(anyhow::Error::msg)?;
+    detect_cascade(&path, gray_img, params)
+}
+
+fn detect_cascade(
+    cascade_path: &str,
+    gray_img: &UMat,
+    params: &HaarParams,
+) -> anyhow::Result<Vec<Face>> {
+    info!("Loading cascade classifier from: {}", cascade_path);
+    let mut face_cascade = CascadeClassifier::new(cascade_path)
+        .with_context(|| format!("Could not load the cascade classifier {}", cascade_path))?;
+    // a missing or broken file loads as an empty classifier
+    if face_cascade.empty()? {
+        bail!(
+            "Could not load the cascade classifier {}, remove it to download it again",
+            cascade_path
+        );
+    }
+
+    let mut faces = Vector::<Rect>::new();
+    let mut reject_levels = Vector::<i32>::new();
+    let mut level_weights = Vector::<f64>::new();
+    loop {
+        let detected = face_cascade.detect_multi_scale3(
+            gray_img,
+            &mut faces,
+            &mut reject_levels,
+            &mut level_weights,
+            params.scale_factor,
+            params.min_neighbors,
+            0,
+            params.min_size,
+            params.max_size,
+            true,
+        );
+        match detected {
+            Ok(()) => break,
+            // OpenCL driver trouble, the same UMat works on the CPU
+            Err(e) if opencv::core::use_opencl().unwrap_or(false) => {
+                warn!("OpenCL detection failed: {}, retrying on the CPU", e);
+                opencv::core::set_use_opencl(false).context("Failed to turn OpenCL off")?;
+            }
+            Err(e) => return Err(e).context("Haar detection failed"),
+        }
+    }
+
+    Ok(faces
+        .iter()
+        .zip(level_weights.iter())
+        .map(|(rect, confidence)| Face { rect, confidence })
+        .collect())
+}
+
+fn iou(a: &Rect, b: &Rect) -> f64 {
+    let intersection = f64::from((*a & *b).area());
+    let union = f64::from(a.area() + b.area()) - intersection;
+    if union <= 0.0 {
+        return 0.0;
+    }
+    intersection / union
+}
+
+// Non-maximum suppression: the most confident detection of every overlapping group wins
+fn non_maximum_suppression(mut faces: Vec<Face>) -> Vec<Face> {
+    faces.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    let mut kept: Vec<Face> = Vec::new();
+    for face in faces {
+        if kept
+            .iter()
+            .all(|k| iou(&k.rect, &face.rect) <= NMS_IOU_THRESHOLD)
+        {
+            kept.push(face);
+        }
+    }
+    kept
+}
+
+// Runs every cascade of the ensemble (downloading missing ones), the profile cascade on the
+// mirrored image too, and merges all detections with non-maximum suppression
+fn detect_ensemble(gray_img: &UMat, params: &HaarParams) -> anyhow::Result<Vec<Face>> {
+    let mut faces = Vec::new();
+    for (file, url) in ENSEMBLE_CASCADES {
+        let path = match ensure_model(file, url) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("{}, skipping {}", e, file);
+                continue;
+            }
+        };
+        let detected = detect_cascade(&path, gray_img, params)?;
+        info!("{}: {} faces", path, detected.len());
+        faces.extend(detected);
+    }
+
+    let mut mirrored = UMat::new_def();
+    opencv::core::flip(gray_img, &mut mirrored, 1).context("Failed to mirror the image")?;
+    let width = mirrored.size()?.width;
+    let profile_path = cache_path(PROFILE_CASCADE_FILE).map_err(anyhow::Error::msg)?;
+    // skipped above when it couldn't be downloaded
+    let detected = detect_cascade(&profile_path, &mirrored, params).unwrap_or_else(|e| {
+        warn!("{:#}", e);
+        Vec::new()
+    });
+    info!("{} (mirrored): {} faces", profile_path, detected.len());
+    faces.extend(detected.into_iter().map(|face| Face {
+        rect: Rect::new(
+            width - face.rect.x - face.rect.width,
+            face.rect.y,
+            face.rect.width,
+            face.rect.height,
+        ),
+        ..face
+    }));
+
+    let merged = non_maximum_suppression(faces);
+    info!("{} faces after non-maximum suppression", merged.len());
+    Ok(merged)
+}
+
+// Downloads the problem image, returns it along with its grayscale version
+fn fetch_image(
+    client: &crate::utils::hackattic_client::HackatticClient,
+) -> anyhow::Result<(Mat, UMat)> {
+    let problem = client.get_problem()?;
+    let image_url = problem["image_url"]
+        .as_str()
+        .with_context(|| format!("Unexpected problem: {}", problem))?;
+    let image_bytes = client.download_file(image_url)?;
+    let path = cache_path(IMAGE_FILE).map_err(anyhow::Error::msg)?;
+    fs::write(&path, image_bytes).with_context(|| format!("Failed to write {}", path))?;
+
+    load_image(&path).context("Failed to load the problem image")
+}
+
+// Reads an image, returns it along with its grayscale version (a UMat, so the conversion and
+// the cascade run on OpenCL when it's enabled)
+fn load_image(path: &str) -> anyhow::Result<(Mat, UMat)> {
+    info!("Loading image from: {}", path);
+    let original_img = imgcodecs::imread(path, imgcodecs::IMREAD_COLOR)
+        .with_context(|| format!("Could not read image at {}", path))?;
+    if original_img.empty() {
+        bail!("Could not read image at {}", path);
+    }
+
+    let mut gray_img = UMat::new_def();
     // Convert to grayscale for the cascade classifier, apparently the model is trained on grayscale images
     imgproc::cvt_color(
-        &original_img,
+        &original_img
+            .get_umat(AccessFlag::ACCESS_READ, UMatUsageFlags::USAGE_DEFAULT)
+            .context("Failed to upload the image")?,
         &mut gray_img,
         imgproc::COLOR_BGR2GRAY,
         0,
         opencv::core::AlgorithmHint::ALGO_HINT_ACCURATE,
     )
-    .unwrap();
-
-    // // --- 3. Load the Cascade Classifier ---
-    println!("Loading cascade classifier from: {}", CASCADE_PATH);
-    let mut face_cascade = match CascadeClassifier::new(&CASCADE_PATH) {
-        Ok(c) => c,
-        Err(_) => {
-            eprintln!(
-                "Error: Could not load the cascade classifier from path: {}",
-                CASCADE_PATH
-            );
-            eprintln!(
-                "Make sure 'haarcascade_frontalface_default.xml' is in the correct location."
-            );
-            return;
-        }
+    .context("Failed to convert the image to grayscale")?;
+
+    Ok((original_img, gray_img))
+}
+
+// Runs the chosen detector, the DNN one falls back to the Haar cascade when it can't run
+fn detect(
+    detector: Detector,
+    params: &HaarParams,
+    score_threshold: f32,
+    gpu: bool,
+    original_img: &Mat,
+    gray_img: &UMat,
+) -> anyhow::Result<Vec<Face>> {
+    let started = Instant::now();
+    let faces = match detector {
+        Detector::Dnn => match detect_dnn(original_img, score_threshold, gpu) {
+            Ok(faces) => Ok(faces),
+            Err(e) => {
+                warn!("{}, falling back to the Haar cascade", e);
+                detect_haar(gray_img, params)
+            }
+        },
+        Detector::Haar => detect_haar(gray_img, params),
+        Detector::Ensemble => detect_ensemble(gray_img, params),
     };
+    info!("Detection took {:.2?}", started.elapsed());
+    faces
+}
 
-    // --- 4. Detect Faces ---
-    let mut faces = Vector::<Rect>::new();
-    face_cascade
-        .detect_multi_scale(
-            &gray_img,
-            &mut faces,
-            1.1,
-            5,
-            0,
-            Size::new(30, 30),
-            Size::default(),
-        )
-        .unwrap();
+// The configured detector first, then the other detectors, then the Haar parameter sweep
+fn attempts(detector: Detector, params: HaarParams) -> Vec<(Detector, HaarParams)> {
+    let mut attempts = vec![(detector, params)];
+    for other in [Detector::Dnn, Detector::Ensemble, Detector::Haar] {
+        if other != detector {
+            attempts.push((other, params));
+        }
+    }
+    for &(scale_factor, min_neighbors) in HAAR_SWEEP {
+        let swept = HaarParams {
+            scale_factor,
+            min_neighbors,
+            ..params
+        };
+        if swept != params {
+            attempts.push((Detector::Haar, swept));
+        }
+    }
+    attempts
+}
 
-    // --- 5. Calculate Face Tiles ---
-    let mut face_tiles = Vec::new();
-    let image_width = original_img.size().unwrap().width;
-    let image_height = original_img.size().unwrap().height;
-    for face in faces.iter() {
-        let x = face.x;
-        let y = face.y;
+/// Detection counts of one configuration over the labeled samples
+#[derive(Default)]
+struct Score {
+    true_positives: usize,
+    false_positives: usize,
+    false_negatives: usize,
+    exact_matches: usize,
+}
 
-        let row = y / (image_height / 8);
-        let col = x / (image_width / 8);
-        face_tiles.push([row, col]);
+impl Score {
+    fn precision(&self) -> f64 {
+        let detected = self.true_positives + self.false_positives;
+        if detected == 0 {
+            return 0.0;
+        }
+        self.true_positives as f64 / detected as f64
     }
 
-    // --- 6. Draw Rectangles for debugging ---
-    let mut detected_faces_img = original_img.clone();
-    let green = Scalar::new(0.0, 255.0, 0.0, 0.0);
-    for face in faces.iter() {
-        imgproc::rectangle(&mut detected_faces_img, face, green, 2, imgproc::LINE_8, 0).unwrap()
+    fn recall(&self) -> f64 {
+        let expected = self.true_positives + self.false_negatives;
+        if expected == 0 {
+            return 0.0;
+        }
+        self.true_positives as f64 / expected as f64
+    }
+}
+
+// Labeled samples in `dir`: every image with a JSON sidecar of the same name holding the
+// expected tiles in the submission format, e.g. `face1.jpeg` + `face1.json` with
+// `{"face_tiles": [[0, 3], [5, 1]]}`
+fn load_samples(dir: &str) -> anyhow::Result<Vec<(String, Vec<[i32; 2]>)>> {
+    let mut samples = Vec::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Failed to read {}", dir))?
+            .path();
+        let is_image = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ["jpg", "jpeg", "png"].contains(&ext.to_lowercase().as_str()));
+        if !is_image {
+            continue;
+        }
+
+        let sidecar = path.with_extension("json");
+        let Ok(json) = fs::read_to_string(&sidecar) else {
+            warn!("Skipping {}: no {}", path.display(), sidecar.display());
+            continue;
+        };
+        let labels: serde_json::Value = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid JSON in {}", sidecar.display()))?;
+        let mut tiles: Vec<[i32; 2]> = serde_json::from_value(labels["face_tiles"].clone())
+            .with_context(|| format!("Invalid face_tiles in {}", sidecar.display()))?;
+        tiles.sort();
+        tiles.dedup();
+
+        samples.push((path.to_string_lossy().to_string(), tiles));
+    }
+    samples.sort();
+    Ok(samples)
+}
+
+// Runs every configuration of the retry sweep against the labeled samples in `dir` and
+// prints precision/recall per configuration, without touching hackattic
+fn evaluate(dir: &str, detector: Detector, score_threshold: f32, gpu: bool) -> anyhow::Result<()> {
+    let samples = load_samples(dir)?;
+    if samples.is_empty() {
+        bail!("No labeled samples in {}", dir);
+    }
+
+    let mut images = Vec::new();
+    for (path, expected) in &samples {
+        match load_image(path) {
+            Ok((original_img, gray_img)) => images.push((original_img, gray_img, expected)),
+            Err(e) => warn!("{:#}, skipping it", e),
+        }
     }
 
-    println!(
-        "Saving image with highlighted faces to: {}",
-        OUTPUT_IMAGE_PATH
+    info!(
+        "{:<8} {:<44} {:>9} {:>7} {:>6}",
+        "detector", "haar params", "precision", "recall", "exact"
     );
-    imgcodecs::imwrite(OUTPUT_IMAGE_PATH, &detected_faces_img, &Vector::new()).unwrap();
+    for (detector, params) in attempts(detector, HaarParams::from_args()?) {
+        let mut score = Score::default();
+        for (original_img, gray_img, expected) in &images {
+            let faces = detect(
+                detector,
+                &params,
+                score_threshold,
+                gpu,
+                original_img,
+                gray_img,
+            )
+            .with_context(|| format!("Face detection failed on {:?}", detector))?;
+            let tiles = face_tiles(&faces, original_img.size()?);
 
-    // --- 7. Submit Solution ---
-    let solution = json!({
-        "face_tiles": face_tiles
-    });
+            let hits = tiles.iter().filter(|tile| expected.contains(tile)).count();
+            score.true_positives += hits;
+            score.false_positives += tiles.len() - hits;
+            score.false_negatives += expected.len() - hits;
+            if tiles == **expected {
+                score.exact_matches += 1;
+            }
+        }
+
+        let params = match detector {
+            Detector::Dnn => format!("score >= {}", score_threshold),
+            Detector::Haar | Detector::Ensemble => format!(
+                "scale {} neighbors {} size {}x{}..{}x{}",
+                params.scale_factor,
+                params.min_neighbors,
+                params.min_size.width,
+                params.min_size.height,
+                params.max_size.width,
+                params.max_size.height
+            ),
+        };
+        info!(
+            "{:<8} {:<44} {:>9.3} {:>7.3} {:>3}/{}",
+            format!("{:?}", detector),
+            params,
+            score.precision(),
+            score.recall(),
+            score.exact_matches,
+            images.len()
+        );
+    }
+    Ok(())
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let detector = match crate::utils::args::get("--detector", "FACE_DETECTOR").as_deref() {
+        None | Some("haar") => Detector::Haar,
+        Some("dnn") => Detector::Dnn,
+        Some("ensemble") => Detector::Ensemble,
+        Some(other) => bail!(
+            "Unknown detector {:?}, expected haar, dnn or ensemble",
+            other
+        ),
+    };
+    let score_threshold = crate::utils::args::get("--score-threshold", "FACE_SCORE_THRESHOLD")
+        .map(|value| value.parse().context("Invalid --score-threshold"))
+        .transpose()?
+        .unwrap_or(DEFAULT_SCORE_THRESHOLD);
+    let retries = crate::utils::args::get("--retries", "FACE_RETRIES")
+        .map(|value| value.parse().context("Invalid --retries"))
+        .transpose()?
+        .unwrap_or(DEFAULT_RETRIES);
+    let gpu = crate::utils::args::is_set("--gpu", "FACE_GPU");
+    setup_gpu(gpu)?;
+
+    if let Some(dir) = crate::utils::args::get("--evaluate", "FACE_EVALUATE_DIR") {
+        return evaluate(&dir, detector, score_threshold, gpu);
+    }
+
+    // retry on a new problem instead of resubmitting for the same image
+    let refetch = crate::utils::args::is_set("--refetch", "FACE_REFETCH");
+
+    // --- 1. Download Image and Pre-process it ---
+    let client = crate::utils::hackattic_client::HackatticClient::new("basic_face_detection")?;
+    let (mut original_img, mut gray_img) = fetch_image(&client)?;
+
+    let mut rejected_tiles: Vec<Vec<[i32; 2]>> = Vec::new();
+    let mut submissions = 0;
+    for (detector, params) in attempts(detector, HaarParams::from_args()?) {
+        if submissions > retries {
+            break;
+        }
+
+        // --- 2. Detect Faces ---
+        info!("Detecting faces with {:?} {:?}", detector, params);
+        let faces = detect(
+            detector,
+            &params,
+            score_threshold,
+            gpu,
+            &original_img,
+            &gray_img,
+        )
+        .with_context(|| format!("Face detection failed with {:?}", detector))?;
+        info!("Detected {} faces", faces.len());
+
+        // --- 3. Calculate Face Tiles ---
+        let face_tiles = face_tiles(&faces, original_img.size()?);
+        info!("Face tiles: {:?}", face_tiles);
+        if rejected_tiles.contains(&face_tiles) {
+            info!("Same tiles as a rejected submission, skipping");
+            continue;
+        }
+
+        // --- 4. Draw Grid, Faces and Tiles for debugging ---
+        let debug_img =
+            draw_debug_image(&original_img, &faces).context("Failed to draw the debug image")?;
+        let output_path = data_path(OUTPUT_IMAGE_FILE).map_err(anyhow::Error::msg)?;
+        info!("Saving image with highlighted faces to: {}", output_path);
+        imgcodecs::imwrite(&output_path, &debug_img, &Vector::new())
+            .with_context(|| format!("Failed to write {}", output_path))?;
+
+        // --- 5. Submit Solution ---
+        let solution = json!({
+            "face_tiles": face_tiles
+        });
+        let response = client.submit_solution(solution)?;
+        if crate::utils::hackattic_client::HackatticClient::is_accepted(&response) {
+            return Ok(());
+        }
+        submissions += 1;
+        info!("Rejected, {} retries left", retries + 1 - submissions);
+
+        if refetch {
+            (original_img, gray_img) = fetch_image(&client)?;
+            rejected_tiles.clear();
+        } else {
+            rejected_tiles.push(face_tiles);
+        }
+    }
 
-    client.submit_solution(solution);
+    bail!("No accepted submission within the retry budget")
 }
@@ -1,29 +1,25 @@
+use anyhow::{Context, bail};
 use crossbeam_channel::{Receiver, Sender, unbounded};
-use serde_json::json;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
-
-// Helper functions for human-readable formatting
-fn format_number(n: u64) -> String {
-    if n >= 1_000_000 {
-        format!("{:.1}M", n as f64 / 1_000_000.0)
-    } else if n >= 1_000 {
-        format!("{:.1}K", n as f64 / 1_000.0)
-    } else {
-        n.to_string()
-    }
+use tracing::{info, info_span};
+
+use crate::utils::notify::{self, Event};
+use crate::utils::progress::Progress;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Problem {
+    pub zip_url: String,
 }
 
-fn format_rate(rate: f64) -> String {
-    if rate >= 1_000_000.0 {
-        format!("{:.1}M", rate / 1_000_000.0)
-    } else if rate >= 1_000.0 {
-        format!("{:.1}K", rate / 1_000.0)
-    } else {
-        format!("{:.0}", rate)
-    }
+#[derive(Debug, Clone, Serialize)]
+pub struct Solution {
+    pub secret: String,
+    /// The archive's password, not part of the submission
+    #[serde(skip)]
+    pub password: String,
 }
 
 fn spawn_password_generator(
@@ -35,9 +31,9 @@ fn spawn_password_generator(
     let found_flag_producer = Arc::clone(&password_found);
     let shutdown_signal_producer = Arc::clone(&shutdown_signal);
     thread::spawn(move || {
-        println!("Password generator thread started.");
+        info!("Password generator thread started.");
         for length in 4..=6 {
-            println!("Generating passwords of length {}", length);
+            info!("Generating passwords of length {}", length);
             let mut indices = vec![0; length];
 
             loop {
@@ -45,7 +41,7 @@ fn spawn_password_generator(
                 if found_flag_producer.load(Ordering::Relaxed)
                     || shutdown_signal_producer.load(Ordering::Relaxed)
                 {
-                    println!("Stopping generator (password found or shutdown signal received).");
+                    info!("Stopping generator (password found or shutdown signal received).");
                     break;
                 }
 
@@ -70,7 +66,7 @@ fn spawn_password_generator(
                     break; // finished all passwords of this length
                 }
             }
-            println!("Finished generating passwords of length {}", length);
+            info!("Finished generating passwords of length {}", length);
         }
         // Dropping the sender signals that no more messages will be sent.
         drop(tx_main);
@@ -82,32 +78,32 @@ fn create_worker_handle(
     rx_worker: Receiver<String>,
     secret_content: Vec<u8>,
     crc32: u32,
-    password_counter: Arc<AtomicU64>,
+    progress: Arc<Progress>,
     password_found: Arc<AtomicBool>,
     shutdown_signal: Arc<AtomicBool>,
     found_password: Arc<Mutex<String>>,
     decrypted_content: Arc<Mutex<Vec<u8>>>,
 ) -> thread::JoinHandle<()> {
     thread::spawn(move || {
-        println!("Worker {} started.", worker_id);
+        info!("Worker {} started.", worker_id);
         // The loop will automatically break when the sender is dropped and the channel is empty.
         while let Ok(password) = rx_worker.recv() {
             // Check for shutdown signal before processing
             if shutdown_signal.load(Ordering::Relaxed) {
-                println!("Worker {} received shutdown signal.", worker_id);
+                info!("Worker {} received shutdown signal.", worker_id);
                 break;
             }
 
             if password_found.load(Ordering::Relaxed) {
-                println!("Worker {} received found signal.", worker_id);
+                info!("Worker {} received found signal.", worker_id);
                 break;
             }
 
             // Increment counter when we actually TRY the password
-            password_counter.fetch_add(1, Ordering::Relaxed);
+            progress.add(1);
 
             if crate::utils::zip::verify_zip_crypto_password(&secret_content, &password, crc32) {
-                println!("Found password: {}", password);
+                info!(success = true, "Found password: {}", password);
 
                 // Decrypt the file content
                 let decrypted =
@@ -125,104 +121,43 @@ fn create_worker_handle(
                 break;
             }
         }
-        println!("Worker {} finished.", worker_id);
+        info!("Worker {} finished.", worker_id);
     })
 }
 
-pub fn run() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("brute_force_zip");
-
-    println!("Getting ZIP file URL from Hackattic API...");
-    let problem = client.get_problem();
-    let zip_url = problem["zip_url"].as_str().unwrap();
-    println!("ZIP URL: {}", zip_url);
+/// Cracks the downloaded ZIP's password and returns its secret.txt. The problem is only the
+/// archive's URL, so this takes the archive itself
+pub fn solve(zip: &[u8]) -> anyhow::Result<Solution> {
+    solve_cancellable(zip, Arc::new(AtomicBool::new(false)))
+}
 
-    println!("Downloading ZIP file...");
-    let file = client.download_file(zip_url);
-    let is_zip = crate::utils::zip::check_if_zip(&file);
-    if !is_zip {
-        panic!("The downloaded file is not a ZIP file");
+/// Cracks the ZIP until the password is found or `shutdown_signal` is set
+pub fn solve_cancellable(zip: &[u8], shutdown_signal: Arc<AtomicBool>) -> anyhow::Result<Solution> {
+    let _span = info_span!("solve", bytes = zip.len()).entered();
+    if !crate::utils::zip::check_if_zip(zip) {
+        bail!("The downloaded file is not a ZIP file");
     }
-    println!("ZIP file downloaded successfully ({} bytes)", file.len());
 
     let charset: Vec<char> = ('a'..='z').chain('0'..='9').collect();
 
-    let password_counter = Arc::new(AtomicU64::new(0));
     let password_found = Arc::new(AtomicBool::new(false));
-    let shutdown_signal = Arc::new(AtomicBool::new(false));
-    let shutdown_signal_clone = Arc::clone(&shutdown_signal);
-    let start_time = Instant::now();
 
     // Shared state for storing the found password and decrypted content
     let found_password = Arc::new(Mutex::new(String::new()));
     let decrypted_content = Arc::new(Mutex::new(Vec::<u8>::new()));
 
-    // Set up Ctrl+C handler
-    ctrlc::set_handler(move || {
-        println!("\nReceived Ctrl+C, shutting down gracefully...");
-        shutdown_signal_clone.store(true, Ordering::Relaxed);
-    })
-    .expect("Error setting Ctrl+C handler");
-
     let (tx_main, rx_main): (Sender<String>, Receiver<String>) = unbounded();
-    let files = crate::utils::zip::extract_all_files(&file);
+    let files = info_span!("extract")
+        .in_scope(|| crate::utils::zip::extract_all_files(zip))
+        .context("Failed to read the ZIP file")?;
     let (_, secret_content, crc32) = files
         .iter()
         .find(|(filename, _, _)| filename == "secret.txt")
-        .unwrap()
+        .context("No secret.txt in the ZIP file")?
         .clone();
 
-    // Spawn logging thread
-    let counter_clone = Arc::clone(&password_counter);
-    let found_flag_logger = Arc::clone(&password_found);
-    let shutdown_signal_logger = Arc::clone(&shutdown_signal);
-    let start_time_clone = start_time;
-    thread::spawn(move || {
-        let log_interval_secs = 2; // Change this to adjust logging frequency
-        let mut last_count = 0u64;
-        let mut last_time = start_time_clone;
-
-        loop {
-            thread::sleep(Duration::from_secs(log_interval_secs));
-
-            // Check if password was found or shutdown signal received
-            if found_flag_logger.load(Ordering::Relaxed)
-                || shutdown_signal_logger.load(Ordering::Relaxed)
-            {
-                break;
-            }
-
-            let current_count = counter_clone.load(Ordering::Relaxed);
-            let current_time = Instant::now();
-
-            // Calculate rates
-            let total_elapsed = start_time_clone.elapsed().as_secs_f64();
-            let interval_elapsed = current_time.duration_since(last_time).as_secs_f64();
-
-            let avg_rate = if total_elapsed > 0.0 {
-                current_count as f64 / total_elapsed
-            } else {
-                0.0
-            };
-
-            let interval_rate = if interval_elapsed > 0.0 {
-                (current_count - last_count) as f64 / interval_elapsed
-            } else {
-                0.0
-            };
-
-            println!(
-                "Passwords tried: {} | Avg rate: {}/sec | Current rate: {}/sec",
-                format_number(current_count),
-                format_rate(avg_rate),
-                format_rate(interval_rate)
-            );
-
-            // Update for next iteration
-            last_count = current_count;
-            last_time = current_time;
-        }
-    });
+    let crack = info_span!("crack").entered();
+    let progress = Arc::new(Progress::start("Passwords", None));
 
     // Spawn password generator thread
     spawn_password_generator(
@@ -233,7 +168,8 @@ pub fn run() {
     );
 
     let mut handles = vec![];
-    let num_workers = num_cpus::get() - 1;
+    // one core is left to the generator, but a single-core machine still needs a worker
+    let num_workers = num_cpus::get().saturating_sub(1).max(1);
 
     // Spawn worker threads
     for i in 0..num_workers {
@@ -244,7 +180,7 @@ pub fn run() {
             rx_worker,
             secret_content.clone(),
             crc32,
-            Arc::clone(&password_counter),
+            Arc::clone(&progress),
             Arc::clone(&password_found),
             Arc::clone(&shutdown_signal),
             Arc::clone(&found_password),
@@ -258,55 +194,49 @@ pub fn run() {
         handle.join().unwrap();
     }
 
-    // Final statistics
-    let final_count = password_counter.load(Ordering::Relaxed);
-    let total_elapsed = start_time.elapsed().as_secs_f64();
-    let final_rate = if total_elapsed > 0.0 {
-        final_count as f64 / total_elapsed
-    } else {
-        0.0
-    };
-
-    let was_shutdown = shutdown_signal.load(Ordering::Relaxed);
-    let was_found = password_found.load(Ordering::Relaxed);
-
-    println!("All threads have finished.");
-    if was_shutdown {
-        println!("Program was interrupted by user (Ctrl+C).");
-    } else if was_found {
-        println!("Password was found successfully!");
-
-        // Print the found password and decrypted content
-        if let Ok(pwd) = found_password.lock() {
-            if !pwd.is_empty() {
-                println!("Password: {}", pwd);
-            }
-        }
+    info!("All threads have finished.");
+    progress.finish();
+    drop(crack);
 
-        if let Ok(content) = decrypted_content.lock() {
-            if !content.is_empty() {
-                println!("Decrypted content:");
-                match String::from_utf8(content.clone()) {
-                    Ok(text) => {
-                        println!("{}", text);
-                        println!("Submitting solution to Hackattic API...");
-                        let solution = json!({
-                            "secret": text.trim()
-                        });
-                        client.submit_solution(solution);
-                    }
-                    Err(_) => {
-                        panic!("Failed to decode decrypted content as UTF-8");
-                    }
-                }
-            }
+    if !password_found.load(Ordering::Relaxed) {
+        if shutdown_signal.load(Ordering::Relaxed) {
+            bail!("Interrupted before the password was found");
         }
-    } else {
-        println!("Search completed without finding password.");
+        bail!("Search completed without finding password");
     }
 
-    println!("Final statistics:");
-    println!("  Total passwords tried: {}", format_number(final_count));
-    println!("  Total time: {:.2} seconds", total_elapsed);
-    println!("  Average rate: {}/sec", format_rate(final_rate));
+    let password = found_password.lock().unwrap().clone();
+    info!("Password: {}", password);
+    let content = decrypted_content.lock().unwrap().clone();
+    let text = String::from_utf8(content).context("Failed to decode decrypted content as UTF-8")?;
+    info!("Decrypted content:");
+    info!("{}", text);
+    Ok(Solution {
+        secret: text.trim().to_string(),
+        password,
+    })
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("brute_force_zip")?;
+
+    info!("Getting ZIP file URL from Hackattic API...");
+    let problem = client.get_problem()?;
+    let problem: Problem = serde_json::from_value(problem.clone())
+        .with_context(|| format!("Unexpected problem: {}", problem))?;
+    info!("ZIP URL: {}", problem.zip_url);
+
+    info!("Downloading ZIP file...");
+    let file = client.download_file(&problem.zip_url)?;
+    info!("ZIP file downloaded ({} bytes)", file.len());
+
+    let solution = solve_cancellable(&file, crate::utils::shutdown::token())?;
+    notify::notify(
+        Event::Found,
+        "brute_force_zip",
+        &format!("password {}", solution.password),
+    );
+    info!("Submitting solution to Hackattic API...");
+    client.submit_solution(serde_json::to_value(&solution)?)?;
+    Ok(())
 }
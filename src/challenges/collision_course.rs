@@ -1,59 +1,513 @@
+use anyhow::{Context, bail};
 use base64::Engine;
+use md5::Md5;
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
-fn execute_fastcoll() -> std::process::Output {
-    // Get current directory and user/group IDs
-    let current_dir = std::env::current_dir().unwrap();
-    let current_dir_str = current_dir.to_str().unwrap();
+use crate::utils::data_dir::DataDir;
+use crate::utils::docker;
+use crate::utils::md5_collision;
+use crate::utils::notify::{self, Event};
+use crate::utils::progress::{Progress, format_duration};
+use crate::utils::seed;
 
-    let volume_mount = format!("{}:/work", current_dir_str);
+const FASTCOLL_IMAGE: &str = "brimstone/fastcoll";
+// How often waiting loops look at the shutdown token
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Stderr lines kept for error messages
+const STDERR_TAIL_LINES: usize = 20;
+// MD5 block size, the collision blocks start on a block boundary
+const BLOCK_SIZE: usize = 64;
 
-    let mut binding = std::process::Command::new("docker");
+enum CollisionEngine {
+    // identical-prefix search in-process
+    Native,
+    // identical-prefix search with the fastcoll docker image
+    Fastcoll,
+    // chosen-prefix search with hashclash, hours instead of seconds
+    Hashclash,
+}
+
+/// The prefix bytes from the problem's `include` string: `text` (its UTF-8 bytes), `base64`,
+/// `hex`, `latin1` (one byte per \u0000-\u00ff character, binary sent as JSON escapes) or
+/// `auto`, which takes base64 only when it decodes to something that isn't text
+fn decode_prefix(include: &str, encoding: &str) -> Result<Vec<u8>, String> {
+    let base64 = || base64::engine::general_purpose::STANDARD.decode(include);
+    match encoding {
+        "text" => Ok(include.as_bytes().to_vec()),
+        "base64" => base64().map_err(|e| format!("Prefix is not valid base64: {}", e)),
+        "hex" => hex::decode(include).map_err(|e| format!("Prefix is not valid hex: {}", e)),
+        "latin1" => include
+            .chars()
+            .map(|c| u8::try_from(c).map_err(|_| format!("Prefix has {:?}, which isn't latin1", c)))
+            .collect(),
+        "auto" => {
+            // short words like "test" are valid base64 too, so ask for a base64-only character
+            // or some length before believing it
+            let looks_base64 = include.contains(['+', '/', '=']) || include.len() >= 24;
+            if let Ok(decoded) = base64()
+                && looks_base64
+                && std::str::from_utf8(&decoded).is_err()
+            {
+                info!(
+                    "Prefix looks like base64, using the {} decoded bytes",
+                    decoded.len()
+                );
+                return Ok(decoded);
+            }
+            Ok(include.as_bytes().to_vec())
+        }
+        other => Err(format!(
+            "Unknown prefix encoding {:?}, expected text, base64, hex, latin1 or auto",
+            other
+        )),
+    }
+}
+
+// prefix.txt, file1.bin and file2.bin are exchanged with the engines in the challenge's cache
+// directory, hashclash works in its hashclash/ subdirectory
+fn data_dir() -> DataDir {
+    DataDir::cache("collision_course")
+}
+
+/// Zero pads the prefix to a multiple of the MD5 block size, as fastcoll does
+fn pad_prefix(prefix: &[u8]) -> Vec<u8> {
+    let mut padded = prefix.to_vec();
+    padded.resize(prefix.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    padded
+}
+
+fn execute_fastcoll(container: &str) -> Result<Child, String> {
+    // docker wants an absolute path for the volume
+    let dir = data_dir().create().and_then(|dir| {
+        std::fs::canonicalize(dir)
+            .map_err(|e| format!("Failed to resolve {}: {}", dir.display(), e))
+    })?;
+    let volume_mount = format!("{}:/work", dir.display());
+
+    let mut binding = Command::new("docker");
     let command = binding
         .arg("run")
         .arg("--rm")
+        // named so it can be killed, killing the docker client leaves the container running
+        .arg("--name")
+        .arg(container)
         .arg("-v")
         .arg(&volume_mount)
         .arg("-w")
         .arg("/work")
-        .arg("brimstone/fastcoll")
+        .arg(FASTCOLL_IMAGE)
         .arg("--prefixfile")
-        .arg("./data/prefix.txt")
+        .arg("prefix.txt")
         .arg("-o")
-        .arg("./data/file1.bin")
-        .arg("./data/file2.bin");
+        .arg("file1.bin")
+        .arg("file2.bin")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
 
     // print command
-    println!("Executing command: {:?}", command);
-    let output = command.output().unwrap();
+    info!("Executing command: {:?}", command);
+    command
+        .spawn()
+        .map_err(|e| format!("Failed to run docker: {}", e))
+}
 
-    return output;
+/// Forwards fastcoll's output as it comes. Fastcoll prints a dot per iteration of the block
+/// searches, those are counted by the progress reporter instead of printed
+fn stream_fastcoll(child: &mut Child) -> JoinHandle<Vec<String>> {
+    let stdout = child.stdout.take().map(|mut stdout| {
+        thread::spawn(move || {
+            let progress = Progress::start("Iterations", None);
+            let mut text = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while let Ok(n) = stdout.read(&mut buffer) {
+                if n == 0 {
+                    break;
+                }
+                for &byte in &buffer[..n] {
+                    if byte == b'.' || byte == b'\n' {
+                        // "Generating first block: ....." shows up before its dots
+                        let line = String::from_utf8_lossy(&text);
+                        if !line.trim().is_empty() {
+                            info!("[fastcoll] {}", line.trim());
+                        }
+                        text.clear();
+                        if byte == b'.' {
+                            progress.add(1);
+                        }
+                    } else {
+                        text.push(byte);
+                    }
+                }
+            }
+            progress.finish();
+        })
+    });
+    let stderr = stream_stderr(child, "fastcoll");
+
+    thread::spawn(move || {
+        if let Some(stdout) = stdout {
+            let _ = stdout.join();
+        }
+        stderr.join().unwrap_or_default()
+    })
 }
 
-pub fn run() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("collision_course");
+/// Pulls the fastcoll image, so `--engine docker` can run offline
+pub fn prefetch() -> Result<Vec<String>, String> {
+    docker::preflight(FASTCOLL_IMAGE)?;
+    Ok(vec![format!("docker image {}", FASTCOLL_IMAGE)])
+}
 
-    let problem = client.get_problem();
-    let prefix = problem["include"].as_str().unwrap();
+fn run_fastcoll(prefix: &[u8]) -> Result<(), String> {
+    docker::preflight(FASTCOLL_IMAGE).map_err(|e| {
+        format!(
+            "{}, or use --engine native for the built-in collision search",
+            e
+        )
+    })?;
 
-    // save prefix to file
-    std::fs::write("./data/prefix.txt", prefix).unwrap();
+    // save prefix to file, padded so the collision blocks start right after it
+    let prefix_path = data_dir().file("prefix.txt")?;
+    std::fs::write(&prefix_path, pad_prefix(prefix))
+        .map_err(|e| format!("Failed to write {}: {}", prefix_path.display(), e))?;
 
-    let output = execute_fastcoll();
-    if !output.status.success() {
-        println!(
-            "fastcoll failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        panic!("fastcoll failed");
+    let container = format!("hackattic-fastcoll-{}", std::process::id());
+    let mut child = execute_fastcoll(&container)?;
+    let output = stream_fastcoll(&mut child);
+    let status = wait_or_cancel(&mut child, |child| {
+        let _ = docker::run(&["kill", &container]);
+        let _ = child.kill();
+    })?;
+    let stderr_tail = output.join().unwrap_or_default();
+    if !status.success() {
+        return Err(docker::explain_error(
+            &stderr_tail.join("\n"),
+            FASTCOLL_IMAGE,
+        ));
     }
-    println!(
-        "fastcoll output: {}",
-        String::from_utf8_lossy(&output.stdout)
+    Ok(())
+}
+
+/// Waits for the child to exit, calling `kill` on it when a shutdown is requested
+fn wait_or_cancel(child: &mut Child, kill: impl Fn(&mut Child)) -> Result<ExitStatus, String> {
+    let shutdown = crate::utils::shutdown::token();
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|e| format!("Failed to wait for the collision search: {}", e))?
+        {
+            return Ok(status);
+        }
+        if shutdown.load(Ordering::Relaxed) {
+            kill(child);
+            let _ = child.wait();
+            return Err("The collision search was cancelled".to_string());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Prints stderr lines as they come, prefixed with the elapsed time. The handle returns the
+/// tail of stderr for the error message
+fn stream_stderr(child: &mut Child, name: &'static str) -> JoinHandle<Vec<String>> {
+    let start = Instant::now();
+    let stderr = child.stderr.take();
+    thread::spawn(move || {
+        let mut tail = Vec::new();
+        let Some(stderr) = stderr else {
+            return tail;
+        };
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            warn!(
+                "[{} {}] {}",
+                name,
+                format_duration(start.elapsed().as_secs_f64()),
+                line
+            );
+            tail.push(line);
+            if tail.len() > STDERR_TAIL_LINES {
+                tail.remove(0);
+            }
+        }
+        tail
+    })
+}
+
+/// Prints every line of the child's output as it comes, prefixed with the elapsed time.
+/// The handle returns the tail of stderr for the error message
+fn stream_output(child: &mut Child, name: &'static str) -> JoinHandle<Vec<String>> {
+    let start = Instant::now();
+    let stdout = child.stdout.take().map(|stdout| {
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                info!(
+                    "[{} {}] {}",
+                    name,
+                    format_duration(start.elapsed().as_secs_f64()),
+                    line
+                );
+            }
+        })
+    });
+    let stderr = stream_stderr(child, name);
+
+    thread::spawn(move || {
+        if let Some(stdout) = stdout {
+            let _ = stdout.join();
+        }
+        stderr.join().unwrap_or_default()
+    })
+}
+
+/// Chosen-prefix collision with hashclash's cpc.sh, from a hashclash build in `HASHCLASH_DIR`
+/// (or `--hashclash-dir`). The two chosen prefixes are the challenge prefix followed by `1` and
+/// `2`, so both files still start with the prefix
+fn run_hashclash(prefix: &[u8]) -> Result<(), String> {
+    let hashclash_dir = crate::utils::args::get("--hashclash-dir", "HASHCLASH_DIR")
+        .ok_or("Set HASHCLASH_DIR (or --hashclash-dir) to a hashclash build")?;
+    let script = std::fs::canonicalize(Path::new(&hashclash_dir).join("scripts/cpc.sh"))
+        .map_err(|e| format!("No scripts/cpc.sh in {}: {}", hashclash_dir, e))?;
+
+    // cpc.sh leaves a lot of intermediate files in the working directory
+    let work_dir = data_dir().join("hashclash");
+    let work_dir = work_dir.create()?;
+    for (name, suffix) in [("prefix1.bin", b'1'), ("prefix2.bin", b'2')] {
+        let path = work_dir.join(name);
+        let mut chosen = prefix.to_vec();
+        chosen.push(suffix);
+        std::fs::write(&path, chosen)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+
+    info!(
+        "Running {} prefix1.bin prefix2.bin, this takes hours",
+        script.display()
     );
+    let mut child = Command::new(&script)
+        .args(["prefix1.bin", "prefix2.bin"])
+        .current_dir(work_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", script.display(), e))?;
+    let output = stream_output(&mut child, "hashclash");
+    let status = wait_or_cancel(&mut child, |child| {
+        let _ = child.kill();
+    })?;
+    let stderr_tail = output.join().unwrap_or_default();
+    if !status.success() {
+        return Err(format!(
+            "hashclash failed ({}): {}",
+            status,
+            stderr_tail.join("\n")
+        ));
+    }
+
+    // the colliding files are written next to the prefixes with a .coll extension
+    for (coll, file) in [
+        ("prefix1.bin.coll", "file1.bin"),
+        ("prefix2.bin.coll", "file2.bin"),
+    ] {
+        let from = work_dir.join(coll);
+        std::fs::copy(&from, data_dir().path().join(file))
+            .map_err(|e| format!("hashclash didn't produce {}: {}", from.display(), e))?;
+    }
+    Ok(())
+}
 
-    let file1 = std::fs::read("./data/file1.bin").unwrap();
-    let file2 = std::fs::read("./data/file2.bin").unwrap();
+/// Runs the collision search on every core, the first worker to finish stops the others.
+/// Ctrl+C stops all of them
+fn generate(prefix: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let shutdown = crate::utils::shutdown::token();
+    let stop = &AtomicBool::new(false);
+    let workers = num_cpus::get() as u64;
+    // each worker searches from its own seed, which one finishes first still varies
+    let seed = seed::Rng::new("collision_course").next_u64();
+
+    info!("Searching for an MD5 collision on {} threads", workers);
+    let start = Instant::now();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| {
+                scope.spawn(move || {
+                    let pair = md5_collision::generate(prefix, seed ^ (worker << 48), stop);
+                    stop.store(true, Ordering::Relaxed);
+                    pair
+                })
+            })
+            .collect();
+
+        // the workers also stop each other, so they get their own flag
+        scope.spawn(|| {
+            while !stop.load(Ordering::Relaxed) {
+                if shutdown.load(Ordering::Relaxed) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+
+        let pair = handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap())
+            .next();
+
+        match pair {
+            Some(pair) => {
+                info!(
+                    "Found a collision in {:.2} seconds",
+                    start.elapsed().as_secs_f64()
+                );
+                Ok(pair)
+            }
+            None => Err("The collision search was cancelled".to_string()),
+        }
+    })
+}
+
+fn md5(data: &[u8]) -> String {
+    hex::encode(Md5::digest(data))
+}
+
+/// Checks the pair before spending a submission on it: both files start with the prefix, they
+/// differ and their MD5 digests match. Returns the shared digest
+fn verify_collision(prefix: &[u8], file1: &[u8], file2: &[u8]) -> Result<String, String> {
+    for (name, file) in [("file1", file1), ("file2", file2)] {
+        if !file.starts_with(prefix) {
+            return Err(format!("{} doesn't start with the required prefix", name));
+        }
+    }
+    if file1 == file2 {
+        return Err("file1 and file2 are identical".to_string());
+    }
+
+    let (digest1, digest2) = (md5(file1), md5(file2));
+    if digest1 != digest2 {
+        return Err(format!(
+            "MD5 digests differ: file1 {}, file2 {}",
+            digest1, digest2
+        ));
+    }
+    Ok(digest1)
+}
+
+// Pairs are kept per prefix as collisions/<sha256 of the prefix>/file{1,2}.bin in the cache
+fn cache_dir(prefix: &[u8]) -> PathBuf {
+    data_dir()
+        .join("collisions")
+        .path()
+        .join(hex::encode(Sha256::digest(prefix)))
+}
+
+fn cached_pair(prefix: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let dir = cache_dir(prefix);
+    let file1 = std::fs::read(dir.join("file1.bin")).ok()?;
+    let file2 = std::fs::read(dir.join("file2.bin")).ok()?;
+    Some((file1, file2))
+}
+
+fn write_pair(file1: &[u8], file2: &[u8]) -> Result<(), String> {
+    let dir = data_dir();
+    let dir = dir.create()?;
+    std::fs::write(dir.join("file1.bin"), file1)
+        .and_then(|_| std::fs::write(dir.join("file2.bin"), file2))
+        .map_err(|e| format!("Failed to write the collision files: {}", e))
+}
+
+fn cache_pair(prefix: &[u8], file1: &[u8], file2: &[u8]) -> Result<(), String> {
+    let dir = cache_dir(prefix);
+    std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(dir.join("file1.bin"), file1))
+        .and_then(|_| std::fs::write(dir.join("file2.bin"), file2))
+        .map_err(|e| format!("Failed to cache the pair in {}: {}", dir.display(), e))
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("collision_course")?;
+
+    let problem = client.get_problem()?;
+    let include = problem["include"]
+        .as_str()
+        .with_context(|| format!("Unexpected problem: {}", problem))?;
+    let encoding = crate::utils::args::get("--prefix-encoding", "COLLISION_PREFIX_ENCODING")
+        .unwrap_or_else(|| "text".to_string());
+    let prefix = decode_prefix(include, &encoding).map_err(anyhow::Error::msg)?;
+    info!("Prefix is {} bytes", prefix.len());
+
+    let engine = match crate::utils::args::get("--engine", "COLLISION_ENGINE").as_deref() {
+        None | Some("native") => CollisionEngine::Native,
+        Some("fastcoll") => CollisionEngine::Fastcoll,
+        Some("hashclash") => CollisionEngine::Hashclash,
+        Some(other) => bail!(
+            "Unknown engine {:?}, expected native, fastcoll or hashclash",
+            other
+        ),
+    };
+
+    let use_cache = !crate::utils::args::is_set("--no-cache", "NO_CACHE");
+    let cached = if use_cache {
+        cached_pair(&prefix)
+    } else {
+        None
+    };
+
+    let from_cache = cached.is_some();
+
+    let generated = match (cached, engine) {
+        // a pair found for the same prefix earlier, usually when retrying a submission
+        (Some((file1, file2)), _) => {
+            info!(
+                "Reusing the collision cached in {}",
+                cache_dir(&prefix).display()
+            );
+            write_pair(&file1, &file2)
+        }
+        (None, CollisionEngine::Native) => {
+            generate(&prefix).and_then(|(file1, file2)| write_pair(&file1, &file2))
+        }
+        (None, CollisionEngine::Fastcoll) => run_fastcoll(&prefix),
+        (None, CollisionEngine::Hashclash) => run_hashclash(&prefix),
+    };
+    generated.map_err(anyhow::Error::msg)?;
+
+    let [file1, file2] = ["file1.bin", "file2.bin"].map(|file| {
+        let path = data_dir().path().join(file);
+        std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))
+    });
+    let (file1, file2) = (file1?, file2?);
+
+    match verify_collision(&prefix, &file1, &file2) {
+        Ok(digest) => {
+            info!(
+                success = true,
+                "Verified collision, both files hash to {}", digest
+            );
+            notify::notify(Event::Found, "collision_course", &format!("MD5 {}", digest));
+        }
+        Err(e) => {
+            if from_cache {
+                // search again on the next run instead of failing the same way
+                let _ = std::fs::remove_dir_all(cache_dir(&prefix));
+            }
+            bail!("Refusing to submit: {}", e);
+        }
+    }
+    if use_cache
+        && !from_cache
+        && let Err(e) = cache_pair(&prefix, &file1, &file2)
+    {
+        // not worth failing the submission over
+        warn!("{}", e);
+    }
 
     // encode to base64
     let file1 = base64::engine::general_purpose::STANDARD.encode(file1);
@@ -63,5 +517,75 @@ pub fn run() {
       "files": [file1, file2]
     });
 
-    client.submit_solution(solution);
+    client.submit_solution(solution)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_explicit_encodings() {
+        assert_eq!(decode_prefix("aGk=", "text"), Ok(b"aGk=".to_vec()));
+        assert_eq!(decode_prefix("aGk=", "base64"), Ok(b"hi".to_vec()));
+        assert_eq!(decode_prefix("00ff10", "hex"), Ok(vec![0x00, 0xff, 0x10]));
+        assert!(decode_prefix("not base64!", "base64").is_err());
+        assert!(decode_prefix("0g", "hex").is_err());
+        assert!(decode_prefix("abc", "rot13").is_err());
+    }
+
+    #[test]
+    fn auto_keeps_text() {
+        // valid base64, but too short to believe it
+        assert_eq!(decode_prefix("test", "auto"), Ok(b"test".to_vec()));
+        // base64 of text stays text
+        assert_eq!(decode_prefix("aGVsbG8=", "auto"), Ok(b"aGVsbG8=".to_vec()));
+        assert_eq!(
+            decode_prefix("some plain prefix", "auto"),
+            Ok(b"some plain prefix".to_vec())
+        );
+    }
+
+    #[test]
+    fn auto_decodes_binary_base64() {
+        assert_eq!(
+            decode_prefix("AP8Q/w==", "auto"),
+            Ok(vec![0x00, 0xff, 0x10, 0xff])
+        );
+    }
+
+    #[test]
+    fn keeps_latin1_text_as_utf8() {
+        for encoding in ["text", "auto"] {
+            assert_eq!(decode_prefix("é", encoding), Ok(vec![0xc3, 0xa9]));
+            assert_eq!(
+                decode_prefix("café", encoding),
+                Ok("café".as_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn reads_latin1_escapes_as_bytes() {
+        // what serde_json makes of "\u0000ÿab\u0080"
+        assert_eq!(
+            decode_prefix("\u{0}\u{ff}ab\u{80}", "latin1"),
+            Ok(vec![0x00, 0xff, b'a', b'b', 0x80])
+        );
+        assert!(decode_prefix("é€", "latin1").is_err());
+    }
+
+    #[test]
+    fn pads_to_whole_blocks() {
+        assert_eq!(pad_prefix(b""), Vec::<u8>::new());
+        assert_eq!(pad_prefix(&[1; 64]), vec![1; 64]);
+
+        let padded = pad_prefix(b"prefix");
+        assert_eq!(padded.len(), 64);
+        assert!(padded.starts_with(b"prefix"));
+        assert!(padded[6..].iter().all(|&b| b == 0));
+
+        assert_eq!(pad_prefix(&[1; 65]).len(), 128);
+    }
 }
@@ -3,11 +3,20 @@ use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use tokio::fs;
-use uuid::Uuid;
+use tracing::{error, info, warn};
 use warp::{Filter, http::StatusCode, reply};
 
-const REGISTRY_DATA_DIR: &str = "./data/registry_data";
-const PORT: u16 = 3030;
+use crate::utils::data_dir::DataDir;
+use crate::utils::health::{self, Readiness};
+use crate::utils::metrics::{REGISTRY_STORED_BYTES, REGISTRY_UPLOADS_IN_FLIGHT};
+use crate::utils::{http_server, seed, supervisor, tunnel};
+
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 3030;
+// bodies are held in memory, a pushed layer arrives in one or a few chunks
+const BLOB_BODY_LIMIT: u64 = 1024 * 1024 * 1024;
+// what registries accept for a manifest
+const MANIFEST_BODY_LIMIT: u64 = 4 * 1024 * 1024;
 
 // ------ STORAGE
 #[derive(Clone)]
@@ -21,7 +30,7 @@ impl RegistryStorage {
     }
 
     async fn init_upload(&self) -> Result<String, String> {
-        let uuid = Uuid::new_v4().to_string();
+        let uuid = seed::uuid().to_string();
         let upload_dir = self.root.join("uploads");
         fs::create_dir_all(&upload_dir)
             .await
@@ -31,6 +40,7 @@ impl RegistryStorage {
         fs::write(&upload_path, &[])
             .await
             .map_err(|e| e.to_string())?;
+        REGISTRY_UPLOADS_IN_FLIGHT.add(&[], 1.0);
 
         Ok(uuid)
     }
@@ -66,12 +76,16 @@ impl RegistryStorage {
 
         let filename = digest.strip_prefix("sha256:").unwrap_or(digest);
         let blob_path = blob_dir.join(filename);
+        // a layer pushed again replaces the same bytes
+        let replaced = fs::metadata(&blob_path).await.map(|m| m.len()).unwrap_or(0);
         fs::write(&blob_path, &data)
             .await
             .map_err(|e| e.to_string())?;
+        REGISTRY_STORED_BYTES.add(&[], data.len() as f64 - replaced as f64);
 
         // Clean up upload file
         let _ = fs::remove_file(&upload_path).await;
+        REGISTRY_UPLOADS_IN_FLIGHT.add(&[], -1.0);
 
         Ok(())
     }
@@ -116,9 +130,14 @@ impl RegistryStorage {
         let manifest_path = manifest_dir.join(&reference);
         let content_type_path = manifest_dir.join(format!("{}.content_type", reference));
 
+        let replaced = fs::metadata(&manifest_path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
         fs::write(&manifest_path, &data)
             .await
             .map_err(|e| e.to_string())?;
+        REGISTRY_STORED_BYTES.add(&[], data.len() as f64 - replaced as f64);
         fs::write(&content_type_path, content_type.as_bytes())
             .await
             .map_err(|e| e.to_string())?;
@@ -138,6 +157,38 @@ impl RegistryStorage {
 
         Some((data, content_type))
     }
+
+    /// Whether files can be written under the root, what every push needs
+    async fn check_writable(&self) -> Result<(), String> {
+        let probe = self.root.join(".probe");
+        let written = async {
+            fs::create_dir_all(&self.root).await?;
+            fs::write(&probe, b"").await?;
+            fs::remove_file(&probe).await
+        };
+        written
+            .await
+            .map_err(|e| format!("{} isn't writable: {}", self.root.display(), e))
+    }
+
+    /// Bytes of the blobs and manifests already on disk, pending uploads aside
+    fn stored_bytes(&self) -> u64 {
+        fn size(path: &std::path::Path) -> u64 {
+            match std::fs::read_dir(path) {
+                Ok(entries) => entries.flatten().map(|entry| size(&entry.path())).sum(),
+                Err(_) => std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            }
+        }
+        std::fs::read_dir(&self.root)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.file_name() != "uploads")
+                    .map(|entry| size(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
 }
 
 // ------ API
@@ -167,7 +218,7 @@ impl RegistryApi {
             .and(warp::post())
             .and(Self::with_storage(storage))
             .and_then(|repo: String, storage: RegistryStorage| async move {
-                println!("POST /v2/{}/blobs/uploads/", repo);
+                info!("POST /v2/{}/blobs/uploads/", repo);
                 match storage.init_upload().await {
                     Ok(uuid) => {
                         let location = format!("/v2/{}/blobs/uploads/{}", repo, uuid);
@@ -181,7 +232,7 @@ impl RegistryApi {
                         ))
                     }
                     Err(e) => {
-                        eprintln!("Error initializing upload: {}", e);
+                        error!("Failed to initialize the upload: {}", e);
                         Ok::<_, warp::Rejection>(reply::with_status(
                             reply::with_header(
                                 reply::with_header("", "Location", ""),
@@ -200,11 +251,11 @@ impl RegistryApi {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("v2" / String / "blobs" / "uploads" / String)
             .and(warp::patch())
-            .and(warp::body::bytes())
+            .and(http_server::body(BLOB_BODY_LIMIT))
             .and(Self::with_storage(storage))
             .and_then(
                 |repo: String, uuid: String, body: Bytes, storage: RegistryStorage| async move {
-                    println!(
+                    info!(
                         "PATCH /v2/{}/blobs/uploads/{} ({} bytes)",
                         repo,
                         uuid,
@@ -220,7 +271,7 @@ impl RegistryApi {
                             ))
                         }
                         Err(e) => {
-                            eprintln!("Error: {}", e);
+                            error!("{}", e);
                             Ok::<_, warp::Rejection>(reply::with_status(
                                 reply::with_header("", "Location", ""),
                                 StatusCode::NOT_FOUND,
@@ -237,7 +288,7 @@ impl RegistryApi {
         warp::path!("v2" / String / "blobs" / "uploads" / String)
             .and(warp::put())
             .and(warp::query::<HashMap<String, String>>())
-            .and(warp::body::bytes())
+            .and(http_server::body(BLOB_BODY_LIMIT))
             .and(Self::with_storage(storage))
             .and_then(
                 |repo: String,
@@ -245,11 +296,11 @@ impl RegistryApi {
                  query: HashMap<String, String>,
                  body: Bytes,
                  storage: RegistryStorage| async move {
-                    println!("PUT /v2/{}/blobs/uploads/{}", repo, uuid);
+                    info!("PUT /v2/{}/blobs/uploads/{}", repo, uuid);
 
                     if !body.is_empty() {
                         if let Err(e) = storage.append_to_upload(&uuid, &body).await {
-                            eprintln!("Error: {}", e);
+                            error!("{}", e);
                         }
                     }
 
@@ -267,7 +318,7 @@ impl RegistryApi {
                                 ))
                             }
                             Err(e) => {
-                                eprintln!("Error: {}", e);
+                                error!("{}", e);
                                 Ok::<_, warp::Rejection>(reply::with_status(
                                     reply::with_header(
                                         reply::with_header("", "Location", ""),
@@ -300,7 +351,7 @@ impl RegistryApi {
             .and(Self::with_storage(storage))
             .and_then(
                 |repo: String, digest: String, storage: RegistryStorage| async move {
-                    println!("HEAD /v2/{}/blobs/{}", repo, digest);
+                    info!("HEAD /v2/{}/blobs/{}", repo, digest);
 
                     if storage.blob_exists(&digest).await {
                         Ok::<_, warp::Rejection>(reply::with_status(
@@ -325,7 +376,7 @@ impl RegistryApi {
             .and(Self::with_storage(storage))
             .and_then(
                 |repo: String, digest: String, storage: RegistryStorage| async move {
-                    println!("GET /v2/{}/blobs/{}", repo, digest);
+                    info!("GET /v2/{}/blobs/{}", repo, digest);
 
                     if let Some(data) = storage.get_blob(&digest).await {
                         Ok::<_, warp::Rejection>(reply::with_status(
@@ -348,7 +399,7 @@ impl RegistryApi {
         warp::path!("v2" / String / "manifests" / String)
             .and(warp::put())
             .and(warp::header::optional::<String>("content-type"))
-            .and(warp::body::bytes())
+            .and(http_server::body(MANIFEST_BODY_LIMIT))
             .and(Self::with_storage(storage))
             .and_then(
                 |repo: String,
@@ -356,20 +407,20 @@ impl RegistryApi {
                  content_type: Option<String>,
                  body: Bytes,
                  storage: RegistryStorage| async move {
-                    println!("PUT /v2/{}/manifests/{}", repo, reference);
+                    info!("PUT /v2/{}/manifests/{}", repo, reference);
 
                     // Use the provided content-type or default to Docker manifest v2
                     let content_type = content_type.unwrap_or_else(|| {
                         "application/vnd.docker.distribution.manifest.v2+json".to_string()
                     });
-                    println!("Content-Type: {}", content_type);
+                    info!("Content-Type: {}", content_type);
 
                     // Calculate SHA256 digest of the manifest
                     let mut hasher = Sha256::new();
                     hasher.update(&body);
                     let digest = format!("sha256:{:x}", hasher.finalize());
 
-                    println!("Manifest digest: {}", digest);
+                    info!("Manifest digest: {}", digest);
 
                     match storage
                         .store_manifest(&repo, &reference, body.to_vec(), content_type.clone())
@@ -388,7 +439,7 @@ impl RegistryApi {
                             StatusCode::CREATED,
                         )),
                         Err(e) => {
-                            eprintln!("Error storing manifest: {}", e);
+                            error!("Failed to store the manifest: {}", e);
                             Ok::<_, warp::Rejection>(reply::with_status(
                                 reply::with_header(
                                     reply::with_header(
@@ -415,7 +466,7 @@ impl RegistryApi {
             .and(Self::with_storage(storage))
             .and_then(
                 |repo: String, reference: String, storage: RegistryStorage| async move {
-                    println!("GET /v2/{}/manifests/{}", repo, reference);
+                    info!("GET /v2/{}/manifests/{}", repo, reference);
 
                     if let Some((data, content_type)) =
                         storage.get_manifest(&repo, &reference).await
@@ -425,7 +476,7 @@ impl RegistryApi {
                         hasher.update(&data);
                         let digest = format!("sha256:{:x}", hasher.finalize());
 
-                        println!("Returning manifest with Content-Type: {}", content_type);
+                        info!("Returning manifest with Content-Type: {}", content_type);
 
                         Ok::<_, warp::Rejection>(reply::with_status(
                             reply::with_header(
@@ -452,8 +503,21 @@ impl RegistryApi {
 
 // ----- MAIN
 #[tokio::main]
-pub async fn run() {
-    let storage = RegistryStorage::new(PathBuf::from(REGISTRY_DATA_DIR));
+pub async fn run() -> anyhow::Result<()> {
+    let storage = RegistryStorage::new(
+        DataDir::challenge("dockerized_solutions")
+            .join("registry")
+            .path()
+            .to_path_buf(),
+    );
+    REGISTRY_STORED_BYTES.set(&[], storage.stored_bytes() as f64);
+    // /readyz waits for writable storage and, with --tunnel, for the public URL
+    let readiness = Readiness::new(&["storage", "tunnel"]);
+    let writable = storage.check_writable().await;
+    if let Err(e) = &writable {
+        error!("{}", e);
+    }
+    readiness.set("storage", writable);
 
     let routes = RegistryApi::version_check()
         .or(RegistryApi::start_upload(storage.clone()))
@@ -464,6 +528,49 @@ pub async fn run() {
         .or(RegistryApi::put_manifest(storage.clone()))
         .or(RegistryApi::get_manifest(storage));
 
-    println!("Starting Docker Registry on http://0.0.0.0:{}", PORT);
-    warp::serve(routes).run(([0, 0, 0, 0], PORT)).await;
+    let routes = http_server::routes("registry", readiness.clone(), routes);
+
+    let address = http_server::address("REGISTRY", DEFAULT_BIND_ADDRESS, DEFAULT_PORT)
+        .map_err(anyhow::Error::msg)?;
+    info!("Starting Docker Registry on http://{}", address);
+    info!("Metrics on http://{}/metrics", address);
+    // restarted if it crashes, the blobs and manifests are on disk; serves until Ctrl+C or the
+    // watchdog
+    let server = tokio::spawn(supervisor::supervise("registry", move || {
+        let routes = routes.clone();
+        async move {
+            let listener = http_server::bind(address)
+                .await
+                .map_err(anyhow::Error::msg)?;
+            http_server::serve(routes, listener, None, std::future::pending()).await;
+            Ok(())
+        }
+    }));
+
+    // docker only pushes to a remote registry over HTTPS, which the tunnels provide
+    let provider = tunnel::Provider::from_args().map_err(anyhow::Error::msg)?;
+    let _tunnel = match provider {
+        Some(provider) => {
+            let tunnel = tokio::task::spawn_blocking(move || {
+                tunnel::Tunnel::start(provider, address.port())
+            })
+            .await?
+            .map_err(anyhow::Error::msg)?;
+            let reachable = tunnel::wait_until_reachable(&health::url(&tunnel.public_url)).await;
+            if let Err(e) = &reachable {
+                warn!("{}", e);
+            }
+            readiness.set("tunnel", reachable);
+            info!("Push to the registry at {}", tunnel.public_url);
+            Some(tunnel)
+        }
+        None => {
+            readiness.ready("tunnel");
+            None
+        }
+    };
+
+    server.await??;
+    info!("Registry stopped");
+    Ok(())
 }
@@ -1,32 +1,84 @@
+use anyhow::{Context, bail};
 use base64::{Engine, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use tracing::{info, info_span, warn};
 
-pub fn run() {
-    let b64 = "gswHh8MpZ92NrQAANtKnQ2wmAdrxzX9AQH/N8doBJmw=";
-    let buf = general_purpose::STANDARD.decode(b64).expect("Invalid");
-    println!("Bytes: {:?}", buf);
+use crate::utils::unpack;
 
-    let mut offset = 0;
-    let int_val = i32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
-    offset += 4;
+// int, unsigned int, short (padded to 4 bytes), float, double, big-endian double
+const FORMAT: &str = "<i I h 2x f d >d";
 
-    let uint_val = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
-    offset += 4;
+#[derive(Debug, Clone, Deserialize)]
+pub struct Problem {
+    /// base64
+    pub bytes: String,
+}
+
+impl Problem {
+    pub fn decode(&self) -> anyhow::Result<Vec<u8>> {
+        general_purpose::STANDARD
+            .decode(&self.bytes)
+            .context("Invalid base64 bytes")
+    }
+}
 
-    let short_val = i16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap());
-    offset += 4;
+#[derive(Debug, Clone, Serialize)]
+pub struct Solution {
+    pub int: i64,
+    pub uint: u64,
+    pub short: i64,
+    pub float: f64,
+    pub double: f64,
+    pub big_endian_double: f64,
+}
+
+/// Unpacks the problem's bytes
+pub fn solve(problem: &Problem) -> anyhow::Result<Solution> {
+    let _span = info_span!("solve").entered();
+    let buf = problem.decode()?;
+    let values = unpack::unpack(FORMAT, &buf).map_err(anyhow::Error::msg)?;
+    // Packing the values back must give the same bytes, padding aside
+    match unpack::pack(FORMAT, &values) {
+        Ok(repacked) if buf.starts_with(&repacked) => {}
+        Ok(repacked) => warn!(
+            "repacked bytes {:?} differ from the buffer, check the format",
+            repacked
+        ),
+        Err(e) => warn!("{}", e),
+    }
 
-    let float_val = f32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
-    offset += 4;
+    let [int, uint, short, float, double, big_endian_double] = values.as_slice() else {
+        bail!("Expected 6 values, unpacked {:?}", values);
+    };
+    Ok(Solution {
+        int: int.as_i64().context("int isn't an integer")?,
+        uint: uint.as_u64().context("uint isn't an unsigned integer")?,
+        short: short.as_i64().context("short isn't an integer")?,
+        float: float.as_f64().context("float isn't a float")?,
+        double: double.as_f64().context("double isn't a float")?,
+        big_endian_double: big_endian_double
+            .as_f64()
+            .context("big_endian_double isn't a float")?,
+    })
+}
 
-    let double_val = f64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap());
-    offset += 8;
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("help_me_unpack")?;
+    let problem = client.get_problem()?;
+    let problem: Problem = serde_json::from_value(problem.clone())
+        .with_context(|| format!("Unexpected problem: {}", problem))?;
+    let buf = problem.decode()?;
+    info!("Bytes: {:?}", buf);
 
-    let double_be_val = f64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap());
+    if crate::utils::args::is_set("--hexdump", "UNPACK_HEXDUMP") {
+        match unpack::annotated_dump(FORMAT, &buf) {
+            Ok(dump) => info!("{}", dump),
+            Err(e) => warn!("{}", e),
+        }
+    }
 
-    println!("i32: {}", int_val);
-    println!("u32: {}", uint_val);
-    println!("i16: {}", short_val);
-    println!("f32: {}", float_val);
-    println!("f64: {}", double_val);
-    println!("f64 (big-endian): {}", double_be_val);
+    let solution = solve(&problem)?;
+    info!("{:?}", solution);
+    client.submit_solution(serde_json::to_value(&solution)?)?;
+    Ok(())
 }
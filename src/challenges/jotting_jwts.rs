@@ -1,11 +1,31 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use anyhow::{Context, bail};
+use base64::Engine;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation, decode, decode_header,
+    encode,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::time::{SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+use tokio::sync::Notify;
+use tokio_rustls::rustls;
+use tracing::{debug, info, warn};
 use warp::{Filter, reply::json};
 
+use crate::utils::data_dir::DataDir;
+use crate::utils::health::{self, Readiness};
+use crate::utils::http_server;
+use crate::utils::metrics::JWT_TOKENS;
+use crate::utils::reachability;
+use crate::utils::seed;
+use crate::utils::tunnel;
+
 #[derive(Serialize, Deserialize)]
 struct Response {
     solution: String,
@@ -15,107 +35,756 @@ struct Response {
 struct Claims {
     append: Option<String>,
     nbf: Option<i64>,
+    exp: Option<i64>,
+    iat: Option<i64>,
+    /// either a single audience or a list of audiences
+    aud: Option<serde_json::Value>,
+    iss: Option<String>,
+}
+
+/// Expected audience/issuer, only enforced when the problem specifies them
+#[derive(Clone, Serialize, Deserialize)]
+struct ClaimRules {
+    audience: Option<String>,
+    issuer: Option<String>,
+}
+
+// Validates the time based and registered claims, returns the rejection reason on failure.
+// Time based claims tolerate `leeway` seconds of clock skew between hackattic and us
+fn validate_claims(claims: &Claims, rules: &ClaimRules, leeway: i64) -> Result<(), String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    if let Some(nbf) = claims.nbf
+        && nbf > now
+    {
+        if nbf > now + leeway {
+            return Err("Token not yet valid".to_string());
+        }
+        info!("Leeway accepted nbf {}s in the future", nbf - now);
+    }
+
+    if let Some(exp) = claims.exp
+        && exp <= now
+    {
+        if exp <= now - leeway {
+            return Err("Token expired".to_string());
+        }
+        info!("Leeway accepted exp {}s in the past", now - exp);
+    }
+
+    if let Some(iat) = claims.iat
+        && iat > now
+    {
+        if iat > now + leeway {
+            return Err("Token issued in the future".to_string());
+        }
+        info!("Leeway accepted iat {}s in the future", iat - now);
+    }
+
+    if let Some(audience) = &rules.audience {
+        let matches = match &claims.aud {
+            Some(serde_json::Value::String(aud)) => aud == audience,
+            Some(serde_json::Value::Array(auds)) => {
+                auds.iter().any(|aud| aud.as_str() == Some(audience))
+            }
+            _ => false,
+        };
+        if !matches {
+            return Err("Invalid audience".to_string());
+        }
+    }
+
+    if let Some(issuer) = &rules.issuer
+        && claims.iss.as_ref() != Some(issuer)
+    {
+        return Err("Invalid issuer".to_string());
+    }
+
+    Ok(())
+}
+
+/// Key material supplied by the problem
+/// HMAC tokens are verified with the shared secret, asymmetric ones with the public key or JWKS
+#[derive(Clone, Serialize, Deserialize)]
+struct JwtKeys {
+    /// shared secret for HS256/HS384/HS512
+    secret: Option<String>,
+    /// PEM encoded public key for RS*/PS*/ES*/EdDSA
+    public_key: Option<String>,
+    /// key set fetched from the problem's JWKS URL
+    jwks: Option<JwkSet>,
+}
+
+impl JwtKeys {
+    // Picks the verification key for the algorithm in the token header.
+    // The key type must match the algorithm family, so an RS256 public key is never
+    // accepted as an HMAC secret (algorithm confusion)
+    fn decoding_key(&self, alg: Algorithm, kid: Option<&str>) -> Result<DecodingKey, TokenError> {
+        match alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => self
+                .secret
+                .as_ref()
+                .map(|secret| DecodingKey::from_secret(secret.as_bytes()))
+                .ok_or(TokenError::UnexpectedAlgorithm(alg)),
+            _ => {
+                if let Some(jwks) = &self.jwks {
+                    let jwk = match kid {
+                        Some(kid) => jwks.find(kid),
+                        None => jwks.keys.first(),
+                    }
+                    .ok_or_else(|| TokenError::Key(format!("no JWKS key for kid {:?}", kid)))?;
+
+                    let matches_alg = matches!(
+                        (&jwk.algorithm, alg),
+                        (
+                            AlgorithmParameters::RSA(_),
+                            Algorithm::RS256
+                                | Algorithm::RS384
+                                | Algorithm::RS512
+                                | Algorithm::PS256
+                                | Algorithm::PS384
+                                | Algorithm::PS512
+                        ) | (
+                            AlgorithmParameters::EllipticCurve(_),
+                            Algorithm::ES256 | Algorithm::ES384
+                        ) | (AlgorithmParameters::OctetKeyPair(_), Algorithm::EdDSA)
+                    );
+                    if !matches_alg {
+                        return Err(TokenError::UnexpectedAlgorithm(alg));
+                    }
+
+                    return DecodingKey::from_jwk(jwk).map_err(|e| TokenError::Key(e.to_string()));
+                }
+
+                let public_key = self
+                    .public_key
+                    .as_ref()
+                    .ok_or(TokenError::UnexpectedAlgorithm(alg))?;
+
+                match alg {
+                    Algorithm::ES256 | Algorithm::ES384 => {
+                        DecodingKey::from_ec_pem(public_key.as_bytes())
+                    }
+                    Algorithm::EdDSA => DecodingKey::from_ed_pem(public_key.as_bytes()),
+                    _ => DecodingKey::from_rsa_pem(public_key.as_bytes()),
+                }
+                .map_err(|e| TokenError::Key(e.to_string()))
+            }
+        }
+    }
+
+    // Identifies the challenge session, every problem fetch comes with fresh key material
+    fn session_id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.secret.as_deref().unwrap_or_default());
+        hasher.update(self.public_key.as_deref().unwrap_or_default());
+        if let Some(jwks) = &self.jwks {
+            hasher.update(serde_json::to_vec(jwks).unwrap());
+        }
+        hex::encode(&hasher.finalize()[..8])
+    }
+}
+
+/// Accumulated solutions keyed by challenge session
+/// Tokens are only ever appended to the current session, so a new round starts from scratch
+#[derive(Clone, Default)]
+struct SessionStore {
+    inner: Arc<Mutex<SessionState>>,
+}
+
+#[derive(Default)]
+struct SessionState {
+    current: Option<String>,
+    solutions: HashMap<String, String>,
+}
+
+impl SessionStore {
+    // Makes the session current, resetting its accumulated solution
+    fn start(&self, session_id: &str) {
+        let mut state = self.inner.lock().unwrap();
+        state
+            .solutions
+            .insert(session_id.to_string(), String::new());
+        state.current = Some(session_id.to_string());
+    }
+
+    // Makes the session current, continuing from a previously accumulated solution
+    fn resume(&self, session_id: &str, solution: String) {
+        let mut state = self.inner.lock().unwrap();
+        state.solutions.insert(session_id.to_string(), solution);
+        state.current = Some(session_id.to_string());
+    }
+
+    // Appends to the current session and returns its accumulated solution
+    fn append(&self, value: &str) -> String {
+        let mut state = self.inner.lock().unwrap();
+        let current = state.current.clone().expect("No active session");
+        let solution = state.solutions.entry(current).or_default();
+        *solution += value;
+        solution.clone()
+    }
+
+    fn solution(&self) -> String {
+        let state = self.inner.lock().unwrap();
+        state
+            .current
+            .as_ref()
+            .and_then(|current| state.solutions.get(current))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+const STATE_FILE: &str = "state.json";
+
+/// Everything needed to pick up a session after a crash or Ctrl+C
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
+    session_id: String,
+    keys: JwtKeys,
+    rules: ClaimRules,
+    solution: String,
+}
+
+// Saves the session after every accepted token, so `--resume` can continue it
+fn save_state(state: &PersistedState) -> anyhow::Result<()> {
+    let path = DataDir::challenge("jotting_jwts")
+        .file(STATE_FILE)
+        .map_err(anyhow::Error::msg)?;
+    let json = serde_json::to_string_pretty(state)?;
+    std::fs::write(path, json).context("Failed to write JWT state")
+}
+
+fn load_state() -> anyhow::Result<PersistedState> {
+    let path = DataDir::challenge("jotting_jwts").path().join(STATE_FILE);
+    let json = std::fs::read_to_string(&path)
+        .with_context(|| format!("No saved state to resume at {}", path.display()))?;
+    serde_json::from_str(&json).context("Failed to parse JWT state")
+}
+
+async fn get_problem() -> anyhow::Result<(JwtKeys, ClaimRules)> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("jotting_jwts")?;
+    let problem = client.get_problem_async().await?;
+
+    let mut keys = JwtKeys {
+        secret: problem["jwt_secret"].as_str().map(|s| s.to_string()),
+        public_key: problem["public_key"].as_str().map(|s| s.to_string()),
+        jwks: None,
+    };
+
+    if let Some(jwks_url) = problem["jwks_url"].as_str() {
+        let jwks = reqwest::get(jwks_url)
+            .await
+            .context("Failed to fetch JWKS")?
+            .json::<JwkSet>()
+            .await
+            .context("Failed to parse JWKS")?;
+        keys.jwks = Some(jwks);
+    }
+
+    let rules = ClaimRules {
+        audience: problem["audience"].as_str().map(|s| s.to_string()),
+        issuer: problem["issuer"].as_str().map(|s| s.to_string()),
+    };
+
+    return Ok((keys, rules));
+}
+
+/// Why a token was refused before its claims were looked at
+#[derive(Debug, thiserror::Error)]
+enum TokenError {
+    /// not a `header.payload.signature` compact serialization
+    #[error("Malformed token: {0}")]
+    Malformed(String),
+    /// unsigned token (`alg: none`)
+    #[error("Unsigned token (alg none)")]
+    AlgNone,
+    /// algorithm jsonwebtoken doesn't know about
+    #[error("Unsupported algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    /// known algorithm, but the problem supplied no key of that type
+    #[error("Unexpected algorithm {0:?}")]
+    UnexpectedAlgorithm(Algorithm),
+    /// the key material couldn't be loaded
+    #[error("Invalid key: {0}")]
+    Key(String),
+    /// signature doesn't verify
+    #[error("Invalid Token: {0}")]
+    InvalidSignature(String),
 }
 
-const URL: &str = "https://c8a9248290ec.ngrok-free.app";
+// Reads the `alg` of the JOSE header by hand, so `none` and unknown algorithms can be told
+// apart from malformed tokens (jsonwebtoken fails all of them the same way)
+fn check_header(token: &str) -> Result<Algorithm, TokenError> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() != 3 {
+        return Err(TokenError::Malformed(format!(
+            "expected 3 segments, got {}",
+            parts.len()
+        )));
+    }
 
-async fn get_problem() -> String {
-    let client = crate::utils::hackattic_client::HackatticClient::new("jotting_jwts");
-    let problem = client.get_problem_async().await;
-    let jwt_secret = problem["jwt_secret"].as_str().unwrap().to_string();
-    return jwt_secret;
+    let header = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(parts[0])
+        .map_err(|_| TokenError::Malformed("header is not base64url".to_string()))?;
+    let header: serde_json::Value = serde_json::from_slice(&header)
+        .map_err(|_| TokenError::Malformed("header is not JSON".to_string()))?;
+    let alg = header["alg"]
+        .as_str()
+        .ok_or_else(|| TokenError::Malformed("header has no alg".to_string()))?;
+
+    if alg.eq_ignore_ascii_case("none") {
+        return Err(TokenError::AlgNone);
+    }
+    if parts[2].is_empty() {
+        return Err(TokenError::Malformed("empty signature".to_string()));
+    }
+
+    alg.parse::<Algorithm>()
+        .map_err(|_| TokenError::UnsupportedAlgorithm(alg.to_string()))
 }
 
-async fn start_challenge() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("jotting_jwts");
-    client
+// Verifies the token signature with the key matching the algorithm in its header
+fn verify_token(token: &str, keys: &JwtKeys) -> Result<TokenData<Claims>, TokenError> {
+    let alg = check_header(token)?;
+    let header = decode_header(token).map_err(|e| TokenError::Malformed(e.to_string()))?;
+    let key = keys.decoding_key(alg, header.kid.as_deref())?;
+
+    // only the signature is checked here, the claims are validated by validate_claims
+    let mut validation = Validation::new(alg);
+    validation.required_spec_claims = Default::default();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+
+    decode::<Claims>(token.trim(), &key, &validation)
+        .map_err(|e| TokenError::InvalidSignature(e.to_string()))
+}
+
+// Clock skew tolerated on nbf/exp/iat, in seconds
+const DEFAULT_LEEWAY_SECS: i64 = 5;
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 3030;
+
+// Served directly over TLS there's no tunnel in front, so listen on all interfaces
+const DEFAULT_TLS_BIND_ADDRESS: &str = "0.0.0.0";
+
+// A token is a few hundred bytes
+const TOKEN_BODY_LIMIT: u64 = 64 * 1024;
+
+// Reads the address to listen on from --bind/--port (or JWT_BIND_ADDRESS/JWT_PORT)
+fn server_address(tls: bool) -> anyhow::Result<SocketAddr> {
+    let default_bind_address = if tls {
+        DEFAULT_TLS_BIND_ADDRESS
+    } else {
+        DEFAULT_BIND_ADDRESS
+    };
+    http_server::address("JWT", default_bind_address, DEFAULT_PORT).map_err(anyhow::Error::msg)
+}
+
+// Shows only whether a secret is present
+fn redact(secret: Option<&str>) -> &'static str {
+    match secret {
+        Some(_) => "<redacted>",
+        None => "<none>",
+    }
+}
+
+// Debug trail of a received token (with -v): header, claims, verdict and action taken.
+// Claims are decoded without verification so rejected tokens are logged too,
+// the signature and keys are never printed
+fn log_token(token: &str, verdict: &str, action: &str) {
+    let header = decode_header(token)
+        .map(|header| format!("{:?}", header))
+        .unwrap_or_else(|_| "<malformed>".to_string());
+
+    let claims = token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(payload)
+                .ok()
+        })
+        .map(|payload| String::from_utf8_lossy(&payload).into_owned())
+        .unwrap_or_else(|| "<malformed>".to_string());
+
+    debug!(
+        "jwt header={} claims={} signature=<redacted> verdict={} action={}",
+        header, claims, verdict, action
+    );
+}
+
+// How long to keep serving after the final request, in case hackattic retries it
+const RETRY_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+// Loads the certificate/key from --tls-cert/--tls-key (or JWT_TLS_CERT/JWT_TLS_KEY), or
+// generates a self-signed certificate for the host of the app URL
+fn tls_config(
+    app_url: Option<&str>,
+    address: SocketAddr,
+) -> anyhow::Result<Arc<rustls::ServerConfig>> {
+    let cert_path = crate::utils::args::get("--tls-cert", "JWT_TLS_CERT");
+    let key_path = crate::utils::args::get("--tls-key", "JWT_TLS_KEY");
+
+    let (cert_pem, key_pem) = match (cert_path, key_path) {
+        (Some(cert_path), Some(key_path)) => (
+            std::fs::read(&cert_path)
+                .with_context(|| format!("Failed to read TLS certificate {}", cert_path))?,
+            std::fs::read(&key_path)
+                .with_context(|| format!("Failed to read TLS private key {}", key_path))?,
+        ),
+        (None, None) => {
+            let host = app_url
+                .and_then(|app_url| reqwest::Url::parse(app_url).ok())
+                .and_then(|url| url.host_str().map(|host| host.to_string()))
+                .unwrap_or_else(|| address.ip().to_string());
+            info!("Generating self-signed certificate for {}", host);
+            crate::utils::tls::self_signed_certificate(&host)?
+        }
+        _ => bail!("--tls-cert and --tls-key must be provided together"),
+    };
+
+    Ok(crate::utils::tls::server_config(&cert_pem, &key_pem)?)
+}
+
+async fn start_challenge(app_url: String) -> anyhow::Result<serde_json::Value> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("jotting_jwts")?;
+    Ok(client
         .submit_solution_async(json!({
-          "app_url": URL
+          "app_url": app_url
         }))
-        .await;
+        .await?)
 }
 
-#[tokio::main]
-pub async fn run() {
-    let solution = Arc::new(Mutex::new(String::new()));
+// Plays hackattic's role against the local server: sends a mix of valid and invalid tokens
+// signed with `secret`, then checks the solution returned for the final token.
+// The result mimics a hackattic response, so it is reported like a real submission
+async fn simulate_hackattic(base_url: String, secret: String) -> anyhow::Result<serde_json::Value> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let sign = |claims: serde_json::Value, secret: &str| {
+        encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .expect("HS256 signing with a byte secret can't fail")
+    };
+    let unsigned = |claims: serde_json::Value| {
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        format!(
+            "{}.{}.",
+            engine.encode(r#"{"alg":"none","typ":"JWT"}"#),
+            engine.encode(claims.to_string())
+        )
+    };
 
-    // get problem
-    let jwt_secret = get_problem().await;
-    println!("JWT Secret: {}", jwt_secret);
+    let tokens = vec![
+        ("valid", sign(json!({ "append": "sim" }), &secret)),
+        (
+            "wrong secret",
+            sign(json!({ "append": "X" }), "not-the-secret"),
+        ),
+        (
+            "not yet valid",
+            sign(json!({ "append": "X", "nbf": now + 3600 }), &secret),
+        ),
+        (
+            "expired",
+            sign(json!({ "append": "X", "exp": now - 3600 }), &secret),
+        ),
+        ("alg none", unsigned(json!({ "append": "X" }))),
+        ("malformed", "not.a.valid.jwt".to_string()),
+        (
+            "valid with exp",
+            sign(json!({ "append": "ulat", "exp": now + 3600 }), &secret),
+        ),
+        ("valid", sign(json!({ "append": "ion" }), &secret)),
+        ("final", sign(json!({}), &secret)),
+    ];
+    let expected = "simulation";
 
-    // Define the hello world route
-    let route = warp::post()
-        .and(warp::path::end())
-        .and(warp::body::bytes())
-        .map(move |body: warp::hyper::body::Bytes| {
-            let solution = Arc::clone(&solution);
-            let jwt_secret = jwt_secret.clone();
+    // the server may be using a self-signed certificate
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let url = format!("{}/", base_url);
+    let mut solution = String::new();
+    for (kind, token) in tokens {
+        let response = client
+            .post(&url)
+            .body(token)
+            .send()
+            .await
+            .context("Failed to send simulated token")?
+            .json::<Response>()
+            .await
+            .context("Failed to parse simulated response")?;
+        info!("[simulate] {} token -> {}", kind, response.solution);
+        solution = response.solution;
+    }
 
-            let token = String::from_utf8(body.to_vec()).unwrap();
+    if solution == expected {
+        Ok(json!({ "result": "simulation passed" }))
+    } else {
+        Ok(json!({ "rejected": format!("expected {:?}, got {:?}", expected, solution) }))
+    }
+}
 
-            let mut validation = Validation::new(Algorithm::HS256);
-            validation.required_spec_claims = Default::default();
+// Resolves once the final (append-less) request was answered and no retries arrived
+// for RETRY_GRACE_PERIOD
+async fn wait_for_final_request(finished: Arc<Notify>, last_request: Arc<Mutex<Instant>>) {
+    finished.notified().await;
 
-            let token = decode::<Claims>(
-                &token,
-                &DecodingKey::from_secret(jwt_secret.as_bytes()),
-                &validation,
-            );
+    loop {
+        let elapsed = last_request.lock().unwrap().elapsed();
+        if elapsed >= RETRY_GRACE_PERIOD {
+            break;
+        }
+        tokio::time::sleep(RETRY_GRACE_PERIOD - elapsed).await;
+    }
+}
 
-            if token.is_err() {
-                println!("Invalid token: {:?}", token);
-                return json(&Response {
-                    solution: "Invalid Token".to_string(),
-                });
-            }
+#[tokio::main]
+pub async fn run() -> anyhow::Result<()> {
+    // continue a session that was interrupted, hackattic is still sending its tokens
+    let resume = crate::utils::args::is_set("--resume", "JWT_RESUME");
+    // run against a local fake hackattic instead of the real challenge
+    let simulate = crate::utils::args::is_set("--simulate", "JWT_SIMULATE");
 
-            let token = token.unwrap();
+    // the public URL (e.g. ngrok tunnel) that forwards to the local server, or a tunnel to
+    // start for it
+    let app_url = crate::utils::args::get("--app-url", "APP_URL");
+    let tunnel_provider = tunnel::Provider::from_args().map_err(anyhow::Error::msg)?;
+    if app_url.is_none() && tunnel_provider.is_none() && !resume && !simulate {
+        bail!(
+            "App URL must be provided with --app-url <url> or APP_URL in environment or .env file, or pass --tunnel ngrok|cloudflared"
+        );
+    }
+    let check_reachability =
+        !crate::utils::args::is_set("--no-reachability-check", "NO_REACHABILITY_CHECK");
+    // serve over TLS directly, for hosts with a routable IP and no tunnel
+    let tls = crate::utils::args::is_set("--tls", "JWT_TLS");
+    let address = server_address(tls)?;
+    let leeway = crate::utils::args::get("--leeway", "JWT_LEEWAY")
+        .map(|leeway| leeway.parse::<i64>().context("Invalid leeway"))
+        .transpose()?
+        .unwrap_or(DEFAULT_LEEWAY_SECS);
 
-            // check nbf
-            if let Some(nbf) = token.claims.nbf {
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64;
+    let sessions = SessionStore::default();
+    // /readyz waits for the problem and for the app URL to reach the server
+    let readiness = Readiness::new(&["problem", "public_url"]);
+    let finished = Arc::new(Notify::new());
+    let last_request = Arc::new(Mutex::new(Instant::now()));
+
+    // get problem, or reload the interrupted one
+    let (keys, rules, session_id) = if simulate {
+        let keys = JwtKeys {
+            secret: Some(seed::uuid().to_string()),
+            public_key: None,
+            jwks: None,
+        };
+        let rules = ClaimRules {
+            audience: None,
+            issuer: None,
+        };
+        let session_id = keys.session_id();
+        sessions.start(&session_id);
+        (keys, rules, session_id)
+    } else if resume {
+        let state = load_state()?;
+        info!(
+            "Resuming session {} with solution: {}",
+            state.session_id, state.solution
+        );
+        sessions.resume(&state.session_id, state.solution);
+        (state.keys, state.rules, state.session_id)
+    } else {
+        let (keys, rules) = get_problem().await?;
+        let session_id = keys.session_id();
+        sessions.start(&session_id);
+        (keys, rules, session_id)
+    };
+    info!("JWT Secret: {}", redact(keys.secret.as_deref()));
+    info!("Public key: {:?}", keys.public_key);
+    info!("Session: {}", session_id);
+    readiness.ready("problem");
+    let simulation_secret = keys.secret.clone().filter(|_| simulate);
+
+    // Define the hello world route
+    let route_finished = Arc::clone(&finished);
+    let route_last_request = Arc::clone(&last_request);
+    let route = warp::post()
+        .and(warp::path::end())
+        .and(http_server::body(TOKEN_BODY_LIMIT))
+        .map(move |body: warp::hyper::body::Bytes| {
+            *route_last_request.lock().unwrap() = Instant::now();
 
-                if nbf > now {
-                    println!("Token not yet valid");
+            let raw_token = String::from_utf8_lossy(&body).into_owned();
+            let token = match verify_token(&raw_token, &keys) {
+                Ok(token) => token,
+                Err(e) => {
+                    info!("Invalid token: {}", e);
+                    JWT_TOKENS.inc(&[("verdict", "invalid")]);
+                    log_token(&raw_token, &format!("invalid ({})", e), "reject");
                     return json(&Response {
-                        solution: "Token not yet valid".to_string(),
+                        solution: e.to_string(),
                     });
                 }
-            }
+            };
 
-            println!("Appending to solution: {:?}", token.claims.append);
-            if token.claims.append.is_none() {
-                let solution = solution.lock().unwrap();
-                println!("RETURNING SOLUTION: {}", solution);
-                return json(&Response {
-                    solution: solution.clone(),
-                });
+            if let Err(reason) = validate_claims(&token.claims, &rules, leeway) {
+                info!("Rejected token: {} ({:?})", reason, token.claims);
+                JWT_TOKENS.inc(&[("verdict", "rejected")]);
+                log_token(&raw_token, &format!("rejected ({})", reason), "reject");
+                return json(&Response { solution: reason });
             }
 
-            let mut solution = solution.lock().unwrap();
-            if let Some(ref append_str) = token.claims.append {
-                *solution += append_str;
+            info!("Appending to solution: {:?}", token.claims.append);
+            match token.claims.append {
+                Some(ref append_str) => {
+                    JWT_TOKENS.inc(&[("verdict", "appended")]);
+                    log_token(&raw_token, "valid", "append");
+                    let solution = sessions.append(append_str);
+                    if !simulate
+                        && let Err(e) = save_state(&PersistedState {
+                            session_id: session_id.clone(),
+                            keys: keys.clone(),
+                            rules: rules.clone(),
+                            solution: solution.clone(),
+                        })
+                    {
+                        // the session goes on, it just can't be resumed
+                        warn!("{:#}", e);
+                    }
+                    json(&Response { solution })
+                }
+                None => {
+                    JWT_TOKENS.inc(&[("verdict", "final")]);
+                    log_token(&raw_token, "valid", "return");
+                    let solution = sessions.solution();
+                    info!("RETURNING SOLUTION: {}", solution);
+                    route_finished.notify_one();
+                    json(&Response { solution })
+                }
             }
-
-            let response = Response {
-                solution: solution.clone(),
-            };
-
-            json(&response)
         });
+    let route = http_server::routes("jwt", readiness.clone(), route);
+
+    // bind first, so the server is listening before hackattic starts sending tokens
+    let listener = http_server::bind(address)
+        .await
+        .map_err(anyhow::Error::msg)?;
+    let tls_config = tls
+        .then(|| tls_config(app_url.as_deref(), address))
+        .transpose()?;
+    // kept until the end of the run, dropping it stops the tunnel
+    let public_tunnel = match tunnel_provider {
+        Some(provider) if app_url.is_none() && !simulate => {
+            Some(tunnel::Tunnel::start(provider, address.port()).map_err(anyhow::Error::msg)?)
+        }
+        _ => None,
+    };
+    let tunnel_url = public_tunnel
+        .as_ref()
+        .map(|tunnel| tunnel.public_url.clone());
+    let app_url = app_url.or(tunnel_url.clone());
+    let scheme = if tls { "https" } else { "http" };
+    info!(
+        "Listening on {}://{}, {} must reach this address",
+        scheme,
+        address,
+        app_url.as_deref().unwrap_or("the app URL")
+    );
+    info!("Metrics on {}://{}/metrics", scheme, address);
 
-    println!("Starting server on http://127.0.0.1:3030");
+    // start challenge, hackattic answers once it is done sending tokens.
+    // A resumed session was already submitted by the interrupted run
+    let submitted = Arc::new(Notify::new());
+    let submission_done = Arc::clone(&submitted);
+    let submission = if let Some(secret) = simulation_secret {
+        readiness.ready("public_url");
+        Some(tokio::spawn(async move {
+            let base_url = format!("{}://{}", scheme, address);
+            let response = simulate_hackattic(base_url, secret).await;
+            submission_done.notify_one();
+            response
+        }))
+    } else {
+        app_url.filter(|_| !resume).map(|app_url| {
+            let readiness = readiness.clone();
+            tokio::spawn(async move {
+                // the server is serving by now, a URL that doesn't reach it would waste the round
+                let reachable = match &tunnel_url {
+                    Some(tunnel_url) => {
+                        tunnel::wait_until_reachable(&health::url(tunnel_url)).await
+                    }
+                    None if check_reachability => reachability::check(&app_url, address).await,
+                    None => Ok(()),
+                };
+                readiness.set("public_url", reachable.clone());
+                let response = match reachable {
+                    Ok(()) => start_challenge(app_url).await,
+                    Err(e) => Err(anyhow::anyhow!("{}, not submitting", e)),
+                };
+                submission_done.notify_one();
+                response
+            })
+        })
+    };
+    if submission.is_none() {
+        // a resumed session's URL was checked by the interrupted run
+        readiness.ready("public_url");
+    }
 
-    // sleep for 1 seconds
-    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    // restarted if it crashes, the sessions and the state file outlive it
+    let mut listener = Some(listener);
+    crate::utils::supervisor::supervise("JWT server", move || {
+        // the first run serves on the listener bound above
+        let listener = listener.take();
+        let route = route.clone();
+        let tls_config = tls_config.clone();
+        let finished = Arc::clone(&finished);
+        let last_request = Arc::clone(&last_request);
+        let submitted = Arc::clone(&submitted);
+        async move {
+            let listener = match listener {
+                Some(listener) => listener,
+                None => http_server::bind(address)
+                    .await
+                    .map_err(anyhow::Error::msg)?,
+            };
+            let done = async move {
+                tokio::select! {
+                    _ = wait_for_final_request(finished, last_request) => {}
+                    _ = submitted.notified() => {}
+                }
+            };
+            http_server::serve(route, listener, tls_config, done).await;
+            Ok(())
+        }
+    })
+    .await?;
+    info!("Server stopped");
 
-    // start challenge
-    tokio::spawn(async move {
-        start_challenge().await;
-    });
+    let Some(submission) = submission else {
+        info!("Resumed session finished, the verdict goes to the original submission");
+        return Ok(());
+    };
+    // hackattic may never answer, e.g. when it can't reach the server
+    if crate::utils::shutdown::token().load(std::sync::atomic::Ordering::Relaxed) {
+        submission.abort();
+        bail!("Stopped before hackattic answered");
+    }
 
-    warp::serve(route).run(([127, 0, 0, 1], 3030)).await;
+    let response = submission.await.context("Submission task failed")??;
+    if crate::utils::hackattic_client::HackatticClient::is_accepted(&response) {
+        info!("Challenge passed!");
+        Ok(())
+    } else {
+        bail!("Challenge failed: {}", response)
+    }
 }
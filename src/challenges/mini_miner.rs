@@ -1,12 +1,35 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use anyhow::{Context, bail};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use sha2::{Digest, Sha256};
+use tracing::{info, info_span};
+
+use crate::utils::notify::{self, Event};
+use crate::utils::progress::{Progress, format_number};
+
+// Nonces a worker hashes between progress updates, keeps the shared counter uncontended
+const PROGRESS_BATCH: u64 = 1 << 14;
+
+// A SHA-256 hash can't have more leading zero bits than it has bits
+const MAX_DIFFICULTY: usize = 32 * 8;
 
-#[derive(Serialize, Deserialize)]
-enum Block {
-    Data(Vec<Vec<(String, i32)>>),
-    Nonce(i32),
+/// The problem's block with every field it came with, in the order received
+pub type Block = IndexMap<String, Value>;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Problem {
+    pub block: Block,
+    /// Leading zero bits the block's hash needs
+    pub difficulty: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Solution {
+    pub nonce: u64,
 }
 
 fn has_leading_zeros(hash: &[u8], bits: usize) -> bool {
@@ -29,33 +52,315 @@ fn has_leading_zeros(hash: &[u8], bits: usize) -> bool {
     true
 }
 
-pub fn run() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("mini_miner");
-    let problem = client.get_problem();
-    let data = problem["block"]["data"].clone();
-    let difficulty = problem["difficulty"].as_i64().unwrap() as usize;
+/// The exact bytes hackattic hashes, Python's `json.dumps(block, sort_keys=True,
+/// separators=(',', ':'))`: no whitespace, sorted keys, plain decimal integers and non-ASCII
+/// characters escaped, e.g. `{"data":[["a",1]],"nonce":42}`. Fields other than `nonce` are
+/// hashed exactly as received
+pub fn serialize_block(block: &Block, nonce: u64) -> String {
+    // replaces the nonce in place, or appends it when the block came without one
+    let mut block = block.clone();
+    block.insert("nonce".to_string(), json!(nonce));
+    block.sort_keys();
+
+    // nested objects come out sorted, serde_json's Map is a BTreeMap
+    escape_non_ascii(&serde_json::to_string(&block).unwrap())
+}
+
+// serde_json writes UTF-8 as is, Python escapes it (ensure_ascii), as UTF-16 for astral chars
+fn escape_non_ascii(json: &str) -> String {
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+            continue;
+        }
+        let mut units = [0u16; 2];
+        for unit in c.encode_utf16(&mut units) {
+            escaped.push_str(&format!("\\u{:04x}", unit));
+        }
+    }
+    escaped
+}
+
+/// The serialized block split around the nonce digits, with the SHA-256 state after the
+/// constant prefix, so each nonce only hashes its digits and the short tail
+pub struct Midstate {
+    prefix: Sha256,
+    suffix: Vec<u8>,
+}
+
+impl Midstate {
+    pub fn new(block: &Block) -> Self {
+        // serialize with a placeholder and cut around it, wherever the nonce ends up
+        let placeholder = format!("\"nonce\":{}", u64::MAX);
+        let serialized = serialize_block(block, u64::MAX);
+        let start = serialized
+            .find(&placeholder)
+            .expect("serialized block has no nonce")
+            + "\"nonce\":".len();
+        let end = start + u64::MAX.to_string().len();
+
+        Self {
+            prefix: Sha256::new_with_prefix(&serialized.as_bytes()[..start]),
+            suffix: serialized.as_bytes()[end..].to_vec(),
+        }
+    }
+
+    pub fn hash(&self, nonce: u64) -> [u8; 32] {
+        let mut digits = [0u8; 20];
+        let mut hasher = self.prefix.clone();
+        hasher.update(format_decimal(nonce, &mut digits));
+        hasher.update(&self.suffix);
+        hasher.finalize().into()
+    }
+}
+
+// u64 in decimal without allocating, written to the end of `buffer`
+fn format_decimal(mut n: u64, buffer: &mut [u8; 20]) -> &[u8] {
+    let mut start = buffer.len();
+    loop {
+        start -= 1;
+        buffer[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &buffer[start..];
+        }
+    }
+}
+
+/// Re-serializes the block with `nonce` and hashes it from scratch, independently of the
+/// midstate search. Returns the hex hash when it has the required leading zeros
+fn verify(block: &Block, nonce: u64, difficulty: usize) -> Result<String, String> {
+    let serialized = serialize_block(block, nonce);
+    let hash = Sha256::digest(serialized.as_bytes());
+    let hex_hash = hex::encode(hash);
+    info!("Block: {}", serialized);
+    info!("SHA-256: {}", hex_hash);
+
+    if has_leading_zeros(&hash, difficulty) {
+        Ok(hex_hash)
+    } else {
+        Err(format!(
+            "Nonce {} hashes to {}, which doesn't have {} leading zero bits",
+            nonce, hex_hash, difficulty
+        ))
+    }
+}
+
+/// Searches the whole u64 nonce space on every core, each worker taking every n-th nonce, until
+/// one of them finds a hash with `difficulty` leading zero bits or `cancelled` is set. Workers
+/// keep going until they pass the best nonce found, so the lowest one comes back whatever the
+/// core count
+fn mine(block: &Block, difficulty: usize, cancelled: &AtomicBool) -> Result<u64, String> {
+    let workers = num_cpus::get() as u64;
+    let best = AtomicU64::new(u64::MAX);
+    let found = AtomicBool::new(false);
+    let midstate = Midstate::new(block);
 
-    let mut solution = json!({
-      "nonce": 0
+    // each hash has a 2^-bits chance of enough leading zeros
+    let expected = 1u64.checked_shl(difficulty as u32).unwrap_or(u64::MAX);
+    info!(
+        "Difficulty {}: ~{} hashes expected, mining on {} threads",
+        difficulty,
+        format_number(expected),
+        workers
+    );
+    let progress = Progress::start("Nonces", Some(expected));
+
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            let (best, found, midstate, progress) = (&best, &found, &midstate, &progress);
+            scope.spawn(move || {
+                for (i, nonce) in (worker..=u64::MAX).step_by(workers as usize).enumerate() {
+                    if nonce > best.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if i as u64 % PROGRESS_BATCH == 0 {
+                        if cancelled.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        if i > 0 {
+                            progress.add(PROGRESS_BATCH);
+                        }
+                    }
+                    if has_leading_zeros(&midstate.hash(nonce), difficulty) {
+                        best.fetch_min(nonce, Ordering::Relaxed);
+                        found.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            });
+        }
     });
+    progress.finish();
+
+    // a search cancelled after a find still has a valid nonce, just maybe not the lowest
+    if found.load(Ordering::Relaxed) {
+        Ok(best.load(Ordering::Relaxed))
+    } else if cancelled.load(Ordering::Relaxed) {
+        Err("Mining was cancelled".to_string())
+    } else {
+        Err(format!("No u64 nonce satisfies difficulty {}", difficulty))
+    }
+}
+
+/// Mines the block on every core
+pub fn solve(problem: &Problem) -> anyhow::Result<Solution> {
+    solve_cancellable(problem, &AtomicBool::new(false))
+}
+
+/// Mines the block until a nonce is found or `cancelled` is set
+pub fn solve_cancellable(problem: &Problem, cancelled: &AtomicBool) -> anyhow::Result<Solution> {
+    let _span = info_span!("solve", difficulty = problem.difficulty).entered();
+    if problem.difficulty > MAX_DIFFICULTY {
+        bail!(
+            "Difficulty {} is more than the {} bits of a SHA-256 hash",
+            problem.difficulty,
+            MAX_DIFFICULTY
+        );
+    }
+    let nonce = info_span!("mine")
+        .in_scope(|| mine(&problem.block, problem.difficulty, cancelled))
+        .map_err(anyhow::Error::msg)?;
+    // never return a nonce that wasn't verified
+    info_span!("verify")
+        .in_scope(|| verify(&problem.block, nonce, problem.difficulty))
+        .map_err(anyhow::Error::msg)?;
+    Ok(Solution { nonce })
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("mini_miner")?;
+    let problem = client.get_problem()?;
+    let problem: Problem = serde_json::from_value(problem.clone())
+        .with_context(|| format!("Unexpected problem {}", problem))?;
+
+    let cancelled = crate::utils::shutdown::token();
+    let solution = solve_cancellable(&problem, &cancelled)?;
+    info!(success = true, "Found nonce: {}", solution.nonce);
+    notify::notify(
+        Event::Found,
+        "mini_miner",
+        &format!("nonce {}", solution.nonce),
+    );
+    client.submit_solution(serde_json::to_value(&solution)?)?;
+    Ok(())
+}
 
-    for nonce in 0..1_000_000 {
-        // use IndexMap to preserve order, as with json is not guaranteed
-        let mut block = IndexMap::new();
-        block.insert("data".to_string(), json!(data));
-        block.insert("nonce".to_string(), json!(nonce));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let full_dynamic_json: Value = Value::Object(block.clone().into_iter().collect());
-        let serialized = serde_json::to_string(&full_dynamic_json).unwrap();
+    // expected strings and hashes from Python's json.dumps(block, sort_keys=True,
+    // separators=(',', ':')) and hashlib.sha256, what hackattic runs
+    fn block(json: &str) -> Block {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn sha256_hex(serialized: &str) -> String {
+        hex::encode(Sha256::digest(serialized.as_bytes()))
+    }
+
+    #[test]
+    fn serializes_like_python() {
+        let plain = block(r#"{"data": [["a1b2", -3], ["c3d4", 7]], "nonce": null}"#);
+        assert_eq!(
+            serialize_block(&plain, 45),
+            r#"{"data":[["a1b2",-3],["c3d4",7]],"nonce":45}"#
+        );
+        assert_eq!(
+            sha256_hex(&serialize_block(&plain, 45)),
+            "341fc45a850a6a16d1b243a5b2fd6da0b929a40a7c8905e22fe5543465a49679"
+        );
+        assert_eq!(
+            serialize_block(&plain, u64::MAX),
+            r#"{"data":[["a1b2",-3],["c3d4",7]],"nonce":18446744073709551615}"#
+        );
+    }
+
+    #[test]
+    fn sorts_nested_objects() {
+        // only holds while serde_json's preserve_order feature stays off
+        let nested = block(
+            r#"{"nonce": null, "data": [{"z": 1, "a": {"y": 2, "b": [3, {"d": 4, "c": 5}]}}]}"#,
+        );
+        let serialized = serialize_block(&nested, 0);
+        assert_eq!(
+            serialized,
+            r#"{"data":[{"a":{"b":[3,{"c":5,"d":4}],"y":2},"z":1}],"nonce":0}"#
+        );
+        assert_eq!(
+            sha256_hex(&serialized),
+            "b203f490d16328b8bfeac9a8c741607226d51fdee22e5bc36a19dd74c860e722"
+        );
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(serialized.as_bytes());
-        let hash = hasher.finalize();
-        if has_leading_zeros(&hash, difficulty) {
-            println!("Found nonce: {}", nonce);
-            solution["nonce"] = json!(nonce);
-            client.submit_solution(solution);
-            break;
+    #[test]
+    fn escapes_non_ascii_like_python() {
+        let unicode = block(r#"{"data": [["café 😀", 1]], "nonce": null}"#);
+        let serialized = serialize_block(&unicode, 45);
+        assert_eq!(
+            serialized,
+            r#"{"data":[["caf\u00e9 \ud83d\ude00",1]],"nonce":45}"#
+        );
+        assert_eq!(
+            sha256_hex(&serialized),
+            "0514cc2b926dd43148b6f4d01e4a9b50c2eee807ab864cea5ed4c5f6de496666"
+        );
+    }
+
+    #[test]
+    fn adds_a_missing_nonce_in_key_order() {
+        let nonceless = block(r#"{"prev": "abc", "data": []}"#);
+        assert_eq!(
+            serialize_block(&nonceless, 0),
+            r#"{"data":[],"nonce":0,"prev":"abc"}"#
+        );
+    }
+
+    #[test]
+    fn midstate_matches_full_hash() {
+        let blocks = [
+            block(r#"{"data": [["a1b2", -3], ["c3d4", 7]], "nonce": null}"#),
+            block(r#"{"nonce": null, "data": [{"z": 1, "a": {"y": 2}}]}"#),
+            block(r#"{"data": [["café 😀", 1]], "nonce": null}"#),
+            block(r#"{"prev": "abc", "data": []}"#),
+        ];
+        for block in &blocks {
+            let midstate = Midstate::new(block);
+            for nonce in [0, 9, 10, 45, 1038, 999_999, u64::MAX - 1, u64::MAX] {
+                let expected: [u8; 32] =
+                    Sha256::digest(serialize_block(block, nonce).as_bytes()).into();
+                assert_eq!(midstate.hash(nonce), expected, "nonce {}", nonce);
+            }
         }
     }
+
+    #[test]
+    fn verifies_leading_zero_bits() {
+        let plain = block(r#"{"data": [["a1b2", -3], ["c3d4", 7]], "nonce": null}"#);
+        // 000d9ec9..., 12 leading zero bits
+        assert_eq!(
+            verify(&plain, 1038, 12).unwrap(),
+            "000d9ec9f0bba40d412c38bfe09a463fb24e7cf081ebdcd6554198664d9cafed"
+        );
+        assert!(verify(&plain, 1038, 13).is_err());
+        assert!(has_leading_zeros(&[0x00, 0x0f], 12));
+        assert!(!has_leading_zeros(&[0x00, 0x1f], 12));
+        assert!(has_leading_zeros(&[0xff], 0));
+    }
+
+    #[test]
+    fn rejects_difficulty_beyond_the_hash() {
+        let problem = Problem {
+            block: block(r#"{"data": [], "nonce": null}"#),
+            difficulty: MAX_DIFFICULTY + 1,
+        };
+        let error = solve(&problem).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Difficulty 257 is more than the 256 bits of a SHA-256 hash"
+        );
+        assert!(has_leading_zeros(&[0; 32], MAX_DIFFICULTY));
+    }
 }
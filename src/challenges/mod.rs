@@ -10,3 +10,36 @@ pub mod visual_basic_math;
 pub mod collision_course;
 pub mod reading_qr;
 pub mod dockerized_solutions;
+
+type Solver = fn() -> anyhow::Result<()>;
+
+/// Every challenge and its solver, in the order `solve-all` runs them
+const SOLVERS: &[(&str, Solver)] = &[
+    ("password_hashing", password_hashing::run),
+    ("help_me_unpack", help_me_unpack::run),
+    ("backup_restore", backup_restore::run),
+    ("brute_force_zip", brute_force_zip::run),
+    ("mini_miner", mini_miner::run),
+    ("tales_of_ssl", tales_of_ssl::run),
+    ("jotting_jwts", jotting_jwts::run),
+    ("basic_face_detection", basic_face_detection::run),
+    ("visual_basic_math", visual_basic_math::run),
+    ("collision_course", collision_course::run),
+    ("reading_qr", reading_qr::run),
+    ("dockerized_solutions", dockerized_solutions::run),
+];
+
+/// The challenges hackattic connects back to, which need the port (and tunnel) to themselves
+pub const SERVERS: &[&str] = &["jotting_jwts", "dockerized_solutions"];
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    SOLVERS.iter().map(|(name, _)| *name)
+}
+
+/// The solver of a challenge by its name
+pub fn find(name: &str) -> Option<Solver> {
+    SOLVERS
+        .iter()
+        .find(|(solver, _)| *solver == name)
+        .map(|(_, run)| *run)
+}
@@ -1,55 +1,169 @@
+use anyhow::Context;
 use base64::Engine;
-use hex;
-use hmac::{Hmac, Mac};
-use pbkdf2::pbkdf2_hmac;
-use scrypt;
-use sha2::{Digest, Sha256};
-
-pub fn run() {
-    let password = "rosebud7415";
-    let salt_encoded = "UskMKp/7WvMEPokF4I8=";
-    let rounds = 650_000;
-    let log_n = 18;
-    let r = 8;
-    let p = 2;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::time::Instant;
+use tracing::{info, info_span, warn};
 
-    let salt_decoded = base64::engine::general_purpose::STANDARD
-        .decode(salt_encoded)
-        .unwrap();
-
-    // SHA256
-    let mut hasher = Sha256::new();
-    hasher.update(password);
-    let sha256_result = hasher.finalize();
-    println!("SHA-256: {:x}", sha256_result);
-
-    // --- HMAC-SHA256 ---
-    type HmacSha256 = Hmac<Sha256>;
-    let mut mac = HmacSha256::new_from_slice(&salt_decoded).expect("HMAC can take key of any size");
-    mac.update(password.as_bytes());
-    let result = mac.finalize();
-    let hmac_bytes = result.into_bytes();
-    println!("HMAC-SHA256: {}", hex::encode(hmac_bytes));
-
-    // PBKDF2-HMAC-SHA256
-    let mut pbkdf2_result = [0u8; 32];
-    pbkdf2_hmac::<Sha256>(
-        password.as_bytes(),
-        &salt_decoded,
-        rounds,
-        &mut pbkdf2_result,
-    );
-    println!("PBKDF2-SHA256: {}", hex::encode(pbkdf2_result));
-
-    // Scrypt
-    let mut scrypt_result = [0u8; 32];
-    let params = scrypt::Params::new(log_n, r, p, 32).expect("invalid params");
-    scrypt::scrypt(
-        password.as_bytes(),
-        &salt_decoded,
-        &params,
-        &mut scrypt_result,
+use crate::utils::hashing::{self, HashOutput};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Problem {
+    pub password: String,
+    /// base64
+    pub salt: String,
+    /// One entry per requested KDF, e.g. `pbkdf2`, `scrypt`, `sha512`
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+/// The hex digest of each algorithm by name: sha256, hmac, and one per requested KDF
+pub type Solution = Map<String, Value>;
+
+#[derive(Debug, Deserialize)]
+struct Pbkdf2Params {
+    rounds: u32,
+    hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScryptParams {
+    #[serde(rename = "N")]
+    n: u64,
+    r: u32,
+    p: u32,
+    buflen: usize,
+}
+
+type Kdf = fn(password: &[u8], salt: &[u8], params: &Value) -> Result<HashOutput, String>;
+
+/// Algorithms computed when the problem has a parameter key of the same name
+const KDFS: &[(&str, Kdf)] = &[
+    ("pbkdf2", pbkdf2),
+    ("scrypt", scrypt),
+    ("sha512", |password, _, _| Ok(hashing::sha512(password))),
+    ("hmac_sha512", |password, salt, _| {
+        Ok(hashing::hmac_sha512(salt, password))
+    }),
+    ("argon2id", |_, _, _| Err(unsupported("argon2id"))),
+    ("bcrypt", |_, _, _| Err(unsupported("bcrypt"))),
+];
+
+fn unsupported(name: &str) -> String {
+    format!(
+        "{} is requested but no implementation is available in this build",
+        name
     )
-    .expect("scrypt failed");
-    println!("Scrypt: {}", hex::encode(scrypt_result));
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(name: &str, params: &Value) -> Result<T, String> {
+    serde_json::from_value(params.clone())
+        .map_err(|e| format!("Invalid {} params {}: {}", name, params, e))
+}
+
+fn pbkdf2(password: &[u8], salt: &[u8], params: &Value) -> Result<HashOutput, String> {
+    let params: Pbkdf2Params = parse("pbkdf2", params)?;
+    match params.hash.as_str() {
+        "sha256" => Ok(hashing::pbkdf2_sha256(
+            password,
+            salt,
+            &hashing::Pbkdf2Params {
+                rounds: params.rounds,
+                len: 32,
+            },
+        )),
+        "sha512" => Ok(hashing::pbkdf2_sha512(
+            password,
+            salt,
+            &hashing::Pbkdf2Params {
+                rounds: params.rounds,
+                len: 64,
+            },
+        )),
+        other => Err(format!(
+            "Unsupported PBKDF2 hash {:?}, expected sha256 or sha512",
+            other
+        )),
+    }
+}
+
+fn scrypt(password: &[u8], salt: &[u8], params: &Value) -> Result<HashOutput, String> {
+    let ScryptParams { n, r, p, buflen } = parse("scrypt", params)?;
+    let params = hashing::ScryptParams::from_n(n, r, p, buflen)?;
+    hashing::scrypt(password, salt, &params)
+}
+
+/// Hashes the password with every algorithm the problem asks for
+pub fn solve(problem: &Problem) -> anyhow::Result<Solution> {
+    let _span = info_span!("solve").entered();
+    let password = problem.password.as_bytes();
+    let salt_decoded = base64::engine::general_purpose::STANDARD
+        .decode(&problem.salt)
+        .with_context(|| format!("Invalid salt {:?}", problem.salt))?;
+    let salt = salt_decoded.as_slice();
+
+    // sha256 and hmac are always expected, the rest only when the problem asks for them
+    let sha256: Kdf = |password, _, _| Ok(hashing::sha256(password));
+    let hmac: Kdf = |password, salt, _| Ok(hashing::hmac_sha256(salt, password));
+    let mut jobs = vec![
+        ("sha256", sha256, &Value::Null),
+        ("hmac", hmac, &Value::Null),
+    ];
+    for (name, params) in &problem.params {
+        match KDFS.iter().find(|(kdf_name, _)| kdf_name == name) {
+            Some((_, kdf)) => jobs.push((name.as_str(), *kdf, params)),
+            None => warn!("Skipping unknown parameter {:?}: {}", name, params),
+        }
+    }
+
+    // The digests are independent, PBKDF2 and scrypt dominate so run them all concurrently
+    let start = Instant::now();
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .iter()
+            .map(|&(name, kdf, params)| {
+                scope.spawn(move || {
+                    let start = Instant::now();
+                    let result = kdf(password, salt, params);
+                    (name, result, start.elapsed())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("hash thread panicked"))
+            .collect()
+    });
+    let total = start.elapsed();
+
+    let mut solution = Map::new();
+    for (name, result, elapsed) in results {
+        let result = result.map_err(anyhow::Error::msg)?;
+        info!(
+            "{:<12} {:>9.3}s  {}",
+            name,
+            elapsed.as_secs_f64(),
+            result.hex()
+        );
+        solution.insert(name.to_string(), json!(result.hex()));
+    }
+    info!("{:<12} {:>9.3}s", "total", total.as_secs_f64());
+
+    Ok(solution)
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("password_hashing")?;
+    let problem = client.get_problem()?;
+    let problem: Problem = serde_json::from_value(problem.clone())
+        .with_context(|| format!("Unexpected problem {}", problem))?;
+    info!("Password {:?}, salt {}", problem.password, problem.salt);
+    for (name, params) in &problem.params {
+        info!("  {}: {}", name, params);
+    }
+
+    hashing::check_test_vectors().map_err(anyhow::Error::msg)?;
+    let solution = solve(&problem)?;
+    client.submit_solution(Value::Object(solution))?;
+    Ok(())
 }
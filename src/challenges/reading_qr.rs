@@ -1,23 +1,80 @@
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose};
 use image;
-use rqrr;
+use tracing::{info, warn};
 
-pub fn run() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("reading_qr");
-    let problem = client.get_problem();
-    let image_url = problem["image_url"].as_str().unwrap();
-    let image_bytes = client.download_file(image_url);
-    std::fs::write("./data/qr_code.png", image_bytes).unwrap();
+use crate::utils::data_dir::DataDir;
+use crate::utils::qr;
 
-    let img = image::open("./data/qr_code.png").unwrap().to_luma8();
-    let mut img = rqrr::PreparedImage::prepare(img);
-    let grids = img.detect_grids();
+/// A payload that isn't UTF-8 can't go in the JSON as is, it's submitted as base64 unless
+/// `--binary-encoding` says otherwise
+fn encode_binary(payload: &[u8]) -> Result<String, String> {
+    let encoding = crate::utils::args::get("--binary-encoding", "QR_BINARY_ENCODING")
+        .unwrap_or_else(|| "base64".to_string());
+    info!(
+        "The payload is {} bytes of {}, submitting it as {}",
+        payload.len(),
+        qr::guess_encoding(payload),
+        encoding
+    );
 
-    let (_meta, content) = grids[0].decode().unwrap();
+    match encoding.as_str() {
+        "base64" => Ok(general_purpose::STANDARD.encode(payload)),
+        "hex" => Ok(hex::encode(payload)),
+        // Each byte as the code point of the same value
+        "latin1" => Ok(payload.iter().map(|&b| b as char).collect()),
+        other => Err(format!(
+            "Unknown binary encoding {:?}, expected base64, hex or latin1",
+            other
+        )),
+    }
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("reading_qr")?;
+    let problem = client.get_problem()?;
+    let image_url = problem["image_url"]
+        .as_str()
+        .with_context(|| format!("Unexpected problem: {}", problem))?;
+    let image_bytes = client.download_file(image_url)?;
+
+    // Only kept on disk for debugging
+    if crate::utils::args::is_set("--save-image", "QR_SAVE_IMAGE") {
+        let saved = DataDir::challenge("reading_qr")
+            .file("qr_code.png")
+            .and_then(|path| {
+                std::fs::write(&path, &image_bytes)
+                    .map(|_| path)
+                    .map_err(|e| e.to_string())
+            });
+        match saved {
+            Ok(path) => info!("Saved the image to {}", path.display()),
+            Err(e) => warn!("Failed to save the image: {}", e),
+        }
+    }
+
+    let img = image::load_from_memory(&image_bytes)
+        .context("Failed to load the image")?
+        .to_luma8();
+    let decoded = qr::decode_with_retries(&img).map_err(anyhow::Error::msg)?;
+    info!(
+        "Decoded {:?} from {} {} of {} with {}",
+        String::from_utf8_lossy(&decoded.payload),
+        decoded.symbology,
+        decoded.grid + 1,
+        decoded.grids,
+        decoded.engine
+    );
+
+    let code = match decoded.text() {
+        Some(text) => text.to_string(),
+        None => encode_binary(&decoded.payload).map_err(anyhow::Error::msg)?,
+    };
 
     let solution = serde_json::json!({
-        "code": content
+        "code": code
     });
 
-    let client = crate::utils::hackattic_client::HackatticClient::new("reading_qr");
-    client.submit_solution(solution);
+    client.submit_solution(solution)?;
+    Ok(())
 }
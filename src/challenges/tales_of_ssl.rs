@@ -1,117 +1,542 @@
+use anyhow::{Context, bail};
 use base64::Engine;
-use openssl::{
-    asn1::Asn1Time,
-    bn::BigNum,
-    hash::MessageDigest,
-    pkey::PKey,
-    x509::{
-        X509, X509NameBuilder,
-        extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName},
-    },
-};
 use serde_json::json;
+use tracing::{info, warn};
 
-pub fn run() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("tales_of_ssl");
+use crate::utils::data_dir::DataDir;
 
-    let problem = client.get_problem();
-    let private_key = problem["private_key"].as_str().unwrap();
+// Submissions per problem, including the first one
+const MAX_ATTEMPTS: usize = 4;
+
+// Reads a string field of the problem
+fn field<'a>(problem: &'a serde_json::Value, pointer: &str) -> Result<&'a str, String> {
+    problem
+        .pointer(pointer)
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| format!("problem is missing {}", pointer))
+}
+
+// What the certificate has to contain, read from `required_data`
+struct Requirements {
+    domain: String,
+    serial_number: String,
+    country: String,
+    // extra subject entries, as (openssl short name, value)
+    name_entries: Vec<(&'static str, String)>,
+    // DNS names besides the domain
+    alt_names: Vec<String>,
+    validity_days: u32,
+}
+
+// One way of encoding the requirements, rejected submissions are retried with the next one
+struct Variant {
+    country_code: &'static str,
+    // basic constraints, key usage and extended key usage
+    extensions: bool,
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "country {}, {}",
+            self.country_code,
+            if self.extensions {
+                "with extensions"
+            } else {
+                "without extensions"
+            }
+        )
+    }
+}
+
+// Subject entries that can be requested, keyed by the names hackattic (or a human) might use
+const NAME_FIELDS: &[(&str, &str)] = &[
+    ("organization", "O"),
+    ("organization_name", "O"),
+    ("organizational_unit", "OU"),
+    ("organizational_unit_name", "OU"),
+    ("state", "ST"),
+    ("province", "ST"),
+    ("state_or_province", "ST"),
+    ("locality", "L"),
+    ("city", "L"),
+    ("email", "emailAddress"),
+    ("email_address", "emailAddress"),
+];
+
+fn as_string(key: &str, value: &serde_json::Value) -> Result<String, String> {
+    match value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        _ => Err(format!("required_data.{} is not a string: {}", key, value)),
+    }
+}
+
+// Maps every key of `required_data` to a certificate field, unknown keys are reported
+fn parse_requirements(problem: &serde_json::Value) -> Result<Requirements, String> {
+    let required_data = problem
+        .get("required_data")
+        .and_then(|value| value.as_object())
+        .ok_or("problem is missing /required_data")?;
+
+    let mut domain = None;
+    let mut serial_number = None;
+    let mut country = None;
+    let mut name_entries = Vec::new();
+    let mut alt_names = Vec::new();
+    let mut validity_days = 365;
+
+    for (key, value) in required_data {
+        match key.as_str() {
+            "domain" | "common_name" => domain = Some(as_string(key, value)?),
+            "serial_number" | "serial" => serial_number = Some(as_string(key, value)?),
+            "country" | "country_name" => country = Some(as_string(key, value)?),
+            "san" | "sans" | "alt_names" | "subject_alt_names" | "additional_domains" => {
+                match value {
+                    serde_json::Value::Array(names) => {
+                        for name in names {
+                            alt_names.push(as_string(key, name)?);
+                        }
+                    }
+                    value => alt_names.push(as_string(key, value)?),
+                }
+            }
+            "validity_days" | "valid_days" | "validity" => {
+                validity_days = as_string(key, value)?
+                    .parse()
+                    .map_err(|e| format!("required_data.{}: {}", key, e))?;
+            }
+            key => match NAME_FIELDS.iter().find(|(name, _)| *name == key) {
+                Some((_, entry)) => name_entries.push((*entry, as_string(key, value)?)),
+                None => warn!(
+                    "unrecognized required_data field {:?} = {}, it is NOT in the certificate",
+                    key, value
+                ),
+            },
+        }
+    }
+
+    Ok(Requirements {
+        domain: domain.ok_or("problem is missing /required_data/domain")?,
+        serial_number: serial_number.ok_or("problem is missing /required_data/serial_number")?,
+        country: country.ok_or("problem is missing /required_data/country")?,
+        name_entries,
+        alt_names,
+        validity_days,
+    })
+}
+
+// Certificate backed by the system OpenSSL
+#[cfg(all(feature = "openssl", not(feature = "rcgen")))]
+mod openssl_backend {
+    use super::{Requirements, Variant};
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        error::ErrorStack,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{Id, PKey, Private},
+        x509::{
+            X509, X509NameBuilder,
+            extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName},
+        },
+    };
+    use tracing::info;
+
+    // Prefixes an openssl error with the step that failed
+    fn step(name: &'static str) -> impl Fn(ErrorStack) -> String {
+        move |e| format!("{}: {}", name, e)
+    }
+
+    // Picks the signature digest for the key type: Ed25519 signs the message itself (no digest),
+    // EC keys use the digest matching their curve size
+    fn signature_digest(pkey: &PKey<Private>) -> Result<MessageDigest, String> {
+        match pkey.id() {
+            Id::RSA => Ok(MessageDigest::sha256()),
+            Id::ED25519 => Ok(MessageDigest::null()),
+            Id::EC => {
+                let ec_key = pkey.ec_key().map_err(step("EC key"))?;
+                match ec_key.group().curve_name() {
+                    Some(Nid::X9_62_PRIME256V1) => Ok(MessageDigest::sha256()),
+                    Some(Nid::SECP384R1) => Ok(MessageDigest::sha384()),
+                    Some(Nid::SECP521R1) => Ok(MessageDigest::sha512()),
+                    curve => Err(format!("unsupported EC curve {:?}", curve)),
+                }
+            }
+            id => Err(format!("unsupported private key type {:?}", id)),
+        }
+    }
+
+    /// Builds and signs the certificate, returns it DER encoded
+    pub fn build(
+        private_key: &[u8],
+        requirements: &Requirements,
+        variant: &Variant,
+    ) -> Result<Vec<u8>, String> {
+        let domain = requirements.domain.as_str();
+        let pkey =
+            PKey::private_key_from_der(private_key).map_err(step("private key DER decode"))?;
+        let digest = signature_digest(&pkey)?;
+        let key_type = match pkey.id() {
+            Id::RSA => "RSA",
+            Id::EC => "EC",
+            _ => "Ed25519",
+        };
+        info!("Private key type: {} ({} bits)", key_type, pkey.bits());
+
+        // Subject/issuer
+        let mut issuer_name = X509NameBuilder::new().map_err(step("name builder"))?;
+        issuer_name
+            .append_entry_by_text("C", variant.country_code)
+            .map_err(step("name country entry"))?;
+        issuer_name
+            .append_entry_by_text("CN", domain)
+            .map_err(step("name common name entry"))?;
+        for (entry, value) in &requirements.name_entries {
+            issuer_name
+                .append_entry_by_text(entry, value)
+                .map_err(|e| format!("name {} entry: {}", entry, e))?;
+        }
+        let issuer_name = issuer_name.build();
+
+        // build cert
+        let mut builder = X509::builder().map_err(step("certificate builder"))?;
+        builder.set_version(2).map_err(step("version"))?;
+        builder
+            .set_subject_name(&issuer_name)
+            .map_err(step("subject name"))?;
+        builder
+            .set_issuer_name(&issuer_name)
+            .map_err(step("issuer name"))?;
+        builder.set_pubkey(&pkey).map_err(step("public key"))?;
+
+        // set serial number
+        let serial_number =
+            BigNum::from_hex_str(requirements.serial_number.trim_start_matches("0x"))
+                .and_then(|serial_number| serial_number.to_asn1_integer())
+                .map_err(step("serial number"))?;
+        builder
+            .set_serial_number(&serial_number)
+            .map_err(step("serial number"))?;
+
+        // set validity
+        let not_before = Asn1Time::days_from_now(0).map_err(step("not before"))?;
+        builder
+            .set_not_before(&not_before)
+            .map_err(step("not before"))?;
+        let not_after =
+            Asn1Time::days_from_now(requirements.validity_days).map_err(step("not after"))?;
+        builder
+            .set_not_after(&not_after)
+            .map_err(step("not after"))?;
+
+        // set extensions
+        if variant.extensions {
+            let basic_constraints = BasicConstraints::new()
+                .critical()
+                .build()
+                .map_err(step("basic constraints extension"))?;
+            builder
+                .append_extension(basic_constraints)
+                .map_err(step("basic constraints extension"))?;
+
+            // key encipherment only makes sense for RSA keys
+            let mut key_usage = KeyUsage::new();
+            key_usage.digital_signature();
+            if pkey.id() == Id::RSA {
+                key_usage.key_encipherment();
+            }
+            let key_usage = key_usage.build().map_err(step("key usage extension"))?;
+            builder
+                .append_extension(key_usage)
+                .map_err(step("key usage extension"))?;
+
+            let ext_key_usage = ExtendedKeyUsage::new()
+                .server_auth()
+                .client_auth()
+                .build()
+                .map_err(step("extended key usage extension"))?;
+            builder
+                .append_extension(ext_key_usage)
+                .map_err(step("extended key usage extension"))?;
+        }
+
+        let mut subject_alt_name = SubjectAlternativeName::new();
+        subject_alt_name.dns(domain);
+        for name in &requirements.alt_names {
+            subject_alt_name.dns(name);
+        }
+        let subject_alt_name = subject_alt_name
+            .build(&builder.x509v3_context(None, None))
+            .map_err(step("subject alt name extension"))?;
+        builder
+            .append_extension(subject_alt_name)
+            .map_err(step("subject alt name extension"))?;
+
+        // sign it with the private key
+        builder.sign(&pkey, digest).map_err(step("signing"))?;
+        let cert: X509 = builder.build();
+
+        // export to DER
+        cert.to_der().map_err(step("DER export"))
+    }
+}
+
+// Pure-Rust certificate, no system OpenSSL needed
+#[cfg(feature = "rcgen")]
+mod rcgen_backend {
+    use super::{Requirements, Variant};
+    use rcgen::{
+        CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+        KeyUsagePurpose, SanType, SerialNumber,
+    };
+    use time::{Duration, OffsetDateTime};
+    use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+    use tracing::info;
+
+    // pkcs-9 emailAddress
+    const EMAIL_ADDRESS: &[u64] = &[1, 2, 840, 113549, 1, 9, 1];
+    // AlgorithmIdentifier of rsaEncryption (OID 1.2.840.113549.1.1.1 with NULL parameters)
+    const RSA_ALGORITHM: &[u8] = &[
+        0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+    ];
+
+    fn der_length(len: usize) -> Vec<u8> {
+        match len {
+            0..=0x7f => vec![len as u8],
+            _ => {
+                let bytes: Vec<u8> = len
+                    .to_be_bytes()
+                    .into_iter()
+                    .skip_while(|b| *b == 0)
+                    .collect();
+                let mut out = vec![0x80 | bytes.len() as u8];
+                out.extend(bytes);
+                out
+            }
+        }
+    }
+
+    fn der_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        out.extend(der_length(content.len()));
+        out.extend_from_slice(content);
+        out
+    }
+
+    // ring only loads PKCS#8, so a PKCS#1 RSA key gets wrapped into a PrivateKeyInfo
+    fn to_pkcs8(private_key: &[u8]) -> Result<Vec<u8>, String> {
+        match PrivateKeyDer::try_from(private_key)
+            .map_err(|e| format!("private key DER decode: {}", e))?
+        {
+            PrivateKeyDer::Pkcs8(key) => Ok(key.secret_pkcs8_der().to_vec()),
+            PrivateKeyDer::Pkcs1(key) => {
+                let mut info = vec![0x02, 0x01, 0x00]; // version 0
+                info.extend_from_slice(RSA_ALGORITHM);
+                info.extend(der_tlv(0x04, key.secret_pkcs1_der()));
+                Ok(der_tlv(0x30, &info))
+            }
+            _ => Err("private key DER decode: SEC1 EC keys are not supported, use PKCS#8".into()),
+        }
+    }
+
+    /// Builds and signs the certificate, returns it DER encoded
+    pub fn build(
+        private_key: &[u8],
+        requirements: &Requirements,
+        variant: &Variant,
+    ) -> Result<Vec<u8>, String> {
+        let key_pair = KeyPair::try_from(to_pkcs8(private_key)?)
+            .map_err(|e| format!("private key load: {}", e))?;
+        info!("Private key algorithm: {:?}", key_pair.algorithm());
+
+        // Subject/issuer
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CountryName, variant.country_code);
+        name.push(DnType::CommonName, requirements.domain.as_str());
+        for (entry, value) in &requirements.name_entries {
+            let dn_type = match *entry {
+                "O" => DnType::OrganizationName,
+                "OU" => DnType::OrganizationalUnitName,
+                "ST" => DnType::StateOrProvinceName,
+                "L" => DnType::LocalityName,
+                "emailAddress" => DnType::CustomDnType(EMAIL_ADDRESS.to_vec()),
+                entry => return Err(format!("name {} entry: not supported by rcgen", entry)),
+            };
+            name.push(dn_type, value.as_str());
+        }
+
+        let mut params = CertificateParams::default();
+        params.distinguished_name = name;
+
+        // set serial number
+        let serial_number = requirements.serial_number.trim_start_matches("0x");
+        let serial_number = if serial_number.len() % 2 == 1 {
+            format!("0{}", serial_number)
+        } else {
+            serial_number.to_string()
+        };
+        let serial_number =
+            hex::decode(serial_number).map_err(|e| format!("serial number: {}", e))?;
+        params.serial_number = Some(SerialNumber::from_slice(&serial_number));
+
+        // set validity
+        params.not_before = OffsetDateTime::now_utc();
+        params.not_after =
+            params.not_before + Duration::days(i64::from(requirements.validity_days));
+
+        // set extensions
+        if variant.extensions {
+            params.is_ca = IsCa::ExplicitNoCa;
+            params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+            if key_pair.algorithm() == &rcgen::PKCS_RSA_SHA256 {
+                params.key_usages.push(KeyUsagePurpose::KeyEncipherment);
+            }
+            params.extended_key_usages = vec![
+                ExtendedKeyUsagePurpose::ServerAuth,
+                ExtendedKeyUsagePurpose::ClientAuth,
+            ];
+        }
+        for name in std::iter::once(&requirements.domain).chain(&requirements.alt_names) {
+            let name = name
+                .clone()
+                .try_into()
+                .map_err(|e| format!("subject alt name extension: {}", e))?;
+            params.subject_alt_names.push(SanType::DnsName(name));
+        }
+
+        // sign it with the private key
+        let cert = params
+            .self_signed(&key_pair)
+            .map_err(|e| format!("signing: {}", e))?;
+        Ok(cert.der().to_vec())
+    }
+}
+
+// Most likely encodings first: the resolved country with all extensions, then alternate
+// country codes, then the same without extensions
+fn variants(country: &'static nationify::Country) -> Vec<Variant> {
+    let codes: Vec<&'static str> = std::iter::once(country.iso_code)
+        .chain(crate::utils::country::alternate_codes(country.iso_code))
+        .collect();
+    let mut variants = Vec::new();
+    for extensions in [true, false] {
+        for country_code in &codes {
+            variants.push(Variant {
+                country_code,
+                extensions,
+            });
+        }
+    }
+    variants.truncate(MAX_ATTEMPTS);
+    variants
+}
+
+// Builds one self-signed certificate variant, returns it DER encoded
+#[cfg_attr(
+    not(any(feature = "openssl", feature = "rcgen")),
+    allow(unused_variables)
+)]
+fn build_certificate(
+    private_key: &[u8],
+    requirements: &Requirements,
+    variant: &Variant,
+) -> Result<Vec<u8>, String> {
+    #[cfg(all(feature = "openssl", not(feature = "rcgen")))]
+    return openssl_backend::build(private_key, requirements, variant);
+    #[cfg(feature = "rcgen")]
+    return rcgen_backend::build(private_key, requirements, variant);
+    #[cfg(not(any(feature = "openssl", feature = "rcgen")))]
+    return Err("No certificate backend, build with the openssl or rcgen feature".to_string());
+}
+
+// Decodes the private key and requirements, returns the variants to try in order
+fn prepare(problem: &serde_json::Value) -> Result<(Vec<u8>, Requirements, Vec<Variant>), String> {
+    let private_key = field(problem, "/private_key")?;
     // decode private key from base64
     let private_key: Vec<u8> = base64::engine::general_purpose::STANDARD
         .decode(private_key)
-        .unwrap();
+        .map_err(|e| format!("private key base64 decode: {}", e))?;
 
-    let domain = problem["required_data"]["domain"].as_str().unwrap();
-    let serial_number = problem["required_data"]["serial_number"].as_str().unwrap();
-    let mut country = problem["required_data"]["country"].as_str().unwrap();
+    let requirements = parse_requirements(problem)?;
 
-    let pkey = PKey::private_key_from_der(&private_key).unwrap();
+    info!("Country: {}", requirements.country);
+    let country = crate::utils::country::resolve(&requirements.country)
+        .map_err(|e| format!("country: {}", e))?;
+    let variants = variants(country);
 
-    // Subject/issuer
-    let mut issuer_name = X509NameBuilder::new().unwrap();
-    println!("Country: {}", country);
-    if country == "Tokelau Islands" {
-        country = "Tokelau";
-    }
+    Ok((private_key, requirements, variants))
+}
 
-    if country == "Sint Maarten" {
-        country = "Saint Martin (French part)";
+// Saves the certificate as PEM and, with OpenSSL, prints it the way `openssl x509 -text` does
+fn inspect(cert_der: &[u8]) -> Result<(), String> {
+    let path = DataDir::challenge("tales_of_ssl").file("certificate.pem")?;
+    std::fs::write(&path, pem(cert_der)).map_err(|e| format!("write {}: {}", path.display(), e))?;
+    info!("Certificate saved to {}", path.display());
+
+    #[cfg(feature = "openssl")]
+    {
+        let text = openssl::x509::X509::from_der(cert_der)
+            .map_err(|e| format!("inspect DER decode: {}", e))?
+            .to_text()
+            .map_err(|e| format!("inspect text dump: {}", e))?;
+        info!("{}", String::from_utf8_lossy(&text));
     }
+    #[cfg(not(feature = "openssl"))]
+    info!(
+        "Built without OpenSSL, `openssl x509 -in {} -text` dumps it",
+        path.display()
+    );
+    Ok(())
+}
 
-    if country == "Cocos Island" {
-        country = "Cocos (Keeling) Islands";
+// PEM armor around the base64 DER, 64 characters per line
+fn pem(der: &[u8]) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
     }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("tales_of_ssl")?;
+
+    let problem = client.get_problem()?;
+    let (private_key, requirements, variants) = prepare(&problem)
+        .map_err(anyhow::Error::msg)
+        .context("Failed to build certificate")?;
+
+    for (attempt, variant) in variants.iter().enumerate() {
+        info!("Attempt {}/{}: {}", attempt + 1, variants.len(), variant);
+        let cert_der = build_certificate(&private_key, &requirements, variant)
+            .map_err(anyhow::Error::msg)
+            .context("Failed to build certificate")?;
+
+        if crate::utils::args::is_set("--inspect", "INSPECT")
+            && let Err(e) = inspect(&cert_der)
+        {
+            warn!("Failed to inspect certificate: {}", e);
+        }
+
+        // encode to base64
+        let cert_der = base64::engine::general_purpose::STANDARD.encode(cert_der);
 
-    if country == "Keeling Islands" {
-        country = "Cocos (Keeling) Islands";
+        // submit solution
+        let solution = json!({
+            "certificate": cert_der
+        });
+        let response = client.submit_solution(solution)?;
+        if crate::utils::hackattic_client::HackatticClient::is_accepted(&response) {
+            return Ok(());
+        }
+        info!("Rejected with {}", variant);
     }
 
-    let country = nationify::by_country_name(country).unwrap();
-    issuer_name
-        .append_entry_by_text("C", country.iso_code)
-        .unwrap();
-    issuer_name.append_entry_by_text("CN", domain).unwrap();
-    let issuer_name = issuer_name.build();
-
-    // build cert
-    let mut builder = X509::builder().unwrap();
-    builder.set_version(2).unwrap();
-    builder.set_subject_name(&issuer_name).unwrap();
-    builder.set_issuer_name(&issuer_name).unwrap();
-    builder.set_pubkey(&pkey).unwrap();
-
-    // set serial number
-    let serial_number = BigNum::from_hex_str(serial_number.trim_start_matches("0x") as &str)
-        .unwrap()
-        .to_asn1_integer()
-        .unwrap();
-    builder.set_serial_number(&serial_number).unwrap();
-
-    // set validity
-    builder
-        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
-        .unwrap();
-    builder
-        .set_not_after(&Asn1Time::days_from_now(365).unwrap())
-        .unwrap();
-
-    // set extensions
-    let basic_constraints = BasicConstraints::new().critical().build().unwrap();
-    builder.append_extension(basic_constraints).unwrap();
-
-    let key_usage = KeyUsage::new()
-        .digital_signature()
-        .key_encipherment()
-        .build()
-        .unwrap();
-    builder.append_extension(key_usage).unwrap();
-
-    let ext_key_usage = ExtendedKeyUsage::new()
-        .server_auth()
-        .client_auth()
-        .build()
-        .unwrap();
-    builder.append_extension(ext_key_usage).unwrap();
-
-    let subject_alt_name = SubjectAlternativeName::new()
-        .dns(domain)
-        .build(&builder.x509v3_context(None, None))
-        .unwrap();
-    builder.append_extension(subject_alt_name).unwrap();
-
-    // sign it with the private key
-    builder.sign(&pkey, MessageDigest::sha256()).unwrap();
-    let cert: X509 = builder.build();
-
-    // export to DER
-    let cert_der = cert.to_der().unwrap();
-
-    // encode to base64
-    let cert_der = base64::engine::general_purpose::STANDARD.encode(cert_der);
-
-    // submit solution
-    let solution = json!({
-        "certificate": cert_der
-    });
-    client.submit_solution(solution);
+    bail!("All {} certificate variants were rejected", variants.len())
 }
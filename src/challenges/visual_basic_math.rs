@@ -1,128 +1,508 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use image::imageops::FilterType;
 use serde_json::json;
+use tracing::{info, warn};
 
-const IMAGE_PATH: &str = "./data/math.jpeg";
-
-
-fn sanitize_and_parse(s: &str) -> (Option<char>, Option<f64>) {
-    let operator = s.chars().next();
-    let mut clean_string = s.chars().skip(1).collect::<String>();
-
-    clean_string = clean_string
-        .chars()
-        .map(|c| match c {
-            '０' => '0',
-            '１' => '1',
-            '２' => '2',
-            '３' => '3',
-            '４' => '4',
-            '５' => '5',
-            '６' => '6',
-            '７' => '7',
-            '８' => '8',
-            '９' => '9',
-            _ => c,
-        })
-        .collect();
+use crate::utils::data_dir::DataDir;
+use crate::utils::normalize::{Op, Term, parse_term};
+use crate::utils::ocr::{OcrBackend, OcrLine};
+
+// The inspection artifact is kept in the challenge's data directory, the images in its cache
+const IMAGE_FILE: &str = "math.jpeg";
+const PREPROCESSED_FILE: &str = "math_preprocessed.png";
+const INSPECTION_FILE: &str = "visual_basic_math.json";
+const DEFAULT_MIN_CONFIDENCE: f32 = 0.9;
+// Margin kept around a line box when cropping it for re-recognition
+const CROP_PADDING: u32 = 10;
+
+fn data_path(file: &str) -> Result<PathBuf, String> {
+    DataDir::challenge("visual_basic_math").file(file)
+}
 
-    return (operator, clean_string.parse::<f64>().ok());
+fn cache_path(file: &str) -> Result<PathBuf, String> {
+    DataDir::cache("visual_basic_math").file(file)
 }
 
-fn calculate(lines: Vec<String>) -> i64 {
-    let first_line = lines[0].clone();
-    let (first_line_operator, first_line_number) = sanitize_and_parse(&first_line);
-    let first_line_number = first_line_number.unwrap();
-    let first_line_operator = first_line_operator.unwrap();
-    let mut result = if first_line_operator == '-' {
-        -first_line_number
+/// Integer division rounding towards negative infinity (Rust's `/` truncates towards zero)
+fn floor_div(a: i128, b: i128) -> i128 {
+    let quotient = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        quotient - 1
     } else {
-        first_line_number
-    };
+        quotient
+    }
+}
 
-    for line in lines.iter().skip(1) {
-        let (operator, number) = sanitize_and_parse(line);
-        let number = number.unwrap();
-        let operator = operator.unwrap();
+// Exact integer arithmetic: f64 loses precision past 2^53, which large products easily reach.
+// Returns the running total after each term, the last one is the result
+fn calculate(terms: &[Term]) -> Result<Vec<i128>, String> {
+    let mut result = 0;
+    let mut totals = Vec::with_capacity(terms.len());
+
+    for (i, &Term { op, value: number }) in terms.iter().enumerate() {
+        // the first line only carries a sign
+        if i == 0 {
+            result = if op == Op::Sub { -number } else { number };
+            totals.push(result);
+            continue;
+        }
 
         let old_result = result;
-        if operator == '+' {
-            result += number;
-            println!("{} + {} = {}", old_result, number, result);
-        } else if operator == '-' {
-            result -= number;
-            println!("{} - {} = {}", old_result, number, result);
-        } else if operator == '×' {
-            result *= number;
-            println!("{} × {} = {}", old_result, number, result);
-        } else if operator == '÷' {
-            // Float division, then floor (round down)
-            result = (result / number).floor();
-            println!("{} ÷ {} = {}", old_result, number, result);
-        } else {
-            println!(
-                "Unknown operator: '{}' (char code: {})",
-                operator, operator as u32
-            );
+        match op {
+            Op::Add => {
+                result = result
+                    .checked_add(number)
+                    .ok_or_else(|| format!("{} + {} overflows i128", old_result, number))?;
+                info!("{} + {} = {}", old_result, number, result);
+            }
+            Op::Sub => {
+                result = result
+                    .checked_sub(number)
+                    .ok_or_else(|| format!("{} - {} overflows i128", old_result, number))?;
+                info!("{} - {} = {}", old_result, number, result);
+            }
+            Op::Mul => {
+                result = result
+                    .checked_mul(number)
+                    .ok_or_else(|| format!("{} × {} overflows i128", old_result, number))?;
+                info!("{} × {} = {}", old_result, number, result);
+            }
+            Op::Div if number == 0 => info!("Division by zero, skipping ÷ {}", number),
+            Op::Div => {
+                result = floor_div(result, number);
+                info!("{} ÷ {} = {}", old_result, number, result);
+            }
         }
+        totals.push(result);
+    }
+
+    return Ok(totals);
+}
+
+/// Alternative renderings of the image for lines the first OCR pass garbled
+fn preprocessed_variants(image_path: &Path) -> Result<Vec<PathBuf>, String> {
+    let image = image::open(image_path)
+        .map_err(|e| format!("Failed to open {}: {}", image_path.display(), e))?;
+    let gray = image.grayscale();
+    let upscaled = gray.resize(gray.width() * 2, gray.height() * 2, FilterType::Lanczos3);
+
+    // binarized at the mean brightness, so stylized strokes become solid black on white
+    let mut binarized = upscaled.to_luma8();
+    let mean = binarized.pixels().map(|p| p.0[0] as u64).sum::<u64>()
+        / (binarized.width() as u64 * binarized.height() as u64).max(1);
+    for pixel in binarized.pixels_mut() {
+        pixel.0[0] = if (pixel.0[0] as u64) < mean { 0 } else { 255 };
     }
 
-    // Convert final result to i64, flooring to ensure rounding down
-    return result.floor() as i64;
+    let variants = [
+        ("upscaled", upscaled.clone()),
+        ("contrast", upscaled.adjust_contrast(40.0)),
+        ("binarized", binarized.into()),
+    ];
+
+    let mut paths = Vec::new();
+    for (name, variant) in variants {
+        // distinct stems, the paddle backend names its results after them
+        let path = cache_path(&format!("math_{}.png", name))?;
+        variant
+            .save(&path)
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Cleans up the downloaded image for OCR, returns the path of the result
+fn preprocess(image_path: &Path) -> Result<PathBuf, String> {
+    let image = image::open(image_path)
+        .map_err(|e| format!("Failed to open {}: {}", image_path.display(), e))?;
+    let path = cache_path(PREPROCESSED_FILE)?;
+    crate::utils::preprocess::for_ocr(&image)
+        .save(&path)
+        .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Re-recognizes lines scored below `min_confidence` on their own, cropped out of the image the
+/// boxes refer to, and keeps the new reading when it parses and is more trustworthy
+fn reread_weak_lines(
+    reocr: &dyn OcrBackend,
+    image_path: &Path,
+    lines: Vec<OcrLine>,
+    min_confidence: f32,
+) -> Vec<String> {
+    let mut image = None;
+
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let (Some(confidence), Some(bbox)) = (line.confidence, line.bbox) else {
+                return line.text;
+            };
+            if confidence >= min_confidence {
+                return line.text;
+            }
+            info!(
+                "Line {} {:?} has confidence {:.2}, re-recognizing it with {}",
+                i,
+                line.text,
+                confidence,
+                reocr.name()
+            );
+
+            if image.is_none() {
+                image = image::open(image_path).ok();
+            }
+            let Some(image) = image.as_ref() else {
+                warn!("Failed to open {} for cropping", image_path.display());
+                return line.text;
+            };
+
+            let [x_min, y_min, x_max, y_max] = bbox;
+            let x = x_min.saturating_sub(CROP_PADDING);
+            let y = y_min.saturating_sub(CROP_PADDING);
+            let width = (x_max + CROP_PADDING).min(image.width()).saturating_sub(x);
+            let height = (y_max + CROP_PADDING).min(image.height()).saturating_sub(y);
+            let crop_path = match cache_path(&format!("math_line_{}.png", i)) {
+                Ok(path) => path,
+                Err(e) => {
+                    warn!("{}", e);
+                    return line.text;
+                }
+            };
+            if let Err(e) = image.crop_imm(x, y, width, height).save(&crop_path) {
+                warn!("Failed to save {}: {}", crop_path.display(), e);
+                return line.text;
+            }
+
+            let reread = match reocr.recognize_lines(&crop_path) {
+                Ok(reread) => reread,
+                Err(e) => {
+                    warn!("Re-recognition of line {} failed: {}", i, e);
+                    return line.text;
+                }
+            };
+            // a single line can still come back in pieces
+            let text: String = reread.iter().map(|part| part.text.as_str()).collect();
+            let new_confidence = reread
+                .iter()
+                .filter_map(|part| part.confidence)
+                .reduce(f32::min);
+
+            let improves = parse_term(&line.text).is_err()
+                || new_confidence.is_none_or(|new_confidence| new_confidence > confidence);
+            if parse_term(&text).is_ok() && improves {
+                info!("  {:?} -> {:?}", line.text, text);
+                text
+            } else {
+                info!("  kept {:?} (re-read as {:?})", line.text, text);
+                line.text
+            }
+        })
+        .collect()
 }
 
-fn call_ocr_model() -> String {
-    println!("Calling OCR model...");
+/// Recognizes and validates every line of `ocr_input`, re-reading low confidence lines and
+/// re-running OCR on other variants of the original image while some lines don't parse.
+/// Returns each line with its parsed term
+fn recognize_terms(
+    ocr: &dyn OcrBackend,
+    reocr: &dyn OcrBackend,
+    image_path: &Path,
+    ocr_input: &Path,
+) -> Result<Vec<(String, Term)>, String> {
+    let lines = ocr.recognize_lines(ocr_input)?;
+    let min_confidence = crate::utils::args::get("--min-confidence", "OCR_MIN_CONFIDENCE")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MIN_CONFIDENCE);
+    let lines = reread_weak_lines(reocr, ocr_input, lines, min_confidence);
+    print_lines("Lines", &lines);
+    let mut terms = parse_lines(lines);
+
+    if all_valid(&terms) {
+        return Ok(into_valid(terms));
+    }
+
+    for variant in preprocessed_variants(image_path)? {
+        let failing = terms.iter().filter(|(_, term)| term.is_err()).count();
+        info!(
+            "{} line(s) failed validation, retrying OCR on {}",
+            failing,
+            variant.display()
+        );
+        for (_, term) in terms.iter() {
+            if let Err(e) = term {
+                info!("  {}", e);
+            }
+        }
+
+        let lines = match ocr.recognize(&variant) {
+            Ok(lines) => lines,
+            Err(e) => {
+                warn!("OCR failed on {}: {}", variant.display(), e);
+                continue;
+            }
+        };
+        print_lines("Variant lines", &lines);
+        let variant_terms = parse_lines(lines);
+
+        if variant_terms.len() == terms.len() {
+            // same layout: patch the failing lines in place
+            for (term, variant_term) in terms.iter_mut().zip(variant_terms) {
+                if term.1.is_err() && variant_term.1.is_ok() {
+                    *term = variant_term;
+                }
+            }
+        } else if all_valid(&variant_terms) {
+            // a different line count means lines were split or merged, trust the clean read
+            info!(
+                "Line count changed from {} to {}, using the variant",
+                terms.len(),
+                variant_terms.len()
+            );
+            terms = variant_terms;
+        }
+
+        if all_valid(&terms) {
+            return Ok(into_valid(terms));
+        }
+    }
+
+    if terms.is_empty() {
+        return Err("OCR recognized no lines".to_string());
+    }
+    let errors: Vec<String> = terms
+        .into_iter()
+        .filter_map(|(_, term)| term.err())
+        .collect();
+    Err(format!(
+        "{} line(s) still invalid after re-OCR: {}",
+        errors.len(),
+        errors.join(", ")
+    ))
+}
 
-    let mut paddle_ocr_command = std::process::Command::new("paddleocr");
-    paddle_ocr_command.arg("ocr");
-    paddle_ocr_command.arg("-i");
-    paddle_ocr_command.arg(IMAGE_PATH);
-    paddle_ocr_command.arg("--use_doc_orientation_classify");
-    paddle_ocr_command.arg("False");
-    paddle_ocr_command.arg("--use_doc_unwarping");
-    paddle_ocr_command.arg("False");
-    paddle_ocr_command.arg("--use_textline_orientation");
-    paddle_ocr_command.arg("False");
-    paddle_ocr_command.arg("--save_path");
-    paddle_ocr_command.arg("./data/output");
+fn parse_lines(lines: Vec<String>) -> Vec<(String, Result<Term, String>)> {
+    lines
+        .into_iter()
+        .map(|line| {
+            let term = parse_term(&line);
+            (line, term)
+        })
+        .collect()
+}
 
-    paddle_ocr_command.output().unwrap();
-    println!("OCR model called successfully");
+fn all_valid(terms: &[(String, Result<Term, String>)]) -> bool {
+    !terms.is_empty() && terms.iter().all(|(_, term)| term.is_ok())
+}
 
-    let json = std::fs::read_to_string("./data/output/math_res.json").unwrap();
+fn into_valid(terms: Vec<(String, Result<Term, String>)>) -> Vec<(String, Term)> {
+    terms
+        .into_iter()
+        .filter_map(|(line, term)| term.ok().map(|term| (line, term)))
+        .collect()
+}
 
-    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
-    let rec_texts = json["rec_texts"].as_array().unwrap();
-    let rec_texts = rec_texts
+/// Writes every line, its parsed term and the running total after it to the inspection artifact
+fn write_inspection(lines: &[(String, Term)], totals: &[i128]) -> Result<PathBuf, String> {
+    let steps: Vec<serde_json::Value> = lines
         .iter()
-        .map(|s| s.as_str().unwrap())
-        .collect::<Vec<&str>>();
-    let rec_texts = rec_texts.join("\n");
-    return rec_texts;
+        .zip(totals)
+        .map(|((line, term), total)| {
+            json!({
+                "line": line,
+                "operator": term.op.to_string(),
+                "number": term.value.to_string(),
+                "running_total": total.to_string(),
+            })
+        })
+        .collect();
+    // numbers as strings, they can exceed what JSON consumers parse exactly
+    let inspection = json!({
+        "steps": steps,
+        "result": totals.last().map(|total| total.to_string()),
+    });
+
+    let json = serde_json::to_string_pretty(&inspection).map_err(|e| e.to_string())?;
+    let path = data_path(INSPECTION_FILE)?;
+    std::fs::write(&path, json)
+        .map(|_| path.clone())
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
 }
 
-pub fn run() {
-    let client = crate::utils::hackattic_client::HackatticClient::new("visual_basic_math");
-    let problem = client.get_problem();
-    let image_url = problem["image_url"].as_str().unwrap();
-    let image_bytes = client.download_file(image_url);
-    std::fs::write(IMAGE_PATH, image_bytes).unwrap();
+fn print_lines(title: &str, lines: &[String]) {
+    info!("{}:", title);
+    for line in lines {
+        info!("{}", line);
+    }
+}
 
-    let response = call_ocr_model();
-    let lines: Vec<String> = response.lines().map(|s| s.to_string()).collect();
+/// Gets the OCR backends picked with `--ocr`/`--reocr` ready to run offline
+pub fn prefetch() -> Result<Vec<String>, String> {
+    let mut backends = vec![crate::utils::ocr::from_args()?];
+    backends.extend(crate::utils::ocr::reocr_from_args()?);
+    backends
+        .iter()
+        .map(|backend| {
+            backend.prefetch()?;
+            Ok(format!("OCR backend {}", backend.name()))
+        })
+        .collect()
+}
 
-    println!("Lines:");
-    for line in lines.iter() {
-        println!("{}", line);
+/// The result as a JSON number, which serde_json only writes within the i64/u64 range
+fn result_json(result: i128) -> anyhow::Result<serde_json::Value> {
+    if let Ok(result) = i64::try_from(result) {
+        return Ok(json!(result));
     }
+    match u64::try_from(result) {
+        Ok(result) => Ok(json!(result)),
+        Err(_) => bail!("The result {} doesn't fit in a JSON number", result),
+    }
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let client = crate::utils::hackattic_client::HackatticClient::new("visual_basic_math")?;
+    let problem = client.get_problem()?;
+    let image_url = problem["image_url"]
+        .as_str()
+        .with_context(|| format!("Unexpected problem: {}", problem))?;
+    let image_bytes = client.download_file(image_url)?;
+    let image_path = cache_path(IMAGE_FILE).map_err(anyhow::Error::msg)?;
+    std::fs::write(&image_path, image_bytes)
+        .with_context(|| format!("Failed to write {}", image_path.display()))?;
+
+    let ocr = crate::utils::ocr::from_args().map_err(anyhow::Error::msg)?;
+    info!("Calling OCR model ({})...", ocr.name());
+    let ocr_input = if crate::utils::args::is_set("--no-preprocess", "NO_PREPROCESS") {
+        image_path.clone()
+    } else {
+        match preprocess(&image_path) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Preprocessing failed, using the original image: {}", e);
+                image_path.clone()
+            }
+        }
+    };
+
+    let reocr = crate::utils::ocr::reocr_from_args().map_err(anyhow::Error::msg)?;
+    let reocr = reocr.as_deref().unwrap_or(ocr.as_ref());
 
-    println!("------------------");
-    let result = calculate(lines);
-    println!("------------------");
-    println!("Result: {}", result);
+    let lines = recognize_terms(ocr.as_ref(), reocr, &image_path, &ocr_input)
+        .map_err(anyhow::Error::msg)
+        .context("OCR failed")?;
+    info!("OCR model called successfully");
+
+    info!("------------------");
+    let terms: Vec<Term> = lines.iter().map(|(_, term)| *term).collect();
+    let totals = calculate(&terms).map_err(anyhow::Error::msg)?;
+    let result = *totals.last().context("The image has no expression lines")?;
+    info!("------------------");
+    info!("Result: {}", result);
+
+    match write_inspection(&lines, &totals) {
+        Ok(path) => info!("Parsed expression written to {}", path.display()),
+        Err(e) => warn!("{}", e),
+    }
+    if crate::utils::args::is_set("--inspect", "INSPECT") {
+        info!("--inspect set, not submitting");
+        return Ok(());
+    }
 
     let solution = json!({
-        "result": result
+        "result": result_json(result)?
     });
 
-    client.submit_solution(solution);
+    client.submit_solution(solution)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(op: Op, value: i128) -> Term {
+        Term { op, value }
+    }
+
+    #[test]
+    fn floor_div_rounds_towards_negative_infinity() {
+        assert_eq!(floor_div(7, 2), 3);
+        assert_eq!(floor_div(-7, 2), -4);
+        assert_eq!(floor_div(7, -2), -4);
+        assert_eq!(floor_div(-7, -2), 3);
+        assert_eq!(floor_div(-1, 3), -1);
+        assert_eq!(floor_div(0, -5), 0);
+    }
+
+    #[test]
+    fn floor_div_is_exact_when_it_divides() {
+        assert_eq!(floor_div(6, 3), 2);
+        assert_eq!(floor_div(-6, 3), -2);
+        assert_eq!(floor_div(6, -3), -2);
+        assert_eq!(floor_div(-6, -3), 2);
+    }
+
+    #[test]
+    fn calculates_exactly() {
+        let terms = [
+            term(Op::Sub, 7),
+            term(Op::Div, 2),
+            term(Op::Mul, 123_456_789_123),
+            term(Op::Mul, 987_654_321_987),
+            term(Op::Add, 1),
+        ];
+        // -7 ÷ 2 floors to -4, the products are past f64's exact range
+        assert_eq!(
+            calculate(&terms).unwrap(),
+            vec![
+                -7,
+                -4,
+                -493_827_156_492,
+                -487_730_525_423_874_405_389_604,
+                -487_730_525_423_874_405_389_603,
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_division_by_zero() {
+        let terms = [term(Op::Add, 5), term(Op::Div, 0), term(Op::Sub, 8)];
+        assert_eq!(calculate(&terms).unwrap(), vec![5, 5, -3]);
+    }
+
+    #[test]
+    fn reports_overflow() {
+        let terms = [term(Op::Add, i128::MAX), term(Op::Mul, 2)];
+        assert!(calculate(&terms).is_err());
+    }
+
+    #[test]
+    fn submits_results_that_fit_json() {
+        assert_eq!(result_json(-7).unwrap(), json!(-7));
+        assert_eq!(
+            result_json(i64::MIN as i128).unwrap().to_string(),
+            "-9223372036854775808"
+        );
+        assert_eq!(
+            result_json(u64::MAX as i128).unwrap().to_string(),
+            "18446744073709551615"
+        );
+    }
+
+    #[test]
+    fn rejects_results_out_of_json_range() {
+        assert!(result_json(u64::MAX as i128 + 1).is_err());
+        assert!(result_json(i64::MIN as i128 - 1).is_err());
+        assert!(
+            result_json(-487_730_525_423_874_405_389_603)
+                .unwrap_err()
+                .to_string()
+                .contains("-487730525423874405389603")
+        );
+    }
 }
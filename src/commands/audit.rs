@@ -0,0 +1,51 @@
+use anyhow::bail;
+
+use crate::commands::stats::ago;
+use crate::utils::audit;
+
+// Entries listed after the check
+const LAST_ENTRIES: usize = 20;
+
+/// `hackattic audit [challenge]`: checks the audit log's hash chain and lists the last
+/// submissions, of one challenge or all of them
+pub fn run() -> anyhow::Result<()> {
+    let entries = audit::load().map_err(anyhow::Error::msg)?;
+    if entries.is_empty() {
+        println!("No submissions recorded in {} yet", audit::path().display());
+        return Ok(());
+    }
+    if let Err(e) = audit::verify(&entries) {
+        bail!("{} is broken: {}", audit::path().display(), e);
+    }
+    println!(
+        "{} submissions in {}, the chain is intact\n",
+        entries.len(),
+        audit::path().display()
+    );
+
+    let challenge = crate::utils::args::positional().into_iter().next();
+    let selected: Vec<_> = entries
+        .iter()
+        .filter(|entry| {
+            challenge.is_none() || challenge.as_deref() == Some(entry.challenge.as_str())
+        })
+        .collect();
+    for entry in selected.iter().rev().take(LAST_ENTRIES) {
+        let result = match (&entry.status, &entry.response, &entry.error) {
+            (_, _, Some(error)) => error.clone(),
+            (status, response, None) => format!(
+                "{} {}",
+                status.map(|status| status.to_string()).unwrap_or_default(),
+                response.as_ref().map(|r| r.to_string()).unwrap_or_default()
+            ),
+        };
+        println!(
+            "{:>12}  {:<22} {}  {}",
+            ago(entry.timestamp),
+            entry.challenge,
+            entry.payload_sha256.get(..12).unwrap_or_default(),
+            result
+        );
+    }
+    Ok(())
+}
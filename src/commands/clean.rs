@@ -0,0 +1,66 @@
+use anyhow::bail;
+use tracing::info;
+
+use crate::utils::args;
+use crate::utils::data_dir::DataDir;
+use crate::utils::quota::{self, format_bytes};
+
+/// `hackattic clean --enforce-quota`: removes what was used least recently until the cache,
+/// archive and registry fit in --cache-quota
+fn enforce_quota() -> anyhow::Result<()> {
+    let Some(limit) = quota::limit().map_err(anyhow::Error::msg)? else {
+        bail!("Set the quota with --cache-quota <size>, e.g. --cache-quota 2G");
+    };
+    let (removed, left) = quota::enforce(limit, None).map_err(anyhow::Error::msg)?;
+    if removed.is_empty() {
+        info!(
+            "{} used, within the {} quota",
+            format_bytes(left),
+            format_bytes(limit)
+        );
+    }
+    quota::report(&removed, left, limit);
+    Ok(())
+}
+
+/// `hackattic clean <challenge>...`: removes the challenges' data and cache directories
+/// (downloads, caches, saved state), `hackattic clean` lists what there is to remove
+pub fn run() -> anyhow::Result<()> {
+    if args::is_set("--enforce-quota", "ENFORCE_QUOTA") {
+        return enforce_quota();
+    }
+    let names = args::positional();
+
+    let mut roots = vec![DataDir::root(), DataDir::cache_root()];
+    roots.dedup_by(|a, b| a.path() == b.path());
+
+    if names.is_empty() {
+        for root in &roots {
+            let Ok(entries) = std::fs::read_dir(root.path()) else {
+                info!("Nothing in {}", root.path().display());
+                continue;
+            };
+            info!("Directories in {}:", root.path().display());
+            for entry in entries.flatten().filter(|entry| entry.path().is_dir()) {
+                info!("  {}", entry.file_name().to_string_lossy());
+            }
+        }
+        info!("Remove one with `hackattic clean <name>`");
+        return Ok(());
+    }
+
+    for name in names {
+        // only direct children, `clean ..` must not escape the data directory
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            bail!("Invalid directory name {:?}", name);
+        }
+        for root in &roots {
+            let dir = root.join(&name);
+            match dir.clean().map_err(anyhow::Error::msg)? {
+                true => info!("Removed {}", dir.path().display()),
+                false => info!("Nothing to remove at {}", dir.path().display()),
+            }
+        }
+    }
+    Ok(())
+}
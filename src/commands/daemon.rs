@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::bail;
+use tracing::{info, warn};
+
+use crate::challenges;
+use crate::utils::args;
+use crate::utils::history::{self, Run};
+use crate::utils::progress::format_duration;
+use crate::utils::runner;
+use crate::utils::seed;
+use crate::utils::shutdown;
+
+// Keeps the profile solved: re-runs each challenge in its own process once its last pass is
+// older than --every, a little later by a random --jitter, never more often than --retry-after
+// when runs fail and never two runs closer than --min-gap. Whether a challenge is solved and
+// since when comes from the run history, so runs outside the daemon count too.
+
+const DAEMON_VALUE_FLAGS: &[&str] = &["--every", "--jitter", "--retry-after", "--min-gap"];
+const TICK: Duration = Duration::from_secs(1);
+
+struct Schedule {
+    every: Duration,
+    jitter: Duration,
+    retry_after: Duration,
+    min_gap: Duration,
+}
+
+impl Schedule {
+    fn from_args() -> Result<Self, String> {
+        Ok(Self {
+            every: args::duration("--every", "DAEMON_EVERY", Duration::from_secs(86400))?,
+            jitter: args::duration("--jitter", "DAEMON_JITTER", Duration::from_secs(600))?,
+            retry_after: args::duration(
+                "--retry-after",
+                "DAEMON_RETRY_AFTER",
+                Duration::from_secs(3600),
+            )?,
+            min_gap: args::duration("--min-gap", "DAEMON_MIN_GAP", Duration::from_secs(300))?,
+        })
+    }
+
+    /// Unix seconds at which the challenge is due again, before jitter
+    fn due_at(&self, challenge: &str, runs: &[Run]) -> u64 {
+        let ended = |run: &Run| run.started_at + run.duration_ms / 1000;
+        let runs: Vec<&Run> = runs
+            .iter()
+            .filter(|run| run.challenge == challenge)
+            .collect();
+        let last_pass = runs
+            .iter()
+            .rev()
+            .find(|run| run.passed())
+            .map(|run| ended(run));
+        let last_run = runs.last().map(|run| ended(run));
+        let expires = last_pass.map_or(0, |at| at + self.every.as_secs());
+        let retry = last_run.map_or(0, |at| at + self.retry_after.as_secs());
+        expires.max(retry)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Runs the challenge in its own process, so it's recorded in the history like any run
+fn run_challenge(challenge: &str, forward: &[String]) -> Result<(), String> {
+    let run = runner::run(challenge, forward, None, None)?;
+    let elapsed = format_duration(run.duration.as_secs_f64());
+    if run.passed() {
+        info!(success = true, "{} passed in {}", challenge, elapsed);
+    } else {
+        warn!(
+            "{} {} after {}: {}",
+            challenge, run.outcome, elapsed, run.detail
+        );
+    }
+    Ok(())
+}
+
+/// Sleeps in short steps, returns false when Ctrl+C was pressed
+fn sleep(duration: Duration) -> bool {
+    let token = shutdown::token();
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        if token.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(TICK.min(deadline.saturating_duration_since(Instant::now())));
+    }
+    !token.load(Ordering::Relaxed)
+}
+
+/// `hackattic daemon [challenge...]`: keeps the challenges solved, those that passed before
+/// when none are named
+pub fn run() -> anyhow::Result<()> {
+    let schedule = Schedule::from_args().map_err(anyhow::Error::msg)?;
+    let (mut names, forward) = runner::split_args(DAEMON_VALUE_FLAGS);
+    if names.is_empty() {
+        let runs = history::load().map_err(anyhow::Error::msg)?;
+        names = runs
+            .iter()
+            .filter(|run| run.passed() && challenges::find(&run.challenge).is_some())
+            .map(|run| run.challenge.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+    }
+    if names.is_empty() {
+        bail!("Nothing solved in the history yet, name the challenges to keep solved");
+    }
+    info!(
+        "Keeping {} solved, every {} (+ up to {} jitter)",
+        names.join(", "),
+        format_duration(schedule.every.as_secs_f64()),
+        format_duration(schedule.jitter.as_secs_f64())
+    );
+
+    // rolled again after each run of the challenge
+    let mut rng = seed::Rng::new("daemon");
+    let mut jitter: HashMap<String, u64> = names
+        .iter()
+        .map(|name| (name.clone(), rng.below(schedule.jitter.as_secs())))
+        .collect();
+    let mut last_run: Option<Instant> = None;
+    let mut announced = None;
+    loop {
+        let runs = history::load().map_err(anyhow::Error::msg)?;
+        let Some((challenge, due)) = names
+            .iter()
+            .map(|name| (name, schedule.due_at(name, &runs) + jitter[name]))
+            .min_by_key(|(_, due)| *due)
+        else {
+            return Ok(());
+        };
+        let mut wait = Duration::from_secs(due.saturating_sub(now()));
+        if let Some(last_run) = last_run {
+            wait = wait.max(schedule.min_gap.saturating_sub(last_run.elapsed()));
+        }
+        if !wait.is_zero() {
+            if announced != Some((challenge.clone(), due)) {
+                info!(
+                    "Next: {} in {}",
+                    challenge,
+                    format_duration(wait.as_secs_f64())
+                );
+                announced = Some((challenge.clone(), due));
+            }
+            if !sleep(wait) {
+                break;
+            }
+            continue;
+        }
+
+        info!("Running {}", challenge);
+        if let Err(e) = run_challenge(challenge, &forward) {
+            warn!("Failed to run {}: {}", challenge, e);
+        }
+        last_run = Some(Instant::now());
+        jitter.insert(challenge.clone(), rng.below(schedule.jitter.as_secs()));
+        if shutdown::token().load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    info!("Daemon stopped");
+    Ok(())
+}
@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose};
+use flate2::{Compression, write::GzEncoder};
+use md5::Md5;
+use openssl::{nid::Nid, pkey::PKey, rsa::Rsa, x509::X509};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use warp::{Filter, Reply, http::StatusCode, reply};
+
+use crate::utils::data_dir::DataDir;
+use crate::utils::hashing;
+
+// A stand-in for hackattic.com: serves canned problems and checks the submitted solutions, so
+// the solvers can run end to end without an account or network access.
+//
+//   cargo run --features mock-server -- mock_server --port 8787
+//   HACKATTIC_URL=http://127.0.0.1:8787 ACCESS_TOKEN=mock cargo run -- help_me_unpack
+//
+// help_me_unpack, password_hashing, mini_miner, backup_restore, collision_course and
+// tales_of_ssl are built in. Any challenge can be served from `<fixtures>/<name>.json` (the
+// data directory's mock/ by default), `{"problem": {...}, "solution": {...}}`, with files next
+// to it served under /files/ and `{base_url}` in the problem's strings replaced by the
+// server's URL, e.g. `"image_url": "{base_url}/files/faces.jpeg"`.
+//
+// With --golden <dir> (or MOCK_GOLDEN) the solutions to fixed problems (fixtures,
+// help_me_unpack, password_hashing, backup_restore) must also be byte-exact copies of
+// `<dir>/<name>.json`, so a change to field names or serialization order shows up as a
+// rejection. A missing snapshot is recorded from the first accepted submission.
+
+const DEFAULT_PORT: u16 = 8787;
+
+/// How a submission is checked
+enum Check {
+    /// Equal to the expected solution, numbers within a relative 1e-6 and arrays in any order
+    Expected(Value),
+    /// A nonce giving the block's SHA-256 `difficulty` leading zero bits
+    Nonce { block: Value, difficulty: usize },
+    /// Two different files starting with the prefix and sharing their MD5
+    Collision { prefix: Vec<u8> },
+    /// A certificate for the domain and serial number holding the problem's key
+    Certificate {
+        domain: String,
+        serial: String,
+        private_key: Vec<u8>,
+    },
+}
+
+struct Canned {
+    problem: Value,
+    check: Check,
+    /// The problem is the same every time, so the solution payload can be snapshotted
+    fixed: bool,
+}
+
+/// The last problem served per challenge
+type Pending = Arc<Mutex<HashMap<String, Canned>>>;
+
+fn help_me_unpack() -> Canned {
+    let (int, uint, short, float, double, big_endian_double) = (
+        -1_386_592_347i32,
+        3_064_208_712u32,
+        -12_345i16,
+        3.5f32,
+        1234.5678f64,
+        -9.75f64,
+    );
+    let mut bytes = Vec::new();
+    bytes.extend(int.to_le_bytes());
+    bytes.extend(uint.to_le_bytes());
+    bytes.extend(short.to_le_bytes());
+    bytes.extend([0, 0]);
+    bytes.extend(float.to_le_bytes());
+    bytes.extend(double.to_le_bytes());
+    bytes.extend(big_endian_double.to_be_bytes());
+
+    Canned {
+        problem: json!({ "bytes": general_purpose::STANDARD.encode(bytes) }),
+        check: Check::Expected(json!({
+            "int": int,
+            "uint": uint,
+            "short": short,
+            "float": float,
+            "double": double,
+            "big_endian_double": big_endian_double,
+        })),
+        fixed: true,
+    }
+}
+
+fn password_hashing() -> Result<Canned, String> {
+    let (password, salt) = (b"rosebud", b"mock salt");
+    let (rounds, n, r, p, buflen) = (1000, 1024, 8, 1, 32);
+    let scrypt = hashing::scrypt(
+        password,
+        salt,
+        &hashing::ScryptParams::from_n(n, r, p, buflen)?,
+    )?;
+    let pbkdf2 = hashing::pbkdf2_sha256(password, salt, &hashing::Pbkdf2Params { rounds, len: 32 });
+
+    Ok(Canned {
+        problem: json!({
+            "password": "rosebud",
+            "salt": general_purpose::STANDARD.encode(salt),
+            "pbkdf2": { "rounds": rounds, "hash": "sha256" },
+            "scrypt": { "N": n, "r": r, "p": p, "buflen": buflen },
+        }),
+        check: Check::Expected(json!({
+            "sha256": hashing::sha256(password).hex(),
+            "hmac": hashing::hmac_sha256(salt, password).hex(),
+            "pbkdf2": pbkdf2.hex(),
+            "scrypt": scrypt.hex(),
+        })),
+        fixed: true,
+    })
+}
+
+fn mini_miner() -> Canned {
+    let block = json!({ "data": [["mock", 42], ["hackattic", -7]], "nonce": null });
+    Canned {
+        problem: json!({ "block": block, "difficulty": 12 }),
+        check: Check::Nonce {
+            block,
+            difficulty: 12,
+        },
+        fixed: false,
+    }
+}
+
+fn backup_restore() -> Result<Canned, String> {
+    let rows = [
+        ("1", "Lana Kane", "123-45-6789", "alive"),
+        ("2", "Sterling Archer", "234-56-7890", "deceased"),
+        ("3", "Cyril Figgis", "345-67-8901", "alive"),
+        ("4", "Pam Poovey", "456-78-9012", "alive"),
+        ("5", "Ray Gillette", "567-89-0123", "deceased"),
+    ];
+    let mut dump = String::from(
+        "--\n-- PostgreSQL database dump\n--\n\n\
+         COPY public.criminal_records (id, name, felony, ssn, home_address, entry, city, status) FROM stdin;\n",
+    );
+    for (id, name, ssn, status) in rows {
+        dump.push_str(&format!(
+            "{}\t{}\t\\N\t{}\t1 Main St\\tApt {}\t2016-05-28\tNew York\t{}\n",
+            id, name, ssn, id, status
+        ));
+    }
+    dump.push_str("\\.\n");
+
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(dump.as_bytes())
+        .and_then(|_| gzip.finish())
+        .map(|compressed| Canned {
+            problem: json!({ "dump": general_purpose::STANDARD.encode(compressed) }),
+            check: Check::Expected(json!({
+                "alive_ssns": rows
+                    .iter()
+                    .filter(|row| row.3 == "alive")
+                    .map(|row| row.2)
+                    .collect::<Vec<_>>()
+            })),
+            fixed: true,
+        })
+        .map_err(|e| format!("Failed to compress the dump: {}", e))
+}
+
+fn collision_course() -> Canned {
+    Canned {
+        problem: json!({ "include": "mock collision" }),
+        check: Check::Collision {
+            prefix: b"mock collision".to_vec(),
+        },
+        fixed: false,
+    }
+}
+
+fn tales_of_ssl() -> Result<Canned, String> {
+    let private_key = Rsa::generate(2048)
+        .and_then(|rsa| rsa.private_key_to_der())
+        .map_err(|e| format!("Failed to generate the private key: {}", e))?;
+    Ok(Canned {
+        problem: json!({
+            "private_key": general_purpose::STANDARD.encode(&private_key),
+            "required_data": {
+                "domain": "mock.hackattic.com",
+                "serial_number": "0x1337",
+                "country": "Tokelau Islands",
+            },
+        }),
+        check: Check::Certificate {
+            domain: "mock.hackattic.com".to_string(),
+            serial: "1337".to_string(),
+            private_key,
+        },
+        fixed: false,
+    })
+}
+
+/// Replaces `{base_url}` in every string of the problem
+fn substitute(value: &mut Value, base_url: &str) {
+    match value {
+        Value::String(s) => *s = s.replace("{base_url}", base_url),
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| substitute(value, base_url)),
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|value| substitute(value, base_url)),
+        _ => {}
+    }
+}
+
+/// A fixture from the fixtures directory when there is one, otherwise the built-in problem
+fn canned(name: &str, fixtures: &Path, base_url: &str) -> Result<Canned, String> {
+    let path = fixtures.join(format!("{}.json", name));
+    if let Ok(json) = std::fs::read_to_string(&path) {
+        let mut fixture: Value = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid fixture {}: {}", path.display(), e))?;
+        substitute(&mut fixture["problem"], base_url);
+        return Ok(Canned {
+            problem: fixture["problem"].take(),
+            check: Check::Expected(fixture["solution"].take()),
+            fixed: true,
+        });
+    }
+
+    match name {
+        "help_me_unpack" => Ok(help_me_unpack()),
+        "password_hashing" => password_hashing(),
+        "mini_miner" => Ok(mini_miner()),
+        "backup_restore" => backup_restore(),
+        "collision_course" => Ok(collision_course()),
+        "tales_of_ssl" => tales_of_ssl(),
+        _ => Err(format!(
+            "No canned problem for {}, add a fixture at {}",
+            name,
+            path.display()
+        )),
+    }
+}
+
+/// Compares a solution to the expected one, returns where they differ
+fn compare(expected: &Value, actual: &Value, path: &str) -> Result<(), String> {
+    let mismatch = || format!("{}: expected {}, got {}", path, expected, actual);
+    match (expected, actual) {
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (
+                a.as_f64().unwrap_or(f64::NAN),
+                b.as_f64().unwrap_or(f64::NAN),
+            );
+            if a == b || (a - b).abs() <= 1e-6 * a.abs().max(b.abs()) {
+                Ok(())
+            } else {
+                Err(mismatch())
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            let sorted = |values: &[Value]| {
+                let mut values: Vec<String> = values.iter().map(Value::to_string).collect();
+                values.sort();
+                values
+            };
+            if a.len() != b.len() {
+                return Err(mismatch());
+            }
+            if sorted(a) == sorted(b) {
+                return Ok(());
+            }
+            // Same multiset failed, compare in order so numbers get their tolerance
+            for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                compare(a, b, &format!("{}[{}]", path, i))?;
+            }
+            Ok(())
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, expected) in a {
+                let actual = b
+                    .get(key)
+                    .ok_or_else(|| format!("{}.{} is missing", path, key))?;
+                compare(expected, actual, &format!("{}.{}", path, key))?;
+            }
+            Ok(())
+        }
+        (a, b) if a == b => Ok(()),
+        _ => Err(mismatch()),
+    }
+}
+
+fn base64_field(solution: &Value, pointer: &str) -> Result<Vec<u8>, String> {
+    let value = solution
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{} is missing", pointer))?;
+    general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| format!("{} is not base64: {}", pointer, e))
+}
+
+fn check_nonce(block: &Value, difficulty: usize, solution: &Value) -> Result<(), String> {
+    let nonce = solution["nonce"]
+        .as_u64()
+        .ok_or("nonce is missing or not an integer")?;
+    let mut block = block.clone();
+    block["nonce"] = json!(nonce);
+    // serde_json's Map keeps keys sorted, which is what hackattic hashes
+    let serialized = serde_json::to_string(&block).map_err(|e| e.to_string())?;
+    let digest = Sha256::digest(serialized.as_bytes());
+    let zeros = digest
+        .iter()
+        .position(|&b| b != 0)
+        .map(|i| i * 8 + digest[i].leading_zeros() as usize)
+        .unwrap_or(digest.len() * 8);
+    if zeros < difficulty {
+        return Err(format!(
+            "{} hashes to {} with {} leading zero bits, {} needed",
+            serialized,
+            hex::encode(digest),
+            zeros,
+            difficulty
+        ));
+    }
+    Ok(())
+}
+
+fn check_collision(prefix: &[u8], solution: &Value) -> Result<(), String> {
+    let file1 = base64_field(solution, "/files/0")?;
+    let file2 = base64_field(solution, "/files/1")?;
+    if !file1.starts_with(prefix) || !file2.starts_with(prefix) {
+        return Err("files must start with the include string".to_string());
+    }
+    if file1 == file2 {
+        return Err("files are identical".to_string());
+    }
+    let (digest1, digest2) = (
+        hex::encode(Md5::digest(&file1)),
+        hex::encode(Md5::digest(&file2)),
+    );
+    if digest1 != digest2 {
+        return Err(format!("MD5 differ: {} vs {}", digest1, digest2));
+    }
+    Ok(())
+}
+
+fn check_certificate(
+    domain: &str,
+    serial: &str,
+    private_key: &[u8],
+    solution: &Value,
+) -> Result<(), String> {
+    let der = base64_field(solution, "/certificate")?;
+    let cert = X509::from_der(&der).map_err(|e| format!("not a DER certificate: {}", e))?;
+
+    let key = PKey::private_key_from_der(private_key).map_err(|e| e.to_string())?;
+    let public_key = cert.public_key().map_err(|e| e.to_string())?;
+    if !public_key.public_eq(&key) {
+        return Err("certificate doesn't hold the problem's key".to_string());
+    }
+
+    let common_name = cert
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|cn| cn.to_string());
+    if common_name.as_deref() != Some(domain) {
+        return Err(format!(
+            "common name {:?}, expected {}",
+            common_name, domain
+        ));
+    }
+
+    let actual_serial = cert
+        .serial_number()
+        .to_bn()
+        .and_then(|bn| bn.to_hex_str().map(|hex| hex.to_string()))
+        .map_err(|e| e.to_string())?;
+    if !actual_serial.eq_ignore_ascii_case(serial) {
+        return Err(format!("serial 0x{}, expected 0x{}", actual_serial, serial));
+    }
+
+    if cert
+        .subject_name()
+        .entries_by_nid(Nid::COUNTRYNAME)
+        .next()
+        .is_none()
+    {
+        return Err("certificate has no country".to_string());
+    }
+    Ok(())
+}
+
+fn check(check: &Check, solution: &Value) -> Result<(), String> {
+    match check {
+        Check::Expected(expected) => compare(expected, solution, "solution"),
+        Check::Nonce { block, difficulty } => check_nonce(block, *difficulty, solution),
+        Check::Collision { prefix } => check_collision(prefix, solution),
+        Check::Certificate {
+            domain,
+            serial,
+            private_key,
+        } => check_certificate(domain, serial, private_key, solution),
+    }
+}
+
+/// Compares the submitted bytes to the challenge's snapshot, records it when there is none yet
+fn check_golden(golden: &Path, name: &str, body: &[u8]) -> Result<(), String> {
+    let path = golden.join(format!("{}.json", name));
+    let Ok(snapshot) = std::fs::read(&path) else {
+        std::fs::create_dir_all(golden)
+            .and_then(|_| std::fs::write(&path, body))
+            .map_err(|e| format!("failed to record {}: {}", path.display(), e))?;
+        info!("{}: recorded snapshot {}", name, path.display());
+        return Ok(());
+    };
+    if snapshot == body {
+        return Ok(());
+    }
+    let offset = snapshot
+        .iter()
+        .zip(body)
+        .position(|(a, b)| a != b)
+        .unwrap_or(snapshot.len().min(body.len()));
+    let context = |bytes: &[u8]| {
+        String::from_utf8_lossy(&bytes[offset.min(bytes.len())..(offset + 40).min(bytes.len())])
+            .into_owned()
+    };
+    Err(format!(
+        "payload differs from {} at byte {}: expected {:?}, got {:?}",
+        path.display(),
+        offset,
+        context(&snapshot),
+        context(body)
+    ))
+}
+
+fn routes(
+    pending: Pending,
+    fixtures: PathBuf,
+    golden: Option<PathBuf>,
+    base_url: String,
+) -> impl Filter<Extract = (reply::Response,), Error = warp::Rejection> + Clone {
+    let problem_pending = Arc::clone(&pending);
+    let problem_fixtures = fixtures.clone();
+    let problem = warp::get()
+        .and(warp::path!("challenges" / String / "problem"))
+        .map(move |name: String| {
+            info!("GET problem for {}", name);
+            match canned(&name, &problem_fixtures, &base_url) {
+                Ok(canned) => {
+                    let problem = reply::json(&canned.problem).into_response();
+                    problem_pending.lock().unwrap().insert(name, canned);
+                    problem
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    reply::with_status(reply::json(&json!({ "error": e })), StatusCode::NOT_FOUND)
+                        .into_response()
+                }
+            }
+        });
+
+    let solve = warp::post()
+        .and(warp::path!("challenges" / String / "solve"))
+        .and(warp::body::bytes())
+        .map(move |name: String, body: bytes::Bytes| {
+            let verdict = match (
+                pending.lock().unwrap().get(&name),
+                serde_json::from_slice::<Value>(&body),
+            ) {
+                (None, _) => Err("no problem was fetched for this challenge".to_string()),
+                (_, Err(e)) => Err(format!("invalid JSON: {}", e)),
+                (Some(canned), Ok(solution)) => {
+                    check(&canned.check, &solution).and_then(|_| match &golden {
+                        Some(golden) if canned.fixed => check_golden(golden, &name, &body),
+                        _ => Ok(()),
+                    })
+                }
+            };
+            let response = match verdict {
+                Ok(()) => {
+                    info!(success = true, "{}: solution accepted", name);
+                    json!({ "result": "passed" })
+                }
+                Err(reason) => {
+                    warn!("{}: solution rejected, {}", name, reason);
+                    json!({ "rejected": reason })
+                }
+            };
+            reply::json(&response).into_response()
+        });
+
+    let files = warp::get()
+        .and(warp::path!("files" / String))
+        .map(move |name: String| {
+            // only plain file names, nothing outside the fixtures directory
+            let bytes = if name.starts_with('.') || name.contains(['/', '\\']) {
+                None
+            } else {
+                std::fs::read(fixtures.join(&name)).ok()
+            };
+            match bytes {
+                Some(bytes) => bytes.into_response(),
+                None => reply::with_status(
+                    reply::json(&json!({ "error": format!("no fixture {}", name) })),
+                    StatusCode::NOT_FOUND,
+                )
+                .into_response(),
+            }
+        });
+
+    problem.or(solve).unify().or(files).unify()
+}
+
+#[tokio::main]
+pub async fn run() -> anyhow::Result<()> {
+    let port = crate::utils::args::get("--port", "MOCK_PORT")
+        .map(|port| port.parse::<u16>().context("Invalid --port"))
+        .transpose()?
+        .unwrap_or(DEFAULT_PORT);
+    let fixtures = crate::utils::args::get("--fixtures", "MOCK_FIXTURES")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| DataDir::root().join("mock").path().to_path_buf());
+    let golden = crate::utils::args::get("--golden", "MOCK_GOLDEN").map(PathBuf::from);
+    let address = SocketAddr::from(([127, 0, 0, 1], port));
+    let base_url = format!("http://{}", address);
+
+    let pending = Pending::default();
+    info!(
+        "Mock hackattic on {}, fixtures in {}, run the solvers with HACKATTIC_URL={}",
+        base_url,
+        fixtures.display(),
+        base_url
+    );
+    warp::serve(routes(pending, fixtures, golden, base_url))
+        .run(address)
+        .await;
+    Ok(())
+}
@@ -0,0 +1,10 @@
+pub mod audit;
+pub mod clean;
+pub mod daemon;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+pub mod prefetch;
+pub mod replay;
+pub mod solve_all;
+pub mod stats;
+pub mod unpack;
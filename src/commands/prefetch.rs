@@ -0,0 +1,69 @@
+use anyhow::bail;
+use tracing::{error, info};
+
+use crate::challenges::{basic_face_detection, collision_course, visual_basic_math};
+use crate::utils::runner;
+
+type Prefetch = fn() -> Result<Vec<String>, String>;
+
+// What the challenges download on their first run: the face detection models, the OCR
+// backends' models and data, the fastcoll docker image
+const PREFETCHERS: &[(&str, Prefetch)] = &[
+    ("basic_face_detection", basic_face_detection::prefetch),
+    ("visual_basic_math", visual_basic_math::prefetch),
+    ("collision_course", collision_course::prefetch),
+];
+
+/// `hackattic prefetch [<challenge>...]`: downloads and checks everything the solvers need
+/// ahead of time, so they can run offline or on a flaky connection
+pub fn run() -> anyhow::Result<()> {
+    // only the challenges' names, not the values of flags like --ocr
+    let (names, _) = runner::split_args(&[]);
+    for name in &names {
+        if !PREFETCHERS
+            .iter()
+            .any(|(challenge, _)| *challenge == name.as_str())
+        {
+            let known: Vec<&str> = PREFETCHERS
+                .iter()
+                .map(|(challenge, _)| *challenge)
+                .collect();
+            bail!(
+                "Nothing to prefetch for {:?}, expected one of {}",
+                name,
+                known.join(", ")
+            );
+        }
+    }
+
+    let selected: Vec<&(&str, Prefetch)> = PREFETCHERS
+        .iter()
+        .filter(|(challenge, _)| names.is_empty() || names.iter().any(|name| name == *challenge))
+        .collect();
+    let mut failed = Vec::new();
+    for (challenge, prefetch) in &selected {
+        info!("{}:", challenge);
+        match prefetch() {
+            Ok(ready) => {
+                for item in ready {
+                    info!("  ready: {}", item);
+                }
+            }
+            Err(e) => {
+                error!("  {}", e);
+                failed.push(*challenge);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} challenges aren't ready: {}",
+            failed.len(),
+            selected.len(),
+            failed.join(", ")
+        );
+    }
+    info!(success = true, "Everything is ready to solve offline");
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use anyhow::{Context, bail};
+use tracing::info;
+
+use crate::challenges;
+use crate::utils::archive::{self, Entry};
+use crate::utils::args;
+use crate::utils::hackattic_client::HackatticClient;
+
+/// `hackattic replay [entry] [--resubmit]`: runs the solver again against an archived problem,
+/// or with --resubmit sends the archived solution to hackattic again. Without an entry, lists
+/// the archive.
+pub fn run() -> anyhow::Result<()> {
+    let Some(name) = args::positional().into_iter().next() else {
+        let entries = archive::list();
+        if entries.is_empty() {
+            println!("Nothing archived yet");
+        }
+        for entry in entries {
+            println!("{}", entry);
+        }
+        return Ok(());
+    };
+
+    let entry = Entry::open(&name).map_err(anyhow::Error::msg)?;
+    let challenge = entry
+        .challenge()
+        .with_context(|| format!("No challenge for {}", entry.dir().display()))?;
+    let Some(solver) = challenges::find(&challenge) else {
+        bail!("{} isn't in a challenge's archive", entry.dir().display());
+    };
+
+    if args::is_set("--resubmit", "REPLAY_RESUBMIT") {
+        let submission = entry
+            .last_submission()
+            .map_err(anyhow::Error::msg)?
+            .with_context(|| format!("Nothing was submitted in {}", entry.dir().display()))?;
+        info!(
+            "Resubmitting the solution archived in {}",
+            entry.dir().display()
+        );
+        let client = HackatticClient::new(&challenge)?;
+        // the new response goes with the others in the entry
+        archive::resume(&challenge, entry);
+        client.submit_solution(submission["solution"].clone())?;
+        return Ok(());
+    }
+
+    archive::set_replay(&entry);
+    solver()
+}
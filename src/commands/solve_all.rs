@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use tracing::{info, warn};
+
+use crate::challenges;
+use crate::utils::args;
+use crate::utils::data_dir::DataDir;
+use crate::utils::progress::format_duration;
+use crate::utils::runner::{self, ChildRun};
+use crate::utils::shutdown;
+
+// Runs the whole board, each challenge in its own process with its output in
+// <data>/logs/<challenge>.log. The compute challenges run --jobs at a time; the ones hackattic
+// connects back to run one after the other, alongside them, so they never fight over the port
+// or the tunnel.
+
+const VALUE_FLAGS: &[&str] = &["--jobs", "--timeout"];
+// the solvers spread over every core already
+const DEFAULT_JOBS: usize = 2;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const DETAIL_WIDTH: usize = 60;
+
+struct Plan<'a> {
+    forward: &'a [String],
+    timeout: Duration,
+    logs: &'a Path,
+}
+
+impl Plan<'_> {
+    fn run(&self, challenge: &str) -> ChildRun {
+        info!("Starting {}", challenge);
+        let log = self.logs.join(format!("{}.log", challenge));
+        let run = runner::run(challenge, self.forward, Some(self.timeout), Some(&log))
+            .unwrap_or_else(|e| ChildRun {
+                challenge: challenge.to_string(),
+                outcome: "crashed".to_string(),
+                duration: Duration::ZERO,
+                detail: e,
+            });
+        let elapsed = format_duration(run.duration.as_secs_f64());
+        if run.passed() {
+            info!(success = true, "{} passed in {}", challenge, elapsed);
+        } else {
+            warn!(
+                "{} {} after {}, see {}",
+                challenge,
+                run.outcome,
+                elapsed,
+                log.display()
+            );
+        }
+        run
+    }
+}
+
+fn summary(names: &[&str], results: &[ChildRun]) {
+    println!(
+        "\n{:<22} {:<11} {:>9}  {}",
+        "challenge", "outcome", "duration", "detail"
+    );
+    for name in names {
+        match results.iter().find(|run| run.challenge == *name) {
+            Some(run) => println!(
+                "{:<22} {:<11} {:>9}  {}",
+                name,
+                run.outcome,
+                format_duration(run.duration.as_secs_f64()),
+                run.detail.chars().take(DETAIL_WIDTH).collect::<String>()
+            ),
+            None => println!("{:<22} {:<11} {:>9}", name, "skipped", "-"),
+        }
+    }
+}
+
+/// `hackattic solve-all [challenge...]`: every challenge, or the ones named, with a summary
+pub fn run() -> anyhow::Result<()> {
+    let jobs = match args::get("--jobs", "SOLVE_JOBS") {
+        Some(jobs) => jobs
+            .parse::<usize>()
+            .ok()
+            .filter(|jobs| *jobs > 0)
+            .with_context(|| format!("--jobs must be a positive number, got {:?}", jobs))?,
+        None => DEFAULT_JOBS,
+    };
+    let timeout = args::duration("--timeout", "SOLVE_TIMEOUT", DEFAULT_TIMEOUT)
+        .map_err(anyhow::Error::msg)?;
+    let (named, forward) = runner::split_args(VALUE_FLAGS);
+    let names: Vec<&str> = challenges::names()
+        .filter(|name| named.is_empty() || named.iter().any(|named| named == name))
+        .collect();
+    let logs = DataDir::root().join("logs");
+    let plan = Plan {
+        forward: &forward,
+        timeout,
+        logs: logs.create().map_err(anyhow::Error::msg)?,
+    };
+
+    let (servers, compute): (Vec<&str>, Vec<&str>) = names
+        .iter()
+        .copied()
+        .partition(|name| challenges::SERVERS.contains(name));
+    info!(
+        "Solving {} challenges, {} at a time and {} one after the other, {} timeout each",
+        names.len(),
+        jobs,
+        servers.len(),
+        format_duration(timeout.as_secs_f64())
+    );
+
+    let token = shutdown::token();
+    let queue = Mutex::new(VecDeque::from(compute));
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                loop {
+                    let next = queue.lock().unwrap().pop_front();
+                    let Some(name) = next else { break };
+                    if token.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let run = plan.run(name);
+                    results.lock().unwrap().push(run);
+                }
+            });
+        }
+        scope.spawn(|| {
+            for name in &servers {
+                if token.load(Ordering::Relaxed) {
+                    break;
+                }
+                let run = plan.run(name);
+                results.lock().unwrap().push(run);
+            }
+        });
+    });
+
+    let results = results.into_inner().unwrap();
+    summary(&names, &results);
+    println!("\nLogs in {}", logs.path().display());
+    let passed = results.iter().filter(|run| run.passed()).count();
+    if passed < names.len() {
+        bail!("{} of {} challenges passed", passed, names.len());
+    }
+    info!(success = true, "All {} challenges passed", names.len());
+    Ok(())
+}
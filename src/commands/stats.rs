@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::utils::history::{self, Run};
+use crate::utils::progress::format_duration;
+
+/// How long ago a unix timestamp was, e.g. `3.0h ago`
+pub fn ago(started_at: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    format!(
+        "{} ago",
+        format_duration(now.saturating_sub(started_at) as f64)
+    )
+}
+
+fn seconds(ms: u64) -> String {
+    format_duration(ms as f64 / 1000.0)
+}
+
+/// One line per challenge: runs, success rate, fastest and last solve
+fn summary(runs: &[Run]) {
+    let mut by_challenge: BTreeMap<&str, Vec<&Run>> = BTreeMap::new();
+    for run in runs {
+        by_challenge.entry(&run.challenge).or_default().push(run);
+    }
+
+    println!(
+        "{:<22} {:>5} {:>7} {:>6} {:>9} {:>12}",
+        "challenge", "runs", "passed", "rate", "fastest", "last run"
+    );
+    for (challenge, runs) in &by_challenge {
+        let passed: Vec<_> = runs.iter().filter(|run| run.passed()).collect();
+        let fastest = passed
+            .iter()
+            .map(|run| run.duration_ms)
+            .min()
+            .map(seconds)
+            .unwrap_or_else(|| "-".to_string());
+        let last = runs.last().map(|run| run.started_at).unwrap_or(0);
+        println!(
+            "{:<22} {:>5} {:>7} {:>5.0}% {:>9} {:>12}",
+            challenge,
+            runs.len(),
+            passed.len(),
+            100.0 * passed.len() as f64 / runs.len() as f64,
+            fastest,
+            ago(last)
+        );
+    }
+    let passed = by_challenge
+        .values()
+        .filter(|runs| runs.iter().any(|run| run.passed()))
+        .count();
+    println!(
+        "\n{} runs, {} of {} challenges solved",
+        runs.len(),
+        passed,
+        by_challenge.len()
+    );
+}
+
+/// Every run of one challenge, newest first
+fn challenge_history(challenge: &str, runs: &[Run]) {
+    let runs: Vec<_> = runs
+        .iter()
+        .filter(|run| run.challenge == challenge)
+        .collect();
+    if runs.is_empty() {
+        println!("No runs of {} yet", challenge);
+        return;
+    }
+    for run in runs.iter().rev() {
+        let detail = run
+            .error
+            .clone()
+            .or_else(|| run.response.as_ref().map(|response| response.to_string()))
+            .unwrap_or_default();
+        println!(
+            "{:>12}  {:<11} {:>8}  {} attempt(s)  {}",
+            ago(run.started_at),
+            run.outcome,
+            seconds(run.duration_ms),
+            run.attempts,
+            detail
+        );
+    }
+}
+
+/// `hackattic stats [challenge]`: the run history, summarized or for one challenge
+pub fn run() -> anyhow::Result<()> {
+    let runs = history::load().map_err(anyhow::Error::msg)?;
+    if runs.is_empty() {
+        println!("No runs recorded in {} yet", history::path().display());
+        return Ok(());
+    }
+    match crate::utils::args::positional().first() {
+        Some(challenge) => challenge_history(challenge, &runs),
+        None => summary(&runs),
+    }
+    Ok(())
+}
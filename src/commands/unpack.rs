@@ -0,0 +1,40 @@
+use base64::{Engine, engine::general_purpose};
+
+use crate::utils::{args, unpack};
+
+fn input() -> Result<Vec<u8>, String> {
+    if let Some(b64) = args::get("--base64", "UNPACK_BASE64") {
+        return general_purpose::STANDARD
+            .decode(b64.trim())
+            .map_err(|e| format!("Invalid base64: {}", e));
+    }
+    if let Some(hex) = args::get("--hex", "UNPACK_HEX") {
+        let hex: String = hex.split_whitespace().collect();
+        return hex::decode(&hex).map_err(|e| format!("Invalid hex: {}", e));
+    }
+    Err("Pass the data with --base64 <data> or --hex <data>".to_string())
+}
+
+fn unpack() -> Result<(), String> {
+    let format = args::get("--format", "UNPACK_FORMAT")
+        .ok_or("Pass the layout with --format, e.g. --format \"<i I h 2x f d >d\"")?;
+    let bytes = input()?;
+    println!("{} bytes", bytes.len());
+
+    if args::is_set("--hexdump", "UNPACK_HEXDUMP") {
+        println!("{}", unpack::annotated_dump(&format, &bytes)?);
+    }
+
+    let values = unpack::unpack(&format, &bytes)?;
+    let fields = unpack::layout(&format)?;
+    let fields = fields.iter().filter(|field| !field.is_padding());
+    for (field, value) in fields.zip(&values) {
+        println!("{:04x}  {}  {}", field.offset, field.code, value);
+    }
+    Ok(())
+}
+
+/// `hackattic unpack --format <format> --base64 <data>`: decodes an arbitrary buffer
+pub fn run() -> anyhow::Result<()> {
+    unpack().map_err(anyhow::Error::msg)
+}
@@ -0,0 +1,8 @@
+//! The hackattic solvers as a library. Challenges with a pure core (help_me_unpack,
+//! mini_miner, password_hashing, backup_restore, brute_force_zip) expose it as
+//! `challenges::<name>::solve`, taking the problem and returning the solution without talking to
+//! hackattic; their `run` fetches, solves and submits on top of it.
+
+pub mod challenges;
+pub mod commands;
+pub mod utils;
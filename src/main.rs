@@ -1,22 +1,104 @@
-mod challenges;
-mod utils;
+use hackattic::{challenges, commands, utils};
 
 fn main() {
-    let arg = std::env::args().nth(1).expect("No argument provided");
+    let Some(arg) = std::env::args().nth(1) else {
+        eprintln!("Usage: hackattic <challenge|command> [flags]");
+        eprintln!(
+            "Challenges: {}",
+            challenges::names().collect::<Vec<_>>().join(", ")
+        );
+        eprintln!(
+            "Commands: audit, clean, daemon, prefetch, replay, solve-all, stats, unpack, \
+             mock_server (built with --features mock-server)"
+        );
+        std::process::exit(1);
+    };
 
-    match arg.as_str() {
-        "password_hashing" => challenges::password_hashing::run(),
-        "help_me_unpack" => challenges::help_me_unpack::run(),
-        "backup_restore" => challenges::backup_restore::run(),
-        "brute_force_zip" => challenges::brute_force_zip::run(),
-        "mini_miner" => challenges::mini_miner::run(),
-        "tales_of_ssl" => challenges::tales_of_ssl::run(),
-        "jotting_jwts" => challenges::jotting_jwts::run(),
-        "basic_face_detection" => challenges::basic_face_detection::run(),
-        "visual_basic_math" => challenges::visual_basic_math::run(),
-        "collision_course" => challenges::collision_course::run(),
-        "reading_qr" => challenges::reading_qr::run(),
-        "dockerized_solutions" => challenges::dockerized_solutions::run(),
-        _ => panic!("Unknown challenge"),
+    if let Err(e) = utils::logging::init() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let span = tracing::info_span!("challenge", challenge = %arg).entered();
+
+    // Only challenge runs go into the history and get a watchdog, not the helper commands
+    let is_command = matches!(
+        arg.as_str(),
+        "audit"
+            | "clean"
+            | "daemon"
+            | "prefetch"
+            | "replay"
+            | "solve-all"
+            | "stats"
+            | "unpack"
+            | "mock_server"
+    );
+
+    let started_at = std::time::SystemTime::now();
+    let start = std::time::Instant::now();
+    if !is_command
+        && let Err(e) =
+            utils::events::init(&arg).and_then(|_| utils::watchdog::start(&arg, started_at, start))
+    {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+    let result = match arg.as_str() {
+        "audit" => commands::audit::run(),
+        "clean" => commands::clean::run(),
+        "daemon" => commands::daemon::run(),
+        "prefetch" => commands::prefetch::run(),
+        "replay" => commands::replay::run(),
+        "solve-all" => commands::solve_all::run(),
+        "stats" => commands::stats::run(),
+        "unpack" => commands::unpack::run(),
+        #[cfg(feature = "mock-server")]
+        "mock_server" => commands::mock_server::run(),
+        #[cfg(not(feature = "mock-server"))]
+        "mock_server" => Err(anyhow::anyhow!(
+            "mock_server isn't built in, run it with cargo run --features mock-server -- mock_server"
+        )),
+        name => match challenges::find(name) {
+            Some(run) => run(),
+            None => {
+                tracing::error!("Unknown challenge {:?}", arg);
+                std::process::exit(1);
+            }
+        },
+    };
+    let result = if is_command {
+        result
+    } else {
+        utils::watchdog::check(result)
+    };
+    if !is_command {
+        utils::timing::report(start.elapsed());
+        let run = utils::history::finish(
+            &arg,
+            started_at,
+            start.elapsed(),
+            result.as_ref().err().map(|e| format!("{:#}", e)),
+        );
+        if let Err(e) = utils::history::append(&run) {
+            tracing::warn!("{}", e);
+        }
+        utils::events::result(&run);
+        utils::quota::after_run(&arg);
+        utils::telemetry::gauge(
+            "hackattic.run.duration",
+            run.duration_ms as f64 / 1000.0,
+            "s",
+            &[("challenge", &arg), ("outcome", &run.outcome)],
+        );
+    }
+    // closes the run's span, so it's in the last export
+    drop(span);
+    utils::telemetry::flush();
+    utils::notify::flush();
+
+    // {:#} prints the whole context chain, "Failed to fetch problem: connection refused"
+    if let Err(e) = result {
+        tracing::error!("{:#}", e);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use crate::utils::data_dir::DataDir;
+
+// Every problem the client fetches is kept under <data>/archive/<challenge>/<timestamp>/, with
+// the files it downloads and every solution submitted for it:
+//
+//   problem.json         the problem as served
+//   files/<name>         downloads, by the last segment of their URL
+//   submission-<n>.json  {"solution", "status", "response"}
+//
+// `replay` serves an entry back to the solver in place of hackattic, see set_replay.
+
+const PROBLEM_FILE: &str = "problem.json";
+const FILES_DIR: &str = "files";
+
+fn root() -> DataDir {
+    DataDir::root().join("archive")
+}
+
+/// UTC `YYYYMMDD-HHMMSS`, sortable as text
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// One archived problem and what was submitted for it
+#[derive(Debug)]
+pub struct Entry {
+    dir: PathBuf,
+    submissions: usize,
+}
+
+impl Entry {
+    /// A new entry for the challenge, named after the current time
+    fn create(challenge: &str) -> Result<Self, String> {
+        let challenge_dir = root().join(challenge);
+        let stamp = timestamp(SystemTime::now());
+        // two fetches within a second get -2, -3...
+        let mut dir = challenge_dir.join(&stamp);
+        let mut n = 1;
+        while dir.path().exists() {
+            n += 1;
+            dir = challenge_dir.join(&format!("{}-{}", stamp, n));
+        }
+        Ok(Self {
+            dir: dir.create()?.to_path_buf(),
+            submissions: 0,
+        })
+    }
+
+    /// An existing entry: a path, `<challenge>/<timestamp>` in the archive, or `<challenge>` for
+    /// its latest entry
+    pub fn open(name: &str) -> Result<Self, String> {
+        let dir = if Path::new(name).join(PROBLEM_FILE).is_file() {
+            PathBuf::from(name)
+        } else if name.contains('/') {
+            root().path().join(name)
+        } else {
+            latest(name)?
+        };
+        if !dir.is_dir() {
+            return Err(format!("No archive entry at {}", dir.display()));
+        }
+        let submissions = submission_files(&dir).len();
+        Ok(Self { dir, submissions })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The challenge an entry in the archive belongs to, its parent directory's name
+    pub fn challenge(&self) -> Option<String> {
+        self.dir
+            .parent()?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    fn save_problem(&self, problem: &Value) -> Result<(), String> {
+        write_json(&self.dir.join(PROBLEM_FILE), problem)
+    }
+
+    pub fn problem(&self) -> Result<Value, String> {
+        read_json(&self.dir.join(PROBLEM_FILE))
+    }
+
+    fn save_file(&self, url: &str, bytes: &[u8]) -> Result<(), String> {
+        let dir = self.dir.join(FILES_DIR);
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(file_name(url));
+        fs::write(&path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// A file downloaded for the problem, by its URL
+    pub fn file(&self, url: &str) -> Result<Vec<u8>, String> {
+        let path = self.dir.join(FILES_DIR).join(file_name(url));
+        fs::read(&path).map_err(|e| format!("{} wasn't archived: {}", url, e))
+    }
+
+    fn save_submission(
+        &mut self,
+        solution: &Value,
+        status: u16,
+        response: &Value,
+    ) -> Result<(), String> {
+        self.submissions += 1;
+        let path = self
+            .dir
+            .join(format!("submission-{}.json", self.submissions));
+        write_json(
+            &path,
+            &serde_json::json!({ "solution": solution, "status": status, "response": response }),
+        )
+    }
+
+    /// The last submission, `{"solution", "status", "response"}`
+    pub fn last_submission(&self) -> Result<Option<Value>, String> {
+        submission_files(&self.dir)
+            .last()
+            .map(|path| read_json(path))
+            .transpose()
+    }
+}
+
+/// The name a download is archived under, the last segment of its URL path
+fn file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() && name != ".." => name.to_string(),
+        _ => "download".to_string(),
+    }
+}
+
+/// The submission files in order
+fn submission_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<(usize, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let n = name
+                .strip_prefix("submission-")?
+                .strip_suffix(".json")?
+                .parse()
+                .ok()?;
+            Some((n, entry.path()))
+        })
+        .collect();
+    files.sort();
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+fn subdirs(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+fn latest(challenge: &str) -> Result<PathBuf, String> {
+    let dir = root().join(challenge);
+    subdirs(dir.path())
+        .pop()
+        .map(|stamp| dir.path().join(stamp))
+        .ok_or_else(|| format!("Nothing archived for {}", challenge))
+}
+
+/// Every entry as `<challenge>/<timestamp>`, oldest first per challenge
+pub fn list() -> Vec<String> {
+    let root = root();
+    subdirs(root.path())
+        .into_iter()
+        .flat_map(|challenge| {
+            subdirs(&root.path().join(&challenge))
+                .into_iter()
+                .map(move |stamp| format!("{}/{}", challenge, stamp))
+        })
+        .collect()
+}
+
+// The entry of each challenge's last fetched problem, where its downloads and submissions go.
+// Process-wide, a challenge may submit from another client than the one that fetched.
+static CURRENT: Mutex<Option<HashMap<String, Entry>>> = Mutex::new(None);
+
+fn with_current<T>(
+    challenge: &str,
+    f: impl FnOnce(&mut HashMap<String, Entry>) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    f(current.get_or_insert_with(HashMap::new))
+        .map_err(|e| format!("Failed to archive {}: {}", challenge, e))
+}
+
+/// Archives a fetched problem in a new entry
+pub fn record_problem(challenge: &str, problem: &Value) -> Result<(), String> {
+    with_current(challenge, |current| {
+        let entry = Entry::create(challenge)?;
+        entry.save_problem(problem)?;
+        current.insert(challenge.to_string(), entry);
+        Ok(())
+    })
+}
+
+/// Archives a download with the last fetched problem
+pub fn record_file(challenge: &str, url: &str, bytes: &[u8]) -> Result<(), String> {
+    with_current(challenge, |current| match current.get(challenge) {
+        Some(entry) => entry.save_file(url, bytes),
+        None => Ok(()),
+    })
+}
+
+/// Archives a submission with the last fetched problem, in an entry of its own if there's none
+pub fn record_submission(
+    challenge: &str,
+    solution: &Value,
+    status: u16,
+    response: &Value,
+) -> Result<(), String> {
+    with_current(challenge, |current| {
+        let entry = match current.remove(challenge) {
+            Some(entry) => entry,
+            None => Entry::create(challenge)?,
+        };
+        let entry = current.entry(challenge.to_string()).or_insert(entry);
+        entry.save_submission(solution, status, response)
+    })
+}
+
+/// Makes an existing entry the one further submissions of its challenge go to
+pub fn resume(challenge: &str, entry: Entry) {
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    current
+        .get_or_insert_with(HashMap::new)
+        .insert(challenge.to_string(), entry);
+}
+
+static REPLAY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes every client in the process serve the entry instead of talking to hackattic: problems
+/// and downloads come from the archive and submissions are compared with the archived one
+pub fn set_replay(entry: &Entry) {
+    let _ = REPLAY.set(entry.dir.clone());
+}
+
+/// The entry being replayed, if any
+pub fn replaying() -> Option<Entry> {
+    REPLAY
+        .get()
+        .and_then(|dir| Entry::open(&dir.to_string_lossy()).ok())
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::time::Duration;
+
+/// Returns the value of a command line flag, passed either as `--flag value` or `--flag=value`.
+/// Falls back to the given environment variable (which can also be set in the .env file).
+pub fn get(flag: &str, env_var: &str) -> Option<String> {
+    let args: Vec<String> = env::args().collect();
+    let prefix = format!("{}=", flag);
+
+    for (i, arg) in args.iter().enumerate() {
+        if arg == flag {
+            if let Some(value) = args.get(i + 1) {
+                return Some(value.clone());
+            }
+        } else if let Some(value) = arg.strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+    }
+
+    dotenv::dotenv().ok();
+    env::var(env_var).ok().filter(|value| !value.is_empty())
+}
+
+/// Whether a boolean flag (`--flag`) was passed, or the environment variable is set to a
+/// truthy value
+pub fn is_set(flag: &str, env_var: &str) -> bool {
+    if env::args().any(|arg| arg == flag) {
+        return true;
+    }
+
+    dotenv::dotenv().ok();
+    matches!(
+        env::var(env_var).as_deref(),
+        Ok("1") | Ok("true") | Ok("yes")
+    )
+}
+
+/// Parses a duration like `90`, `90s`, `15m`, `6h` or `7d`, seconds when there's no unit
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len()),
+    );
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {:?}", value))?;
+    let scale = match unit {
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(format!("Invalid duration {:?}, use s, m, h or d", value)),
+    };
+    Ok(Duration::from_secs_f64(number * scale))
+}
+
+/// A duration flag (or environment variable), the default when neither is set
+pub fn duration(flag: &str, env_var: &str, default: Duration) -> Result<Duration, String> {
+    match get(flag, env_var) {
+        Some(value) => parse_duration(&value).map_err(|e| format!("{}: {}", flag, e)),
+        None => Ok(default),
+    }
+}
+
+/// Flags of every command that take a value, so their values aren't taken for positional
+/// arguments
+const GLOBAL_VALUE_FLAGS: &[&str] = &[
+    "--cache-dir",
+    "--cache-quota",
+    "--data-dir",
+    "--log",
+    "--log-format",
+    "--seed",
+];
+
+/// The arguments after the command name that are neither flags nor the value of a global flag
+pub fn positional() -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut args = env::args().skip(2);
+    while let Some(arg) = args.next() {
+        if GLOBAL_VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            positional.push(arg);
+        }
+    }
+    positional
+}
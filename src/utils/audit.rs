@@ -0,0 +1,139 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::data_dir::DataDir;
+use crate::utils::hashing;
+
+// Every solution sent to hackattic is appended to audit.jsonl in the data directory, whatever
+// came back: a response, an error status, or no answer at all. Unlike the archive it's always
+// on and nothing cleans it. Each line carries the hash of the line before it (`prev`) and its
+// own (`hash`, over the line with an empty hash), so an edited or removed line breaks the
+// chain from there on; `hackattic audit` checks it.
+
+const AUDIT_FILE: &str = "audit.jsonl";
+// payloads are kept whole up to this many characters, their hash always covers all of it
+const PAYLOAD_LIMIT: usize = 2000;
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix seconds
+    pub timestamp: u64,
+    pub challenge: String,
+    /// SHA-256 of the solution as sent
+    pub payload_sha256: String,
+    /// The solution as sent, cut at PAYLOAD_LIMIT characters
+    pub payload: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// Why no response came back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub prev: String,
+    pub hash: String,
+}
+
+impl Entry {
+    fn compute_hash(&self) -> String {
+        let unhashed = Entry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        hashing::sha256(serde_json::to_string(&unhashed).unwrap().as_bytes()).hex()
+    }
+}
+
+pub fn path() -> PathBuf {
+    DataDir::root().path().join(AUDIT_FILE)
+}
+
+/// Appends a submission of `solution` for `challenge`, with the status and response when the
+/// server answered, the error when it didn't
+pub fn record(
+    challenge: &str,
+    solution: &Value,
+    status: Option<u16>,
+    response: Result<&Value, &str>,
+) -> Result<(), String> {
+    let payload = solution.to_string();
+    let mut entry = Entry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0),
+        challenge: challenge.to_string(),
+        payload_sha256: hashing::sha256(payload.as_bytes()).hex(),
+        payload: payload.chars().take(PAYLOAD_LIMIT).collect(),
+        status,
+        response: response.ok().cloned(),
+        error: response.err().map(|e| e.to_string()),
+        prev: String::new(),
+        hash: String::new(),
+    };
+
+    let path = DataDir::root().file(AUDIT_FILE)?;
+    let error = |e: std::io::Error| format!("Failed to write {}: {}", path.display(), e);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(&path)
+        .map_err(error)?;
+    // runs in parallel (solve-all) must not chain onto the same line
+    file.lock().map_err(error)?;
+    let mut text = String::new();
+    file.read_to_string(&mut text).map_err(error)?;
+    entry.prev = text
+        .lines()
+        .last()
+        .and_then(|line| serde_json::from_str::<Entry>(line).ok())
+        .map(|last| last.hash)
+        .unwrap_or_else(|| GENESIS.to_string());
+    entry.hash = entry.compute_hash();
+
+    let line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+    writeln!(file, "{}", line).map_err(error)
+}
+
+/// Every entry, oldest first
+pub fn load() -> Result<Vec<Entry>, String> {
+    let path = path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("Line {} of {} is unreadable: {}", i + 1, path.display(), e))
+        })
+        .collect()
+}
+
+/// Checks every entry's hash and link to the one before, the error names the first that fails
+pub fn verify(entries: &[Entry]) -> Result<(), String> {
+    let mut prev = GENESIS;
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.prev != prev {
+            return Err(format!(
+                "Line {} doesn't follow line {}, a line was removed or reordered",
+                i + 1,
+                i
+            ));
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(format!("Line {} was modified after it was written", i + 1));
+        }
+        prev = &entry.hash;
+    }
+    Ok(())
+}
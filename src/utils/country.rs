@@ -0,0 +1,249 @@
+use nationify::Country;
+use tracing::info;
+
+/// Spellings seen in (or expected from) hackattic problems that don't match an ISO-3166 name,
+/// mapped to the ISO code hackattic accepts for them
+const ALIASES: &[(&str, &str)] = &[
+    // "Islands" variants
+    ("Tokelau Islands", "TK"),
+    ("Cocos Island", "CC"),
+    ("Cocos Islands", "CC"),
+    ("Keeling Islands", "CC"),
+    ("Falkland Islands", "FK"),
+    ("Pitcairn Islands", "PN"),
+    ("Wallis and Futuna Islands", "WF"),
+    ("Heard Island", "HM"),
+    ("McDonald Islands", "HM"),
+    ("British Virgin Islands", "VG"),
+    ("US Virgin Islands", "VI"),
+    ("U.S. Virgin Islands", "VI"),
+    ("Aland Islands", "AX"),
+    ("Svalbard", "SJ"),
+    ("Jan Mayen", "SJ"),
+    // hackattic expects the French part for this one
+    ("Sint Maarten", "MF"),
+    ("Saint Martin", "MF"),
+    // common names
+    ("United Kingdom", "GB"),
+    ("UK", "GB"),
+    ("Great Britain", "GB"),
+    ("England", "GB"),
+    ("USA", "US"),
+    ("United States of America", "US"),
+    ("Russia", "RU"),
+    ("South Korea", "KR"),
+    ("North Korea", "KP"),
+    ("Vietnam", "VN"),
+    ("Laos", "LA"),
+    ("Syria", "SY"),
+    ("Iran", "IR"),
+    ("Bolivia", "BO"),
+    ("Venezuela", "VE"),
+    ("Tanzania", "TZ"),
+    ("Moldova", "MD"),
+    ("Macedonia", "MK"),
+    ("Czech Republic", "CZ"),
+    ("Turkey", "TR"),
+    ("Ivory Coast", "CI"),
+    ("Cape Verde", "CV"),
+    ("East Timor", "TL"),
+    ("Vatican", "VA"),
+    ("Vatican City", "VA"),
+    ("Palestine", "PS"),
+    ("Taiwan", "TW"),
+    ("Brunei", "BN"),
+    ("Micronesia", "FM"),
+    ("DR Congo", "CD"),
+    ("Democratic Republic of the Congo", "CD"),
+    ("Congo-Kinshasa", "CD"),
+    ("Republic of the Congo", "CG"),
+    ("Congo-Brazzaville", "CG"),
+    ("Holland", "NL"),
+    ("Macau", "MO"),
+    ("Saint Helena", "SH"),
+    ("Bonaire", "BQ"),
+    ("Caribbean Netherlands", "BQ"),
+    // historical names
+    ("Swaziland", "SZ"),
+    ("Burma", "MM"),
+    ("Ceylon", "LK"),
+    ("Persia", "IR"),
+    ("Siam", "TH"),
+    ("Zaire", "CD"),
+    ("Rhodesia", "ZW"),
+    ("Dahomey", "BJ"),
+    ("Upper Volta", "BF"),
+    ("Kampuchea", "KH"),
+];
+
+/// Other ISO codes a territory is sometimes filed under (split or dependent territories),
+/// tried when a certificate with the primary code gets rejected
+const ALTERNATE_CODES: &[(&str, &[&str])] = &[
+    ("MF", &["SX", "FR"]),
+    ("SX", &["MF", "NL"]),
+    ("BQ", &["NL"]),
+    ("CW", &["NL"]),
+    ("TK", &["NZ"]),
+    ("CC", &["AU"]),
+    ("CX", &["AU"]),
+    ("HM", &["AU"]),
+    ("NF", &["AU"]),
+    ("SJ", &["NO"]),
+    ("BV", &["NO"]),
+    ("AX", &["FI"]),
+    ("PR", &["US"]),
+    ("VI", &["US"]),
+    ("UM", &["US"]),
+    ("GU", &["US"]),
+    ("GF", &["FR"]),
+    ("RE", &["FR"]),
+    ("YT", &["FR"]),
+    ("PM", &["FR"]),
+    ("WF", &["FR"]),
+    ("SH", &["GB"]),
+    ("FK", &["GB"]),
+    ("PN", &["GB"]),
+    ("VG", &["GB"]),
+];
+
+// Largest edit distance still accepted as a typo of a country name
+const MAX_FUZZY_DISTANCE: usize = 2;
+
+/// Resolves a country name as written in a problem to its ISO-3166 entry.
+/// Tries exact names/codes, the alias table, unofficial names, "Islands" variants and
+/// finally fuzzy matching. On failure the error lists the nearest known names
+pub fn resolve(name: &str) -> Result<&'static Country, String> {
+    if let Some(country) = nationify::by_country_name_or_code_case_insensitive(name.trim()) {
+        return Ok(country);
+    }
+
+    let normalized = normalize(name);
+    if let Some((_, code)) = ALIASES
+        .iter()
+        .find(|(alias, _)| normalize(alias) == normalized)
+    {
+        return Ok(nationify::by_iso_code(code).expect("alias table has an unknown ISO code"));
+    }
+
+    let countries = all_countries();
+    if let Some(country) = countries
+        .iter()
+        .find(|country| names(country).any(|n| normalize(n) == normalized))
+    {
+        return Ok(country);
+    }
+
+    let without_islands = strip_islands(&normalized);
+    if let Some(country) = countries
+        .iter()
+        .find(|country| names(country).any(|n| strip_islands(&normalize(n)) == without_islands))
+    {
+        return Ok(country);
+    }
+
+    // nearest known name for every country, closest first
+    let mut candidates: Vec<(usize, &'static Country, &'static str)> = countries
+        .iter()
+        .filter_map(|country| {
+            names(country)
+                .map(|n| (levenshtein(&normalize(n), &normalized), *country, n))
+                .min_by_key(|(distance, _, _)| *distance)
+        })
+        .collect();
+    candidates.sort_by_key(|(distance, _, _)| *distance);
+
+    match candidates.as_slice() {
+        [(best, country, matched), rest @ ..]
+            if *best <= MAX_FUZZY_DISTANCE && rest.first().is_none_or(|(d, _, _)| d > best) =>
+        {
+            info!(
+                "Country {:?} fuzzy matched to {:?} ({})",
+                name, matched, country.iso_code
+            );
+            Ok(country)
+        }
+        _ => {
+            let near_matches: Vec<String> = candidates
+                .iter()
+                .take(5)
+                .map(|(_, country, matched)| format!("{} ({})", matched, country.iso_code))
+                .collect();
+            Err(format!(
+                "Unknown country {:?}, near matches: {}",
+                name,
+                near_matches.join(", ")
+            ))
+        }
+    }
+}
+
+/// Alternative codes for edge-case territories, empty for ordinary countries
+pub fn alternate_codes(iso_code: &str) -> Vec<&'static str> {
+    ALTERNATE_CODES
+        .iter()
+        .find(|(code, _)| *code == iso_code)
+        .map(|(_, alternates)| alternates.to_vec())
+        .unwrap_or_default()
+}
+
+fn all_countries() -> Vec<&'static Country> {
+    nationify::iso_codes()
+        .into_iter()
+        .filter_map(nationify::by_iso_code)
+        .collect()
+}
+
+// Every name a country is known by
+fn names(country: &'static Country) -> impl Iterator<Item = &'static str> {
+    [country.iso_short_name, country.iso_long_name]
+        .into_iter()
+        .chain(country.unofficial_names.iter().copied())
+}
+
+// Lowercase ASCII words only: accents folded, punctuation and leading "the" dropped
+fn normalize(name: &str) -> String {
+    let folded: String = name
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ä' | 'å' | 'Å' | 'Á' => 'a',
+            'é' | 'è' | 'ê' | 'ë' | 'É' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'ö' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ç' => 'c',
+            'ñ' => 'n',
+            c if c.is_alphanumeric() => c.to_ascii_lowercase(),
+            _ => ' ',
+        })
+        .collect();
+
+    let words: Vec<&str> = folded.split_whitespace().collect();
+    match words.as_slice() {
+        ["the", rest @ ..] => rest.join(" "),
+        _ => words.join(" "),
+    }
+}
+
+fn strip_islands(normalized: &str) -> String {
+    normalized
+        .split(' ')
+        .filter(|word| *word != "islands" && *word != "island")
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::utils::args;
+
+// Everything the solvers write lives under two roots with a subdirectory per challenge: the data
+// directory for what's worth keeping (state, history, inspection artifacts) and the cache
+// directory for what can be downloaded or computed again (models, problem images, OCR results,
+// collisions). Directories are created when a file is first asked for.
+//
+// --data-dir (or HACKATTIC_DATA_DIR) puts both in one directory, --cache-dir (or
+// HACKATTIC_CACHE_DIR) moves the cache on its own. Otherwise they follow the platform's
+// conventions: $XDG_DATA_HOME/hackattic and $XDG_CACHE_HOME/hackattic (~/.local/share and
+// ~/.cache) on Linux, ~/Library/Application Support and ~/Library/Caches on macOS,
+// %APPDATA% and %LOCALAPPDATA% on Windows, and ./data when no home directory is known.
+
+const APP_NAME: &str = "hackattic";
+const FALLBACK_ROOT: &str = "./data";
+
+fn env_path(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+fn home() -> Option<PathBuf> {
+    env_path("HOME").or_else(|| env_path("USERPROFILE"))
+}
+
+/// The platform's per-user data directory
+fn platform_data_home() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env_path("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Application Support"))
+    } else {
+        env_path("XDG_DATA_HOME").or_else(|| home().map(|home| home.join(".local/share")))
+    }
+}
+
+/// The platform's per-user cache directory
+fn platform_cache_home() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env_path("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library/Caches"))
+    } else {
+        env_path("XDG_CACHE_HOME").or_else(|| home().map(|home| home.join(".cache")))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    path: PathBuf,
+}
+
+impl DataDir {
+    /// The data directory itself, for files shared between challenges
+    pub fn root() -> Self {
+        let path = args::get("--data-dir", "HACKATTIC_DATA_DIR")
+            .map(PathBuf::from)
+            .or_else(|| platform_data_home().map(|dir| dir.join(APP_NAME)))
+            .unwrap_or_else(|| PathBuf::from(FALLBACK_ROOT));
+        Self { path }
+    }
+
+    /// The cache directory itself
+    pub fn cache_root() -> Self {
+        let path = args::get("--cache-dir", "HACKATTIC_CACHE_DIR")
+            .map(PathBuf::from)
+            .or_else(|| args::get("--data-dir", "HACKATTIC_DATA_DIR").map(PathBuf::from))
+            .or_else(|| platform_cache_home().map(|dir| dir.join(APP_NAME)))
+            .unwrap_or_else(|| PathBuf::from(FALLBACK_ROOT));
+        Self { path }
+    }
+
+    /// `<data dir>/<challenge>`
+    pub fn challenge(name: &str) -> Self {
+        Self::root().join(name)
+    }
+
+    /// `<cache dir>/<challenge>`
+    pub fn cache(name: &str) -> Self {
+        Self::cache_root().join(name)
+    }
+
+    /// A subdirectory
+    pub fn join(&self, name: &str) -> Self {
+        Self {
+            path: self.path.join(name),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates the directory if needed
+    pub fn create(&self) -> Result<&Path, String> {
+        fs::create_dir_all(&self.path)
+            .map_err(|e| format!("Failed to create {}: {}", self.path.display(), e))?;
+        Ok(&self.path)
+    }
+
+    /// The path of a file in the directory, which is created if needed
+    pub fn file(&self, name: &str) -> Result<PathBuf, String> {
+        self.create().map(|dir| dir.join(name))
+    }
+
+    /// Removes the directory and everything in it, returns whether there was anything to remove
+    pub fn clean(&self) -> Result<bool, String> {
+        match fs::remove_dir_all(&self.path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Failed to remove {}: {}", self.path.display(), e)),
+        }
+    }
+}
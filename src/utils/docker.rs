@@ -0,0 +1,69 @@
+use std::io::ErrorKind;
+use std::process::{Command, Output, Stdio};
+use tracing::info;
+
+/// Turns docker's stderr into an error that says what to do about it
+pub fn explain_error(stderr: &str, image: &str) -> String {
+    let lower = stderr.to_lowercase();
+    if lower.contains("permission denied") {
+        "Permission denied on the docker socket: add your user to the docker group \
+         (sudo usermod -aG docker $USER, then log in again) or run as root"
+            .to_string()
+    } else if lower.contains("cannot connect to the docker daemon")
+        || lower.contains("is the docker daemon running")
+        || lower.contains("error during connect")
+    {
+        "The docker daemon is not running: start it (e.g. sudo systemctl start docker, or open \
+         Docker Desktop)"
+            .to_string()
+    } else if lower.contains("unable to find image")
+        || lower.contains("pull access denied")
+        || lower.contains("manifest unknown")
+        || lower.contains("no such image")
+    {
+        format!(
+            "The {} image is missing and couldn't be pulled: run docker pull {} and check your \
+             network and registry login",
+            image, image
+        )
+    } else {
+        format!("docker failed: {}", stderr.trim())
+    }
+}
+
+pub fn run(args: &[&str]) -> Result<Output, String> {
+    Command::new("docker").args(args).output().map_err(|e| {
+        if e.kind() == ErrorKind::NotFound {
+            "docker is not installed (or not on PATH)".to_string()
+        } else {
+            format!("Failed to run docker: {}", e)
+        }
+    })
+}
+
+/// Checks that the docker daemon is reachable and pulls the image when it's missing
+pub fn preflight(image: &str) -> Result<(), String> {
+    let info = run(&["info", "--format", "{{.ServerVersion}}"])?;
+    if !info.status.success() {
+        return Err(explain_error(&String::from_utf8_lossy(&info.stderr), image));
+    }
+
+    let inspect = run(&["image", "inspect", "--format", "{{.Id}}", image])?;
+    if inspect.status.success() {
+        return Ok(());
+    }
+
+    // stdout is inherited so the pull progress shows up live
+    info!("{} not found locally, pulling it", image);
+    let pull = Command::new("docker")
+        .args(["pull", image])
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|child| child.wait_with_output())
+        .map_err(|e| format!("Failed to run docker pull: {}", e))?;
+    if !pull.status.success() {
+        return Err(explain_error(&String::from_utf8_lossy(&pull.stderr), image));
+    }
+    Ok(())
+}
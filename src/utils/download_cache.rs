@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::utils::data_dir::DataDir;
+use crate::utils::hashing;
+
+// Files the challenges download (ZIPs, images, dumps) are kept under <cache>/downloads/ by the
+// SHA-256 of their content, with index.json mapping each URL to its hash:
+//
+//   <sha256>     the file, stored once however many URLs served it
+//   index.json   {"<url>": "<sha256>"}
+//
+// A URL fetched again is read back from the cache, as long as the file still hashes to what
+// the index says. `--no-cache` (or NO_CACHE) downloads again and refreshes the cached copy.
+
+const INDEX_FILE: &str = "index.json";
+
+fn dir() -> DataDir {
+    DataDir::cache_root().join("downloads")
+}
+
+fn read_index() -> BTreeMap<String, String> {
+    fs::read_to_string(dir().path().join(INDEX_FILE))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// Whether downloads may be served from the cache
+pub fn enabled() -> bool {
+    !crate::utils::args::is_set("--no-cache", "NO_CACHE")
+}
+
+/// The cached content of `url`, unless it's missing or no longer matches its hash
+pub fn get(url: &str) -> Option<Vec<u8>> {
+    let hash = read_index().remove(url)?;
+    let path = dir().path().join(&hash);
+    let bytes = fs::read(&path).ok()?;
+    if hashing::sha256(&bytes).hex() != hash {
+        return None;
+    }
+    // marks it used for the cache quota, access times are often not kept
+    let _ = fs::File::options()
+        .append(true)
+        .open(&path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    Some(bytes)
+}
+
+/// Stores the content downloaded from `url` and points the index at it, returns its path
+pub fn put(url: &str, bytes: &[u8]) -> Result<PathBuf, String> {
+    let hash = hashing::sha256(bytes).hex();
+    let path = dir().file(&hash)?;
+    if !path.exists() {
+        write_atomically(&path, bytes)?;
+    }
+
+    let mut index = read_index();
+    if index.get(url) != Some(&hash) {
+        index.insert(url.to_string(), hash);
+        let text = serde_json::to_string_pretty(&index).map_err(|e| e.to_string())?;
+        write_atomically(&dir().file(INDEX_FILE)?, text.as_bytes())?;
+    }
+    Ok(path)
+}
+
+// through a temporary file, so parallel runs never read a half-written file
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    fs::write(&tmp, bytes)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
@@ -0,0 +1,92 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+use crate::utils::args;
+use crate::utils::history::Run;
+
+// A JSON line per event of a challenge run, for dashboards and scripts following runs as they
+// happen: --events-file <path> (or EVENTS_FILE) appends to a file, `-` writes to stdout,
+// `--events -` being the short form. Every line has the time, the event and the challenge:
+//
+//   run_started          the run began
+//   progress             attempts so far, rates and the expected count, every couple of seconds
+//   stage_completed      a stage (fetch_problem, download, solve, solve/mine, submit...) ended
+//   solution_submitted   hackattic's response to a solution and whether it was accepted
+//   result               the run's outcome, duration and error
+//
+// Lines are written whole and flushed right away, so several runs can share a file. Events are
+// also emitted from the logger, nothing in here logs through tracing.
+
+struct Sink {
+    challenge: String,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+/// Opens the event stream of `challenge`'s run when one is asked for
+pub fn init(challenge: &str) -> Result<(), String> {
+    let Some(path) =
+        args::get("--events-file", "EVENTS_FILE").or_else(|| args::get("--events", "EVENTS"))
+    else {
+        return Ok(());
+    };
+    let out: Box<dyn Write + Send> = if path == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| format!("Failed to open the events file {}: {}", path, e))?,
+        )
+    };
+    let _ = SINK.set(Sink {
+        challenge: challenge.to_string(),
+        out: Mutex::new(out),
+    });
+    emit("run_started", json!({}));
+    Ok(())
+}
+
+/// Writes one event, `fields` being an object merged into the line
+pub fn emit(event: &str, fields: Value) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+    let mut line = json!({
+        "timestamp_ms": timestamp,
+        "event": event,
+        "challenge": sink.challenge,
+    });
+    if let (Some(line), Value::Object(fields)) = (line.as_object_mut(), fields) {
+        line.extend(fields);
+    }
+    let mut out = sink.out.lock().unwrap();
+    // one write per line, appends from other processes don't cut into it
+    let _ = out
+        .write_all(format!("{}\n", line).as_bytes())
+        .and_then(|_| out.flush());
+}
+
+/// The run's outcome, once it's recorded in the history
+pub fn result(run: &Run) {
+    emit(
+        "result",
+        json!({
+            "outcome": run.outcome,
+            "duration_ms": run.duration_ms,
+            "attempts": run.attempts,
+            "error": run.error,
+        }),
+    );
+}
@@ -1,93 +1,313 @@
 use std::env;
+use tracing::{Instrument, info, info_span, warn};
+
+use crate::utils::archive;
+use crate::utils::audit;
+use crate::utils::download_cache;
+use crate::utils::events;
+use crate::utils::history;
+use crate::utils::notify::{self, Event};
 
 const BASE_URL: &str = "https://hackattic.com/challenges";
 
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("ACCESS_TOKEN must be set in environment or .env file")]
+    MissingToken,
+    #[error("Failed to {action}")]
+    Http {
+        action: &'static str,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("{0}")]
+    Replay(String),
+}
+
+/// Attaches what the client was doing to a reqwest error, without the URL: it holds the access
+/// token, and the error ends up in the logs and the audit log
+fn http(action: &'static str) -> impl FnOnce(reqwest::Error) -> ClientError {
+    move |source| ClientError::Http {
+        action,
+        source: source.without_url(),
+    }
+}
+
 pub struct HackatticClient {
+    base_url: String,
     challenge_name: String,
     access_token: String,
 }
 
 impl HackatticClient {
-    pub fn new(challenge_name: &str) -> Self {
+    pub fn new(challenge_name: &str) -> Result<Self, ClientError> {
         // Load environment variables from .env file
         dotenv::dotenv().ok();
 
-        let access_token =
-            env::var("ACCESS_TOKEN").expect("ACCESS_TOKEN must be set in environment or .env file");
+        // a replay never reaches hackattic
+        let access_token = match env::var("ACCESS_TOKEN") {
+            Ok(token) => token,
+            Err(_) if archive::replaying().is_some() => String::new(),
+            Err(_) => return Err(ClientError::MissingToken),
+        };
 
-        Self {
+        // HACKATTIC_URL points the solvers at another server, e.g. the mock server
+        let base_url = env::var("HACKATTIC_URL")
+            .map(|url| format!("{}/challenges", url.trim_end_matches('/')))
+            .unwrap_or_else(|_| BASE_URL.to_string());
+
+        Ok(Self {
+            base_url,
             challenge_name: challenge_name.to_string(),
             access_token,
-        }
+        })
     }
 
-    pub fn get_problem(&self) -> serde_json::Value {
+    pub fn get_problem(&self) -> Result<serde_json::Value, ClientError> {
+        let _span = info_span!("fetch_problem", challenge = %self.challenge_name).entered();
+        if let Some(entry) = archive::replaying() {
+            return Self::replay_problem(&entry);
+        }
         let url = format!(
             "{}/{}/problem?access_token={}",
-            BASE_URL, self.challenge_name, self.access_token
+            self.base_url, self.challenge_name, self.access_token
         );
 
-        reqwest::blocking::get(&url)
-            .expect("Failed to fetch problem")
+        let problem = reqwest::blocking::get(&url)
+            .and_then(|resp| resp.error_for_status())
+            .map_err(http("fetch problem"))?
             .json::<serde_json::Value>()
-            .expect("Failed to parse JSON")
+            .map_err(http("parse problem JSON"))?;
+        self.archive(archive::record_problem(&self.challenge_name, &problem));
+        Ok(problem)
     }
 
-    pub async fn get_problem_async(&self) -> serde_json::Value {
+    pub async fn get_problem_async(&self) -> Result<serde_json::Value, ClientError> {
+        if let Some(entry) = archive::replaying() {
+            return Self::replay_problem(&entry);
+        }
         let url = format!(
             "{}/{}/problem?access_token={}",
-            BASE_URL, self.challenge_name, self.access_token
+            self.base_url, self.challenge_name, self.access_token
         );
 
-        reqwest::get(&url)
-            .await
-            .expect("Failed to fetch problem")
-            .json::<serde_json::Value>()
-            .await
-            .expect("Failed to parse JSON")
+        let problem = async {
+            reqwest::get(&url)
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(http("fetch problem"))?
+                .json::<serde_json::Value>()
+                .await
+                .map_err(http("parse problem JSON"))
+        }
+        .instrument(info_span!("fetch_problem", challenge = %self.challenge_name))
+        .await?;
+        self.archive(archive::record_problem(&self.challenge_name, &problem));
+        Ok(problem)
     }
 
-    pub fn submit_solution(&self, solution: serde_json::Value) {
+    /// Submit the solution and return the parsed response
+    pub fn submit_solution(
+        &self,
+        solution: serde_json::Value,
+    ) -> Result<serde_json::Value, ClientError> {
+        let _span = info_span!("submit", challenge = %self.challenge_name).entered();
+        if let Some(entry) = archive::replaying() {
+            return Self::replay_submission(&entry, &solution);
+        }
         let url = format!(
             "{}/{}/solve?access_token={}",
-            BASE_URL, self.challenge_name, self.access_token
+            self.base_url, self.challenge_name, self.access_token
         );
 
-        let resp = reqwest::blocking::Client::new()
+        let sent = reqwest::blocking::Client::new()
             .post(&url)
             .json(&solution)
             .send()
-            .expect("Failed to send POST");
-
-        let status = resp.status();
-        let text = resp.text().expect("Failed to read response body");
-        println!("Status: {}", status);
-        println!("Response: {}", text);
+            .map_err(http("send solution"))
+            .and_then(|resp| {
+                let status = resp.status();
+                let text = resp.text().map_err(http("read response body"))?;
+                Ok((status, text))
+            });
+        let (status, text) = self.audit_failure(&solution, sent)?;
+        Ok(self.report(&solution, status, text))
     }
 
-    pub async fn submit_solution_async(&self, solution: serde_json::Value) {
+    /// Submit the solution and return the parsed response
+    pub async fn submit_solution_async(
+        &self,
+        solution: serde_json::Value,
+    ) -> Result<serde_json::Value, ClientError> {
+        if let Some(entry) = archive::replaying() {
+            return Self::replay_submission(&entry, &solution);
+        }
         let url = format!(
             "{}/{}/solve?access_token={}",
-            BASE_URL, self.challenge_name, self.access_token
+            self.base_url, self.challenge_name, self.access_token
         );
-        let resp = reqwest::Client::new()
-            .post(&url)
-            .json(&solution)
-            .send()
-            .await
-            .expect("Failed to send POST");
-        let status = resp.status();
-        let text = resp.text().await.expect("Failed to read response body");
-        println!("Status: {}", status);
-        println!("Response: {}", text);
+        let sent = async {
+            let resp = reqwest::Client::new()
+                .post(&url)
+                .json(&solution)
+                .send()
+                .await
+                .map_err(http("send solution"))?;
+            let status = resp.status();
+            let text = resp.text().await.map_err(http("read response body"))?;
+            Ok::<_, ClientError>((status, text))
+        }
+        .instrument(info_span!("submit", challenge = %self.challenge_name))
+        .await;
+        let (status, text) = self.audit_failure(&solution, sent)?;
+        Ok(self.report(&solution, status, text))
     }
 
-    /// Download a file from a URL
-    pub fn download_file(&self, url: &str) -> Vec<u8> {
-        reqwest::blocking::get(url)
-            .expect("Failed to download file")
+    /// Logs the response to a submission, records it in the run history, the audit log and the
+    /// archive and notifies
+    fn report(
+        &self,
+        solution: &serde_json::Value,
+        status: reqwest::StatusCode,
+        text: String,
+    ) -> serde_json::Value {
+        info!("Status: {}", status);
+        let response =
+            serde_json::from_str(&text).unwrap_or_else(|_| serde_json::Value::String(text.clone()));
+        let accepted = Self::is_accepted(&response);
+        events::emit(
+            "solution_submitted",
+            serde_json::json!({
+                "status": status.as_u16(),
+                "accepted": accepted,
+                "response": response,
+            }),
+        );
+        if accepted {
+            info!(success = true, "Response: {}", text);
+            notify::notify(Event::Accepted, &self.challenge_name, &text);
+        } else {
+            warn!("Response: {}", text);
+            notify::notify(Event::Rejected, &self.challenge_name, &text);
+        }
+        history::record_submission(&response);
+        self.audit(audit::record(
+            &self.challenge_name,
+            solution,
+            Some(status.as_u16()),
+            Ok(&response),
+        ));
+        self.archive(archive::record_submission(
+            &self.challenge_name,
+            solution,
+            status.as_u16(),
+            &response,
+        ));
+        response
+    }
+
+    /// Records a submission that got no response in the audit log
+    fn audit_failure<T>(
+        &self,
+        solution: &serde_json::Value,
+        sent: Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        if let Err(e) = &sent {
+            let error = match std::error::Error::source(e) {
+                Some(source) => format!("{}: {}", e, source),
+                None => e.to_string(),
+            };
+            self.audit(audit::record(
+                &self.challenge_name,
+                solution,
+                None,
+                Err(&error),
+            ));
+        }
+        sent
+    }
+
+    /// A failure to write the audit log is logged, it never fails the run
+    fn audit(&self, result: Result<(), String>) {
+        if let Err(e) = result {
+            warn!("{}", e);
+        }
+    }
+
+    /// A failure to archive is logged, it never fails the run
+    fn archive(&self, result: Result<(), String>) {
+        if let Err(e) = result {
+            warn!("{}", e);
+        }
+    }
+
+    fn replay_problem(entry: &archive::Entry) -> Result<serde_json::Value, ClientError> {
+        info!(
+            "Replaying the problem archived in {}",
+            entry.dir().display()
+        );
+        entry.problem().map_err(ClientError::Replay)
+    }
+
+    /// Compares the solution with the one archived instead of submitting it, and answers with
+    /// the archived response when they're the same
+    fn replay_submission(
+        entry: &archive::Entry,
+        solution: &serde_json::Value,
+    ) -> Result<serde_json::Value, ClientError> {
+        let archived = entry.last_submission().map_err(ClientError::Replay)?;
+        let Some(archived) = archived else {
+            info!("Solution: {}", solution);
+            info!("Nothing was submitted for this problem, no solution to compare with");
+            return Ok(serde_json::json!({ "replayed": true }));
+        };
+        if &archived["solution"] == solution {
+            info!(success = true, "Same solution as the one archived");
+            info!("Archived response: {}", archived["response"]);
+            Ok(archived["response"].clone())
+        } else {
+            warn!("The solution differs from the one archived");
+            info!("Solution: {}", solution);
+            info!("Archived: {}", archived["solution"]);
+            Ok(serde_json::json!({ "replayed": true, "rejected": "differs from the archive" }))
+        }
+    }
+
+    /// Whether hackattic accepted a submission, rejected ones carry a "rejected" or "error" field
+    pub fn is_accepted(response: &serde_json::Value) -> bool {
+        response.is_object()
+            && response.get("rejected").is_none()
+            && response.get("error").is_none()
+    }
+
+    /// Download a file from a URL, or read it from the download cache
+    pub fn download_file(&self, url: &str) -> Result<Vec<u8>, ClientError> {
+        let _span = info_span!("download", challenge = %self.challenge_name).entered();
+        if let Some(entry) = archive::replaying() {
+            return entry.file(url).map_err(ClientError::Replay);
+        }
+        if download_cache::enabled()
+            && let Some(bytes) = download_cache::get(url)
+        {
+            info!(
+                "Using the cached download of {} ({} bytes)",
+                url,
+                bytes.len()
+            );
+            self.archive(archive::record_file(&self.challenge_name, url, &bytes));
+            return Ok(bytes);
+        }
+        let bytes = reqwest::blocking::get(url)
+            .and_then(|resp| resp.error_for_status())
+            .map_err(http("download file"))?
             .bytes()
-            .expect("Failed to read file bytes")
-            .to_vec()
+            .map_err(http("read file bytes"))?
+            .to_vec();
+        // a download that can't be cached is just downloaded again next time
+        if let Err(e) = download_cache::put(url, &bytes) {
+            warn!("{}", e);
+        }
+        self.archive(archive::record_file(&self.challenge_name, url, &bytes));
+        Ok(bytes)
     }
 }
@@ -0,0 +1,248 @@
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+/// PBKDF2-HMAC parameters
+#[derive(Debug, Clone, Copy)]
+pub struct Pbkdf2Params {
+    pub rounds: u32,
+    /// Output length in bytes
+    pub len: usize,
+}
+
+/// scrypt parameters, the cost as log2(N)
+#[derive(Debug, Clone, Copy)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// Output length in bytes
+    pub len: usize,
+}
+
+impl ScryptParams {
+    /// From the cost N itself, which has to be a power of two
+    pub fn from_n(n: u64, r: u32, p: u32, len: usize) -> Result<Self, String> {
+        if !n.is_power_of_two() {
+            return Err(format!("scrypt N must be a power of two, got {}", n));
+        }
+        Ok(Self {
+            log_n: n.trailing_zeros() as u8,
+            r,
+            p,
+            len,
+        })
+    }
+}
+
+/// Raw digest or derived key bytes, with the encodings challenges submit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashOutput(pub Vec<u8>);
+
+impl HashOutput {
+    pub fn hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+
+    #[allow(dead_code)]
+    pub fn base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(&self.0)
+    }
+}
+
+pub fn sha256(data: &[u8]) -> HashOutput {
+    HashOutput(Sha256::digest(data).to_vec())
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> HashOutput {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    HashOutput(mac.finalize().into_bytes().to_vec())
+}
+
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], params: &Pbkdf2Params) -> HashOutput {
+    let mut output = vec![0u8; params.len];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, params.rounds, &mut output);
+    HashOutput(output)
+}
+
+pub fn sha512(data: &[u8]) -> HashOutput {
+    HashOutput(Sha512::digest(data).to_vec())
+}
+
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> HashOutput {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data);
+    HashOutput(mac.finalize().into_bytes().to_vec())
+}
+
+pub fn pbkdf2_sha512(password: &[u8], salt: &[u8], params: &Pbkdf2Params) -> HashOutput {
+    let mut output = vec![0u8; params.len];
+    pbkdf2::pbkdf2_hmac::<Sha512>(password, salt, params.rounds, &mut output);
+    HashOutput(output)
+}
+
+pub fn scrypt(password: &[u8], salt: &[u8], params: &ScryptParams) -> Result<HashOutput, String> {
+    let scrypt_params = scrypt::Params::new(params.log_n, params.r, params.p, params.len)
+        .map_err(|e| format!("Invalid scrypt params: {}", e))?;
+    let mut output = vec![0u8; params.len];
+    scrypt::scrypt(password, salt, &scrypt_params, &mut output)
+        .map_err(|e| format!("scrypt failed: {}", e))?;
+    Ok(HashOutput(output))
+}
+
+/// Checks every function against published test vectors (FIPS 180-2, RFC 4231, RFC 7914),
+/// cheap enough to run before spending minutes on the real parameters
+pub fn check_test_vectors() -> Result<(), String> {
+    let checks = [
+        (
+            "SHA-256",
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            "HMAC-SHA256",
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        ),
+        (
+            "SHA-512",
+            sha512(b"abc"),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        ),
+        (
+            "HMAC-SHA512",
+            hmac_sha512(b"Jefe", b"what do ya want for nothing?"),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+        ),
+        (
+            "PBKDF2-SHA256",
+            pbkdf2_sha256(b"password", b"salt", &Pbkdf2Params { rounds: 1, len: 32 }),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+        ),
+        (
+            "PBKDF2-SHA512",
+            pbkdf2_sha512(b"password", b"salt", &Pbkdf2Params { rounds: 1, len: 64 }),
+            "867f70cf1ade02cff3752599a3a53dc4af34c7a669815ae5d513554e1c8cf252\
+             c02d470a285a0501bad999bfe943c08f050235d7d68b1da55e63f73b60a57fce",
+        ),
+        (
+            "scrypt",
+            scrypt(
+                b"password",
+                b"NaCl",
+                &ScryptParams {
+                    log_n: 10,
+                    r: 8,
+                    p: 16,
+                    len: 64,
+                },
+            )?,
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+             2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640",
+        ),
+    ];
+
+    for (name, output, expected) in checks {
+        if output.hex() != expected {
+            return Err(format!(
+                "{} test vector mismatch: got {}, expected {}",
+                name,
+                output.hex(),
+                expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_its_own_checks() {
+        assert_eq!(check_test_vectors(), Ok(()));
+    }
+
+    #[test]
+    fn sha256_fips_180_2() {
+        assert_eq!(
+            sha256(b"").hex(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").hex(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn hmac_rfc_4231() {
+        // test case 1
+        assert_eq!(
+            hmac_sha256(&[0x0b; 20], b"Hi There").hex(),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hmac_sha512(&[0x0b; 20], b"Hi There").hex(),
+            "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cde\
+             daa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854"
+        );
+        // test case 6, a key longer than the block size
+        assert_eq!(
+            hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )
+            .hex(),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn pbkdf2_sha256_rfc_7914() {
+        assert_eq!(
+            pbkdf2_sha256(b"passwd", b"salt", &Pbkdf2Params { rounds: 1, len: 64 }).hex(),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+        assert_eq!(
+            pbkdf2_sha256(
+                b"password",
+                b"salt",
+                &Pbkdf2Params {
+                    rounds: 4096,
+                    len: 32
+                }
+            )
+            .hex(),
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+    }
+
+    #[test]
+    fn scrypt_rfc_7914() {
+        let params = ScryptParams::from_n(16, 1, 1, 64).unwrap();
+        assert_eq!(
+            scrypt(b"", b"", &params).unwrap().hex(),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+    }
+
+    #[test]
+    fn scrypt_n_is_a_power_of_two() {
+        assert_eq!(ScryptParams::from_n(1024, 8, 16, 64).unwrap().log_n, 10);
+        assert!(ScryptParams::from_n(1000, 8, 16, 64).is_err());
+    }
+
+    #[test]
+    fn encodes_output() {
+        let output = HashOutput(vec![0x00, 0xff, 0x10]);
+        assert_eq!(output.hex(), "00ff10");
+        assert_eq!(output.base64(), "AP8Q");
+    }
+}
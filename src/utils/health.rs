@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+
+use indexmap::IndexMap;
+use serde_json::json;
+use warp::{Filter, http::StatusCode, reply};
+
+// Health endpoints of the servers hosted during a challenge, for supervisors and the
+// reachability checks to probe. GET /healthz answers 200 as long as the server serves,
+// GET /readyz only once every check of the challenge passed (problem fetched, storage
+// writable, tunnel up...), 503 with the failing checks until then.
+
+/// What a server probes for liveness, and the reachability checks request
+pub const HEALTH_PATH: &str = "healthz";
+
+/// The readiness checks of a server, each pending until the challenge reports it
+#[derive(Clone)]
+pub struct Readiness {
+    checks: Arc<Mutex<IndexMap<&'static str, Result<(), String>>>>,
+}
+
+impl Readiness {
+    pub fn new(checks: &[&'static str]) -> Self {
+        let checks = checks
+            .iter()
+            .map(|check| (*check, Err("pending".to_string())))
+            .collect();
+        Self {
+            checks: Arc::new(Mutex::new(checks)),
+        }
+    }
+
+    /// Records the outcome of `check`, the reason it fails being shown by /readyz
+    pub fn set(&self, check: &'static str, result: Result<(), String>) {
+        self.checks.lock().unwrap().insert(check, result);
+    }
+
+    pub fn ready(&self, check: &'static str) {
+        self.set(check, Ok(()));
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.checks
+            .lock()
+            .unwrap()
+            .values()
+            .all(|result| result.is_ok())
+    }
+
+    fn report(&self) -> serde_json::Value {
+        let checks: serde_json::Map<String, serde_json::Value> = self
+            .checks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(check, result)| {
+                let status = match result {
+                    Ok(()) => "ok".to_string(),
+                    Err(reason) => reason.clone(),
+                };
+                (check.to_string(), json!(status))
+            })
+            .collect();
+        json!({ "ready": self.is_ready(), "checks": checks })
+    }
+}
+
+/// GET /healthz and GET /readyz, added to a server's routes
+pub fn routes(
+    readiness: Readiness,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let health = warp::path(HEALTH_PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| reply::json(&json!({ "status": "ok" })));
+    let ready = warp::path!("readyz").and(warp::get()).map(move || {
+        let status = if readiness.is_ready() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        reply::with_status(reply::json(&readiness.report()), status)
+    });
+    health.or(ready)
+}
+
+/// The health endpoint under a server's base URL, e.g. `https://abc.trycloudflare.com/healthz`
+pub fn url(base_url: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), HEALTH_PATH)
+}
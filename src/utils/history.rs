@@ -0,0 +1,118 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::data_dir::DataDir;
+
+// Every challenge run is appended as one JSON line to history.jsonl in the data directory,
+// `hackattic stats` summarizes it.
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub challenge: String,
+    /// Unix seconds
+    pub started_at: u64,
+    pub duration_ms: u64,
+    /// Solutions submitted during the run
+    pub attempts: u32,
+    /// passed, rejected, error, timeout or unsubmitted
+    pub outcome: String,
+    /// The last server response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Milliseconds spent in each stage, e.g. `fetch_problem`, `solve`, `solve/mine`, `submit`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub stages: IndexMap<String, u64>,
+}
+
+impl Run {
+    pub fn passed(&self) -> bool {
+        self.outcome == "passed"
+    }
+}
+
+/// Submissions of the current run: their count and the last response
+static SUBMISSIONS: Mutex<(u32, Option<Value>)> = Mutex::new((0, None));
+
+/// Called by the client for every response to a submitted solution
+pub fn record_submission(response: &Value) {
+    let mut submissions = SUBMISSIONS.lock().unwrap();
+    submissions.0 += 1;
+    submissions.1 = Some(response.clone());
+}
+
+/// Builds the run from the submissions seen so far and the challenge's result
+pub fn finish(
+    challenge: &str,
+    started_at: SystemTime,
+    duration: Duration,
+    error: Option<String>,
+) -> Run {
+    let (attempts, response) = SUBMISSIONS.lock().unwrap().clone();
+    let outcome = match (&error, &response) {
+        _ if crate::utils::watchdog::expired() => "timeout",
+        (Some(_), _) => "error",
+        (None, Some(response))
+            if crate::utils::hackattic_client::HackatticClient::is_accepted(response) =>
+        {
+            "passed"
+        }
+        (None, Some(_)) => "rejected",
+        (None, None) => "unsubmitted",
+    };
+    Run {
+        challenge: challenge.to_string(),
+        started_at: started_at
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0),
+        duration_ms: duration.as_millis() as u64,
+        attempts,
+        outcome: outcome.to_string(),
+        response,
+        error,
+        stages: crate::utils::timing::stages()
+            .into_iter()
+            .map(|stage| (stage.path, stage.total.as_millis() as u64))
+            .collect(),
+    }
+}
+
+pub fn path() -> PathBuf {
+    DataDir::root().path().join(HISTORY_FILE)
+}
+
+pub fn append(run: &Run) -> Result<(), String> {
+    let path = DataDir::root().file(HISTORY_FILE)?;
+    let line = serde_json::to_string(run).map_err(|e| format!("Failed to encode run: {}", e))?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// All recorded runs, oldest first. Unreadable lines (e.g. a run killed mid-write) are skipped
+pub fn load() -> Result<Vec<Run>, String> {
+    let path = path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
@@ -0,0 +1,143 @@
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::ServerConfig;
+use tracing::debug;
+use warp::{Filter, Rejection, Reply, http::StatusCode, reply};
+
+use crate::utils::args;
+use crate::utils::health::{self, Readiness};
+use crate::utils::{metrics, shutdown, tls};
+
+// What every server hosted during a challenge (the JWT server, the registry) shares: where it
+// listens (--bind/--port or its own variables), the /metrics, /healthz and /readyz routes,
+// request logging at debug level, headers on every response, capped request bodies, and
+// stopping on Ctrl+C or the watchdog.
+
+/// Bodies over a route's limit
+#[derive(Debug)]
+struct TooLarge {
+    limit: u64,
+}
+
+impl warp::reject::Reject for TooLarge {}
+
+/// The address to listen on, from --bind/--port (or `<PREFIX>_BIND_ADDRESS`/`<PREFIX>_PORT`)
+pub fn address(prefix: &str, default_ip: &str, default_port: u16) -> Result<SocketAddr, String> {
+    let ip = args::get("--bind", &format!("{}_BIND_ADDRESS", prefix))
+        .unwrap_or_else(|| default_ip.to_string());
+    let ip = ip
+        .parse::<IpAddr>()
+        .map_err(|e| format!("Invalid bind address {:?}: {}", ip, e))?;
+    let port = match args::get("--port", &format!("{}_PORT", prefix)) {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|e| format!("Invalid port {:?}: {}", port, e))?,
+        None => default_port,
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+pub async fn bind(address: SocketAddr) -> Result<TcpListener, String> {
+    TcpListener::bind(address)
+        .await
+        .map_err(|e| format!("Failed to bind to {}: {}", address, e))
+}
+
+/// The request body, rejected with 413 past `limit` bytes: by its Content-Length up front, or
+/// once read when it's sent in chunks
+pub fn body(limit: u64) -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    warp::header::optional::<u64>("content-length")
+        .and_then(move |length: Option<u64>| async move {
+            match length {
+                Some(length) if length > limit => Err(warp::reject::custom(TooLarge { limit })),
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(warp::body::bytes())
+        .and_then(move |body: Bytes| async move {
+            if body.len() as u64 > limit {
+                Err(warp::reject::custom(TooLarge { limit }))
+            } else {
+                Ok(body)
+            }
+        })
+}
+
+async fn too_large(rejection: Rejection) -> Result<reply::Response, Rejection> {
+    match rejection.find::<TooLarge>() {
+        Some(TooLarge { limit }) => Ok(reply::with_status(
+            format!("Request body over {} bytes", limit),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        )
+        .into_response()),
+        None => Err(rejection),
+    }
+}
+
+/// `routes` of the server named `server`, with /metrics, /healthz and /readyz, the standard
+/// headers, request logging and metrics
+pub fn routes<F>(
+    server: &'static str,
+    readiness: Readiness,
+    routes: F,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    routes
+        .or(metrics::route())
+        .or(health::routes(readiness))
+        .recover(too_large)
+        .with(reply::with::default_header("Server", "hackattic"))
+        .with(reply::with::default_header("Cache-Control", "no-store"))
+        .with(reply::with::default_header(
+            "X-Content-Type-Options",
+            "nosniff",
+        ))
+        .with(metrics::requests(server))
+        .with(warp::log::custom(move |info| {
+            debug!(
+                "{} {} {} {} ({:?})",
+                server,
+                info.method(),
+                info.path(),
+                info.status().as_u16(),
+                info.elapsed()
+            );
+        }))
+}
+
+/// Serves `routes` on `listener`, over TLS with `tls_config`, until `until` resolves or the run
+/// is stopped (Ctrl+C, the watchdog); requests in flight are answered first
+pub async fn serve<F>(
+    routes: F,
+    listener: TcpListener,
+    tls_config: Option<Arc<ServerConfig>>,
+    until: impl Future<Output = ()> + Send + 'static,
+) where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let stop = async move {
+        tokio::select! {
+            _ = until => {}
+            _ = shutdown::cancelled() => {}
+        }
+    };
+    match tls_config {
+        Some(tls_config) => tls::serve(warp::service(routes), listener, tls_config, stop).await,
+        None => {
+            warp::serve(routes)
+                .incoming(listener)
+                .graceful(stop)
+                .run()
+                .await
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use serde_json::{Map, Value, json};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Registry};
+
+use crate::utils::args;
+use crate::utils::output::{self, Stream, Style};
+use crate::utils::{telemetry, timing};
+
+// tracing-subscriber's registry with an EnvFilter, a human or JSON line per event, and a layer
+// recording closed spans.
+//
+// Verbosity: -q/--quiet (warnings and errors), -v/--verbose (debug), -vv (trace), or EnvFilter
+// directives in --log (or RUST_LOG) like `info,hackattic::utils::tls=debug`, the most specific
+// target wins. --log-format json (or LOG_FORMAT) switches to one JSON object per line. Closed
+// spans go to the timing breakdown, and to the OTLP exporter when one is configured (see
+// telemetry).
+
+/// What `SpanRecorder` keeps in each span's extensions
+struct SpanData {
+    /// Unique for the whole run, unlike span ids which the registry reuses
+    id: u64,
+    parent: Option<u64>,
+    fields: Map<String, Value>,
+    /// Span names from the root down, e.g. `challenge/solve`
+    path: String,
+    start: SystemTime,
+}
+
+/// Collects an event's or span's fields, `message` apart
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: Map<String, Value>,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            self.values
+                .insert(field.name().to_string(), json!(format!("{:?}", value)));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            self.values.insert(field.name().to_string(), json!(value));
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.values.insert(field.name().to_string(), json!(value));
+    }
+}
+
+/// Keeps every span's fields and start, and hands closed spans to the timing breakdown and
+/// the OTLP exporter
+struct SpanRecorder {
+    next_id: AtomicU64,
+}
+
+impl<S> Layer<S> for SpanRecorder
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|data| (data.id, data.path.clone()))
+        });
+        let path = match &parent {
+            Some((_, path)) => format!("{}/{}", path, span.name()),
+            None => span.name().to_string(),
+        };
+        span.extensions_mut().insert(SpanData {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            parent: parent.map(|(id, _)| id),
+            fields: fields.values,
+            path,
+            start: SystemTime::now(),
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = ctx.span(id)
+            && let Some(data) = span.extensions_mut().get_mut::<SpanData>()
+        {
+            data.fields.extend(fields.values);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        // out of the extensions before calling anything that could log
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        let end = SystemTime::now();
+        timing::record(
+            &data.path,
+            data.start,
+            end.duration_since(data.start).unwrap_or_default(),
+        );
+        if telemetry::enabled() {
+            telemetry::record_span(telemetry::FinishedSpan {
+                id: data.id,
+                parent: data.parent,
+                name: span.name(),
+                start: data.start,
+                end,
+                fields: data.fields,
+            });
+        }
+    }
+}
+
+/// The console format: warnings and errors labelled and colored, debug and trace dimmed, and
+/// with `spans` the span context before the message
+struct HumanFormat {
+    spans: bool,
+}
+
+// Warnings and errors go to stderr like they used to
+fn stream_for(level: Level) -> Stream {
+    if level <= Level::WARN {
+        Stream::Stderr
+    } else {
+        Stream::Stdout
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for HumanFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let level = *event.metadata().level();
+        let stream = stream_for(level);
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        // `info!(success = true, ...)` marks a result worth spotting, e.g. an accepted solution
+        let success = fields.values.remove("success") == Some(json!(true));
+
+        let mut line = String::new();
+        match level {
+            Level::ERROR => line.push_str(&output::paint("error:", Style::Error, stream)),
+            Level::WARN => line.push_str(&output::paint("warning:", Style::Warn, stream)),
+            Level::DEBUG => line.push_str("[debug]"),
+            Level::TRACE => line.push_str("[trace]"),
+            Level::INFO => {}
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        // The span context only adds noise to the normal output
+        if self.spans
+            && let Some(scope) = ctx.event_scope()
+        {
+            for span in scope.from_root() {
+                let _ = write!(line, "{}", span.name());
+                if let Some(data) = span.extensions().get::<SpanData>() {
+                    for (key, value) in &data.fields {
+                        let _ = write!(line, "{{{}={}}}", key, display(value));
+                    }
+                }
+                line.push_str(": ");
+            }
+        }
+        let message = fields.message.as_deref().unwrap_or_default();
+        if success {
+            line.push_str(&output::paint(message, Style::Success, stream));
+        } else {
+            line.push_str(message);
+        }
+        for (key, value) in &fields.values {
+            let _ = write!(line, " {}={}", key, display(value));
+        }
+        if level >= Level::DEBUG {
+            line = output::paint(&line, Style::Dim, stream);
+        }
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// The filter for the verbosity flags, refined by `spec` (`--log`) directives. A bare level in
+/// `spec` replaces the verbosity, targets without a directive keep it.
+fn filter(verbosity: LevelFilter, spec: Option<&str>) -> Result<EnvFilter, String> {
+    let directives = match spec {
+        Some(spec) => format!("{},{}", verbosity, spec),
+        None => verbosity.to_string(),
+    };
+    EnvFilter::builder().parse(&directives).map_err(|e| {
+        format!(
+            "Invalid log directives {:?}: {}",
+            spec.unwrap_or_default(),
+            e
+        )
+    })
+}
+
+/// Installs the global subscriber from the verbosity and format flags
+pub fn init() -> Result<(), String> {
+    let verbosity = if args::is_set("-vv", "LOG_TRACE") {
+        LevelFilter::TRACE
+    } else if args::is_set("-v", "LOG_VERBOSE") || args::is_set("--verbose", "LOG_VERBOSE") {
+        LevelFilter::DEBUG
+    } else if args::is_set("-q", "LOG_QUIET") || args::is_set("--quiet", "LOG_QUIET") {
+        LevelFilter::WARN
+    } else {
+        LevelFilter::INFO
+    };
+    let filter = filter(verbosity, args::get("--log", "RUST_LOG").as_deref())?;
+    let spans = filter.max_level_hint() >= Some(LevelFilter::DEBUG);
+
+    let format = match args::get("--log-format", "LOG_FORMAT").as_deref() {
+        None | Some("human") => tracing_subscriber::fmt::layer()
+            .event_format(HumanFormat { spans })
+            .with_writer(
+                std::io::stderr
+                    .with_max_level(Level::WARN)
+                    .or_else(std::io::stdout),
+            )
+            .boxed(),
+        Some("json") => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_writer(std::io::stdout)
+            .boxed(),
+        Some(other) => {
+            return Err(format!(
+                "Unknown log format {:?}, expected human or json",
+                other
+            ));
+        }
+    };
+
+    Registry::default()
+        .with(filter)
+        .with(SpanRecorder {
+            next_id: AtomicU64::new(1),
+        })
+        .with(format)
+        .try_init()
+        .map_err(|e| format!("Failed to install the logger: {}", e))?;
+    telemetry::init();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_directives_refine_the_verbosity() {
+        let debug = filter(LevelFilter::INFO, Some("hackattic::utils=debug")).unwrap();
+        assert_eq!(debug.to_string(), "hackattic::utils=debug,info");
+        assert_eq!(debug.max_level_hint(), Some(LevelFilter::DEBUG));
+
+        // a bare level replaces the verbosity
+        let quiet = filter(LevelFilter::DEBUG, Some("warn,hackattic=info")).unwrap();
+        assert_eq!(quiet.to_string(), "hackattic=info,warn");
+
+        assert!(filter(LevelFilter::INFO, Some("hackattic=loud")).is_err());
+    }
+
+    #[test]
+    fn records_span_paths() {
+        let subscriber = Registry::default().with(SpanRecorder {
+            next_id: AtomicU64::new(1),
+        });
+        tracing::subscriber::with_default(subscriber, || {
+            let _challenge = tracing::info_span!("challenge").entered();
+            let _solve = tracing::info_span!("solve").entered();
+            tracing::info_span!("log_span_test").in_scope(|| {});
+        });
+        let stages = timing::stages();
+        assert!(
+            stages
+                .iter()
+                .any(|stage| stage.path == "solve/log_span_test"),
+            "{:?}",
+            stages
+        );
+        assert!(stages.iter().any(|stage| stage.path == "solve"));
+    }
+}
@@ -0,0 +1,762 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Index of the chaining value in Q: Q[QOFF - 3..=QOFF] hold the IV as a, d, c, b, and
+// Q[QOFF + t + 1] is the state word computed by step t
+const QOFF: usize = 3;
+
+const MD5_IV: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+/// Two different messages with the same MD5, both starting with `prefix` (zero padded to a
+/// 64-byte boundary) and followed by two 64-byte collision blocks.
+/// Fastcoll's identical-prefix attack on Wang's differential path, usually takes seconds to a
+/// minute. `seed` picks where the search starts, returns `None` when `cancelled` is set
+pub fn generate(prefix: &[u8], seed: u64, cancelled: &AtomicBool) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut padded = prefix.to_vec();
+    padded.resize(prefix.len().div_ceil(64) * 64, 0);
+
+    let mut ihv = MD5_IV;
+    for chunk in padded.chunks(64) {
+        compress(&mut ihv, &to_words(chunk));
+    }
+
+    let mut rng = Rng::new(seed);
+    let block0 = find_block0(&ihv, &mut rng, cancelled)?;
+    let block0_other = with_difference(&block0, 1 << 15);
+
+    let mut ihv1 = ihv;
+    compress(&mut ihv1, &block0);
+    let mut ihv2 = ihv;
+    compress(&mut ihv2, &block0_other);
+
+    let block1 = find_block1(&ihv1, &ihv2, &mut rng, cancelled)?;
+    let block1_other = with_difference(&block1, (1u32 << 15).wrapping_neg());
+
+    let mut file1 = padded.clone();
+    let mut file2 = padded;
+    for word in block0.iter().chain(&block1) {
+        file1.extend_from_slice(&word.to_le_bytes());
+    }
+    for word in block0_other.iter().chain(&block1_other) {
+        file2.extend_from_slice(&word.to_le_bytes());
+    }
+    Some((file1, file2))
+}
+
+// Wang's message difference: +2^31 on m4 and m14, `m11` (+-2^15) on m11
+fn with_difference(block: &[u32; 16], m11: u32) -> [u32; 16] {
+    let mut other = *block;
+    other[4] = other[4].wrapping_add(1 << 31);
+    other[11] = other[11].wrapping_add(m11);
+    other[14] = other[14].wrapping_add(1 << 31);
+    other
+}
+
+fn to_words(chunk: &[u8]) -> [u32; 16] {
+    let mut words = [0u32; 16];
+    for (word, bytes) in words.iter_mut().zip(chunk.chunks(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    words
+}
+
+// Xorshift, fast and good enough to pick the free bits of the state words
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+}
+
+fn ff(b: u32, c: u32, d: u32) -> u32 {
+    d ^ (b & (c ^ d))
+}
+
+fn gg(b: u32, c: u32, d: u32) -> u32 {
+    c ^ (d & (b ^ c))
+}
+
+fn hh(b: u32, c: u32, d: u32) -> u32 {
+    b ^ c ^ d
+}
+
+fn ii(b: u32, c: u32, d: u32) -> u32 {
+    c ^ (b | !d)
+}
+
+fn rl(x: u32, n: u32) -> u32 {
+    x.rotate_left(n)
+}
+
+fn rr(x: u32, n: u32) -> u32 {
+    x.rotate_right(n)
+}
+
+macro_rules! step {
+    ($f:ident, $a:ident, $b:ident, $c:ident, $d:ident, $m:expr, $ac:expr, $rc:expr) => {
+        $a = $a
+            .wrapping_add($f($b, $c, $d))
+            .wrapping_add($m)
+            .wrapping_add($ac);
+        $a = rl($a, $rc).wrapping_add($b);
+    };
+}
+
+/// The message word that makes step `t` go from the state words in Q to Q[QOFF + t + 1]
+fn reverse_step(block: &mut [u32; 16], q: &[u32; 68], t: usize, ac: u32, rc: u32) {
+    block[t] = rr(q[QOFF + t + 1].wrapping_sub(q[QOFF + t]), rc)
+        .wrapping_sub(ff(q[QOFF + t], q[QOFF + t - 1], q[QOFF + t - 2]))
+        .wrapping_sub(q[QOFF + t - 3])
+        .wrapping_sub(ac);
+}
+
+fn compress(ihv: &mut [u32; 4], block: &[u32; 16]) {
+    let [mut a, mut b, mut c, mut d] = *ihv;
+
+    step!(ff, a, b, c, d, block[0], 0xd76aa478, 7);
+    step!(ff, d, a, b, c, block[1], 0xe8c7b756, 12);
+    step!(ff, c, d, a, b, block[2], 0x242070db, 17);
+    step!(ff, b, c, d, a, block[3], 0xc1bdceee, 22);
+    step!(ff, a, b, c, d, block[4], 0xf57c0faf, 7);
+    step!(ff, d, a, b, c, block[5], 0x4787c62a, 12);
+    step!(ff, c, d, a, b, block[6], 0xa8304613, 17);
+    step!(ff, b, c, d, a, block[7], 0xfd469501, 22);
+    step!(ff, a, b, c, d, block[8], 0x698098d8, 7);
+    step!(ff, d, a, b, c, block[9], 0x8b44f7af, 12);
+    step!(ff, c, d, a, b, block[10], 0xffff5bb1, 17);
+    step!(ff, b, c, d, a, block[11], 0x895cd7be, 22);
+    step!(ff, a, b, c, d, block[12], 0x6b901122, 7);
+    step!(ff, d, a, b, c, block[13], 0xfd987193, 12);
+    step!(ff, c, d, a, b, block[14], 0xa679438e, 17);
+    step!(ff, b, c, d, a, block[15], 0x49b40821, 22);
+
+    step!(gg, a, b, c, d, block[1], 0xf61e2562, 5);
+    step!(gg, d, a, b, c, block[6], 0xc040b340, 9);
+    step!(gg, c, d, a, b, block[11], 0x265e5a51, 14);
+    step!(gg, b, c, d, a, block[0], 0xe9b6c7aa, 20);
+    step!(gg, a, b, c, d, block[5], 0xd62f105d, 5);
+    step!(gg, d, a, b, c, block[10], 0x02441453, 9);
+    step!(gg, c, d, a, b, block[15], 0xd8a1e681, 14);
+    step!(gg, b, c, d, a, block[4], 0xe7d3fbc8, 20);
+    step!(gg, a, b, c, d, block[9], 0x21e1cde6, 5);
+    step!(gg, d, a, b, c, block[14], 0xc33707d6, 9);
+    step!(gg, c, d, a, b, block[3], 0xf4d50d87, 14);
+    step!(gg, b, c, d, a, block[8], 0x455a14ed, 20);
+    step!(gg, a, b, c, d, block[13], 0xa9e3e905, 5);
+    step!(gg, d, a, b, c, block[2], 0xfcefa3f8, 9);
+    step!(gg, c, d, a, b, block[7], 0x676f02d9, 14);
+    step!(gg, b, c, d, a, block[12], 0x8d2a4c8a, 20);
+
+    step!(hh, a, b, c, d, block[5], 0xfffa3942, 4);
+    step!(hh, d, a, b, c, block[8], 0x8771f681, 11);
+    step!(hh, c, d, a, b, block[11], 0x6d9d6122, 16);
+    step!(hh, b, c, d, a, block[14], 0xfde5380c, 23);
+    step!(hh, a, b, c, d, block[1], 0xa4beea44, 4);
+    step!(hh, d, a, b, c, block[4], 0x4bdecfa9, 11);
+    step!(hh, c, d, a, b, block[7], 0xf6bb4b60, 16);
+    step!(hh, b, c, d, a, block[10], 0xbebfbc70, 23);
+    step!(hh, a, b, c, d, block[13], 0x289b7ec6, 4);
+    step!(hh, d, a, b, c, block[0], 0xeaa127fa, 11);
+    step!(hh, c, d, a, b, block[3], 0xd4ef3085, 16);
+    step!(hh, b, c, d, a, block[6], 0x04881d05, 23);
+    step!(hh, a, b, c, d, block[9], 0xd9d4d039, 4);
+    step!(hh, d, a, b, c, block[12], 0xe6db99e5, 11);
+    step!(hh, c, d, a, b, block[15], 0x1fa27cf8, 16);
+    step!(hh, b, c, d, a, block[2], 0xc4ac5665, 23);
+
+    step!(ii, a, b, c, d, block[0], 0xf4292244, 6);
+    step!(ii, d, a, b, c, block[7], 0x432aff97, 10);
+    step!(ii, c, d, a, b, block[14], 0xab9423a7, 15);
+    step!(ii, b, c, d, a, block[5], 0xfc93a039, 21);
+    step!(ii, a, b, c, d, block[12], 0x655b59c3, 6);
+    step!(ii, d, a, b, c, block[3], 0x8f0ccc92, 10);
+    step!(ii, c, d, a, b, block[10], 0xffeff47d, 15);
+    step!(ii, b, c, d, a, block[1], 0x85845dd1, 21);
+    step!(ii, a, b, c, d, block[8], 0x6fa87e4f, 6);
+    step!(ii, d, a, b, c, block[15], 0xfe2ce6e0, 10);
+    step!(ii, c, d, a, b, block[6], 0xa3014314, 15);
+    step!(ii, b, c, d, a, block[13], 0x4e0811a1, 21);
+    step!(ii, a, b, c, d, block[4], 0xf7537e82, 6);
+    step!(ii, d, a, b, c, block[11], 0xbd3af235, 10);
+    step!(ii, c, d, a, b, block[2], 0x2ad7d2bb, 15);
+    step!(ii, b, c, d, a, block[9], 0xeb86d391, 21);
+
+    ihv[0] = ihv[0].wrapping_add(a);
+    ihv[1] = ihv[1].wrapping_add(b);
+    ihv[2] = ihv[2].wrapping_add(c);
+    ihv[3] = ihv[3].wrapping_add(d);
+}
+
+/// Steps 24 to 63 from the state after step 23, bailing out as soon as a condition of the
+/// last two rounds fails. `bit15` is bit 15 of the sum m11 is added to in step 34, the m11
+/// difference must flip it without a carry. Returns whether the block made it through
+fn last_rounds_hold(block: &[u32; 16], [mut a, mut b, mut c, mut d]: [u32; 4], bit15: u32) -> bool {
+    step!(gg, a, b, c, d, block[9], 0x21e1cde6, 5);
+    step!(gg, d, a, b, c, block[14], 0xc33707d6, 9);
+    step!(gg, c, d, a, b, block[3], 0xf4d50d87, 14);
+    step!(gg, b, c, d, a, block[8], 0x455a14ed, 20);
+    step!(gg, a, b, c, d, block[13], 0xa9e3e905, 5);
+    step!(gg, d, a, b, c, block[2], 0xfcefa3f8, 9);
+    step!(gg, c, d, a, b, block[7], 0x676f02d9, 14);
+    step!(gg, b, c, d, a, block[12], 0x8d2a4c8a, 20);
+    step!(hh, a, b, c, d, block[5], 0xfffa3942, 4);
+    step!(hh, d, a, b, c, block[8], 0x8771f681, 11);
+
+    c = c
+        .wrapping_add(hh(d, a, b))
+        .wrapping_add(block[11])
+        .wrapping_add(0x6d9d6122);
+    if c & (1 << 15) != bit15 {
+        return false;
+    }
+    c = rl(c, 16).wrapping_add(d);
+
+    step!(hh, b, c, d, a, block[14], 0xfde5380c, 23);
+    step!(hh, a, b, c, d, block[1], 0xa4beea44, 4);
+    step!(hh, d, a, b, c, block[4], 0x4bdecfa9, 11);
+    step!(hh, c, d, a, b, block[7], 0xf6bb4b60, 16);
+    step!(hh, b, c, d, a, block[10], 0xbebfbc70, 23);
+    step!(hh, a, b, c, d, block[13], 0x289b7ec6, 4);
+    step!(hh, d, a, b, c, block[0], 0xeaa127fa, 11);
+    step!(hh, c, d, a, b, block[3], 0xd4ef3085, 16);
+    step!(hh, b, c, d, a, block[6], 0x04881d05, 23);
+    step!(hh, a, b, c, d, block[9], 0xd9d4d039, 4);
+    step!(hh, d, a, b, c, block[12], 0xe6db99e5, 11);
+    step!(hh, c, d, a, b, block[15], 0x1fa27cf8, 16);
+    step!(hh, b, c, d, a, block[2], 0xc4ac5665, 23);
+    if (b ^ d) & 0x80000000 != 0 {
+        return false;
+    }
+
+    // the sign bits of the last round must alternate as the differential path expects
+    step!(ii, a, b, c, d, block[0], 0xf4292244, 6);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, d, a, b, c, block[7], 0x432aff97, 10);
+    if (b ^ d) >> 31 == 0 {
+        return false;
+    }
+    step!(ii, c, d, a, b, block[14], 0xab9423a7, 15);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, b, c, d, a, block[5], 0xfc93a039, 21);
+    if (b ^ d) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, a, b, c, d, block[12], 0x655b59c3, 6);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, d, a, b, c, block[3], 0x8f0ccc92, 10);
+    if (b ^ d) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, c, d, a, b, block[10], 0xffeff47d, 15);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, b, c, d, a, block[1], 0x85845dd1, 21);
+    if (b ^ d) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, a, b, c, d, block[8], 0x6fa87e4f, 6);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, d, a, b, c, block[15], 0xfe2ce6e0, 10);
+    if (b ^ d) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, c, d, a, b, block[6], 0xa3014314, 15);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, b, c, d, a, block[13], 0x4e0811a1, 21);
+    if (b ^ d) >> 31 == 0 {
+        return false;
+    }
+    step!(ii, a, b, c, d, block[4], 0xf7537e82, 6);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, d, a, b, c, block[11], 0xbd3af235, 10);
+    if (b ^ d) >> 31 != 0 {
+        return false;
+    }
+    step!(ii, c, d, a, b, block[2], 0x2ad7d2bb, 15);
+    if (a ^ c) >> 31 != 0 {
+        return false;
+    }
+    true
+}
+
+/// First near-collision block: afterwards the two chaining values differ by
+/// (2^31, 2^31 + 2^25, 2^31 + 2^25, 2^31 + 2^25)
+fn find_block0(ihv: &[u32; 4], rng: &mut Rng, cancelled: &AtomicBool) -> Option<[u32; 16]> {
+    let mut block = [0u32; 16];
+    let mut q = [0u32; 68];
+    q[..4].copy_from_slice(&[ihv[0], ihv[3], ihv[2], ihv[1]]);
+
+    // bits of q4, q9 and q10 that can be flipped without breaking the conditions
+    let q4mask: Vec<u32> = (0..1u32 << 4)
+        .map(|k| ((k << 2) ^ (k << 26)) & 0x38000004)
+        .collect();
+    let q9q10mask: Vec<u32> = (0..1u32 << 3)
+        .map(|k| ((k << 13) ^ (k << 4)) & 0x2060)
+        .collect();
+    let q9mask: Vec<u32> = (0..1u32 << 16)
+        .map(|k| {
+            ((k << 1)
+                ^ (k << 2)
+                ^ (k << 5)
+                ^ (k << 7)
+                ^ (k << 8)
+                ^ (k << 10)
+                ^ (k << 11)
+                ^ (k << 13))
+                & 0x0eb94f16
+        })
+        .collect();
+
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        // random state words satisfying the sufficient conditions of the first round
+        q[QOFF + 1] = rng.next();
+        q[QOFF + 3] = (rng.next() & 0xfe87bc3f) | 0x017841c0;
+        q[QOFF + 4] = (rng.next() & 0x44000033) | 0x000002c0 | (q[QOFF + 3] & 0x0287bc00);
+        q[QOFF + 5] = 0x41ffffc8 | (q[QOFF + 4] & 0x04000033);
+        q[QOFF + 6] = 0xb84b82d6;
+        q[QOFF + 7] = (rng.next() & 0x68000084) | 0x02401b43;
+        q[QOFF + 8] = (rng.next() & 0x2b8f6e04) | 0x005090d3 | (!q[QOFF + 7] & 0x40000000);
+        q[QOFF + 9] = 0x20040068 | (q[QOFF + 8] & 0x00020000) | (!q[QOFF + 8] & 0x40000000);
+        q[QOFF + 10] = (rng.next() & 0x40000000) | 0x1040b089;
+        q[QOFF + 11] = (rng.next() & 0x10408008) | 0x0fbb7f16 | (!q[QOFF + 10] & 0x40000000);
+        q[QOFF + 12] = (rng.next() & 0x1ed9df7f) | 0x00022080 | (!q[QOFF + 11] & 0x40200000);
+        q[QOFF + 13] = (rng.next() & 0x5efb4f77) | 0x20049008;
+        q[QOFF + 14] = (rng.next() & 0x1fff5f77) | 0x0000a088 | (!q[QOFF + 13] & 0x40000000);
+        q[QOFF + 15] = (rng.next() & 0x5efe7ff7) | 0x80008000 | (!q[QOFF + 14] & 0x00010000);
+        q[QOFF + 16] = (rng.next() & 0x1ffdffff) | 0xa0000000 | (!q[QOFF + 15] & 0x40020000);
+
+        reverse_step(&mut block, &q, 0, 0xd76aa478, 7);
+        reverse_step(&mut block, &q, 6, 0xa8304613, 17);
+        reverse_step(&mut block, &q, 7, 0xfd469501, 22);
+        reverse_step(&mut block, &q, 11, 0x895cd7be, 22);
+        reverse_step(&mut block, &q, 14, 0xa679438e, 17);
+        reverse_step(&mut block, &q, 15, 0x49b40821, 22);
+
+        let tt1 = ff(q[QOFF + 1], q[QOFF], q[QOFF - 1])
+            .wrapping_add(q[QOFF - 2])
+            .wrapping_add(0xe8c7b756);
+        let tt17 = gg(q[QOFF + 16], q[QOFF + 15], q[QOFF + 14])
+            .wrapping_add(q[QOFF + 13])
+            .wrapping_add(0xf61e2562);
+        let tt18 = q[QOFF + 14].wrapping_add(0xc040b340).wrapping_add(block[6]);
+        let tt19 = q[QOFF + 15]
+            .wrapping_add(0x265e5a51)
+            .wrapping_add(block[11]);
+        let tt20 = q[QOFF + 16].wrapping_add(0xe9b6c7aa).wrapping_add(block[0]);
+
+        // pick q17 (and through m1, q2) until q18 to q20 meet their conditions
+        let mut found = false;
+        for _ in 0..1 << 7 {
+            let q16 = q[QOFF + 16];
+            let q17 = ((rng.next() & 0x3ffd7ff7) | (q16 & 0xc0008008)) ^ 0x40000000;
+
+            let q18 = rl(gg(q17, q16, q[QOFF + 15]).wrapping_add(tt18), 9).wrapping_add(q17);
+            if (q18 ^ q17) & 0xa0020000 != 0x00020000 {
+                continue;
+            }
+            let q19 = rl(gg(q18, q17, q16).wrapping_add(tt19), 14).wrapping_add(q18);
+            if q19 & 0x80020000 != 0x80000000 {
+                continue;
+            }
+            let q20 = rl(gg(q19, q18, q17).wrapping_add(tt20), 20).wrapping_add(q19);
+            if (q20 ^ q19) & 0x80040000 != 0x00040000 {
+                continue;
+            }
+
+            let m1 = rr(q17.wrapping_sub(q16), 5).wrapping_sub(tt17);
+            let q2 = rl(m1.wrapping_add(tt1), 12).wrapping_add(q[QOFF + 1]);
+
+            q[QOFF + 2] = q2;
+            q[QOFF + 17] = q17;
+            q[QOFF + 18] = q18;
+            q[QOFF + 19] = q19;
+            q[QOFF + 20] = q20;
+            block[1] = m1;
+            reverse_step(&mut block, &q, 2, 0x242070db, 17);
+            found = true;
+            break;
+        }
+        if !found {
+            continue;
+        }
+
+        let q4 = q[QOFF + 4];
+        let q9backup = q[QOFF + 9];
+        let tt21 = gg(q[QOFF + 20], q[QOFF + 19], q[QOFF + 18])
+            .wrapping_add(q[QOFF + 17])
+            .wrapping_add(0xd62f105d);
+
+        // flip free bits of q4, keeping q1 to q20 intact, this changes m3, m4, m5 and m7
+        for mask in &q4mask {
+            q[QOFF + 4] = q4 ^ mask;
+            reverse_step(&mut block, &q, 5, 0x4787c62a, 12);
+            let q21 = rl(tt21.wrapping_add(block[5]), 5).wrapping_add(q[QOFF + 20]);
+            if (q21 ^ q[QOFF + 20]) & 0x80020000 != 0 {
+                continue;
+            }
+
+            q[QOFF + 21] = q21;
+            reverse_step(&mut block, &q, 3, 0xc1bdceee, 22);
+            reverse_step(&mut block, &q, 4, 0xf57c0faf, 7);
+            reverse_step(&mut block, &q, 7, 0xfd469501, 22);
+
+            let tt22 = gg(q[QOFF + 21], q[QOFF + 20], q[QOFF + 19])
+                .wrapping_add(q[QOFF + 18])
+                .wrapping_add(0x02441453);
+            let tt23 = q[QOFF + 19]
+                .wrapping_add(0xd8a1e681)
+                .wrapping_add(block[15]);
+            let tt24 = q[QOFF + 20].wrapping_add(0xe7d3fbc8).wrapping_add(block[4]);
+
+            let tt9 = q[QOFF + 6].wrapping_add(0x8b44f7af);
+            let tt10 = q[QOFF + 7].wrapping_add(0xffff5bb1);
+            let tt8 = ff(q[QOFF + 8], q[QOFF + 7], q[QOFF + 6])
+                .wrapping_add(q[QOFF + 5])
+                .wrapping_add(0x698098d8);
+            let tt12 = rr(q[QOFF + 13].wrapping_sub(q[QOFF + 12]), 7).wrapping_sub(0x6b901122);
+            let tt13 = rr(q[QOFF + 14].wrapping_sub(q[QOFF + 13]), 12)
+                .wrapping_sub(ff(q[QOFF + 13], q[QOFF + 12], q[QOFF + 11]))
+                .wrapping_sub(0xfd987193);
+
+            // flip free bits of q9 and q10, keeping q1 to q21 intact, this changes m8, m9,
+            // m10, m12 and m13
+            for q9q10 in &q9q10mask {
+                let q10 = q[QOFF + 10] ^ (q9q10 & 0x60);
+                q[QOFF + 9] = q9backup ^ (q9q10 & 0x2000);
+                let m10 = rr(q[QOFF + 11].wrapping_sub(q10), 17)
+                    .wrapping_sub(ff(q10, q[QOFF + 9], q[QOFF + 8]).wrapping_add(tt10));
+
+                let aa = q[QOFF + 21];
+                let dd = rl(tt22.wrapping_add(m10), 9).wrapping_add(aa);
+                if dd & 0x80000000 != 0x80000000 {
+                    continue;
+                }
+                let bb = q[QOFF + 20];
+                let cc = tt23.wrapping_add(gg(dd, aa, bb));
+                if cc & 0x20000 != 0 {
+                    continue;
+                }
+                let cc = rl(cc, 14).wrapping_add(dd);
+                if cc & 0x80000000 != 0 {
+                    continue;
+                }
+                let bb = rl(tt24.wrapping_add(gg(cc, dd, aa)), 20).wrapping_add(cc);
+                if bb & 0x80000000 == 0 {
+                    continue;
+                }
+
+                block[10] = m10;
+                q[QOFF + 10] = q10;
+                block[13] = tt13.wrapping_sub(q10);
+
+                // flip the bits of q9 that leave m10 and m13 alone, this changes m8, m9 and m12
+                for mask in &q9mask {
+                    let q9 = q[QOFF + 9] ^ mask;
+                    block[12] = tt12
+                        .wrapping_sub(ff(q[QOFF + 12], q[QOFF + 11], q10))
+                        .wrapping_sub(q9);
+                    block[8] = rr(q9.wrapping_sub(q[QOFF + 8]), 7).wrapping_sub(tt8);
+                    block[9] = rr(q10.wrapping_sub(q9), 12)
+                        .wrapping_sub(ff(q9, q[QOFF + 8], q[QOFF + 7]))
+                        .wrapping_sub(tt9);
+
+                    if !last_rounds_hold(&block, [aa, bb, cc, dd], 0) {
+                        continue;
+                    }
+
+                    let mut ihv1 = *ihv;
+                    let mut ihv2 = *ihv;
+                    compress(&mut ihv1, &block);
+                    compress(&mut ihv2, &with_difference(&block, 1 << 15));
+                    let expected = [
+                        ihv1[0].wrapping_add(1 << 31),
+                        ihv1[1].wrapping_add((1 << 31) + (1 << 25)),
+                        ihv1[2].wrapping_add((1 << 31) + (1 << 25)),
+                        ihv1[3].wrapping_add((1 << 31) + (1 << 25)),
+                    ];
+                    // the second block only handles chaining values that meet the conditions its
+                    // first steps put on b, c and d
+                    let wang = (ihv1[2] ^ ihv1[1]) & 0x86000000 == 0x02000000
+                        && (ihv1[1] ^ ihv1[3]) & 0x82000000 == 0
+                        && ihv1[1] & 0x06000020 == 0;
+                    if ihv2 == expected && wang {
+                        return Some(block);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Second block: cancels the chaining value difference left by the first one
+fn find_block1(
+    ihv1: &[u32; 4],
+    ihv2: &[u32; 4],
+    rng: &mut Rng,
+    cancelled: &AtomicBool,
+) -> Option<[u32; 16]> {
+    let mut block = [0u32; 16];
+    let mut q = [0u32; 68];
+    q[..4].copy_from_slice(&[ihv1[0], ihv1[3], ihv1[2], ihv1[1]]);
+
+    let q4mask: Vec<u32> = (0..1u32 << 6)
+        .map(|k| ((k << 13) ^ (k << 19)) & 0x01c0e000)
+        .collect();
+    let (q9mask, q10mask): (Vec<u32>, Vec<u32>) = (0..1u32 << 5)
+        .map(|k| {
+            let mask = (k << 5) ^ (k << 13) ^ (k << 17) ^ (k << 24);
+            (mask & 0x00084000, mask & 0x18000020)
+        })
+        .unzip();
+    let q9mask2: Vec<u32> = (0..1u32 << 10)
+        .map(|k| ((k << 1) ^ (k << 7) ^ (k << 14) ^ (k << 15) ^ (k << 22)) & 0x6074041c)
+        .collect();
+
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let aa = q[QOFF] & 0x80000000;
+        let bb = 0x80000000 ^ aa;
+
+        q[QOFF + 2] = (rng.next() & 0x71de7799) | 0x0c008840 | bb;
+        q[QOFF + 3] = (rng.next() & 0x01c06601) | 0x3e1f0966 | (q[QOFF + 2] & 0x80000018);
+        q[QOFF + 4] = 0x3a040010 | (q[QOFF + 3] & 0x80000601);
+        q[QOFF + 5] = (rng.next() & 0x03c0e000) | 0x482f0e50 | aa;
+        q[QOFF + 6] = (rng.next() & 0x600c0000) | 0x05e2ec56 | aa;
+        q[QOFF + 7] = (rng.next() & 0x604c203e) | 0x16819e01 | bb | (q[QOFF + 6] & 0x01000000);
+        q[QOFF + 8] = (rng.next() & 0x604c7c1c) | 0x043283e0 | (q[QOFF + 7] & 0x80000002);
+        q[QOFF + 9] = (rng.next() & 0x00002800) | 0x1c0101c1 | (q[QOFF + 8] & 0x80001000);
+        q[QOFF + 10] = 0x078bcbc0 | bb;
+        q[QOFF + 11] = (rng.next() & 0x07800000) | 0x607dc7df | bb;
+        q[QOFF + 12] = (rng.next() & 0x00f00f7f) | 0x00081080 | (q[QOFF + 11] & 0xe7000000);
+        q[QOFF + 13] = (rng.next() & 0x00701f77) | 0x3f0fe008 | aa;
+        q[QOFF + 14] = (rng.next() & 0x00701f77) | 0x408be088 | aa;
+        q[QOFF + 15] = (rng.next() & 0x00ff3ff7) | 0x7d000000;
+        q[QOFF + 16] = (rng.next() & 0x4ffdffff) | 0x20000000 | (!q[QOFF + 15] & 0x00020000);
+
+        reverse_step(&mut block, &q, 5, 0x4787c62a, 12);
+        reverse_step(&mut block, &q, 6, 0xa8304613, 17);
+        reverse_step(&mut block, &q, 7, 0xfd469501, 22);
+        reverse_step(&mut block, &q, 11, 0x895cd7be, 22);
+        reverse_step(&mut block, &q, 14, 0xa679438e, 17);
+        reverse_step(&mut block, &q, 15, 0x49b40821, 22);
+
+        let tt17 = gg(q[QOFF + 16], q[QOFF + 15], q[QOFF + 14])
+            .wrapping_add(q[QOFF + 13])
+            .wrapping_add(0xf61e2562);
+        let tt18 = q[QOFF + 14].wrapping_add(0xc040b340).wrapping_add(block[6]);
+        let tt19 = q[QOFF + 15]
+            .wrapping_add(0x265e5a51)
+            .wrapping_add(block[11]);
+        let tt0 = ff(q[QOFF], q[QOFF - 1], q[QOFF - 2])
+            .wrapping_add(q[QOFF - 3])
+            .wrapping_add(0xd76aa478);
+        let tt1 = q[QOFF - 2].wrapping_add(0xe8c7b756);
+        let q1a = 0x04200040 | (q[QOFF + 2] & 0xf01e1080);
+
+        // pick q1 (and through m0 and m1, q17) until q17 to q20 meet their conditions
+        let mut found = false;
+        for _ in 0..1 << 12 {
+            let q1 = q1a | (rng.next() & 0x01c0e71f);
+            let m1 = rr(q[QOFF + 2].wrapping_sub(q1), 12)
+                .wrapping_sub(ff(q1, q[QOFF], q[QOFF - 1]))
+                .wrapping_sub(tt1);
+
+            let q16 = q[QOFF + 16];
+            let q17 = rl(tt17.wrapping_add(m1), 5).wrapping_add(q16);
+            if (q17 ^ q16) & 0xc0008008 != 0x40000000 || q17 & 0x00020000 != 0 {
+                continue;
+            }
+            let q18 = rl(gg(q17, q16, q[QOFF + 15]).wrapping_add(tt18), 9).wrapping_add(q17);
+            if (q18 ^ q17) & 0xa0020000 != 0x00020000 {
+                continue;
+            }
+            let q19 = rl(gg(q18, q17, q16).wrapping_add(tt19), 14).wrapping_add(q18);
+            if q19 & 0x80020000 != 0 {
+                continue;
+            }
+            let m0 = rr(q1.wrapping_sub(q[QOFF]), 7).wrapping_sub(tt0);
+            let q20 = rl(
+                gg(q19, q18, q17)
+                    .wrapping_add(q16)
+                    .wrapping_add(0xe9b6c7aa)
+                    .wrapping_add(m0),
+                20,
+            )
+            .wrapping_add(q19);
+            if (q20 ^ q19) & 0x80040000 != 0x00040000 {
+                continue;
+            }
+
+            q[QOFF + 1] = q1;
+            q[QOFF + 17] = q17;
+            q[QOFF + 18] = q18;
+            q[QOFF + 19] = q19;
+            q[QOFF + 20] = q20;
+            block[0] = m0;
+            block[1] = m1;
+            reverse_step(&mut block, &q, 2, 0x242070db, 17);
+            found = true;
+            break;
+        }
+        if !found {
+            continue;
+        }
+
+        let q4b = q[QOFF + 4];
+        let q9b = q[QOFF + 9];
+        let q10b = q[QOFF + 10];
+        let tt21 = gg(q[QOFF + 20], q[QOFF + 19], q[QOFF + 18])
+            .wrapping_add(q[QOFF + 17])
+            .wrapping_add(0xd62f105d);
+
+        for mask in &q4mask {
+            q[QOFF + 4] = q4b ^ mask;
+            reverse_step(&mut block, &q, 5, 0x4787c62a, 12);
+            let q21 = rl(tt21.wrapping_add(block[5]), 5).wrapping_add(q[QOFF + 20]);
+            if (q21 ^ q[QOFF + 20]) & 0x80020000 != 0 {
+                continue;
+            }
+
+            q[QOFF + 21] = q21;
+            reverse_step(&mut block, &q, 3, 0xc1bdceee, 22);
+            reverse_step(&mut block, &q, 4, 0xf57c0faf, 7);
+            reverse_step(&mut block, &q, 7, 0xfd469501, 22);
+
+            let tt22 = gg(q[QOFF + 21], q[QOFF + 20], q[QOFF + 19])
+                .wrapping_add(q[QOFF + 18])
+                .wrapping_add(0x02441453);
+            let tt23 = q[QOFF + 19]
+                .wrapping_add(0xd8a1e681)
+                .wrapping_add(block[15]);
+            let tt24 = q[QOFF + 20].wrapping_add(0xe7d3fbc8).wrapping_add(block[4]);
+
+            for (q9m, q10m) in q9mask.iter().zip(&q10mask) {
+                let q10 = q10b ^ q10m;
+                let q9 = q9b ^ q9m;
+                let m10 = rr(q[QOFF + 11].wrapping_sub(q10), 17).wrapping_sub(
+                    ff(q10, q9, q[QOFF + 8])
+                        .wrapping_add(q[QOFF + 7])
+                        .wrapping_add(0xffff5bb1),
+                );
+
+                let aa = q[QOFF + 21];
+                let dd = rl(tt22.wrapping_add(m10), 9).wrapping_add(aa);
+                if dd & 0x80000000 != 0 {
+                    continue;
+                }
+                let bb = q[QOFF + 20];
+                let cc = tt23.wrapping_add(gg(dd, aa, bb));
+                if cc & 0x20000 != 0 {
+                    continue;
+                }
+                let cc = rl(cc, 14).wrapping_add(dd);
+                if cc & 0x80000000 != 0 {
+                    continue;
+                }
+                let bb = rl(tt24.wrapping_add(gg(cc, dd, aa)), 20).wrapping_add(cc);
+                if bb & 0x80000000 == 0 {
+                    continue;
+                }
+
+                block[10] = m10;
+                q[QOFF + 9] = q9;
+                q[QOFF + 10] = q10;
+                reverse_step(&mut block, &q, 13, 0xfd987193, 12);
+
+                for mask in &q9mask2 {
+                    q[QOFF + 9] = q9 ^ mask;
+                    reverse_step(&mut block, &q, 8, 0x698098d8, 7);
+                    reverse_step(&mut block, &q, 9, 0x8b44f7af, 12);
+                    reverse_step(&mut block, &q, 12, 0x6b901122, 7);
+
+                    if !last_rounds_hold(&block, [aa, bb, cc, dd], 1 << 15) {
+                        continue;
+                    }
+
+                    let mut out1 = *ihv1;
+                    let mut out2 = *ihv2;
+                    compress(&mut out1, &block);
+                    compress(
+                        &mut out2,
+                        &with_difference(&block, (1u32 << 15).wrapping_neg()),
+                    );
+                    if out1 == out2 {
+                        return Some(block);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use md5::{Digest, Md5};
+
+    use super::*;
+
+    // finds the collision for PREFIX in well under a second, most seeds take 10s to a minute
+    const FAST_SEED: u64 = 488;
+    // takes more than a minute
+    const SLOW_SEED: u64 = 2;
+    const PREFIX: &[u8] = b"hackattic";
+
+    #[test]
+    fn generates_a_collision() {
+        let (file1, file2) = generate(PREFIX, FAST_SEED, &AtomicBool::new(false)).unwrap();
+        assert!(file1.starts_with(PREFIX));
+        assert!(file2.starts_with(PREFIX));
+        // the prefix padded to a block, then the two collision blocks
+        assert_eq!(file1.len(), 64 + 128);
+        assert_eq!(file2.len(), 64 + 128);
+        assert_ne!(file1, file2);
+        assert_eq!(Md5::digest(&file1), Md5::digest(&file2));
+    }
+
+    #[test]
+    fn returns_none_when_cancelled() {
+        assert_eq!(generate(PREFIX, FAST_SEED, &AtomicBool::new(true)), None);
+
+        let cancelled = AtomicBool::new(false);
+        let started = Instant::now();
+        let pair = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                cancelled.store(true, Ordering::Relaxed);
+            });
+            generate(PREFIX, SLOW_SEED, &cancelled)
+        });
+        assert_eq!(pair, None);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
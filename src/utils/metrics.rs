@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use warp::{Filter, reply};
+
+// Prometheus metrics of the servers hosted during a challenge (the registry, the JWT server),
+// scraped from their own port at GET /metrics while the challenge window is open. Kept in
+// process in the text exposition format, nothing is pushed anywhere.
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// A metric family, its series told apart by their labels
+#[derive(Clone, Copy)]
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: Kind,
+}
+
+pub const HTTP_REQUESTS: Metric = Metric {
+    name: "hackattic_http_requests_total",
+    help: "Requests served, by server, method and status",
+    kind: Kind::Counter,
+};
+pub const HTTP_REQUEST_DURATION: Metric = Metric {
+    name: "hackattic_http_request_duration_seconds",
+    help: "Time to answer a request, by server",
+    kind: Kind::Histogram,
+};
+pub const REGISTRY_STORED_BYTES: Metric = Metric {
+    name: "hackattic_registry_stored_bytes",
+    help: "Bytes of blobs and manifests the registry stores",
+    kind: Kind::Gauge,
+};
+pub const REGISTRY_UPLOADS_IN_FLIGHT: Metric = Metric {
+    name: "hackattic_registry_uploads_in_flight",
+    help: "Blob uploads started and not completed yet",
+    kind: Kind::Gauge,
+};
+pub const JWT_TOKENS: Metric = Metric {
+    name: "hackattic_jwt_tokens_total",
+    help: "Tokens the JWT server processed, by verdict",
+    kind: Kind::Counter,
+};
+
+// upper bounds in seconds, a local server answers in milliseconds
+const BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+enum Value {
+    Number(f64),
+    Histogram {
+        buckets: [u64; BUCKETS.len()],
+        sum: f64,
+        count: u64,
+    },
+}
+
+// families by name, their series by rendered labels
+static FAMILIES: Mutex<BTreeMap<&'static str, (Metric, BTreeMap<String, Value>)>> =
+    Mutex::new(BTreeMap::new());
+
+impl Metric {
+    /// Adds `by` to a counter or gauge, negative to decrease a gauge
+    pub fn add(&self, labels: &[(&str, &str)], by: f64) {
+        self.update(labels, |value| {
+            if let Value::Number(number) = value {
+                *number += by;
+            }
+        });
+    }
+
+    pub fn inc(&self, labels: &[(&str, &str)]) {
+        self.add(labels, 1.0);
+    }
+
+    pub fn set(&self, labels: &[(&str, &str)], to: f64) {
+        self.update(labels, |value| *value = Value::Number(to));
+    }
+
+    /// Records one observation of a histogram
+    pub fn observe(&self, labels: &[(&str, &str)], observed: f64) {
+        self.update(labels, |value| {
+            if let Value::Histogram {
+                buckets,
+                sum,
+                count,
+            } = value
+            {
+                for (bucket, bound) in buckets.iter_mut().zip(BUCKETS) {
+                    if observed <= bound {
+                        *bucket += 1;
+                    }
+                }
+                *sum += observed;
+                *count += 1;
+            }
+        });
+    }
+
+    fn update(&self, labels: &[(&str, &str)], update: impl FnOnce(&mut Value)) {
+        let mut families = FAMILIES.lock().unwrap();
+        let (_, series) = families
+            .entry(self.name)
+            .or_insert_with(|| (*self, BTreeMap::new()));
+        let value = series.entry(render_labels(labels)).or_insert_with(|| {
+            if self.kind == Kind::Histogram {
+                Value::Histogram {
+                    buckets: [0; BUCKETS.len()],
+                    sum: 0.0,
+                    count: 0,
+                }
+            } else {
+                Value::Number(0.0)
+            }
+        });
+        update(value);
+    }
+}
+
+// `a="1",b="2"`, values escaped like the exposition format wants
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// `name{labels}`, with `extra` appended to the labels
+fn series_name(name: &str, suffix: &str, labels: &str, extra: &str) -> String {
+    let labels = [labels, extra]
+        .into_iter()
+        .filter(|labels| !labels.is_empty())
+        .collect::<Vec<_>>()
+        .join(",");
+    if labels.is_empty() {
+        format!("{}{}", name, suffix)
+    } else {
+        format!("{}{}{{{}}}", name, suffix, labels)
+    }
+}
+
+/// Every metric recorded so far, in the Prometheus text format
+pub fn render() -> String {
+    let families = FAMILIES.lock().unwrap();
+    let mut text = String::new();
+    for (metric, series) in families.values() {
+        let kind = match metric.kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        };
+        let _ = writeln!(text, "# HELP {} {}", metric.name, metric.help);
+        let _ = writeln!(text, "# TYPE {} {}", metric.name, kind);
+        for (labels, value) in series {
+            match value {
+                Value::Number(number) => {
+                    let _ = writeln!(
+                        text,
+                        "{} {}",
+                        series_name(metric.name, "", labels, ""),
+                        number
+                    );
+                }
+                Value::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                } => {
+                    for (bucket, bound) in buckets.iter().zip(BUCKETS) {
+                        let le = format!("le=\"{}\"", bound);
+                        let name = series_name(metric.name, "_bucket", labels, &le);
+                        let _ = writeln!(text, "{} {}", name, bucket);
+                    }
+                    let name = series_name(metric.name, "_bucket", labels, "le=\"+Inf\"");
+                    let _ = writeln!(text, "{} {}", name, count);
+                    let _ = writeln!(
+                        text,
+                        "{} {}",
+                        series_name(metric.name, "_sum", labels, ""),
+                        sum
+                    );
+                    let _ = writeln!(
+                        text,
+                        "{} {}",
+                        series_name(metric.name, "_count", labels, ""),
+                        count
+                    );
+                }
+            }
+        }
+    }
+    text
+}
+
+/// GET /metrics, added to a server's routes
+pub fn route() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .map(|| reply::with_header(render(), "Content-Type", "text/plain; version=0.0.4"))
+}
+
+/// Counts and times every request `server` answers, wrapped around its routes with `.with()`
+pub fn requests(server: &'static str) -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Clone> {
+    warp::log::custom(move |info| {
+        let status = info.status().as_u16().to_string();
+        HTTP_REQUESTS.inc(&[
+            ("server", server),
+            ("method", info.method().as_str()),
+            ("status", &status),
+        ]);
+        HTTP_REQUEST_DURATION.observe(&[("server", server)], info.elapsed().as_secs_f64());
+    })
+}
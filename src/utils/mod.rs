@@ -1,2 +1,37 @@
+pub mod archive;
+pub mod args;
+pub mod audit;
+pub mod country;
+pub mod data_dir;
+pub mod docker;
+pub mod download_cache;
+pub mod events;
 pub mod hackattic_client;
+pub mod hashing;
+pub mod health;
+pub mod history;
+pub mod http_server;
+pub mod logging;
+pub mod md5_collision;
+pub mod metrics;
+pub mod normalize;
+pub mod notify;
+pub mod output;
+pub mod ocr;
+pub mod pg_dump;
+pub mod preprocess;
+pub mod progress;
+pub mod qr;
+pub mod quota;
+pub mod reachability;
+pub mod runner;
+pub mod seed;
+pub mod shutdown;
+pub mod supervisor;
+pub mod telemetry;
+pub mod timing;
+pub mod unpack;
+pub mod watchdog;
 pub mod zip;
+pub mod tls;
+pub mod tunnel;
@@ -0,0 +1,215 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Op {
+    /// Reads an operator, including the look-alikes OCR tends to produce
+    pub fn from_char(c: char) -> Option<Op> {
+        match c {
+            '+' | '＋' | '﹢' => Some(Op::Add),
+            // hyphen, unicode minus, dashes and their full-width/small forms
+            '-' | '−' | '‐' | '‑' | '‒' | '–' | '—' | '﹣' | '－' => Some(Op::Sub),
+            '×' | 'x' | 'X' | '*' | '✕' | '✖' | '⨯' | '∗' | '＊' => Some(Op::Mul),
+            '÷' | '/' | '∕' | '／' => Some(Op::Div),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Op::Add => '+',
+            Op::Sub => '-',
+            Op::Mul => '×',
+            Op::Div => '÷',
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// One line of the expression: an operator applied to a number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Term {
+    pub op: Op,
+    pub value: i128,
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.op, self.value)
+    }
+}
+
+/// Parses an OCR line like `× 1,234` or `－１２` into a term
+pub fn parse_term(line: &str) -> Result<Term, String> {
+    let line = line.trim();
+    let mut chars = line.chars();
+    let op = chars
+        .next()
+        .and_then(Op::from_char)
+        .ok_or_else(|| format!("{:?} doesn't start with an operator", line))?;
+    let value = parse_number(chars.as_str())
+        .map_err(|e| format!("{:?} doesn't end with a number: {}", line, e))?;
+
+    Ok(Term { op, value })
+}
+
+/// Parses an integer written with full-width digits, stray whitespace and/or thousands separators
+pub fn parse_number(s: &str) -> Result<i128, String> {
+    let digits: String = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '０'..='９' => char::from_digit(c as u32 - '０' as u32, 10).unwrap(),
+            _ => c,
+        })
+        // separators that can't be mistaken for a decimal point
+        .filter(|c| !matches!(c, ',' | '\'' | '’' | '_' | '，'))
+        .collect();
+
+    // a dot only counts as a thousands separator when it splits off groups of three digits
+    let mut groups = digits.split('.');
+    let first = groups.next().unwrap_or_default();
+    let rest: Vec<&str> = groups.collect();
+    if rest.iter().any(|group| group.len() != 3) {
+        return Err(format!("{:?} is not an integer", s.trim()));
+    }
+    let digits = std::iter::once(first).chain(rest).collect::<String>();
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("{:?} is not a number", s.trim()));
+    }
+    digits
+        .parse::<i128>()
+        .map_err(|e| format!("{:?} is out of range: {}", s.trim(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_operator_look_alike() {
+        for (c, op) in [
+            ("+＋﹢", Op::Add),
+            ("-−‐‑‒–—﹣－", Op::Sub),
+            ("×xX*✕✖⨯∗＊", Op::Mul),
+            ("÷/∕／", Op::Div),
+        ] {
+            for c in c.chars() {
+                assert_eq!(Op::from_char(c), Some(op), "{:?}", c);
+            }
+        }
+        assert_eq!(Op::from_char('='), None);
+        assert_eq!(Op::from_char('1'), None);
+    }
+
+    #[test]
+    fn parses_terms() {
+        let term = |op, value| Ok(Term { op, value });
+        assert_eq!(parse_term("+ 12"), term(Op::Add, 12));
+        assert_eq!(parse_term("  −7  "), term(Op::Sub, 7));
+        assert_eq!(parse_term("× 1,234"), term(Op::Mul, 1234));
+        assert_eq!(parse_term("－１２"), term(Op::Sub, 12));
+        assert_eq!(parse_term("÷3"), term(Op::Div, 3));
+    }
+
+    #[test]
+    fn parses_full_width_digits() {
+        assert_eq!(parse_number("１２３４５６７８９０"), Ok(1234567890));
+    }
+
+    #[test]
+    fn skips_whitespace() {
+        assert_eq!(parse_number(" 1 234\t567 "), Ok(1234567));
+    }
+
+    #[test]
+    fn skips_thousands_separators() {
+        for s in [
+            "1,234,567",
+            "1'234'567",
+            "1’234’567",
+            "1_234_567",
+            "1，234，567",
+        ] {
+            assert_eq!(parse_number(s), Ok(1234567), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn takes_dots_grouping_thousands_as_separators() {
+        assert_eq!(parse_number("1.234.567"), Ok(1234567));
+        assert_eq!(parse_number("12.345"), Ok(12345));
+    }
+
+    #[test]
+    fn rejects_decimals() {
+        assert_eq!(
+            parse_number("1.5"),
+            Err("\"1.5\" is not an integer".to_string())
+        );
+        assert_eq!(
+            parse_number("12.34"),
+            Err("\"12.34\" is not an integer".to_string())
+        );
+        assert_eq!(
+            parse_number("1.2345"),
+            Err("\"1.2345\" is not an integer".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_number() {
+        assert_eq!(parse_number(""), Err("\"\" is not a number".to_string()));
+        assert_eq!(
+            parse_number(" , "),
+            Err("\",\" is not a number".to_string())
+        );
+        assert_eq!(
+            parse_number("12a"),
+            Err("\"12a\" is not a number".to_string())
+        );
+        assert_eq!(
+            parse_number("-12"),
+            Err("\"-12\" is not a number".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_numbers_out_of_range() {
+        let s = "9".repeat(40);
+        assert!(
+            parse_number(&s)
+                .unwrap_err()
+                .starts_with(&format!("{:?} is out of range", s))
+        );
+    }
+
+    #[test]
+    fn rejects_terms_without_an_operator_or_number() {
+        assert_eq!(
+            parse_term("12"),
+            Err("\"12\" doesn't start with an operator".to_string())
+        );
+        assert_eq!(
+            parse_term(""),
+            Err("\"\" doesn't start with an operator".to_string())
+        );
+        assert_eq!(
+            parse_term("+ 1.5"),
+            Err("\"+ 1.5\" doesn't end with a number: \"1.5\" is not an integer".to_string())
+        );
+        assert_eq!(
+            parse_term("×"),
+            Err("\"×\" doesn't end with a number: \"\" is not a number".to_string())
+        );
+    }
+}
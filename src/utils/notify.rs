@@ -0,0 +1,139 @@
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::utils::args;
+
+// Pings for unattended runs: a generic JSON webhook, Slack and Discord incoming webhooks and a
+// desktop notification, whichever are configured. Fired when a solver finds its result and when
+// a submission is accepted or rejected. Failures are only logged, a missed ping must never fail
+// the run.
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// notifications still being sent, waited for by flush()
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A solver found what it was searching for (a password, a nonce, a collision)
+    Found,
+    Accepted,
+    Rejected,
+}
+
+impl Event {
+    fn as_str(self) -> &'static str {
+        match self {
+            Event::Found => "found",
+            Event::Accepted => "accepted",
+            Event::Rejected => "rejected",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Config {
+    /// POSTed `{"event", "challenge", "message"}`
+    webhook: Option<String>,
+    slack: Option<String>,
+    discord: Option<String>,
+    desktop: bool,
+}
+
+impl Config {
+    fn is_empty(&self) -> bool {
+        self.webhook.is_none() && self.slack.is_none() && self.discord.is_none() && !self.desktop
+    }
+}
+
+fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| Config {
+        webhook: args::get("--notify-webhook", "NOTIFY_WEBHOOK"),
+        slack: args::get("--notify-slack", "NOTIFY_SLACK"),
+        discord: args::get("--notify-discord", "NOTIFY_DISCORD"),
+        desktop: args::is_set("--notify-desktop", "NOTIFY_DESKTOP"),
+    })
+}
+
+fn post(url: &str, body: serde_json::Value) -> Result<(), String> {
+    reqwest::blocking::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .and_then(|client| client.post(url).json(&body).send())
+        .and_then(|resp| resp.error_for_status())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn desktop(title: &str, message: &str) -> Result<(), String> {
+    let mut command = if cfg!(target_os = "macos") {
+        let script = format!("display notification {:?} with title {:?}", message, title);
+        let mut command = Command::new("osascript");
+        command.args(["-e", &script]);
+        command
+    } else if cfg!(unix) {
+        let mut command = Command::new("notify-send");
+        command.args([title, message]);
+        command
+    } else {
+        return Err("desktop notifications are only supported on Linux and macOS".to_string());
+    };
+    let status = command.status().map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("exited with {}", status));
+    }
+    Ok(())
+}
+
+fn send(config: &Config, event: Event, challenge: &str, message: &str) {
+    let text = format!("hackattic {}: {}", challenge, message);
+    if let Some(url) = &config.webhook {
+        let body = json!({ "event": event.as_str(), "challenge": challenge, "message": message });
+        if let Err(e) = post(url, body) {
+            warn!("Webhook notification failed: {}", e);
+        }
+    }
+    if let Some(url) = &config.slack
+        && let Err(e) = post(url, json!({ "text": text }))
+    {
+        warn!("Slack notification failed: {}", e);
+    }
+    if let Some(url) = &config.discord
+        && let Err(e) = post(url, json!({ "content": text }))
+    {
+        warn!("Discord notification failed: {}", e);
+    }
+    if config.desktop
+        && let Err(e) = desktop(&format!("hackattic {}", challenge), message)
+    {
+        warn!("Desktop notification failed: {}", e);
+    }
+}
+
+/// Sends the event to every configured channel, a no-op when none is
+pub fn notify(event: Event, challenge: &str, message: &str) {
+    let config = config();
+    if config.is_empty() {
+        return;
+    }
+    debug!("Notifying {} for {}", event.as_str(), challenge);
+    // on its own thread, blocking reqwest can't run on the async solvers' runtime, and not
+    // waited for here so a slow webhook never holds up the solver (or the runtime's worker)
+    let (challenge, message) = (challenge.to_string(), message.to_string());
+    let handle = std::thread::spawn(move || send(config, event, &challenge, &message));
+    PENDING.lock().unwrap().push(handle);
+}
+
+/// Waits for the notifications still being sent, before the process exits
+pub fn flush() {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap());
+    for handle in pending {
+        let _ = handle.join();
+    }
+}
@@ -0,0 +1,360 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::utils::data_dir::DataDir;
+
+const DEFAULT_CLOUD_OCR_URL: &str = "https://api.ocr.space/parse/image";
+
+/// A recognized line with the recognition score and bounding box, when the backend reports them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OcrLine {
+    pub text: String,
+    pub confidence: Option<f32>,
+    /// x_min, y_min, x_max, y_max in pixels of the recognized image
+    pub bbox: Option<[u32; 4]>,
+}
+
+impl OcrLine {
+    fn from_text(text: String) -> Self {
+        Self {
+            text,
+            confidence: None,
+            bbox: None,
+        }
+    }
+}
+
+/// Recognizes the text lines of an image
+pub trait OcrBackend {
+    fn name(&self) -> &'static str;
+    fn recognize(&self, image_path: &Path) -> Result<Vec<String>, String>;
+
+    /// Like `recognize`, with scores and boxes for backends that report them
+    fn recognize_lines(&self, image_path: &Path) -> Result<Vec<OcrLine>, String> {
+        let lines = self.recognize(image_path)?;
+        Ok(lines.into_iter().map(OcrLine::from_text).collect())
+    }
+
+    /// Runs the backend once on a blank image, which downloads its models and checks it works
+    fn prefetch(&self) -> Result<(), String> {
+        let path = DataDir::cache("ocr").file("prefetch.png")?;
+        image::GrayImage::from_pixel(64, 32, image::Luma([255]))
+            .save(&path)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        self.recognize(&path).map(|_| ())
+    }
+}
+
+// in-process OCR needs no external tools, so it's the default whenever it's compiled in
+#[cfg(feature = "leptess")]
+const DEFAULT_BACKEND: &str = "inprocess";
+#[cfg(not(feature = "leptess"))]
+const DEFAULT_BACKEND: &str = "paddle";
+
+/// The backend picked with `--ocr` (or `OCR_BACKEND`): paddle, tesseract, cloud or inprocess
+/// (with the `leptess` feature, and then the default). Results are cached unless `--no-cache`
+pub fn from_args() -> Result<Box<dyn OcrBackend>, String> {
+    let name = crate::utils::args::get("--ocr", "OCR_BACKEND");
+    let backend = from_name(name.as_deref().unwrap_or(DEFAULT_BACKEND))?;
+    Ok(cached(backend))
+}
+
+/// The backend picked with `--reocr` (or `REOCR_BACKEND`) to re-read low confidence lines,
+/// `None` to reuse the main one
+pub fn reocr_from_args() -> Result<Option<Box<dyn OcrBackend>>, String> {
+    match crate::utils::args::get("--reocr", "REOCR_BACKEND") {
+        Some(name) => Ok(Some(cached(from_name(&name)?))),
+        None => Ok(None),
+    }
+}
+
+fn cached(backend: Box<dyn OcrBackend>) -> Box<dyn OcrBackend> {
+    if crate::utils::args::is_set("--no-cache", "NO_CACHE") {
+        return backend;
+    }
+    Box::new(Cached { inner: backend })
+}
+
+pub fn from_name(name: &str) -> Result<Box<dyn OcrBackend>, String> {
+    match name {
+        "paddle" | "paddleocr" => Ok(Box::new(PaddleOcr)),
+        "tesseract" => Ok(Box::new(TesseractCli)),
+        "cloud" => Ok(Box::new(CloudOcr::from_env()?)),
+        #[cfg(feature = "leptess")]
+        "inprocess" | "leptess" => Ok(Box::new(InProcessOcr)),
+        #[cfg(not(feature = "leptess"))]
+        "inprocess" | "leptess" => Err("In-process OCR needs the leptess feature".to_string()),
+        _ => Err(format!(
+            "Unknown OCR backend {:?}, expected paddle, tesseract, cloud or inprocess",
+            name
+        )),
+    }
+}
+
+/// The `paddleocr` CLI (pip install paddlepaddle paddleocr)
+pub struct PaddleOcr;
+
+impl OcrBackend for PaddleOcr {
+    fn name(&self) -> &'static str {
+        "paddleocr"
+    }
+
+    fn recognize(&self, image_path: &Path) -> Result<Vec<String>, String> {
+        let lines = self.recognize_lines(image_path)?;
+        Ok(lines.into_iter().map(|line| line.text).collect())
+    }
+
+    fn recognize_lines(&self, image_path: &Path) -> Result<Vec<OcrLine>, String> {
+        // shared by the challenges, so under the cache's ocr/ rather than a challenge's
+        let output_dir = DataDir::cache("ocr").join("output");
+        let output_dir = output_dir.path();
+        let output = Command::new("paddleocr")
+            .arg("ocr")
+            .arg("-i")
+            .arg(image_path)
+            .args(["--use_doc_orientation_classify", "False"])
+            .args(["--use_doc_unwarping", "False"])
+            .args(["--use_textline_orientation", "False"])
+            .arg("--save_path")
+            .arg(output_dir)
+            .output()
+            .map_err(|e| format!("Failed to run paddleocr: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "paddleocr failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        // results are saved as <image stem>_res.json
+        let stem = image_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or("Invalid image path")?;
+        let result_path = output_dir.join(format!("{}_res.json", stem));
+        let json = std::fs::read_to_string(&result_path)
+            .map_err(|e| format!("Failed to read {}: {}", result_path.display(), e))?;
+        let json: serde_json::Value = serde_json::from_str(&json)
+            .map_err(|e| format!("Failed to parse {}: {}", result_path.display(), e))?;
+
+        let texts = json["rec_texts"]
+            .as_array()
+            .ok_or("paddleocr output has no rec_texts")?;
+        texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let text = text
+                    .as_str()
+                    .ok_or_else(|| format!("Invalid rec_texts entry: {}", text))?;
+                // scores and boxes are parallel to the texts
+                let confidence = json["rec_scores"][i].as_f64().map(|score| score as f32);
+                let bbox = json["rec_boxes"][i].as_array().and_then(|bbox| {
+                    let coords: Vec<u32> = bbox
+                        .iter()
+                        .filter_map(|c| c.as_f64())
+                        .map(|c| c.max(0.0) as u32)
+                        .collect();
+                    coords.try_into().ok()
+                });
+                Ok(OcrLine {
+                    text: text.to_string(),
+                    confidence,
+                    bbox,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The `tesseract` CLI, treating the image as a single block of text
+pub struct TesseractCli;
+
+impl OcrBackend for TesseractCli {
+    fn name(&self) -> &'static str {
+        "tesseract"
+    }
+
+    fn recognize(&self, image_path: &Path) -> Result<Vec<String>, String> {
+        let output = Command::new("tesseract")
+            .arg(image_path)
+            .arg("stdout")
+            .args(["--psm", "6"])
+            .output()
+            .map_err(|e| format!("Failed to run tesseract: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "tesseract failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        Ok(non_empty_lines(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// Tesseract linked in through leptess: no subprocess, temp files or Python environment,
+/// only the tesseract/leptonica libraries and the `eng` traineddata
+#[cfg(feature = "leptess")]
+pub struct InProcessOcr;
+
+#[cfg(feature = "leptess")]
+impl OcrBackend for InProcessOcr {
+    fn name(&self) -> &'static str {
+        "inprocess"
+    }
+
+    fn recognize(&self, image_path: &Path) -> Result<Vec<String>, String> {
+        let image = std::fs::read(image_path)
+            .map_err(|e| format!("Failed to read {}: {}", image_path.display(), e))?;
+
+        let mut tesseract = leptess::LepTess::new(None, "eng")
+            .map_err(|e| format!("Failed to initialize tesseract: {}", e))?;
+        // a single uniform block of text
+        tesseract
+            .set_variable(leptess::Variable::TesseditPagesegMode, "6")
+            .map_err(|e| format!("Failed to configure tesseract: {}", e))?;
+        tesseract
+            .set_image_from_mem(&image)
+            .map_err(|e| format!("Failed to load image into tesseract: {}", e))?;
+        let text = tesseract
+            .get_utf8_text()
+            .map_err(|e| format!("Tesseract returned invalid UTF-8: {}", e))?;
+
+        Ok(non_empty_lines(&text))
+    }
+}
+
+/// An OCR.space compatible HTTP API, configured with `OCR_API_KEY` and optionally `OCR_API_URL`
+pub struct CloudOcr {
+    url: String,
+    api_key: String,
+}
+
+impl CloudOcr {
+    pub fn from_env() -> Result<Self, String> {
+        let api_key = crate::utils::args::get("--ocr-api-key", "OCR_API_KEY")
+            .ok_or("OCR_API_KEY must be set for the cloud OCR backend")?;
+        let url = crate::utils::args::get("--ocr-api-url", "OCR_API_URL")
+            .unwrap_or(DEFAULT_CLOUD_OCR_URL.to_string());
+        Ok(Self { url, api_key })
+    }
+}
+
+impl OcrBackend for CloudOcr {
+    fn name(&self) -> &'static str {
+        "cloud"
+    }
+
+    // nothing to download, and it needs the network anyway
+    fn prefetch(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn recognize(&self, image_path: &Path) -> Result<Vec<String>, String> {
+        let image = std::fs::read(image_path)
+            .map_err(|e| format!("Failed to read {}: {}", image_path.display(), e))?;
+        let image = format!(
+            "data:image/jpeg;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(image)
+        );
+
+        let response: serde_json::Value = reqwest::blocking::Client::new()
+            .post(&self.url)
+            .header("apikey", &self.api_key)
+            .form(&[
+                ("base64Image", image.as_str()),
+                ("OCREngine", "2"),
+                ("scale", "true"),
+            ])
+            .send()
+            .and_then(|resp| resp.error_for_status())
+            .and_then(|resp| resp.json())
+            .map_err(|e| format!("Cloud OCR request failed: {}", e))?;
+
+        if response["IsErroredOnProcessing"].as_bool() == Some(true) {
+            return Err(format!("Cloud OCR failed: {}", response["ErrorMessage"]));
+        }
+        let text = response["ParsedResults"][0]["ParsedText"]
+            .as_str()
+            .ok_or_else(|| format!("Unexpected cloud OCR response: {}", response))?;
+
+        Ok(non_empty_lines(text))
+    }
+}
+
+/// Remembers the lines another backend recognized, keyed by the SHA-256 of the image, so
+/// re-running the same problem skips the slow model
+pub struct Cached {
+    inner: Box<dyn OcrBackend>,
+}
+
+impl Cached {
+    fn cache_dir() -> DataDir {
+        DataDir::cache("ocr").join("results")
+    }
+
+    fn cache_path(&self, image: &[u8]) -> PathBuf {
+        let hash = hex::encode(Sha256::digest(image));
+        Self::cache_dir()
+            .path()
+            .join(format!("{}_{}.json", self.inner.name(), hash))
+    }
+}
+
+impl OcrBackend for Cached {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    // a cached result would hide a backend that can't run
+    fn prefetch(&self) -> Result<(), String> {
+        self.inner.prefetch()
+    }
+
+    fn recognize(&self, image_path: &Path) -> Result<Vec<String>, String> {
+        let lines = self.recognize_lines(image_path)?;
+        Ok(lines.into_iter().map(|line| line.text).collect())
+    }
+
+    fn recognize_lines(&self, image_path: &Path) -> Result<Vec<OcrLine>, String> {
+        let image = std::fs::read(image_path)
+            .map_err(|e| format!("Failed to read {}: {}", image_path.display(), e))?;
+        let cache_path = self.cache_path(&image);
+
+        if let Some(lines) = std::fs::read_to_string(&cache_path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<Vec<OcrLine>>(&json).ok())
+        {
+            debug!("Using cached OCR result {}", cache_path.display());
+            return Ok(lines);
+        }
+
+        let lines = self.inner.recognize_lines(image_path)?;
+        // a failed write only costs the next run a model invocation
+        if let Err(e) = Self::cache_dir().create().and_then(|_| {
+            std::fs::write(&cache_path, serde_json::json!(lines).to_string())
+                .map_err(|e| e.to_string())
+        }) {
+            warn!(
+                "Failed to cache OCR result in {}: {}",
+                cache_path.display(),
+                e
+            );
+        }
+        Ok(lines)
+    }
+}
+
+fn non_empty_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
@@ -0,0 +1,62 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+use crate::utils::args;
+
+// ANSI colors for the console, on only when the stream is a terminal, --no-color isn't passed,
+// NO_COLOR (https://no-color.org) is unset and TERM isn't dumb. The logger's JSON format never
+// uses them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Error,
+    Warn,
+    Success,
+    Dim,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Error => "1;31",
+            Style::Warn => "33",
+            Style::Success => "32",
+            Style::Dim => "2",
+        }
+    }
+}
+
+fn disabled_by_user() -> bool {
+    static DISABLED: OnceLock<bool> = OnceLock::new();
+    *DISABLED.get_or_init(|| {
+        args::is_set("--no-color", "HACKATTIC_NO_COLOR")
+            || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+            || std::env::var("TERM").is_ok_and(|term| term == "dumb")
+    })
+}
+
+/// Whether text written to the stream should be colored
+pub fn color_enabled(stream: Stream) -> bool {
+    if disabled_by_user() {
+        return false;
+    }
+    match stream {
+        Stream::Stdout => std::io::stdout().is_terminal(),
+        Stream::Stderr => std::io::stderr().is_terminal(),
+    }
+}
+
+/// The text in the style when the stream is colored, as is otherwise
+pub fn paint(text: &str, style: Style, stream: Stream) -> String {
+    if color_enabled(stream) {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
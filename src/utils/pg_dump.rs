@@ -0,0 +1,750 @@
+// Parser for the data sections of a plain-text pg_dump:
+//
+// COPY public.criminal_records (id, name, ssn, status) FROM stdin;
+// 1    John    123-45-6789    alive
+// 2    \N      987-65-4321    dead
+// \.
+//
+// Fields are separated by a tab, `\N` is NULL, and tabs, newlines and backslashes inside values
+// are backslash escaped, so a row is always exactly one line
+
+use std::fmt;
+use std::io::{BufRead, ErrorKind, Write};
+use std::iter::Peekable;
+use std::process::{Command, Output, Stdio};
+use std::str::{Chars, FromStr};
+use std::thread;
+
+use serde::de::value::{self, MapDeserializer};
+use serde::de::{self, DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use tracing::info;
+
+use crate::utils::docker;
+
+/// Image providing pg_restore when it isn't installed locally
+pub const POSTGRES_IMAGE: &str = "postgres:17-alpine";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// SQL script, what `pg_dump` writes by default
+    Plain,
+    /// `pg_dump -Fc`, starts with `PGDMP`
+    Custom,
+    /// `pg_dump -Ft`
+    Tar,
+}
+
+impl DumpFormat {
+    /// Detects the format from the first bytes of the (decompressed) dump
+    pub fn detect(head: &[u8]) -> DumpFormat {
+        if head.starts_with(b"PGDMP") {
+            DumpFormat::Custom
+        } else if head.get(257..262) == Some(b"ustar") {
+            DumpFormat::Tar
+        } else {
+            DumpFormat::Plain
+        }
+    }
+}
+
+/// Converts a custom or tar archive to a plain SQL script with pg_restore, the local binary when
+/// it's installed, otherwise the one in the postgres image. The script is buffered in memory.
+pub fn archive_to_sql(archive: &[u8], format: DumpFormat) -> Result<Vec<u8>, String> {
+    let format_flag = match format {
+        DumpFormat::Plain => return Ok(archive.to_vec()),
+        DumpFormat::Custom => "--format=custom",
+        DumpFormat::Tar => "--format=tar",
+    };
+
+    let output = match pg_restore(
+        Command::new("pg_restore").args([format_flag, "-f", "-"]),
+        archive,
+    ) {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            info!(
+                "pg_restore isn't installed, running it in {}",
+                POSTGRES_IMAGE
+            );
+            docker::preflight(POSTGRES_IMAGE)?;
+            pg_restore(
+                Command::new("docker").args([
+                    "run",
+                    "-i",
+                    "--rm",
+                    POSTGRES_IMAGE,
+                    "pg_restore",
+                    format_flag,
+                    "-f",
+                    "-",
+                ]),
+                archive,
+            )
+        }
+        output => output,
+    }
+    .map_err(|e| format!("Failed to run pg_restore: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "pg_restore failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+fn pg_restore(command: &mut Command, archive: &[u8]) -> std::io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+
+    // Fed from another thread, pg_restore starts writing before it has read everything
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let _ = stdin.write_all(archive);
+        });
+        child.wait_with_output()
+    })
+}
+
+/// The `COPY ... FROM stdin;` statement starting a block of rows
+#[derive(Debug, Clone)]
+pub struct CopyHeader {
+    pub table: String,
+    /// Empty when the statement doesn't list them
+    pub columns: Vec<String>,
+}
+
+impl CopyHeader {
+    /// Whether this is the given table, with or without its schema (`public.users` is `users`)
+    pub fn is_table(&self, name: &str) -> bool {
+        self.table == name
+            || self
+                .table
+                .rsplit_once('.')
+                .is_some_and(|(_, table)| table == name)
+    }
+
+    /// Index of the named column in every row
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column == name)
+    }
+}
+
+/// Streams the rows of every COPY block in the dump to `on_row` as they're read, `None` for
+/// NULL fields. The rest of the SQL is skipped. Returns the headers of all blocks seen, or the
+/// first error of the parser or `on_row`.
+pub fn for_each_row<R: BufRead>(
+    mut reader: R,
+    mut on_row: impl FnMut(&CopyHeader, Vec<Option<String>>) -> Result<(), String>,
+) -> Result<Vec<CopyHeader>, String> {
+    let mut headers = Vec::new();
+    let mut in_block = false;
+    let mut line = String::new();
+    let mut line_number = 0;
+
+    loop {
+        line.clear();
+        line_number += 1;
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| format!("Failed to read the dump at line {}: {}", line_number, e))?;
+        if read == 0 {
+            break;
+        }
+        let line = line.trim_end_matches('\n');
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if in_block {
+            if line == "\\." {
+                in_block = false;
+            } else {
+                parse_row(line)
+                    .and_then(|row| on_row(headers.last().expect("a block has a header"), row))
+                    .map_err(|e| format!("Line {}: {}", line_number, e))?;
+            }
+        } else if line.starts_with("COPY ") {
+            let header =
+                parse_copy_statement(line).map_err(|e| format!("Line {}: {}", line_number, e))?;
+            headers.push(header);
+            in_block = true;
+        }
+    }
+
+    if in_block && let Some(header) = headers.last() {
+        return Err(format!(
+            "COPY block for {} isn't terminated by \\.",
+            header.table
+        ));
+    }
+    Ok(headers)
+}
+
+/// Streams the rows of `table` deserialized into `T`, whose fields are matched to the columns
+/// by name. Columns without a field are ignored.
+pub fn for_each_record<T: DeserializeOwned, R: BufRead>(
+    reader: R,
+    table: &str,
+    mut on_record: impl FnMut(T),
+) -> Result<Vec<CopyHeader>, String> {
+    for_each_row(reader, |header, row| {
+        if header.is_table(table) {
+            on_record(from_row(header, row)?);
+        }
+        Ok(())
+    })
+}
+
+/// Deserializes one row, NULL maps to `None` and the text values are parsed for numeric and
+/// boolean (`t`/`f`) fields
+pub fn from_row<T: DeserializeOwned>(
+    header: &CopyHeader,
+    row: Vec<Option<String>>,
+) -> Result<T, String> {
+    if header.columns.len() != row.len() {
+        return Err(format!(
+            "{} has {} columns but the row has {} fields",
+            header.table,
+            header.columns.len(),
+            row.len()
+        ));
+    }
+    let fields = header
+        .columns
+        .iter()
+        .map(String::as_str)
+        .zip(row.into_iter().map(FieldDeserializer));
+    T::deserialize(MapDeserializer::<_, value::Error>::new(fields))
+        .map_err(|e| format!("Invalid {} row: {}", header.table, e))
+}
+
+struct FieldDeserializer(Option<String>);
+
+impl FieldDeserializer {
+    fn parse<T: FromStr>(self, kind: &str) -> Result<T, value::Error>
+    where
+        T::Err: fmt::Display,
+    {
+        let text = self
+            .0
+            .ok_or_else(|| de::Error::custom(format!("unexpected NULL, expected {}", kind)))?;
+        text.parse()
+            .map_err(|e| de::Error::custom(format!("invalid {} {:?}: {}", kind, text, e)))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, value::Error> for FieldDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, value::Error> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for FieldDeserializer {
+    type Error = value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, value::Error> {
+        match self.0 {
+            Some(text) => visitor.visit_string(text),
+            None => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, value::Error> {
+        match self.0 {
+            Some(_) => visitor.visit_some(self),
+            None => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, value::Error> {
+        match self.0.as_deref() {
+            Some("t" | "true") => visitor.visit_bool(true),
+            Some("f" | "false") => visitor.visit_bool(false),
+            _ => Err(de::Error::custom(format!("invalid bool {:?}", self.0))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8(i8),
+        deserialize_i16 => visit_i16(i16),
+        deserialize_i32 => visit_i32(i32),
+        deserialize_i64 => visit_i64(i64),
+        deserialize_u8 => visit_u8(u8),
+        deserialize_u16 => visit_u16(u16),
+        deserialize_u32 => visit_u32(u32),
+        deserialize_u64 => visit_u64(u64),
+        deserialize_f32 => visit_f32(f32),
+        deserialize_f64 => visit_f64(f64),
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Reads the table and column names from `COPY <table> [(columns)] FROM stdin;`
+fn parse_copy_statement(line: &str) -> Result<CopyHeader, String> {
+    if !line.trim_end().ends_with("FROM stdin;") {
+        return Err(format!("Unsupported COPY statement {:?}", line));
+    }
+    let mut chars = line["COPY ".len()..].trim_start().chars().peekable();
+
+    let table = read_identifier(&mut chars);
+    if table.is_empty() {
+        return Err(format!("COPY statement without a table {:?}", line));
+    }
+
+    let mut columns = Vec::new();
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.next_if_eq(&'(').is_some() {
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            columns.push(read_identifier(&mut chars));
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match chars.next() {
+                Some(',') => continue,
+                Some(')') => break,
+                _ => return Err(format!("Unterminated column list in {:?}", line)),
+            }
+        }
+    }
+
+    Ok(CopyHeader { table, columns })
+}
+
+/// Reads a possibly schema qualified, possibly quoted name. Quoted parts keep their case and
+/// can contain spaces, commas or parens, `""` is a literal quote.
+fn read_identifier(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    let mut quoted = false;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '"' if quoted && chars.clone().nth(1) == Some('"') => {
+                name.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if !quoted && (c.is_whitespace() || matches!(c, '(' | ')' | ',')) => break,
+            c => name.push(c),
+        }
+        chars.next();
+    }
+    name
+}
+
+fn parse_row(line: &str) -> Result<Vec<Option<String>>, String> {
+    line.split('\t').map(parse_field).collect()
+}
+
+fn parse_field(field: &str) -> Result<Option<String>, String> {
+    if field == "\\N" {
+        return Ok(None);
+    }
+    if !field.contains('\\') {
+        return Ok(Some(field.to_string()));
+    }
+
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let Some(escaped) = chars.next() else {
+            return Err(format!("Dangling backslash in {:?}", field));
+        };
+        match escaped {
+            'b' => bytes.push(0x08),
+            'f' => bytes.push(0x0c),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'v' => bytes.push(0x0b),
+            // \x followed by one or two hex digits
+            'x' if chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) => {
+                let mut value = 0u32;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => {
+                            value = value * 16 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            // One to three octal digits
+            '0'..='7' => {
+                let mut value = escaped.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            // Any other escaped character stands for itself, `\\` included
+            other => {
+                let mut buf = [0u8; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+
+    String::from_utf8(bytes)
+        .map(Some)
+        .map_err(|e| format!("Invalid UTF-8 in {:?}: {}", field, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Row = (String, Vec<Option<String>>);
+
+    fn rows(dump: &str) -> Result<Vec<Row>, String> {
+        let mut rows = Vec::new();
+        for_each_row(dump.as_bytes(), |header, row| {
+            rows.push((header.table.clone(), row));
+            Ok(())
+        })?;
+        Ok(rows)
+    }
+
+    fn text(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn parses_plain_fields_and_null() {
+        assert_eq!(parse_field("alive"), Ok(text("alive")));
+        assert_eq!(parse_field(""), Ok(text("")));
+        assert_eq!(parse_field("\\N"), Ok(None));
+        // only a whole field is NULL
+        assert_eq!(parse_field("\\\\N"), Ok(text("\\N")));
+        assert_eq!(parse_row("1\t\\N\tx"), Ok(vec![text("1"), None, text("x")]));
+    }
+
+    #[test]
+    fn unescapes_control_characters() {
+        assert_eq!(
+            parse_field("a\\tb\\nc\\rd\\be\\ff\\vg"),
+            Ok(text("a\tb\nc\rd\u{8}e\u{c}f\u{b}g"))
+        );
+        assert_eq!(parse_field("back\\\\slash"), Ok(text("back\\slash")));
+        // any other escaped character stands for itself
+        assert_eq!(parse_field("\\q\\."), Ok(text("q.")));
+    }
+
+    #[test]
+    fn unescapes_octal() {
+        assert_eq!(parse_field("\\101"), Ok(text("A")));
+        assert_eq!(parse_field("\\11x"), Ok(text("\tx")));
+        assert_eq!(parse_field("\\0"), Ok(text("\0")));
+        // three digits at most
+        assert_eq!(parse_field("\\1011"), Ok(text("A1")));
+        // bytes build up UTF-8
+        assert_eq!(parse_field("caf\\303\\251"), Ok(text("café")));
+    }
+
+    #[test]
+    fn unescapes_hex() {
+        assert_eq!(parse_field("\\x41"), Ok(text("A")));
+        assert_eq!(parse_field("\\x9z"), Ok(text("\tz")));
+        // two digits at most
+        assert_eq!(parse_field("\\x414"), Ok(text("A4")));
+        assert_eq!(parse_field("caf\\xc3\\xA9"), Ok(text("café")));
+        // without a digit it's a plain x
+        assert_eq!(parse_field("\\xg"), Ok(text("xg")));
+    }
+
+    #[test]
+    fn rejects_bad_escapes() {
+        assert!(
+            parse_field("oops\\")
+                .unwrap_err()
+                .starts_with("Dangling backslash")
+        );
+        assert!(
+            parse_field("\\xff")
+                .unwrap_err()
+                .starts_with("Invalid UTF-8")
+        );
+    }
+
+    #[test]
+    fn parses_copy_statements() {
+        let header =
+            parse_copy_statement("COPY public.criminal_records (id, name, ssn) FROM stdin;")
+                .unwrap();
+        assert_eq!(header.table, "public.criminal_records");
+        assert_eq!(header.columns, ["id", "name", "ssn"]);
+        assert!(header.is_table("criminal_records"));
+        assert!(header.is_table("public.criminal_records"));
+        assert!(!header.is_table("records"));
+        assert_eq!(header.column("ssn"), Some(2));
+
+        let header = parse_copy_statement("COPY users FROM stdin;").unwrap();
+        assert_eq!(header.table, "users");
+        assert!(header.columns.is_empty());
+    }
+
+    #[test]
+    fn parses_quoted_identifiers() {
+        let header = parse_copy_statement(
+            r#"COPY "My Schema"."Weird, (Table)" ("Full Name", "say ""hi""", plain) FROM stdin;"#,
+        )
+        .unwrap();
+        assert_eq!(header.table, "My Schema.Weird, (Table)");
+        assert_eq!(header.columns, ["Full Name", r#"say "hi""#, "plain"]);
+        assert!(header.is_table("Weird, (Table)"));
+    }
+
+    #[test]
+    fn rejects_bad_copy_statements() {
+        assert!(parse_copy_statement("COPY users TO stdout;").is_err());
+        assert!(parse_copy_statement("COPY (id) FROM stdin;").is_err());
+        assert!(
+            parse_copy_statement("COPY users (id, name FROM stdin;")
+                .unwrap_err()
+                .starts_with("Unterminated column list")
+        );
+    }
+
+    #[test]
+    fn reads_every_block() {
+        let dump = "SET client_encoding = 'UTF8';\n\
+                    COPY public.cities (id, name) FROM stdin;\n\
+                    1\tNew York\n\
+                    \\.\n\
+                    \n\
+                    ALTER TABLE ONLY public.cities;\n\
+                    COPY public.criminal_records (id, ssn) FROM stdin;\r\n\
+                    1\t123-45-6789\r\n\
+                    2\t\\N\n\
+                    \\.\n";
+        assert_eq!(
+            rows(dump),
+            Ok(vec![
+                (
+                    "public.cities".to_string(),
+                    vec![text("1"), text("New York")]
+                ),
+                (
+                    "public.criminal_records".to_string(),
+                    vec![text("1"), text("123-45-6789")]
+                ),
+                ("public.criminal_records".to_string(), vec![text("2"), None]),
+            ])
+        );
+
+        let mut ssns = Vec::new();
+        let headers = for_each_row(dump.as_bytes(), |header, row| {
+            if header.is_table("criminal_records") {
+                ssns.push(row[header.column("ssn").unwrap()].clone());
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(ssns, [text("123-45-6789"), None]);
+    }
+
+    #[test]
+    fn rejects_an_unterminated_block() {
+        let dump = "COPY public.users (id) FROM stdin;\n1\n2\n";
+        assert_eq!(
+            rows(dump),
+            Err("COPY block for public.users isn't terminated by \\.".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_the_failing_line() {
+        let dump = "COPY users (id) FROM stdin;\n1\nbad\\\n\\.\n";
+        assert!(
+            rows(dump)
+                .unwrap_err()
+                .starts_with("Line 3: Dangling backslash")
+        );
+
+        let error = for_each_row(dump.as_bytes(), |_, _| Err("stop".to_string()));
+        assert_eq!(error.unwrap_err(), "Line 2: stop");
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Record {
+        id: u32,
+        name: String,
+        nickname: Option<String>,
+        alive: bool,
+        balance: i64,
+        score: Option<f64>,
+    }
+
+    fn header(columns: &[&str]) -> CopyHeader {
+        CopyHeader {
+            table: "public.records".to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    const COLUMNS: [&str; 6] = ["id", "name", "nickname", "alive", "balance", "score"];
+
+    fn record(row: [Option<&str>; 6]) -> Result<Record, String> {
+        from_row(
+            &header(&COLUMNS),
+            row.iter().map(|field| field.map(str::to_string)).collect(),
+        )
+    }
+
+    #[test]
+    fn deserializes_rows_by_column_name() {
+        assert_eq!(
+            record([
+                Some("7"),
+                Some("John"),
+                Some("Johnny"),
+                Some("t"),
+                Some("-42"),
+                Some("1.5")
+            ]),
+            Ok(Record {
+                id: 7,
+                name: "John".to_string(),
+                nickname: Some("Johnny".to_string()),
+                alive: true,
+                balance: -42,
+                score: Some(1.5),
+            })
+        );
+
+        // the columns needn't be in field order
+        let row = ["f", "0", "Jane", "\\N", "8", "\\N"]
+            .iter()
+            .map(|field| parse_field(field).unwrap())
+            .collect();
+        let header = header(&["alive", "balance", "name", "nickname", "id", "score"]);
+        assert_eq!(
+            from_row::<Record>(&header, row),
+            Ok(Record {
+                id: 8,
+                name: "Jane".to_string(),
+                nickname: None,
+                alive: false,
+                balance: 0,
+                score: None,
+            })
+        );
+    }
+
+    #[test]
+    fn deserializes_bools() {
+        for (text, expected) in [("t", true), ("true", true), ("f", false), ("false", false)] {
+            let record = record([Some("1"), Some(""), None, Some(text), Some("0"), None]);
+            assert_eq!(record.map(|r| r.alive), Ok(expected), "{}", text);
+        }
+
+        let error = record([Some("1"), Some(""), None, Some("yes"), Some("0"), None]);
+        assert!(error.unwrap_err().contains("invalid bool"));
+    }
+
+    #[test]
+    fn rejects_fields_that_dont_parse() {
+        let error = record([Some("x1"), Some(""), None, Some("t"), Some("0"), None]);
+        assert!(error.unwrap_err().contains("invalid u32 \"x1\""));
+
+        let error = record([Some("-1"), Some(""), None, Some("t"), Some("0"), None]);
+        assert!(error.unwrap_err().contains("invalid u32 \"-1\""));
+
+        let error = record([Some("1"), Some(""), None, Some("t"), Some("1e3"), None]);
+        assert!(error.unwrap_err().contains("invalid i64 \"1e3\""));
+
+        let error = record([
+            Some("1"),
+            Some(""),
+            None,
+            Some("t"),
+            Some("0"),
+            Some("high"),
+        ]);
+        assert!(error.unwrap_err().contains("invalid f64 \"high\""));
+    }
+
+    #[test]
+    fn rejects_null_in_required_fields() {
+        let error = record([None, Some(""), None, Some("t"), Some("0"), None]);
+        assert!(
+            error
+                .unwrap_err()
+                .starts_with("Invalid public.records row: unexpected NULL, expected u32")
+        );
+
+        let error = record([Some("1"), Some(""), None, None, Some("0"), None]);
+        assert!(error.unwrap_err().contains("invalid bool None"));
+
+        // a String field doesn't take NULL either
+        let error = record([Some("1"), None, None, Some("t"), Some("0"), None]);
+        assert!(error.unwrap_err().starts_with("Invalid public.records row"));
+    }
+
+    #[test]
+    fn rejects_a_column_count_mismatch() {
+        let row = vec![text("1"), text("John")];
+        assert_eq!(
+            from_row::<Record>(&header(&COLUMNS), row),
+            Err("public.records has 6 columns but the row has 2 fields".to_string())
+        );
+    }
+
+    #[test]
+    fn ignores_extra_columns() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Ssn {
+            ssn: String,
+        }
+
+        let dump = "COPY public.criminal_records (id, name, ssn, status) FROM stdin;\n\
+                    1\tJohn\t123-45-6789\talive\n\
+                    2\t\\N\t987-65-4321\tdead\n\
+                    \\.\n";
+        let mut ssns = Vec::new();
+        for_each_record(dump.as_bytes(), "criminal_records", |record: Ssn| {
+            ssns.push(record.ssn)
+        })
+        .unwrap();
+        assert_eq!(ssns, ["123-45-6789", "987-65-4321"]);
+
+        // a missing field is an error though
+        let error = for_each_record(dump.as_bytes(), "criminal_records", |_: Record| {});
+        assert!(
+            error
+                .unwrap_err()
+                .starts_with("Line 2: Invalid public.criminal_records row")
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma};
+use imageproc::contrast::{adaptive_threshold, otsu_level, stretch_contrast};
+use imageproc::geometric_transformations::{Interpolation, rotate_about_center};
+use tracing::info;
+
+const UPSCALE: u32 = 3;
+// Share of the darkest/brightest pixels clipped by contrast normalization
+const CONTRAST_CLIP: f64 = 0.01;
+// Skew angles tried by deskew, in degrees
+const MAX_SKEW_DEGREES: f32 = 5.0;
+const SKEW_STEP_DEGREES: f32 = 0.5;
+// Neighbourhood of the adaptive threshold, before upscaling
+const THRESHOLD_BLOCK_RADIUS: u32 = 7;
+
+/// Cleans up an image for OCR: grayscale, contrast normalization, deskew, upscale and adaptive
+/// threshold, leaving black text on a white background
+pub fn for_ocr(image: &DynamicImage) -> GrayImage {
+    let mut gray = image.to_luma8();
+
+    // OCR engines expect dark text on a light background
+    if mean(&gray) < 128.0 {
+        image::imageops::invert(&mut gray);
+    }
+
+    let gray = normalize_contrast(&gray);
+
+    // the skew is estimated on the original size, rotating is cheaper there
+    let angle = estimate_skew(&adaptive_threshold(&gray, THRESHOLD_BLOCK_RADIUS));
+    if angle != 0.0 {
+        info!("Deskewing by {:.1}°", angle);
+    }
+
+    let upscaled = image::imageops::resize(
+        &gray,
+        gray.width() * UPSCALE,
+        gray.height() * UPSCALE,
+        FilterType::Lanczos3,
+    );
+    let deskewed = rotate(&upscaled, angle, Interpolation::Bilinear);
+
+    adaptive_threshold(&deskewed, THRESHOLD_BLOCK_RADIUS * UPSCALE)
+}
+
+pub type Transform = fn(&GrayImage) -> GrayImage;
+
+/// Transforms retried in order when a QR code doesn't decode as is
+pub const QR_RETRIES: &[(&str, Transform)] = &[
+    ("Otsu threshold", otsu_threshold),
+    ("2x upscale", |gray| upscale(gray, 2)),
+    ("3x upscale", |gray| upscale(gray, 3)),
+    ("4x upscale", |gray| upscale(gray, 4)),
+    ("sharpen", |gray| image::imageops::unsharpen(gray, 1.5, 10)),
+    ("rotate 90°", image::imageops::rotate90),
+    ("rotate 180°", image::imageops::rotate180),
+    ("rotate 270°", image::imageops::rotate270),
+    ("invert", |gray| {
+        let mut inverted = gray.clone();
+        image::imageops::invert(&mut inverted);
+        inverted
+    }),
+];
+
+/// Black and white at the level that best separates the two intensity classes
+fn otsu_threshold(gray: &GrayImage) -> GrayImage {
+    let level = otsu_level(gray);
+    GrayImage::from_fn(gray.width(), gray.height(), |x, y| {
+        if gray.get_pixel(x, y).0[0] > level {
+            Luma([255])
+        } else {
+            Luma([0])
+        }
+    })
+}
+
+// Nearest neighbour keeps the modules' edges sharp
+fn upscale(gray: &GrayImage, factor: u32) -> GrayImage {
+    image::imageops::resize(
+        gray,
+        gray.width() * factor,
+        gray.height() * factor,
+        FilterType::Nearest,
+    )
+}
+
+/// Stretches the intensities between the 1st and 99th percentile to the full range
+fn normalize_contrast(gray: &GrayImage) -> GrayImage {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let total = gray.pixels().len() as f64;
+    let percentile = |share: f64| {
+        let mut seen = 0;
+        for (level, count) in histogram.iter().enumerate() {
+            seen += count;
+            if seen as f64 >= total * share {
+                return level as u8;
+            }
+        }
+        u8::MAX
+    };
+
+    let (lower, upper) = (percentile(CONTRAST_CLIP), percentile(1.0 - CONTRAST_CLIP));
+    if lower >= upper {
+        // flat image, nothing to stretch
+        return gray.clone();
+    }
+    stretch_contrast(gray, lower, upper, 0, 255)
+}
+
+/// The rotation (in degrees) that best aligns the text lines with the rows, picked by the
+/// sharpest horizontal projection profile of the dark pixels
+fn estimate_skew(binary: &GrayImage) -> f32 {
+    let steps = (MAX_SKEW_DEGREES / SKEW_STEP_DEGREES) as i32;
+    let mut best = (0.0, profile_sharpness(binary));
+
+    for step in -steps..=steps {
+        let angle = step as f32 * SKEW_STEP_DEGREES;
+        if step == 0 {
+            continue;
+        }
+        let sharpness = profile_sharpness(&rotate(binary, angle, Interpolation::Nearest));
+        if sharpness > best.1 {
+            best = (angle, sharpness);
+        }
+    }
+
+    best.0
+}
+
+// Sum of squared differences between the dark pixel counts of adjacent rows: text lines
+// aligned with the rows give tall peaks and empty gaps
+fn profile_sharpness(binary: &GrayImage) -> f64 {
+    let rows: Vec<f64> = binary
+        .rows()
+        .map(|row| row.filter(|pixel| pixel.0[0] < 128).count() as f64)
+        .collect();
+
+    rows.windows(2)
+        .map(|pair| (pair[1] - pair[0]).powi(2))
+        .sum()
+}
+
+fn rotate(gray: &GrayImage, degrees: f32, interpolation: Interpolation) -> GrayImage {
+    if degrees == 0.0 {
+        return gray.clone();
+    }
+    // the corners uncovered by the rotation are filled with background
+    rotate_about_center(gray, degrees.to_radians(), interpolation, Luma([255]))
+}
+
+fn mean(gray: &GrayImage) -> f64 {
+    let sum: u64 = gray.pixels().map(|pixel| pixel.0[0] as u64).sum();
+    sum as f64 / (gray.pixels().len() as f64).max(1.0)
+}
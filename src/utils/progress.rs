@@ -0,0 +1,185 @@
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::utils::{events, telemetry};
+
+const LOG_INTERVAL: Duration = Duration::from_secs(2);
+
+// Helper functions for human-readable formatting
+pub fn format_number(n: u64) -> String {
+    if n >= 1_000_000_000 {
+        format!("{:.1}G", n as f64 / 1_000_000_000.0)
+    } else if n >= 1_000_000 {
+        format!("{:.1}M", n as f64 / 1_000_000.0)
+    } else if n >= 1_000 {
+        format!("{:.1}K", n as f64 / 1_000.0)
+    } else {
+        n.to_string()
+    }
+}
+
+pub fn format_rate(rate: f64) -> String {
+    if rate >= 1_000_000_000.0 {
+        format!("{:.1}G", rate / 1_000_000_000.0)
+    } else if rate >= 1_000_000.0 {
+        format!("{:.1}M", rate / 1_000_000.0)
+    } else if rate >= 1_000.0 {
+        format!("{:.1}K", rate / 1_000.0)
+    } else {
+        format!("{:.0}", rate)
+    }
+}
+
+pub fn format_duration(secs: f64) -> String {
+    if !secs.is_finite() {
+        "forever".to_string()
+    } else if secs >= 86_400.0 {
+        format!("{:.1}d", secs / 86_400.0)
+    } else if secs >= 3_600.0 {
+        format!("{:.1}h", secs / 3_600.0)
+    } else if secs >= 60.0 {
+        format!("{:.1}m", secs / 60.0)
+    } else {
+        format!("{:.1}s", secs)
+    }
+}
+
+/// Counts attempts from any number of threads and logs the count and rates every couple of
+/// seconds, with an ETA when the expected number of attempts is known
+pub struct Progress {
+    label: &'static str,
+    count: Arc<AtomicU64>,
+    stopped: Arc<AtomicBool>,
+    start_time: Instant,
+}
+
+impl Progress {
+    pub fn start(label: &'static str, expected: Option<u64>) -> Self {
+        let progress = Self {
+            label,
+            count: Arc::new(AtomicU64::new(0)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            start_time: Instant::now(),
+        };
+
+        let count = Arc::clone(&progress.count);
+        let stopped = Arc::clone(&progress.stopped);
+        let start_time = progress.start_time;
+        thread::spawn(move || {
+            let mut last_count = 0u64;
+            let mut last_time = start_time;
+
+            loop {
+                thread::sleep(LOG_INTERVAL);
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let current_count = count.load(Ordering::Relaxed);
+                let current_time = Instant::now();
+
+                let total_elapsed = start_time.elapsed().as_secs_f64();
+                let interval_elapsed = current_time.duration_since(last_time).as_secs_f64();
+                let avg_rate = rate(current_count, total_elapsed);
+                let interval_rate = rate(current_count - last_count, interval_elapsed);
+
+                let mut line = format!(
+                    "{} tried: {} | Avg rate: {}/sec | Current rate: {}/sec",
+                    label,
+                    format_number(current_count),
+                    format_rate(avg_rate),
+                    format_rate(interval_rate)
+                );
+                if let Some(expected) = expected {
+                    // past the expected count the estimate says nothing, the search is just unlucky
+                    let remaining = expected.saturating_sub(current_count);
+                    if remaining > 0 && avg_rate > 0.0 {
+                        let eta = format_duration(remaining as f64 / avg_rate);
+                        line.push_str(&format!(" | ETA: ~{}", eta));
+                    } else if remaining == 0 {
+                        line.push_str(" | past the expected count");
+                    }
+                }
+                info!("{}", line);
+                events::emit(
+                    "progress",
+                    json!({
+                        "label": label,
+                        "count": current_count,
+                        "avg_rate": avg_rate,
+                        "current_rate": interval_rate,
+                        "expected": expected,
+                    }),
+                );
+
+                last_count = current_count;
+                last_time = current_time;
+            }
+        });
+
+        progress
+    }
+
+    pub fn add(&self, n: u64) {
+        self.count.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
+    /// Stops the periodic logging
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops the periodic logging and prints the final statistics
+    pub fn finish(&self) {
+        self.stop();
+
+        let total_elapsed = self.elapsed().as_secs_f64();
+        info!("Final statistics:");
+        info!(
+            "  Total {} tried: {}",
+            self.label.to_lowercase(),
+            format_number(self.count())
+        );
+        info!("  Total time: {:.2} seconds", total_elapsed);
+        info!(
+            "  Average rate: {}/sec",
+            format_rate(rate(self.count(), total_elapsed))
+        );
+
+        let label = self.label.to_lowercase();
+        telemetry::gauge(
+            "hackattic.attempts",
+            self.count() as f64,
+            "1",
+            &[("kind", &label)],
+        );
+        telemetry::gauge(
+            "hackattic.attempt_rate",
+            rate(self.count(), total_elapsed),
+            "1/s",
+            &[("kind", &label)],
+        );
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn rate(count: u64, secs: f64) -> f64 {
+    if secs > 0.0 { count as f64 / secs } else { 0.0 }
+}
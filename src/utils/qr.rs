@@ -0,0 +1,418 @@
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::process::Command;
+
+use image::GrayImage;
+use rqrr::Point;
+use tracing::{debug, info};
+
+use crate::utils::preprocess;
+
+type Decoder = fn(&GrayImage) -> Result<Decoded, String>;
+
+/// Tried in order until one reads the code, the QR decoders first since that's the
+/// challenge's symbology and the in-process ones before the external tools
+const DECODERS: &[(&str, Decoder)] = &[
+    ("rqrr", decode_rqrr),
+    ("bardecoder", decode_bardecoder),
+    #[cfg(feature = "zbar")]
+    ("zbar", decode_zbar),
+    ("zxing", decode_zxing),
+    ("dmtx", decode_dmtx),
+];
+
+const QR_CODE: &str = "QR Code";
+const DATA_MATRIX: &str = "Data Matrix";
+const AZTEC: &str = "Aztec";
+
+/// Payload of the code picked among everything detected in the image
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    /// Raw bytes, codes can carry binary data or non UTF-8 text
+    pub payload: Vec<u8>,
+    /// Index of the picked grid and how many were detected
+    pub grid: usize,
+    pub grids: usize,
+    /// Decoder that read it
+    pub engine: &'static str,
+    pub symbology: &'static str,
+}
+
+impl Decoded {
+    /// The payload when it's UTF-8 text
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.payload).ok()
+    }
+}
+
+/// Best guess at how a payload is encoded, for logging. rqrr doesn't report the ECI
+/// designator so this goes by the bytes alone.
+pub fn guess_encoding(payload: &[u8]) -> &'static str {
+    if std::str::from_utf8(payload).is_ok() {
+        "UTF-8"
+    } else if payload
+        .iter()
+        .all(|&b| matches!(b, b'\t' | b'\n' | b'\r' | 0x20..=0x7e | 0xa0..=0xff))
+    {
+        "ISO-8859-1 text"
+    } else {
+        "binary"
+    }
+}
+
+/// How much a payload looks like real content, the printable share of its characters.
+/// False positive grids that still pass error correction tend to decode to control chars or
+/// invalid UTF-8, so binary payloads score 0 and only win when nothing else decodes.
+fn plausibility(payload: &[u8]) -> f64 {
+    let Ok(content) = std::str::from_utf8(payload) else {
+        return 0.0;
+    };
+    let total = content.chars().count();
+    if total == 0 {
+        return 0.0;
+    }
+    let printable = content
+        .chars()
+        .filter(|c| !c.is_control() && *c != char::REPLACEMENT_CHARACTER)
+        .count();
+    printable as f64 / total as f64
+}
+
+/// Area of the grid's bounds in pixels (shoelace formula)
+fn area(bounds: &[Point; 4]) -> i64 {
+    let mut twice_area = 0i64;
+    for i in 0..4 {
+        let (a, b) = (bounds[i], bounds[(i + 1) % 4]);
+        twice_area += a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64;
+    }
+    twice_area.abs() / 2
+}
+
+/// Decodes the QR code with the first decoder of the chain that succeeds
+pub fn decode(img: &GrayImage) -> Result<Decoded, String> {
+    let mut errors = Vec::new();
+    for (name, decoder) in DECODERS {
+        match decoder(img) {
+            Ok(decoded) => return Ok(decoded),
+            Err(e) => {
+                debug!("{} failed: {}", name, e);
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+    Err(format!(
+        "No decoder could read the code ({})",
+        errors.join("; ")
+    ))
+}
+
+/// Like `decode`, but on failure retries on preprocessed versions of the image (thresholding,
+/// upscaling, sharpening, rotations, inversion) until one reads
+pub fn decode_with_retries(img: &GrayImage) -> Result<Decoded, String> {
+    let error = match decode(img) {
+        Ok(decoded) => return Ok(decoded),
+        Err(e) => e,
+    };
+
+    for (name, transform) in preprocess::QR_RETRIES {
+        info!("Retrying with {}", name);
+        if let Ok(decoded) = decode(&transform(img)) {
+            info!("Decoded after {}", name);
+            return Ok(decoded);
+        }
+    }
+    Err(format!(
+        "{}, and none of the preprocessing retries helped",
+        error
+    ))
+}
+
+/// Decodes every grid rqrr detects and keeps the most plausible payload, the largest code
+/// breaking ties
+fn decode_rqrr(img: &GrayImage) -> Result<Decoded, String> {
+    let mut prepared = rqrr::PreparedImage::prepare(img.clone());
+    let grids = prepared.detect_grids();
+    if grids.is_empty() {
+        return Err("No QR code detected in the image".to_string());
+    }
+
+    let mut best: Option<(f64, i64, Decoded)> = None;
+    let mut errors = Vec::new();
+    for (i, grid) in grids.iter().enumerate() {
+        let mut payload = Vec::new();
+        let meta = match grid.decode_to(&mut payload) {
+            Ok(meta) => meta,
+            Err(e) => {
+                errors.push(format!("grid {}: {}", i + 1, e));
+                continue;
+            }
+        };
+        let score = plausibility(&payload);
+        let size = area(&grid.bounds);
+        if grids.len() > 1 {
+            debug!(
+                "Grid {}: {:?} (version {}, plausibility {:.2}, {} px²)",
+                i + 1,
+                String::from_utf8_lossy(&payload),
+                meta.version.0,
+                score,
+                size
+            );
+        }
+
+        let better = best
+            .as_ref()
+            .is_none_or(|(best_score, best_size, _)| (score, size) > (*best_score, *best_size));
+        if better {
+            let decoded = Decoded {
+                payload,
+                grid: i,
+                grids: grids.len(),
+                engine: "rqrr",
+                symbology: QR_CODE,
+            };
+            best = Some((score, size, decoded));
+        }
+    }
+
+    match best {
+        Some((_, _, decoded)) => Ok(decoded),
+        None => Err(format!(
+            "None of the {} detected grids decoded: {}",
+            grids.len(),
+            errors.join(", ")
+        )),
+    }
+}
+
+/// Decodes QR codes with bardecoder, in process like rqrr, whose detection copes with images
+/// rqrr misses. It only returns text, so a binary payload comes back lossy
+fn decode_bardecoder(img: &GrayImage) -> Result<Decoded, String> {
+    // bardecoder is built on image 0.24, the pixels are handed over as they are
+    let (width, height) = img.dimensions();
+    let img = image024::GrayImage::from_raw(width, height, img.as_raw().clone())
+        .map(image024::DynamicImage::ImageLuma8)
+        .ok_or("Image buffer doesn't match its dimensions")?;
+
+    let mut symbols = Vec::new();
+    let mut errors = Vec::new();
+    for result in bardecoder::default_decoder().decode(&img) {
+        match result {
+            Ok(text) => symbols.push((text.into_bytes(), QR_CODE)),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    if symbols.is_empty() && !errors.is_empty() {
+        return Err(format!(
+            "None of the {} detected codes decoded: {}",
+            errors.len(),
+            errors.join(", ")
+        ));
+    }
+    pick(symbols, "bardecoder").map_err(|_| "No QR code detected in the image".to_string())
+}
+
+/// Runs an external decoder on the image, they read files so it goes through a temporary PNG.
+/// Returns stdout, `None` when the tool ran but found nothing.
+fn run_tool(
+    img: &GrayImage,
+    program: &str,
+    args: &[&str],
+    install_hint: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    // an unguessable name, created here, so nothing else can plant a file or symlink there
+    let path = std::env::temp_dir().join(format!(
+        "hackattic-{}-{}.png",
+        program,
+        uuid::Uuid::new_v4().simple()
+    ));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let written = img.write_to(&mut file, image::ImageFormat::Png);
+    drop(file);
+    let output = written
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+        .map(|_| Command::new(program).args(args).arg(&path).output());
+    let _ = std::fs::remove_file(&path);
+
+    let output = output?.map_err(|e| {
+        if e.kind() == ErrorKind::NotFound {
+            format!("{} is not installed ({})", program, install_hint)
+        } else {
+            format!("Failed to run {}: {}", program, e)
+        }
+    })?;
+    if !output.status.success() || output.stdout.trim_ascii().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(output.stdout))
+}
+
+/// Symbols printed one per line, unless the output isn't text: a binary payload can contain
+/// newlines itself, so it's taken as a single symbol
+fn split_symbols(stdout: &[u8], symbology: &'static str) -> Vec<(Vec<u8>, &'static str)> {
+    let stdout = stdout.strip_suffix(b"\n").unwrap_or(stdout);
+    if std::str::from_utf8(stdout).is_err() {
+        return vec![(stdout.to_vec(), symbology)];
+    }
+    stdout
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| (line.to_vec(), symbology))
+        .collect()
+}
+
+/// The most plausible of the symbols a tool decoded, like with rqrr's grids
+fn pick(symbols: Vec<(Vec<u8>, &'static str)>, engine: &'static str) -> Result<Decoded, String> {
+    let grids = symbols.len();
+    let (grid, (payload, symbology)) = symbols
+        .into_iter()
+        .enumerate()
+        .max_by(|(_, (a, _)), (_, (b, _))| plausibility(a).total_cmp(&plausibility(b)))
+        .ok_or("no code found")?;
+    Ok(Decoded {
+        payload,
+        grid,
+        grids,
+        engine,
+        symbology,
+    })
+}
+
+/// Decodes with zbar's `zbarimg`, which copes better with noise and perspective
+#[cfg(feature = "zbar")]
+fn decode_zbar(img: &GrayImage) -> Result<Decoded, String> {
+    let stdout = run_tool(
+        img,
+        "zbarimg",
+        &["--raw", "-q", "-Sbinary", "-Sdisable", "-Sqrcode.enable"],
+        "apt install zbar-tools, brew install zbar",
+    )?
+    .ok_or("no QR code found")?;
+
+    pick(split_symbols(&stdout, QR_CODE), "zbar")
+}
+
+/// Decodes any of QR, Data Matrix and Aztec codes with zxing-cpp's `ZXingReader`, which
+/// prints a block per symbol:
+///
+/// Text:       "payload"
+/// Bytes:      70 61 79 6C 6F 61 64
+/// Format:     DataMatrix
+fn decode_zxing(img: &GrayImage) -> Result<Decoded, String> {
+    let stdout = run_tool(
+        img,
+        "ZXingReader",
+        &[],
+        "build zxing-cpp with -DZXING_EXAMPLES=ON",
+    )?
+    .ok_or("no code found")?;
+
+    let stdout = String::from_utf8_lossy(&stdout);
+    let mut symbols = Vec::new();
+    for line in stdout.lines() {
+        if let Some(text) = line.strip_prefix("Text:") {
+            let text = text.trim();
+            let text = text
+                .strip_prefix('"')
+                .and_then(|text| text.strip_suffix('"'))
+                .unwrap_or(text);
+            symbols.push((text.as_bytes().to_vec(), "unknown"));
+        } else if let Some(bytes) = line.strip_prefix("Bytes:")
+            && let Some((payload, _)) = symbols.last_mut()
+        {
+            // The exact payload, Text is escaped for display
+            let hex: String = bytes.split_whitespace().collect();
+            if let Ok(bytes) = hex::decode(hex) {
+                *payload = bytes;
+            }
+        } else if let Some(format) = line.strip_prefix("Format:")
+            && let Some((_, symbology)) = symbols.last_mut()
+        {
+            *symbology = match format.trim() {
+                "QRCode" | "MicroQRCode" | "rMQRCode" => QR_CODE,
+                "DataMatrix" => DATA_MATRIX,
+                "Aztec" => AZTEC,
+                _ => "other",
+            };
+        }
+    }
+    pick(symbols, "zxing")
+}
+
+/// Decodes Data Matrix codes with libdmtx's `dmtxread`, one symbol per line
+fn decode_dmtx(img: &GrayImage) -> Result<Decoded, String> {
+    let stdout = run_tool(
+        img,
+        "dmtxread",
+        &[],
+        "apt install dmtx-utils, brew install libdmtx",
+    )?
+    .ok_or("no Data Matrix code found")?;
+
+    pick(split_symbols(&stdout, DATA_MATRIX), "dmtx")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(payloads: &[&[u8]]) -> Vec<(Vec<u8>, &'static str)> {
+        payloads
+            .iter()
+            .map(|payload| (payload.to_vec(), QR_CODE))
+            .collect()
+    }
+
+    #[test]
+    fn scores_printable_text() {
+        assert_eq!(plausibility(b"hackattic"), 1.0);
+        assert_eq!(plausibility("café ☕".as_bytes()), 1.0);
+        assert_eq!(plausibility(b"ab\x01\x02"), 0.5);
+        assert_eq!(plausibility("a\u{fffd}".as_bytes()), 0.5);
+        // binary and empty payloads only win when nothing else decodes
+        assert_eq!(plausibility(b"\xff\xfe"), 0.0);
+        assert_eq!(plausibility(b""), 0.0);
+    }
+
+    #[test]
+    fn splits_text_output_into_lines() {
+        assert_eq!(
+            split_symbols(b"first\nsecond\n", QR_CODE),
+            symbols(&[b"first", b"second"])
+        );
+        assert_eq!(split_symbols(b"\none\n\n", QR_CODE), symbols(&[b"one"]));
+        assert_eq!(split_symbols(b"", DATA_MATRIX), Vec::new());
+        assert_eq!(
+            split_symbols(b"code\n", DATA_MATRIX),
+            vec![(b"code".to_vec(), DATA_MATRIX)]
+        );
+    }
+
+    #[test]
+    fn keeps_binary_output_whole() {
+        // the newline is part of the payload, only the one the tool adds is dropped
+        assert_eq!(
+            split_symbols(b"\xff\n\x00\xfe\n", QR_CODE),
+            symbols(&[b"\xff\n\x00\xfe"])
+        );
+    }
+
+    #[test]
+    fn picks_the_most_plausible_symbol() {
+        let decoded = pick(symbols(&[b"\x01\x02x", b"\xff\xfe", b"flag"]), "zbar").unwrap();
+        assert_eq!(decoded.payload, b"flag");
+        assert_eq!((decoded.grid, decoded.grids), (2, 3));
+        assert_eq!((decoded.engine, decoded.symbology), ("zbar", QR_CODE));
+
+        // a binary payload still wins when it's all there is
+        let decoded = pick(symbols(&[b"\xff\xfe"]), "dmtx").unwrap();
+        assert_eq!(decoded.payload, b"\xff\xfe");
+        assert_eq!((decoded.grid, decoded.grids), (0, 1));
+
+        assert!(pick(Vec::new(), "zxing").is_err());
+    }
+}
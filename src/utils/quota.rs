@@ -0,0 +1,218 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tracing::{info, warn};
+
+use crate::utils::args;
+use crate::utils::data_dir::DataDir;
+use crate::utils::progress::format_number;
+
+// What only grows: the cache (downloads, models, OCR outputs, collisions...), the archive and
+// the registry's blobs. With --cache-quota <size> (or HACKATTIC_CACHE_QUOTA), e.g. 2G or 500M,
+// every challenge run ends by removing what was used least recently until they fit again, and
+// logs what went. A file of the cache, an archived problem and a registry repository go as a
+// whole. Never removed: anything of the challenge running, session state and the download
+// index. With the data and the cache in one directory, only the challenges' directories are
+// looked at, the history and other files at the top stay.
+
+const PROTECTED_FILES: &[&str] = &["state.json", "index.json"];
+
+/// A file or directory removed as a whole
+#[derive(Debug)]
+pub struct Unit {
+    pub path: PathBuf,
+    pub bytes: u64,
+    last_used: SystemTime,
+}
+
+/// Parses a size like `500M`, `2G` or `1.5GB`, bytes when there's no unit
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(value.len()),
+    );
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size {:?}", value))?;
+    let scale = match unit
+        .trim_end_matches(['B', 'b'])
+        .to_ascii_uppercase()
+        .as_str()
+    {
+        "" => 1.0,
+        "K" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        _ => return Err(format!("Invalid size {:?}, use K, M, G or T", value)),
+    };
+    Ok((number * scale) as u64)
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    format!("{}B", format_number(bytes))
+}
+
+/// The quota set with --cache-quota, if any
+pub fn limit() -> Result<Option<u64>, String> {
+    args::get("--cache-quota", "HACKATTIC_CACHE_QUOTA")
+        .map(|value| parse_size(&value).map_err(|e| format!("--cache-quota: {}", e)))
+        .transpose()
+}
+
+fn archive_root() -> PathBuf {
+    DataDir::root().join("archive").path().to_path_buf()
+}
+
+fn registry_root() -> PathBuf {
+    DataDir::challenge("dockerized_solutions")
+        .join("registry")
+        .path()
+        .to_path_buf()
+}
+
+// total size and last use of a file or everything under a directory, a file's last use being
+// its last access or modification, whichever is later
+fn measure(path: &Path) -> (u64, SystemTime) {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return (0, SystemTime::UNIX_EPOCH);
+    };
+    if !metadata.is_dir() {
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let accessed = metadata.accessed().unwrap_or(modified);
+        return (metadata.len(), modified.max(accessed));
+    }
+    let mut total = (0, SystemTime::UNIX_EPOCH);
+    for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+        let (bytes, last_used) = measure(&entry.path());
+        total = (total.0 + bytes, total.1.max(last_used));
+    }
+    total
+}
+
+fn unit(path: PathBuf) -> Unit {
+    let (bytes, last_used) = measure(&path);
+    Unit {
+        path,
+        bytes,
+        last_used,
+    }
+}
+
+fn children(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .collect()
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+// every file under `dir` except the protected ones and the archive and registry, counted apart
+fn cache_files(dir: &Path, units: &mut Vec<Unit>) {
+    for path in children(dir) {
+        if path == archive_root() || path == registry_root() {
+            continue;
+        }
+        if path.is_dir() {
+            cache_files(&path, units);
+        } else if !PROTECTED_FILES.contains(&file_name(&path)) {
+            units.push(unit(path));
+        }
+    }
+}
+
+/// Everything the quota counts, except what belongs to the `active` challenge
+pub fn units(active: Option<&str>) -> Vec<Unit> {
+    let is_active = |path: &Path| active == Some(file_name(path));
+    let mut units = Vec::new();
+
+    for challenge in children(&archive_root()) {
+        if !is_active(&challenge) {
+            units.extend(children(&challenge).into_iter().map(unit));
+        }
+    }
+
+    if active != Some("dockerized_solutions") {
+        for path in children(&registry_root()) {
+            // pending uploads one by one, each repository as a whole
+            if file_name(&path) == "uploads" {
+                units.extend(children(&path).into_iter().map(unit));
+            } else {
+                units.push(unit(path));
+            }
+        }
+    }
+
+    for dir in children(DataDir::cache_root().path()) {
+        if dir.is_dir() && !is_active(&dir) {
+            cache_files(&dir, &mut units);
+        }
+    }
+    units
+}
+
+/// Removes the least recently used units until what's left fits in `limit` bytes, returns
+/// what was removed and the size left, the active challenge's files aside
+pub fn enforce(limit: u64, active: Option<&str>) -> Result<(Vec<Unit>, u64), String> {
+    let mut units = units(active);
+    let mut total: u64 = units.iter().map(|unit| unit.bytes).sum();
+    units.sort_by_key(|unit| unit.last_used);
+
+    let mut removed = Vec::new();
+    for unit in units {
+        if total <= limit {
+            break;
+        }
+        let result = if unit.path.is_dir() {
+            fs::remove_dir_all(&unit.path)
+        } else {
+            fs::remove_file(&unit.path)
+        };
+        result.map_err(|e| format!("Failed to remove {}: {}", unit.path.display(), e))?;
+        total -= unit.bytes;
+        removed.push(unit);
+    }
+    Ok((removed, total))
+}
+
+/// Logs what enforcing the quota removed
+pub fn report(removed: &[Unit], left: u64, limit: u64) {
+    if removed.is_empty() {
+        return;
+    }
+    let freed: u64 = removed.iter().map(|unit| unit.bytes).sum();
+    info!(
+        "Cache quota {}: removed the {} least recently used entries, {} freed, {} left",
+        format_bytes(limit),
+        removed.len(),
+        format_bytes(freed),
+        format_bytes(left)
+    );
+    for unit in removed {
+        info!("  {:>8}  {}", format_bytes(unit.bytes), unit.path.display());
+    }
+}
+
+/// Enforces the quota, if one is set, at the end of `challenge`'s run. Failures are logged,
+/// they never fail the run
+pub fn after_run(challenge: &str) {
+    let result = limit().and_then(|limit| match limit {
+        Some(limit) => enforce(limit, Some(challenge)).map(|(removed, left)| {
+            report(&removed, left, limit);
+        }),
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        warn!("{}", e);
+    }
+}
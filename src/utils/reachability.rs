@@ -0,0 +1,174 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tracing::{debug, info};
+
+use crate::utils::{args, health};
+
+// Checks that a callback URL reaches the local server before it's handed to hackattic, a URL
+// that doesn't would waste the round. Walks the path from the inside out so the error says
+// where it breaks: the local server, the URL, DNS, then the public address itself.
+//
+// The last step requests the URL's health endpoint from this machine, unless --probe-service (or
+// REACHABILITY_PROBE) names an outside checker: a URL with `{url}` in it that answers 2xx when
+// it could fetch the URL. From the inside, NAT hairpinning can make a port look open when it
+// isn't from the internet.
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+const PUBLIC_IP_SERVICE: &str = "https://api.ipify.org";
+
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+        }
+        // fc00::/7 unique local and fe80::/10 link local
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Percent-encodes everything but the unreserved characters
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        // the --tls servers use self-signed certificates
+        .danger_accept_invalid_certs(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// This machine's public IP, for the diagnosis only
+async fn public_ip() -> Option<IpAddr> {
+    let text = client().ok()?.get(PUBLIC_IP_SERVICE).send().await.ok()?;
+    text.text().await.ok()?.trim().parse().ok()
+}
+
+/// Why requesting the URL directly failed
+async fn diagnose(error: &reqwest::Error, resolved: &[IpAddr]) -> String {
+    let cause = if error.is_timeout() {
+        "timed out, a firewall is dropping the traffic or the port isn't forwarded through the NAT"
+    } else if error.is_connect() {
+        "the connection failed, the port isn't open or isn't forwarded to this machine"
+    } else {
+        "the request failed"
+    };
+    let nat = match public_ip().await {
+        Some(ip) if !resolved.contains(&ip) => format!(
+            "; the host resolves to {:?} but this machine's public IP is {}, check the DNS record or the router's port forwarding",
+            resolved, ip
+        ),
+        Some(ip) => format!(
+            "; the host is this machine's public IP {}, open the port in the firewall",
+            ip
+        ),
+        None => String::new(),
+    };
+    format!("{}: {}{}", cause, error, nat)
+}
+
+/// Checks that `url` reaches the server listening on `local`, with a diagnosis when it doesn't
+pub async fn check(url: &str, local: SocketAddr) -> Result<(), String> {
+    let parsed =
+        reqwest::Url::parse(url).map_err(|e| format!("{} isn't a valid URL: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("{} must be an http or https URL", url));
+    }
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("{} has no host", url))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    // The local server first, nothing else matters if it isn't up
+    let local_probe = match local.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), local.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), local.port())
+        }
+        _ => local,
+    };
+    match tokio::time::timeout(CONNECT_TIMEOUT, tokio::net::TcpStream::connect(local_probe)).await {
+        Ok(Ok(_)) => debug!("The local server answers on {}", local_probe),
+        _ => {
+            return Err(format!(
+                "Nothing is listening on {}, the local server isn't up",
+                local_probe
+            ));
+        }
+    }
+
+    let resolved: Vec<IpAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("{} doesn't resolve ({}), check the URL", host, e))?
+        .map(|address| address.ip())
+        .collect();
+    if resolved.iter().all(|ip| is_private(*ip)) {
+        return Err(format!(
+            "{} resolves to {:?}, private addresses hackattic can't reach; use the public IP or --tunnel",
+            host, resolved
+        ));
+    }
+
+    // the servers answer on /healthz whatever else they serve
+    let health_url = health::url(url);
+    let client = client()?;
+    if let Some(template) = args::get("--probe-service", "REACHABILITY_PROBE") {
+        let probe = template.replace("{url}", &url_encode(&health_url));
+        let resp = client
+            .get(&probe)
+            .send()
+            .await
+            .map_err(|e| format!("The probe service {} failed: {}", probe, e))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(format!(
+                "{} isn't reachable from the outside, the probe service answered {}: {}",
+                url,
+                status,
+                body.chars().take(200).collect::<String>()
+            ));
+        }
+        info!("{} is reachable from the outside", url);
+        return Ok(());
+    }
+
+    match client.get(&health_url).send().await {
+        // a tunnel or proxy in front answering for a local server it can't reach
+        Ok(resp) if matches!(resp.status().as_u16(), 502..=504) => Err(format!(
+            "{} answered {}, whatever is in front of the server can't reach it, is it forwarding to port {}?",
+            url,
+            resp.status(),
+            local.port()
+        )),
+        Ok(resp) => {
+            info!("{} is reachable ({})", url, resp.status());
+            debug!("Checked from this machine, --probe-service checks from the outside");
+            Ok(())
+        }
+        Err(e) => Err(format!(
+            "{} isn't reachable, {}",
+            url,
+            diagnose(&e, &resolved).await
+        )),
+    }
+}
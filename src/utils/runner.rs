@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::challenges;
+use crate::utils::{history, watchdog};
+
+// Runs a challenge as a `hackattic <challenge>` child process, for the commands that run many:
+// the child records itself in the run history like any run, and a solver that hangs or
+// crashes can't take the caller down with it.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// on top of the child's own timeout and grace period
+const KILL_MARGIN: Duration = Duration::from_secs(5);
+
+/// How a child run ended
+#[derive(Debug, Clone)]
+pub struct ChildRun {
+    pub challenge: String,
+    /// The run's outcome in the history (passed, rejected, error, timeout or unsubmitted), or
+    /// timeout or crashed when the child didn't get to record one
+    pub outcome: String,
+    pub duration: Duration,
+    /// The error or server response recorded with the run, or the exit status
+    pub detail: String,
+}
+
+impl ChildRun {
+    pub fn passed(&self) -> bool {
+        self.outcome == "passed"
+    }
+}
+
+/// Splits the command's arguments into the challenges named and everything else, which is
+/// passed on to the children; the command's own flags taking a value are dropped
+pub fn split_args(own_value_flags: &[&str]) -> (Vec<String>, Vec<String>) {
+    let (mut names, mut forward) = (Vec::new(), Vec::new());
+    let mut args = std::env::args().skip(2);
+    while let Some(arg) = args.next() {
+        let own_flag = own_value_flags.iter().find(|flag| {
+            arg.strip_prefix(**flag)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+        });
+        if let Some(flag) = own_flag {
+            if arg == *flag {
+                args.next();
+            }
+        } else if challenges::find(&arg).is_some() {
+            names.push(arg);
+        } else {
+            forward.push(arg);
+        }
+    }
+    (names, forward)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Runs the challenge in a child process with `timeout` as its limit, killed when it overruns it.
+/// Its output goes to `log` when given, to this process' otherwise
+pub fn run(
+    challenge: &str,
+    forward: &[String],
+    timeout: Option<Duration>,
+    log: Option<&Path>,
+) -> Result<ChildRun, String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut command = Command::new(exe);
+    command.arg(challenge).args(forward).stdin(Stdio::null());
+    // the child's watchdog stops it cleanly, killing it is for when that doesn't work
+    let kill_after = timeout.map(|timeout| {
+        command.arg(format!("--timeout={}s", timeout.as_secs()));
+        timeout + watchdog::GRACE + KILL_MARGIN
+    });
+    if let Some(log) = log {
+        let file =
+            File::create(log).map_err(|e| format!("Failed to create {}: {}", log.display(), e))?;
+        let stderr = file.try_clone().map_err(|e| e.to_string())?;
+        command.stdout(file).stderr(stderr);
+    }
+
+    let started_at = unix_secs(SystemTime::now());
+    let start = Instant::now();
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {}", challenge, e))?;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break Some(status);
+        }
+        if kill_after.is_some_and(|kill_after| start.elapsed() >= kill_after) {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let duration = start.elapsed();
+
+    let Some(status) = status else {
+        return Ok(ChildRun {
+            challenge: challenge.to_string(),
+            outcome: "timeout".to_string(),
+            duration,
+            detail: format!("killed after {:.0?}", duration),
+        });
+    };
+    let recorded = history::load()?
+        .into_iter()
+        .rev()
+        .find(|run| run.challenge == challenge && run.started_at >= started_at);
+    Ok(match recorded {
+        Some(run) => ChildRun {
+            challenge: challenge.to_string(),
+            detail: run
+                .error
+                .or_else(|| run.response.map(|response| response.to_string()))
+                .unwrap_or_default(),
+            outcome: run.outcome,
+            duration,
+        },
+        None => ChildRun {
+            challenge: challenge.to_string(),
+            outcome: "crashed".to_string(),
+            duration,
+            detail: status.to_string(),
+        },
+    })
+}
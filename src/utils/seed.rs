@@ -0,0 +1,68 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Mutex, OnceLock};
+
+use tracing::info;
+
+use crate::utils::args;
+
+// Everything random in a run (where the collision search starts, the daemon's jitter, upload
+// ids, the simulated JWT secret) is drawn from one seed. `--seed <n>` (or HACKATTIC_SEED) sets
+// it so a run can be replayed exactly, any other text is hashed into a number. Without it a
+// seed is picked at random and logged the first time something random is drawn. Each user
+// draws from its own stream, so adding one doesn't shift what the others get.
+
+static SEED: OnceLock<u64> = OnceLock::new();
+static UUIDS: Mutex<Option<Rng>> = Mutex::new(None);
+
+/// The run's seed, from --seed or picked at random
+pub fn seed() -> u64 {
+    *SEED.get_or_init(|| match args::get("--seed", "HACKATTIC_SEED") {
+        Some(value) => value.parse().unwrap_or_else(|_| fnv1a(&value)),
+        None => {
+            let seed = RandomState::new().build_hasher().finish();
+            info!("Random seed {}, --seed {} replays it", seed, seed);
+            seed
+        }
+    })
+}
+
+/// SplitMix64 over the run's seed
+pub struct Rng(u64);
+
+impl Rng {
+    /// The stream named `stream`, the same for the same seed whatever else is drawn
+    pub fn new(stream: &str) -> Self {
+        Rng(seed() ^ fnv1a(stream))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform enough in 0..max, 0 when max is 0
+    pub fn below(&mut self, max: u64) -> u64 {
+        if max == 0 {
+            return 0;
+        }
+        self.next_u64() % max
+    }
+}
+
+/// A version 4 UUID from the "uuid" stream
+pub fn uuid() -> uuid::Uuid {
+    let mut uuids = UUIDS.lock().unwrap();
+    let rng = uuids.get_or_insert_with(|| Rng::new("uuid"));
+    let bytes = (((rng.next_u64() as u128) << 64) | rng.next_u64() as u128).to_be_bytes();
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use tracing::warn;
+
+static TOKEN: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// Flag set when Ctrl+C is pressed, shared by everything that wants to stop early.
+/// The first call installs the handler, ctrlc only allows one per process
+pub fn token() -> Arc<AtomicBool> {
+    let token = TOKEN.get_or_init(|| {
+        let token = Arc::new(AtomicBool::new(false));
+        let handler_token = Arc::clone(&token);
+        ctrlc::set_handler(move || {
+            warn!("Received Ctrl+C, shutting down gracefully...");
+            handler_token.store(true, Ordering::Relaxed);
+        })
+        .expect("Error setting Ctrl+C handler");
+        token
+    });
+    Arc::clone(token)
+}
+
+/// Resolves once the token is set, for servers to stop on Ctrl+C or the watchdog
+pub async fn cancelled() {
+    let token = token();
+    while !token.load(Ordering::Relaxed) {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+}
@@ -0,0 +1,83 @@
+use std::any::Any;
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use tracing::{info, warn};
+
+use crate::utils::shutdown;
+
+// Keeps a long-lived service (the registry, the JWT endpoint) up while hackattic calls back: a
+// service task that panics or fails is started again after a backoff, doubling from 1s up to
+// 30s, until it stops on its own, the run is shut down, or it failed MAX_RESTARTS times in a
+// row. Services keep their state outside the task, on disk or in the shared handles `start`
+// captures, so a restarted service picks up where the crashed one was.
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_RESTARTS: u32 = 5;
+// a service that ran this long before failing starts over from the initial backoff
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// Runs the service `start` returns, starting it again when it panics or fails. Returns once
+/// a run of it ends with Ok
+pub async fn supervise<F, Fut>(name: &str, mut start: F) -> anyhow::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let token = shutdown::token();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut failures = 0;
+    loop {
+        let started = Instant::now();
+        let error = match tokio::spawn(start()).await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => format!("{:#}", e),
+            Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+            Err(e) => bail!("{} was cancelled: {}", name, e),
+        };
+        if token.load(Ordering::Relaxed) {
+            bail!("{} failed while shutting down: {}", name, error);
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            backoff = INITIAL_BACKOFF;
+            failures = 0;
+        }
+        failures += 1;
+        if failures > MAX_RESTARTS {
+            bail!(
+                "{} failed {} times in a row, giving up: {}",
+                name,
+                failures,
+                error
+            );
+        }
+        warn!(
+            "{} failed: {}, restarting in {}s ({}/{})",
+            name,
+            error,
+            backoff.as_secs(),
+            failures,
+            MAX_RESTARTS
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown::cancelled() => bail!("{} stopped before it could restart", name),
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        info!("Restarting {}", name);
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value, json};
+use tracing::warn;
+
+use crate::utils::args;
+
+// Optional OTLP export of the run's spans and a few metrics, so long runs can be followed in
+// Jaeger or Grafana. --otlp-endpoint (or OTEL_EXPORTER_OTLP_ENDPOINT) is the collector's
+// OTLP/HTTP address, e.g. http://localhost:4318; spans and metrics are POSTed as OTLP JSON to
+// /v1/traces and /v1/metrics every few seconds and when the run ends. Each process is one trace.
+//
+// Spans are recorded from a layer of the subscriber (see logging), so recording never logs.
+// Only flush() does, outside the subscriber, to warn about the first failed export.
+
+const SERVICE_NAME: &str = "hackattic";
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A closed span, as the logger saw it
+pub struct FinishedSpan {
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub fields: Map<String, Value>,
+}
+
+struct Metric {
+    name: String,
+    unit: &'static str,
+    value: f64,
+    time: SystemTime,
+    attributes: Map<String, Value>,
+}
+
+struct Exporter {
+    endpoint: String,
+    trace_id: String,
+    spans: Mutex<Vec<FinishedSpan>>,
+    metrics: Mutex<Vec<Metric>>,
+    /// Set once a failed export was warned about, a collector that stays down warns only once
+    warned: AtomicBool,
+}
+
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+/// Starts exporting when an endpoint is configured
+pub fn init() {
+    let Some(endpoint) = args::get("--otlp-endpoint", "OTEL_EXPORTER_OTLP_ENDPOINT") else {
+        return;
+    };
+    let exporter = Exporter {
+        endpoint: endpoint.trim_end_matches('/').to_string(),
+        trace_id: uuid::Uuid::new_v4().simple().to_string(),
+        spans: Mutex::new(Vec::new()),
+        metrics: Mutex::new(Vec::new()),
+        warned: AtomicBool::new(false),
+    };
+    if EXPORTER.set(exporter).is_ok() {
+        thread::spawn(|| {
+            loop {
+                thread::sleep(EXPORT_INTERVAL);
+                flush();
+            }
+        });
+    }
+}
+
+pub fn enabled() -> bool {
+    EXPORTER.get().is_some()
+}
+
+pub fn record_span(span: FinishedSpan) {
+    if let Some(exporter) = EXPORTER.get() {
+        exporter.spans.lock().unwrap().push(span);
+    }
+}
+
+/// Records the current value of a metric, e.g. `gauge("hackattic.run.duration", 12.5, "s",
+/// &[("challenge", "mini_miner")])`
+pub fn gauge(name: &str, value: f64, unit: &'static str, attributes: &[(&str, &str)]) {
+    if let Some(exporter) = EXPORTER.get() {
+        exporter.metrics.lock().unwrap().push(Metric {
+            name: name.to_string(),
+            unit,
+            value,
+            time: SystemTime::now(),
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), json!(value)))
+                .collect(),
+        });
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0)
+        .to_string()
+}
+
+/// OTLP's key/value list
+fn attributes(fields: &Map<String, Value>) -> Vec<Value> {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(b) => json!({ "boolValue": b }),
+                Value::Number(n) if n.is_i64() || n.is_u64() => {
+                    json!({ "intValue": n.to_string() })
+                }
+                Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+                Value::String(s) => json!({ "stringValue": s }),
+                other => json!({ "stringValue": other.to_string() }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+fn resource() -> Value {
+    json!({
+        "attributes": [{ "key": "service.name", "value": { "stringValue": SERVICE_NAME } }]
+    })
+}
+
+fn traces_body(exporter: &Exporter, spans: &[FinishedSpan]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let mut otlp = json!({
+                "traceId": exporter.trace_id,
+                "spanId": format!("{:016x}", span.id),
+                "name": span.name,
+                // internal
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": attributes(&span.fields),
+            });
+            if let Some(parent) = span.parent {
+                otlp["parentSpanId"] = json!(format!("{:016x}", parent));
+            }
+            otlp
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{ "scope": { "name": SERVICE_NAME }, "spans": spans }],
+        }]
+    })
+}
+
+fn metrics_body(metrics: &[Metric]) -> Value {
+    let metrics: Vec<Value> = metrics
+        .iter()
+        .map(|metric| {
+            json!({
+                "name": metric.name,
+                "unit": metric.unit,
+                "gauge": {
+                    "dataPoints": [{
+                        "asDouble": metric.value,
+                        "timeUnixNano": unix_nanos(metric.time),
+                        "attributes": attributes(&metric.attributes),
+                    }]
+                },
+            })
+        })
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{ "scope": { "name": SERVICE_NAME }, "metrics": metrics }],
+        }]
+    })
+}
+
+fn post(exporter: &Exporter, path: &str, body: &Value) -> Result<(), String> {
+    let url = format!("{}{}", exporter.endpoint, path);
+    reqwest::blocking::Client::builder()
+        .timeout(EXPORT_TIMEOUT)
+        .build()
+        .and_then(|client| client.post(&url).json(body).send())
+        .and_then(|response| response.error_for_status())
+        .map(|_| ())
+        .map_err(|e| format!("Failed to export to {}: {}", url, e))
+}
+
+/// Sends what was recorded since the last export
+pub fn flush() {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    let spans = std::mem::take(&mut *exporter.spans.lock().unwrap());
+    let metrics = std::mem::take(&mut *exporter.metrics.lock().unwrap());
+    let mut result = Ok(());
+    if !spans.is_empty() {
+        result = post(exporter, "/v1/traces", &traces_body(exporter, &spans));
+    }
+    if !metrics.is_empty() {
+        result = result.and(post(exporter, "/v1/metrics", &metrics_body(&metrics)));
+    }
+    // a collector that's down mustn't break the run, or fill the log
+    if let Err(e) = result
+        && !exporter.warned.swap(true, Ordering::Relaxed)
+    {
+        warn!("{}, later export failures aren't logged", e);
+    }
+}
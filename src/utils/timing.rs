@@ -0,0 +1,112 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde_json::json;
+use tracing::info;
+
+use crate::utils::events;
+use crate::utils::progress::format_duration;
+
+// Time spent in each stage of the run, the spans below the run's own: fetch_problem, download,
+// solve and its sub-stages (solve/mine...), submit. Repeated stages, like several downloads,
+// are summed. The logger records every closed span, the run prints the breakdown when it ends
+// and keeps it in the history.
+
+const ROOT_SPAN: &str = "challenge";
+
+#[derive(Debug, Clone)]
+pub struct Stage {
+    /// Span names from the outermost stage down, e.g. `solve/mine`
+    pub path: String,
+    pub total: Duration,
+    pub count: u32,
+    first_start: SystemTime,
+}
+
+static STAGES: Mutex<Vec<Stage>> = Mutex::new(Vec::new());
+
+/// Called by the logger when a span closes, `path` being the span names from the root down
+pub fn record(path: &str, start: SystemTime, duration: Duration) {
+    let Some(path) = path
+        .strip_prefix(ROOT_SPAN)
+        .and_then(|p| p.strip_prefix('/'))
+    else {
+        return;
+    };
+    events::emit(
+        "stage_completed",
+        json!({ "stage": path, "duration_ms": duration.as_millis() as u64 }),
+    );
+    let mut stages = STAGES.lock().unwrap();
+    match stages.iter_mut().find(|stage| stage.path == path) {
+        Some(stage) => {
+            stage.total += duration;
+            stage.count += 1;
+            stage.first_start = stage.first_start.min(start);
+        }
+        None => stages.push(Stage {
+            path: path.to_string(),
+            total: duration,
+            count: 1,
+            first_start: start,
+        }),
+    }
+}
+
+/// The stages in the order they started, so sub-stages follow their stage
+pub fn stages() -> Vec<Stage> {
+    let mut stages = STAGES.lock().unwrap().clone();
+    stages.sort_by_key(|stage| stage.first_start);
+    stages
+}
+
+/// Logs the breakdown of the run's `total` time
+pub fn report(total: Duration) {
+    let stages = stages();
+    if stages.is_empty() {
+        return;
+    }
+    let share = |duration: Duration| {
+        if total.is_zero() {
+            0.0
+        } else {
+            100.0 * duration.as_secs_f64() / total.as_secs_f64()
+        }
+    };
+    info!("Timing:");
+    for stage in &stages {
+        let depth = stage.path.matches('/').count();
+        let name = stage.path.rsplit('/').next().unwrap_or_default();
+        let repeats = if stage.count > 1 {
+            format!("  x{}", stage.count)
+        } else {
+            String::new()
+        };
+        info!(
+            "  {:<width$} {:>8} {:>4.0}%{}",
+            format!("{}{}", "  ".repeat(depth), name),
+            format_duration(stage.total.as_secs_f64()),
+            share(stage.total),
+            repeats,
+            width = 20
+        );
+    }
+    // what the top-level stages don't cover: setup, waiting, logging
+    let covered: Duration = stages
+        .iter()
+        .filter(|stage| !stage.path.contains('/'))
+        .map(|stage| stage.total)
+        .sum();
+    let other = total.saturating_sub(covered);
+    info!(
+        "  {:<20} {:>8} {:>4.0}%",
+        "other",
+        format_duration(other.as_secs_f64()),
+        share(other)
+    );
+    info!(
+        "  {:<20} {:>8}",
+        "total",
+        format_duration(total.as_secs_f64())
+    );
+}
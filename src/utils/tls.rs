@@ -0,0 +1,199 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper_util::rt::TokioIo;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{
+    ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use tracing::warn;
+
+// How long connections get to finish their requests once the server is shutting down
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Failed to generate the self-signed certificate: {0}")]
+    Generate(String),
+    #[error("Invalid certificate PEM: {0}")]
+    Certificate(tokio_rustls::rustls::pki_types::pem::Error),
+    #[error("Invalid private key PEM: {0}")]
+    PrivateKey(tokio_rustls::rustls::pki_types::pem::Error),
+    #[error("Certificate and private key don't match: {0}")]
+    Config(#[from] tokio_rustls::rustls::Error),
+}
+
+// Certificates valid from now on
+#[cfg(any(feature = "openssl", feature = "rcgen"))]
+const VALIDITY_DAYS: u32 = 30;
+
+/// Generates a self-signed certificate for `host` (DNS name or IP address), with rcgen when the
+/// `rcgen` feature is on and OpenSSL otherwise
+/// Returns the certificate and its PKCS#8 private key, both PEM encoded
+pub fn self_signed_certificate(host: &str) -> Result<(Vec<u8>, Vec<u8>), TlsError> {
+    #[cfg(feature = "rcgen")]
+    return rcgen_certificate(host).map_err(|e| TlsError::Generate(e.to_string()));
+    #[cfg(all(feature = "openssl", not(feature = "rcgen")))]
+    return openssl_certificate(host).map_err(|e| TlsError::Generate(e.to_string()));
+    #[cfg(not(any(feature = "openssl", feature = "rcgen")))]
+    return Err(TlsError::Generate(format!(
+        "no certificate backend for {}, build with the openssl or rcgen feature",
+        host
+    )));
+}
+
+#[cfg(feature = "rcgen")]
+fn rcgen_certificate(host: &str) -> Result<(Vec<u8>, Vec<u8>), rcgen::Error> {
+    use rcgen::{CertificateParams, DnType, KeyPair};
+
+    // IP addresses become IP subject alternative names, anything else a DNS name
+    let mut params = CertificateParams::new(vec![host.to_string()])?;
+    params.distinguished_name.push(DnType::CommonName, host);
+    params.not_before = time::OffsetDateTime::now_utc();
+    params.not_after = params.not_before + time::Duration::days(VALIDITY_DAYS.into());
+
+    let key_pair = KeyPair::generate()?;
+    let cert = params.self_signed(&key_pair)?;
+    Ok((
+        cert.pem().into_bytes(),
+        key_pair.serialize_pem().into_bytes(),
+    ))
+}
+
+#[cfg(all(feature = "openssl", not(feature = "rcgen")))]
+fn openssl_certificate(host: &str) -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{X509, X509NameBuilder, extension::SubjectAlternativeName},
+    };
+
+    let pkey = PKey::from_rsa(Rsa::generate(2048)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", host)?;
+    let name = name.build();
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&pkey)?;
+    let serial_number = BigNum::from_u32(1)?.to_asn1_integer()?;
+    builder.set_serial_number(&serial_number)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    builder.set_not_before(&not_before)?;
+    let not_after = Asn1Time::days_from_now(VALIDITY_DAYS)?;
+    builder.set_not_after(&not_after)?;
+
+    let mut subject_alt_name = SubjectAlternativeName::new();
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        subject_alt_name.ip(host);
+    } else {
+        subject_alt_name.dns(host);
+    }
+    let subject_alt_name = subject_alt_name.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(subject_alt_name)?;
+
+    builder.sign(&pkey, MessageDigest::sha256())?;
+    let cert = builder.build();
+
+    Ok((cert.to_pem()?, pkey.private_key_to_pem_pkcs8()?))
+}
+
+/// Builds the TLS config from PEM encoded certificate chain and private key
+/// Only HTTP/1.1 is served, which is also what gets advertised through ALPN
+pub fn server_config(cert_pem: &[u8], key_pem: &[u8]) -> Result<Arc<ServerConfig>, TlsError> {
+    let certs = CertificateDer::pem_slice_iter(cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TlsError::Certificate)?;
+    let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(TlsError::PrivateKey)?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// Serves `service` (e.g. `warp::service(routes)`) over TLS until `shutdown` resolves, then
+/// lets the open connections answer the requests in flight, for up to `DRAIN_TIMEOUT`
+pub async fn serve<S>(
+    service: S,
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    shutdown: impl Future<Output = ()>,
+) where
+    S: tower_service::Service<
+            warp::http::Request<hyper::body::Incoming>,
+            Response = warp::reply::Response,
+            Error = Infallible,
+        > + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let acceptor = TlsAcceptor::from(config);
+    let graceful = GracefulShutdown::new();
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        // forget the connections that are done
+        while connections.try_join_next().is_some() {}
+
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(service.clone());
+        // taken before the handshake, so a connection still handshaking is waited for too
+        let watcher = graceful.watcher();
+        connections.spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+
+            let connection = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service);
+            if let Err(e) = watcher.watch(connection).await {
+                warn!("Connection error with {}: {}", peer, e);
+            }
+        });
+    }
+
+    // stops keep-alive connections once their current request is answered
+    if tokio::time::timeout(DRAIN_TIMEOUT, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        warn!(
+            "Closing the connections still open {:?} after shutdown",
+            DRAIN_TIMEOUT
+        );
+    }
+    connections.shutdown().await;
+}
@@ -0,0 +1,204 @@
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use tracing::{debug, info};
+
+use crate::utils::args;
+
+// Public URLs for the challenges hackattic calls back (jotting_jwts, dockerized_solutions):
+// starts ngrok or cloudflared in front of a local port, reads the public address from its
+// output, and stops it again when the Tunnel is dropped.
+//
+//   --tunnel ngrok         needs NGROK_AUTHTOKEN (or --ngrok-token)
+//   --tunnel cloudflared   a free quick tunnel on trycloudflare.com, no account needed
+
+// How long the tunnel gets to report its address
+const START_TIMEOUT: Duration = Duration::from_secs(30);
+// How long the public URL gets to start answering
+const REACHABLE_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Ngrok,
+    Cloudflared,
+}
+
+impl Provider {
+    /// The provider picked with --tunnel (or TUNNEL), None when no tunnel is wanted
+    pub fn from_args() -> Result<Option<Self>, String> {
+        match args::get("--tunnel", "TUNNEL").as_deref() {
+            None => Ok(None),
+            Some("ngrok") => Ok(Some(Provider::Ngrok)),
+            Some("cloudflared") => Ok(Some(Provider::Cloudflared)),
+            Some(other) => Err(format!(
+                "Unknown tunnel {:?}, expected ngrok or cloudflared",
+                other
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Provider::Ngrok => "ngrok",
+            Provider::Cloudflared => "cloudflared",
+        }
+    }
+
+    fn command(self, port: u16) -> Result<Command, String> {
+        match self {
+            Provider::Ngrok => {
+                let token = args::get("--ngrok-token", "NGROK_AUTHTOKEN")
+                    .ok_or("ngrok needs an auth token, set NGROK_AUTHTOKEN or --ngrok-token")?;
+                let mut command = Command::new("ngrok");
+                command
+                    .args(["http", &port.to_string()])
+                    .args(["--log", "stdout", "--log-format", "json"])
+                    .env("NGROK_AUTHTOKEN", token);
+                Ok(command)
+            }
+            Provider::Cloudflared => {
+                let mut command = Command::new("cloudflared");
+                command.args([
+                    "tunnel",
+                    "--no-autoupdate",
+                    "--url",
+                    &format!("http://localhost:{}", port),
+                ]);
+                Ok(command)
+            }
+        }
+    }
+
+    /// The public URL in a line of the tunnel's output
+    fn public_url(self, line: &str) -> Option<String> {
+        match self {
+            // {"lvl":"info","msg":"started tunnel",...,"url":"https://<id>.ngrok-free.app"}
+            Provider::Ngrok => serde_json::from_str::<serde_json::Value>(line)
+                .ok()
+                .filter(|log| log["msg"] == "started tunnel")
+                .and_then(|log| log["url"].as_str().map(str::to_string)),
+            // printed in a banner on stderr
+            Provider::Cloudflared => Regex::new(r"https://[a-z0-9-]+\.trycloudflare\.com")
+                .ok()?
+                .find(line)
+                .map(|url| url.as_str().to_string()),
+        }
+    }
+}
+
+/// Forwards the tunnel's output lines to the channel, then keeps draining them so the process
+/// never blocks on a full pipe
+fn forward_lines(
+    output: impl Read + Send + 'static,
+    lines: mpsc::Sender<String>,
+    name: &'static str,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            debug!("[{}] {}", name, line);
+            let _ = lines.send(line);
+        }
+    });
+}
+
+/// A running tunnel, stopped when dropped
+pub struct Tunnel {
+    provider: Provider,
+    child: Child,
+    pub public_url: String,
+}
+
+impl Tunnel {
+    /// Starts the tunnel to the local port and waits for its public URL
+    pub fn start(provider: Provider, port: u16) -> Result<Self, String> {
+        info!("Starting a {} tunnel to port {}", provider.name(), port);
+        let mut child = provider
+            .command(port)?
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", provider.name(), e))?;
+
+        let (sender, lines) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            forward_lines(stdout, sender.clone(), provider.name());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward_lines(stderr, sender, provider.name());
+        }
+
+        let deadline = Instant::now() + START_TIMEOUT;
+        let mut last_lines = Vec::new();
+        let public_url = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match lines.recv_timeout(remaining) {
+                Ok(line) => {
+                    if let Some(url) = provider.public_url(&line) {
+                        break url;
+                    }
+                    last_lines.push(line);
+                    if last_lines.len() > 5 {
+                        last_lines.remove(0);
+                    }
+                }
+                // timed out, or the process exited and closed its output
+                Err(_) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!(
+                        "{} didn't report a public URL: {}",
+                        provider.name(),
+                        last_lines.join(" | ")
+                    ));
+                }
+            }
+        };
+        info!("Tunnel up at {}", public_url);
+
+        Ok(Self {
+            provider,
+            child,
+            public_url,
+        })
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        info!("Stopping the {} tunnel", self.provider.name());
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Waits until the public URL reaches the local server: any response that isn't the tunnel's own
+/// 5xx (the local port not answering yet, the tunnel not routed yet) counts
+pub async fn wait_until_reachable(url: &str) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + REACHABLE_TIMEOUT;
+    let mut last_error;
+    loop {
+        match client.get(url).send().await {
+            Ok(resp) if !resp.status().is_server_error() => {
+                info!("{} is reachable ({})", url, resp.status());
+                return Ok(());
+            }
+            Ok(resp) => last_error = format!("answered {}", resp.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("{} isn't reachable: {}", url, last_error));
+        }
+        debug!("{} not reachable yet: {}", url, last_error);
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
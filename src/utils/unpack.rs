@@ -0,0 +1,764 @@
+use std::fmt;
+
+// Python struct-like format strings: type codes separated by optional whitespace, each with
+// an optional repeat count, e.g. "<i I h 2x f d >d"
+//
+// x pad byte   ? bool   b/B i8/u8   h/H i16/u16   i/I (l/L) i32/u32   q/Q i64/u64
+// f f32        d f64    s bytes (the count is the length, "4s")
+// p Pascal string: a length byte, then up to count - 1 bytes ("5p" holds 4)
+//
+// Byte order characters apply to every field after them, so unlike Python they can switch
+// mid-format: < little-endian, > or ! big-endian, = native, @ native with every field
+// aligned to its own size like a C struct (the default)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    const NATIVE: ByteOrder = if cfg!(target_endian = "big") {
+        ByteOrder::Big
+    } else {
+        ByteOrder::Little
+    };
+}
+
+/// One field of a format string, placed at its offset in the buffer
+#[derive(Debug, Clone, Copy)]
+pub struct Field {
+    pub code: char,
+    pub order: ByteOrder,
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl Field {
+    pub fn is_padding(&self) -> bool {
+        self.code == 'x'
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Int(v) => Some(v),
+            Value::UInt(v) => i64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::UInt(v) => Some(v),
+            Value::Int(v) => u64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Float(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::UInt(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Bytes(v) => match std::str::from_utf8(v) {
+                Ok(text) => write!(f, "{:?}", text),
+                Err(_) => write!(f, "0x{}", hex::encode(v)),
+            },
+        }
+    }
+}
+
+// Largest buffer a format can describe, so a huge repeat count is an error instead of
+// millions of fields
+const MAX_SIZE: usize = 1 << 20;
+
+fn size_of(code: char) -> Option<usize> {
+    match code {
+        'x' | '?' | 'b' | 'B' | 's' | 'p' => Some(1),
+        'h' | 'H' => Some(2),
+        'i' | 'I' | 'l' | 'L' | 'f' => Some(4),
+        'q' | 'Q' | 'd' => Some(8),
+        _ => None,
+    }
+}
+
+/// Resolves a format string into fields with their offsets
+pub fn layout(format: &str) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    let mut order = ByteOrder::NATIVE;
+    let mut aligned = true;
+    let mut offset: usize = 0;
+    let mut count: Option<usize> = None;
+
+    for (position, c) in format.char_indices() {
+        if let Some(digit) = c.to_digit(10) {
+            count = count
+                .unwrap_or(0)
+                .checked_mul(10)
+                .and_then(|n| n.checked_add(digit as usize));
+            if count.is_none() {
+                return Err(format!(
+                    "Repeat count overflows at position {} in {:?}",
+                    position, format
+                ));
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            if count.is_some() {
+                return Err(format!(
+                    "Repeat count without a type code at position {} in {:?}",
+                    position, format
+                ));
+            }
+            continue;
+        }
+
+        let byte_order = match c {
+            '<' => Some((ByteOrder::Little, false)),
+            '>' | '!' => Some((ByteOrder::Big, false)),
+            '=' => Some((ByteOrder::NATIVE, false)),
+            '@' => Some((ByteOrder::NATIVE, true)),
+            _ => None,
+        };
+        if let Some((new_order, new_aligned)) = byte_order {
+            if count.is_some() {
+                return Err(format!(
+                    "Repeat count before byte order {:?} at position {} in {:?}",
+                    c, position, format
+                ));
+            }
+            order = new_order;
+            aligned = new_aligned;
+            continue;
+        }
+
+        let Some(size) = size_of(c) else {
+            return Err(format!(
+                "Unknown format code {:?} at position {} in {:?}",
+                c, position, format
+            ));
+        };
+        let count = count.take().unwrap_or(1);
+
+        // Strings and padding are a single field spanning `count` bytes
+        let (repeat, size) = match c {
+            's' | 'p' | 'x' => (1, count),
+            _ => (count, size),
+        };
+        let too_large = || {
+            format!(
+                "{:?} describes more than {} bytes at position {}",
+                format, MAX_SIZE, position
+            )
+        };
+        for _ in 0..repeat {
+            if aligned && !matches!(c, 's' | 'p' | 'x') {
+                offset = offset.next_multiple_of(size);
+            }
+            let end = offset
+                .checked_add(size)
+                .filter(|end| *end <= MAX_SIZE)
+                .ok_or_else(too_large)?;
+            fields.push(Field {
+                code: c,
+                order,
+                offset,
+                size,
+            });
+            offset = end;
+        }
+    }
+
+    if count.is_some() {
+        return Err(format!(
+            "Repeat count without a type code at the end of {:?}",
+            format
+        ));
+    }
+    Ok(fields)
+}
+
+fn decode(field: &Field, bytes: &[u8]) -> Value {
+    macro_rules! read {
+        ($ty:ty) => {{
+            let array = bytes.try_into().expect("field size matches the type");
+            match field.order {
+                ByteOrder::Little => <$ty>::from_le_bytes(array),
+                ByteOrder::Big => <$ty>::from_be_bytes(array),
+            }
+        }};
+    }
+
+    match field.code {
+        '?' => Value::Bool(bytes[0] != 0),
+        'b' => Value::Int(bytes[0] as i8 as i64),
+        'B' => Value::UInt(bytes[0] as u64),
+        'h' => Value::Int(read!(i16) as i64),
+        'H' => Value::UInt(read!(u16) as u64),
+        'i' | 'l' => Value::Int(read!(i32) as i64),
+        'I' | 'L' => Value::UInt(read!(u32) as u64),
+        'q' => Value::Int(read!(i64)),
+        'Q' => Value::UInt(read!(u64)),
+        'f' => Value::Float(read!(f32) as f64),
+        'd' => Value::Float(read!(f64)),
+        's' => Value::Bytes(bytes.to_vec()),
+        // Like Python, a length past the field's end is cut to the bytes there are
+        'p' => match bytes.split_first() {
+            Some((&len, rest)) => Value::Bytes(rest[..rest.len().min(len as usize)].to_vec()),
+            None => Value::Bytes(Vec::new()),
+        },
+        code => unreachable!("no decoder for {:?}", code),
+    }
+}
+
+/// Unpacks the start of `bytes` according to `format`, one value per non-padding field.
+/// Trailing bytes past the format's size are ignored.
+pub fn unpack(format: &str, bytes: &[u8]) -> Result<Vec<Value>, String> {
+    let fields = layout(format)?;
+    check_length(format, &fields, bytes)?;
+
+    Ok(fields
+        .iter()
+        .filter(|field| !field.is_padding())
+        .map(|field| decode(field, &bytes[field.offset..field.offset + field.size]))
+        .collect())
+}
+
+fn check_length(format: &str, fields: &[Field], bytes: &[u8]) -> Result<(), String> {
+    match fields
+        .iter()
+        .find(|field| field.offset + field.size > bytes.len())
+    {
+        Some(field) => Err(format!(
+            "Buffer too short for {:?}: field {:?} at offset {} needs {} bytes, only {} of {} available",
+            format,
+            field.code,
+            field.offset,
+            field.size,
+            bytes.len().saturating_sub(field.offset),
+            bytes.len()
+        )),
+        None => Ok(()),
+    }
+}
+
+fn encode(field: &Field, value: &Value, out: &mut [u8]) -> Result<(), String> {
+    let mismatch = || {
+        format!(
+            "Value {:?} doesn't fit field {:?} at offset {}",
+            value, field.code, field.offset
+        )
+    };
+    macro_rules! write {
+        ($ty:ty, $v:expr) => {{
+            let v: $ty = $v;
+            out.copy_from_slice(&match field.order {
+                ByteOrder::Little => v.to_le_bytes(),
+                ByteOrder::Big => v.to_be_bytes(),
+            });
+        }};
+    }
+    macro_rules! int {
+        ($ty:ty) => {{
+            let v = match *value {
+                Value::Int(v) => <$ty>::try_from(v).ok(),
+                Value::UInt(v) => <$ty>::try_from(v).ok(),
+                Value::Bool(v) => Some(v as $ty),
+                _ => None,
+            };
+            write!($ty, v.ok_or_else(mismatch)?)
+        }};
+    }
+    let float = || match *value {
+        Value::Float(v) => Ok(v),
+        Value::Int(v) => Ok(v as f64),
+        Value::UInt(v) => Ok(v as f64),
+        _ => Err(mismatch()),
+    };
+
+    match field.code {
+        '?' => match *value {
+            Value::Bool(v) => out[0] = v as u8,
+            _ => return Err(mismatch()),
+        },
+        'b' => int!(i8),
+        'B' => int!(u8),
+        'h' => int!(i16),
+        'H' => int!(u16),
+        'i' | 'l' => int!(i32),
+        'I' | 'L' => int!(u32),
+        'q' => int!(i64),
+        'Q' => int!(u64),
+        'f' => write!(f32, float()? as f32),
+        'd' => write!(f64, float()?),
+        // Like Python, strings are truncated or zero padded to the field size
+        's' => match value {
+            Value::Bytes(v) => {
+                let len = v.len().min(out.len());
+                out[..len].copy_from_slice(&v[..len]);
+            }
+            _ => return Err(mismatch()),
+        },
+        // and a Pascal string keeps what fits after the length byte, at most 255 bytes
+        'p' => match (value, out.split_first_mut()) {
+            (Value::Bytes(v), Some((len, rest))) => {
+                let n = v.len().min(rest.len()).min(u8::MAX as usize);
+                *len = n as u8;
+                rest[..n].copy_from_slice(&v[..n]);
+            }
+            (Value::Bytes(_), None) => {}
+            _ => return Err(mismatch()),
+        },
+        code => unreachable!("no encoder for {:?}", code),
+    }
+    Ok(())
+}
+
+/// Packs `values` according to `format`, the inverse of `unpack`. Padding and alignment
+/// gaps are zero filled.
+pub fn pack(format: &str, values: &[Value]) -> Result<Vec<u8>, String> {
+    let fields = layout(format)?;
+    let data_fields: Vec<&Field> = fields.iter().filter(|field| !field.is_padding()).collect();
+    if data_fields.len() != values.len() {
+        return Err(format!(
+            "{:?} has {} fields but {} values were given",
+            format,
+            data_fields.len(),
+            values.len()
+        ));
+    }
+
+    let size = fields
+        .last()
+        .map(|field| field.offset + field.size)
+        .unwrap_or(0);
+    let mut bytes = vec![0u8; size];
+    for (field, value) in data_fields.into_iter().zip(values) {
+        encode(
+            field,
+            value,
+            &mut bytes[field.offset..field.offset + field.size],
+        )?;
+    }
+    Ok(bytes)
+}
+
+const DUMP_WIDTH: usize = 16;
+
+/// Hex dump of `bytes` with each line labelled by the field it decodes to, so a wrong offset
+/// or padding assumption is visible at a glance. Unlike `unpack` it doesn't fail on a short
+/// buffer: missing bytes show as `--` and trailing bytes past the format are listed too.
+pub fn annotated_dump(format: &str, bytes: &[u8]) -> Result<String, String> {
+    let fields = layout(format)?;
+    let mut lines = Vec::new();
+    let mut end = 0;
+
+    for field in &fields {
+        let value = if field.is_padding() {
+            "padding".to_string()
+        } else if field.offset + field.size <= bytes.len() {
+            decode(field, &bytes[field.offset..field.offset + field.size]).to_string()
+        } else {
+            "<missing>".to_string()
+        };
+        let order = match (field.size, field.order) {
+            (1, _) => "",
+            _ if matches!(field.code, 's' | 'p' | 'x') => "",
+            (_, ByteOrder::Little) => "little",
+            (_, ByteOrder::Big) => "big",
+        };
+        let label = format!("{}  {:<6}  {}", field.code, order, value);
+        dump_range(
+            &mut lines,
+            bytes,
+            field.offset,
+            field.offset + field.size,
+            &label,
+        );
+        end = field.offset + field.size;
+    }
+    if bytes.len() > end {
+        dump_range(&mut lines, bytes, end, bytes.len(), "trailing");
+    }
+
+    Ok(lines.join("\n"))
+}
+
+fn dump_range(lines: &mut Vec<String>, bytes: &[u8], start: usize, end: usize, label: &str) {
+    let mut offset = start;
+    while offset < end {
+        let chunk_end = (offset + DUMP_WIDTH).min(end);
+        let hex: Vec<String> = (offset..chunk_end)
+            .map(|i| match bytes.get(i) {
+                Some(byte) => format!("{:02x}", byte),
+                None => "--".to_string(),
+            })
+            .collect();
+        // Only the first line of a field carries its label
+        let label = if offset == start { label } else { "" };
+        let line = format!(
+            "{:04x}  {:<width$}  {}",
+            offset,
+            hex.join(" "),
+            label,
+            width = DUMP_WIDTH * 3 - 1
+        );
+        lines.push(line.trim_end().to_string());
+        offset = chunk_end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(format: &str, values: Vec<Value>, expected: &str) {
+        let bytes = pack(format, &values).unwrap();
+        assert_eq!(hex::encode(&bytes), expected, "pack {:?}", format);
+        assert_eq!(
+            unpack(format, &bytes).unwrap(),
+            values,
+            "unpack {:?}",
+            format
+        );
+    }
+
+    #[test]
+    fn round_trips_every_type() {
+        round_trip(
+            "<? b B h H i I l L q Q f d 3s",
+            vec![
+                Value::Bool(true),
+                Value::Int(-128),
+                Value::UInt(255),
+                Value::Int(-32768),
+                Value::UInt(65535),
+                Value::Int(i32::MIN as i64),
+                Value::UInt(u32::MAX as u64),
+                Value::Int(-1),
+                Value::UInt(1),
+                Value::Int(i64::MIN),
+                Value::UInt(u64::MAX),
+                Value::Float(1.5),
+                Value::Float(-0.1),
+                Value::Bytes(b"abc".to_vec()),
+            ],
+            "01\
+             80\
+             ff\
+             0080\
+             ffff\
+             00000080\
+             ffffffff\
+             ffffffff\
+             01000000\
+             0000000000000080\
+             ffffffffffffffff\
+             0000c03f\
+             9a9999999999b9bf\
+             616263",
+        );
+    }
+
+    #[test]
+    fn round_trips_like_python_struct() {
+        // struct.pack("<i I h 2x f d", -5, 7, -2, 1.5, 2.25) + struct.pack(">d", -0.5)
+        round_trip(
+            "<i I h 2x f d >d",
+            vec![
+                Value::Int(-5),
+                Value::UInt(7),
+                Value::Int(-2),
+                Value::Float(1.5),
+                Value::Float(2.25),
+                Value::Float(-0.5),
+            ],
+            "fbffffff07000000feff00000000c03f0000000000000240bfe0000000000000",
+        );
+    }
+
+    #[test]
+    fn aligns_native_fields() {
+        // struct.pack("@bihq", -1, 2, -3, 4) on a little-endian machine
+        let values = vec![Value::Int(-1), Value::Int(2), Value::Int(-3), Value::Int(4)];
+        let bytes = pack("@bihq", &values).unwrap();
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(unpack("@bihq", &bytes).unwrap(), values);
+        if cfg!(target_endian = "little") {
+            assert_eq!(
+                hex::encode(&bytes),
+                "ff00000002000000fdff0000000000000400000000000000"
+            );
+        }
+    }
+
+    #[test]
+    fn pads_strings() {
+        let bytes = pack("<4s?", &[Value::Bytes(b"ab".to_vec()), Value::Bool(true)]).unwrap();
+        assert_eq!(hex::encode(&bytes), "6162000001");
+        assert_eq!(
+            unpack("<4s?", &bytes).unwrap(),
+            vec![Value::Bytes(b"ab\0\0".to_vec()), Value::Bool(true)]
+        );
+    }
+
+    #[test]
+    fn packs_pascal_strings() {
+        // struct.pack("<5p 3p 0p", b"abcdefg", b"", b"ab")
+        let values = [b"abcdefg".to_vec(), Vec::new(), b"ab".to_vec()].map(Value::Bytes);
+        let bytes = pack("<5p 3p 0p", &values).unwrap();
+        assert_eq!(hex::encode(&bytes), "0461626364000000");
+        assert_eq!(
+            unpack("<5p 3p 0p", &bytes).unwrap(),
+            [b"abcd".to_vec(), Vec::new(), Vec::new()].map(Value::Bytes)
+        );
+        // a length past the field is cut, like struct.unpack("3p", b"\xffab")
+        assert_eq!(
+            unpack("3p", b"\xffab").unwrap(),
+            vec![Value::Bytes(b"ab".to_vec())]
+        );
+        assert!(pack("3p", &[Value::Int(1)]).is_err());
+    }
+
+    #[test]
+    fn unpack_ignores_trailing_bytes() {
+        assert_eq!(unpack("<H", &[1, 0, 0xff]).unwrap(), vec![Value::UInt(1)]);
+    }
+
+    #[test]
+    fn rejects_bad_formats() {
+        assert!(
+            layout("<z")
+                .unwrap_err()
+                .starts_with("Unknown format code 'z'")
+        );
+        assert!(
+            layout("2 i")
+                .unwrap_err()
+                .starts_with("Repeat count without a type code")
+        );
+        assert!(
+            layout("i2")
+                .unwrap_err()
+                .starts_with("Repeat count without a type code")
+        );
+        assert!(
+            layout("2<i")
+                .unwrap_err()
+                .starts_with("Repeat count before byte order")
+        );
+        assert!(
+            layout("99999999999999999999i")
+                .unwrap_err()
+                .starts_with("Repeat count overflows")
+        );
+        assert!(
+            layout("2000000i")
+                .unwrap_err()
+                .contains("describes more than")
+        );
+    }
+
+    #[test]
+    fn rejects_values_that_dont_fit() {
+        assert!(pack("<b", &[Value::Int(128)]).is_err());
+        assert!(pack("<B", &[Value::Int(-1)]).is_err());
+        assert!(pack("<?", &[Value::Int(1)]).is_err());
+        assert!(pack("<i", &[Value::Float(1.0)]).is_err());
+        assert!(pack("<i i", &[Value::Int(1)]).is_err());
+    }
+
+    #[test]
+    fn rejects_short_buffers() {
+        assert!(
+            unpack("<i", &[0, 0, 0])
+                .unwrap_err()
+                .starts_with("Buffer too short")
+        );
+    }
+
+    // SplitMix64, a fixed seed keeps failures reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len())]
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    const CODES: &[char] = &[
+        'x', '?', 'b', 'B', 'h', 'H', 'i', 'I', 'l', 'L', 'q', 'Q', 'f', 'd', 's', 'p',
+    ];
+    const ORDERS: &[&str] = &["<", ">", "!", "=", "@"];
+
+    fn random_value(rng: &mut Rng, code: char) -> Value {
+        let n = rng.next();
+        match code {
+            '?' => Value::Bool(n & 1 == 1),
+            'b' => Value::Int(n as i8 as i64),
+            'B' => Value::UInt(n as u8 as u64),
+            'h' => Value::Int(n as i16 as i64),
+            'H' => Value::UInt(n as u16 as u64),
+            'i' | 'l' => Value::Int(n as i32 as i64),
+            'I' | 'L' => Value::UInt(n as u32 as u64),
+            'q' => Value::Int(n as i64),
+            'Q' => Value::UInt(n),
+            // NaN never equals itself
+            'f' => match f32::from_bits(n as u32) {
+                v if v.is_nan() => Value::Float(-0.0),
+                v => Value::Float(v as f64),
+            },
+            'd' => match f64::from_bits(n) {
+                v if v.is_nan() => Value::Float(f64::INFINITY),
+                v => Value::Float(v),
+            },
+            code => unreachable!("{:?} has no single value", code),
+        }
+    }
+
+    /// A random format, the same format with its repeat counts written out as separate codes,
+    /// and values for its fields
+    fn random_case(rng: &mut Rng) -> (String, String, Vec<Value>) {
+        let (mut format, mut expanded, mut values) = (String::new(), String::new(), Vec::new());
+        for _ in 0..1 + rng.below(8) {
+            // without one the format starts native and aligned
+            if rng.below(3) == 0 {
+                let order = rng.pick(ORDERS);
+                format.push_str(order);
+                expanded.push_str(order);
+            }
+            let code = rng.pick(CODES);
+            let count = match rng.below(3) {
+                0 => None,
+                _ if matches!(code, 's' | 'p' | 'x') => Some(rng.below(12)),
+                _ => Some(rng.below(4)),
+            };
+            if let Some(count) = count {
+                format.push_str(&count.to_string());
+            }
+            format.push(code);
+            format.push(' ');
+
+            let count = count.unwrap_or(1);
+            match code {
+                'x' => expanded.push_str(&format!("{}x ", count)),
+                's' => {
+                    expanded.push_str(&format!("{}s ", count));
+                    values.push(Value::Bytes(rng.bytes(count)));
+                }
+                'p' => {
+                    expanded.push_str(&format!("{}p ", count));
+                    let len = rng.below(count.max(1));
+                    values.push(Value::Bytes(rng.bytes(len)));
+                }
+                _ => {
+                    for _ in 0..count {
+                        expanded.push(code);
+                        expanded.push(' ');
+                        values.push(random_value(rng, code));
+                    }
+                }
+            }
+        }
+        (format, expanded, values)
+    }
+
+    /// The field's bytes written independently of `encode`
+    fn expected_bytes(field: &Field, value: &Value) -> Vec<u8> {
+        let mut bytes = match (field.code, value) {
+            ('s', Value::Bytes(v)) => return v.clone(),
+            // "0p" has no room for the length byte
+            ('p', _) if field.size == 0 => return Vec::new(),
+            ('p', Value::Bytes(v)) => return [&[v.len() as u8][..], v].concat(),
+            ('f', Value::Float(v)) => (*v as f32).to_le_bytes().to_vec(),
+            ('d', Value::Float(v)) => v.to_le_bytes().to_vec(),
+            (_, Value::Bool(v)) => vec![*v as u8],
+            (_, Value::Int(v)) => v.to_le_bytes()[..field.size].to_vec(),
+            (_, Value::UInt(v)) => v.to_le_bytes()[..field.size].to_vec(),
+            other => unreachable!("{:?}", other),
+        };
+        if field.order == ByteOrder::Big {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trips_random_formats() {
+        let mut rng = Rng(2182);
+        for case in 0..2000 {
+            let (format, expanded, values) = random_case(&mut rng);
+            let context = format!("case {}: {:?} {:?}", case, format, values);
+
+            let bytes = pack(&format, &values).expect(&context);
+            assert_eq!(
+                unpack(&format, &bytes).expect(&context),
+                values,
+                "{}",
+                context
+            );
+            assert_eq!(
+                pack(&expanded, &values).expect(&context),
+                bytes,
+                "{}",
+                context
+            );
+
+            let fields = layout(&format).unwrap();
+            let mut values = values.iter();
+            for field in &fields {
+                let field_bytes = &bytes[field.offset..field.offset + field.size];
+                if field.is_padding() {
+                    assert!(field_bytes.iter().all(|&b| b == 0), "{}", context);
+                    continue;
+                }
+                let expected = expected_bytes(field, values.next().unwrap());
+                // the rest of a Pascal string's field is zero filled
+                assert_eq!(&field_bytes[..expected.len()], expected, "{}", context);
+                assert!(
+                    field_bytes[expected.len()..].iter().all(|&b| b == 0),
+                    "{}",
+                    context
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::OnceLock;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{error, warn};
+
+use crate::utils::progress::format_duration;
+use crate::utils::{args, events, history, shutdown, telemetry};
+
+// Wall-clock limit of a challenge run, --timeout (or CHALLENGE_TIMEOUT). When it's reached the
+// shutdown token is set, as if Ctrl+C was pressed, so workers and servers stop the way they
+// already do; a run that is still going after the grace period is recorded and exited.
+
+/// Time a run gets to stop on its own once its limit is reached
+pub const GRACE: Duration = Duration::from_secs(10);
+
+/// The limit, set once it has been reached
+static EXPIRED: OnceLock<Duration> = OnceLock::new();
+
+/// Starts the watchdog for this run when a limit is set
+pub fn start(challenge: &str, started_at: SystemTime, start: Instant) -> Result<(), String> {
+    let Some(limit) = args::get("--timeout", "CHALLENGE_TIMEOUT") else {
+        return Ok(());
+    };
+    let limit = args::parse_duration(&limit).map_err(|e| format!("--timeout: {}", e))?;
+    let challenge = challenge.to_string();
+    let token = shutdown::token();
+    thread::spawn(move || {
+        thread::sleep(limit.saturating_sub(start.elapsed()));
+        let _ = EXPIRED.set(limit);
+        warn!(
+            "{} reached its {} limit, stopping",
+            challenge,
+            format_duration(limit.as_secs_f64())
+        );
+        token.store(true, Ordering::Relaxed);
+
+        thread::sleep(GRACE);
+        let message = format!(
+            "Timed out after {}, didn't stop within {}s",
+            format_duration(limit.as_secs_f64()),
+            GRACE.as_secs()
+        );
+        error!("{}", message);
+        let run = history::finish(&challenge, started_at, start.elapsed(), Some(message));
+        if let Err(e) = history::append(&run) {
+            warn!("{}", e);
+        }
+        events::result(&run);
+        telemetry::flush();
+        std::process::exit(1);
+    });
+    Ok(())
+}
+
+/// Whether the run's limit was reached
+pub fn expired() -> bool {
+    EXPIRED.get().is_some()
+}
+
+/// The run's result, turned into a timeout error when the limit was reached: a solver that was
+/// cancelled may well return Ok without having solved anything
+pub fn check(result: anyhow::Result<()>) -> anyhow::Result<()> {
+    let Some(limit) = EXPIRED.get() else {
+        return result;
+    };
+    let message = format!("Timed out after {}", format_duration(limit.as_secs_f64()));
+    match result {
+        Ok(()) => Err(anyhow::Error::msg(message)),
+        Err(e) => Err(e.context(message)),
+    }
+}
@@ -2,6 +2,47 @@ const ZIP_FILE_SIGNATURE: &[u8; 4] = b"PK\x03\x04";
 const EOCD_SIGNATURE: &[u8; 4] = b"PK\x05\x06";
 const ZIP_CRYPTO_HEADER_SIZE: usize = 12;
 
+#[derive(Debug, thiserror::Error)]
+pub enum ZipError {
+    #[error("no End of Central Directory record, not a ZIP file")]
+    NoEocd,
+    #[error("truncated archive, {what} at offset {offset} runs past the end ({len} bytes)")]
+    Truncated {
+        what: &'static str,
+        offset: usize,
+        len: usize,
+    },
+    #[error("invalid Central Directory entry signature {0:#010x}")]
+    BadSignature(u32),
+}
+
+// Bounds checked reads, a corrupt offset or length is an error instead of a panic
+fn slice<'a>(
+    bytes: &'a [u8],
+    offset: usize,
+    size: usize,
+    what: &'static str,
+) -> Result<&'a [u8], ZipError> {
+    offset
+        .checked_add(size)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or(ZipError::Truncated {
+            what,
+            offset,
+            len: bytes.len(),
+        })
+}
+
+fn u16_at(bytes: &[u8], offset: usize, what: &'static str) -> Result<u16, ZipError> {
+    let field = slice(bytes, offset, 2, what)?;
+    Ok(u16::from_le_bytes([field[0], field[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize, what: &'static str) -> Result<u32, ZipError> {
+    let field = slice(bytes, offset, 4, what)?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
 // ZIP Layout
 // [Local File Header 1][File Data 1][Data Descriptor?]
 // [Local File Header 2][File Data 2][Data Descriptor?]
@@ -49,31 +90,24 @@ struct EndOfCentralDirectory {
 }
 
 // Reads the End of Central Directory (EOCD) record from a ZIP file
-fn read_eocd(bytes: &[u8]) -> EndOfCentralDirectory {
-    let mut pos = 0;
-    let mut i = bytes.len().saturating_sub(4);
-
-    while i > 0 {
-        if &bytes[i..(i + 4)] == EOCD_SIGNATURE {
-            pos = i;
-            break;
-        }
-        i -= 1;
-    }
-
-    let disk_number = u16::from_le_bytes(bytes[pos + 4..pos + 6].try_into().unwrap());
-    let start_disk = u16::from_le_bytes(bytes[pos + 6..pos + 8].try_into().unwrap());
-    let entries_on_disk = u16::from_le_bytes(bytes[pos + 8..pos + 10].try_into().unwrap());
-    let total_entries = u16::from_le_bytes(bytes[pos + 10..pos + 12].try_into().unwrap());
-    let central_directory_size = u32::from_le_bytes(bytes[pos + 12..pos + 16].try_into().unwrap());
-    let central_directory_offset =
-        u32::from_le_bytes(bytes[pos + 16..pos + 20].try_into().unwrap());
-    let comment_length = u16::from_le_bytes(bytes[pos + 20..pos + 22].try_into().unwrap());
-
-    let comment_bytes = &bytes[pos + 22..pos + 22 + comment_length as usize];
+fn read_eocd(bytes: &[u8]) -> Result<EndOfCentralDirectory, ZipError> {
+    let pos = bytes
+        .windows(4)
+        .rposition(|window| window == EOCD_SIGNATURE)
+        .ok_or(ZipError::NoEocd)?;
+
+    let disk_number = u16_at(bytes, pos + 4, "EOCD")?;
+    let start_disk = u16_at(bytes, pos + 6, "EOCD")?;
+    let entries_on_disk = u16_at(bytes, pos + 8, "EOCD")?;
+    let total_entries = u16_at(bytes, pos + 10, "EOCD")?;
+    let central_directory_size = u32_at(bytes, pos + 12, "EOCD")?;
+    let central_directory_offset = u32_at(bytes, pos + 16, "EOCD")?;
+    let comment_length = u16_at(bytes, pos + 20, "EOCD")?;
+
+    let comment_bytes = slice(bytes, pos + 22, comment_length as usize, "EOCD comment")?;
     let comment = String::from_utf8_lossy(comment_bytes).into_owned();
 
-    EndOfCentralDirectory {
+    Ok(EndOfCentralDirectory {
         disk_number,
         start_disk,
         entries_on_disk,
@@ -82,7 +116,7 @@ fn read_eocd(bytes: &[u8]) -> EndOfCentralDirectory {
         central_directory_offset,
         comment_length,
         comment,
-    }
+    })
 }
 
 /// Represents a single file entry in the Central Directory
@@ -131,41 +165,41 @@ struct CentralDirectoryEntry {
 }
 
 // Reads a single entry from the Central Directory, returns the entry and the offset of the next entry
-fn read_central_directory_entry(bytes: &[u8], offset: usize) -> (CentralDirectoryEntry, usize) {
+fn read_central_directory_entry(
+    bytes: &[u8],
+    offset: usize,
+) -> Result<(CentralDirectoryEntry, usize), ZipError> {
     // signature
-    let sig = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
-    assert_eq!(sig, 0x02014b50, "Invalid CD entry signature");
+    let sig = u32_at(bytes, offset, "Central Directory entry")?;
+    if sig != 0x02014b50 {
+        return Err(ZipError::BadSignature(sig));
+    }
 
-    let general_purpose_flag =
-        u16::from_le_bytes(bytes[offset + 8..offset + 10].try_into().unwrap());
+    let general_purpose_flag = u16_at(bytes, offset + 8, "Central Directory entry")?;
 
-    let compression_method =
-        u16::from_le_bytes(bytes[offset + 10..offset + 12].try_into().unwrap());
+    let compression_method = u16_at(bytes, offset + 10, "Central Directory entry")?;
 
-    let last_mod_time = u16::from_le_bytes(bytes[offset + 12..offset + 14].try_into().unwrap());
+    let last_mod_time = u16_at(bytes, offset + 12, "Central Directory entry")?;
 
-    let crc32 = u32::from_le_bytes(bytes[offset + 16..offset + 20].try_into().unwrap());
+    let crc32 = u32_at(bytes, offset + 16, "Central Directory entry")?;
 
-    let compressed_size = u32::from_le_bytes(bytes[offset + 20..offset + 24].try_into().unwrap());
-    let uncompressed_size = u32::from_le_bytes(bytes[offset + 24..offset + 28].try_into().unwrap());
+    let compressed_size = u32_at(bytes, offset + 20, "Central Directory entry")?;
+    let uncompressed_size = u32_at(bytes, offset + 24, "Central Directory entry")?;
 
-    let filename_len =
-        u16::from_le_bytes(bytes[offset + 28..offset + 30].try_into().unwrap()) as usize;
-    let extra_len =
-        u16::from_le_bytes(bytes[offset + 30..offset + 32].try_into().unwrap()) as usize;
-    let comment_len =
-        u16::from_le_bytes(bytes[offset + 32..offset + 34].try_into().unwrap()) as usize;
+    let filename_len = u16_at(bytes, offset + 28, "Central Directory entry")? as usize;
+    let extra_len = u16_at(bytes, offset + 30, "Central Directory entry")? as usize;
+    let comment_len = u16_at(bytes, offset + 32, "Central Directory entry")? as usize;
 
     let filename_start = offset + 46;
     let filename_end = filename_start + filename_len;
-    let filename = String::from_utf8_lossy(&bytes[filename_start..filename_end]).into_owned();
+    let filename = String::from_utf8_lossy(slice(bytes, filename_start, filename_len, "filename")?)
+        .into_owned();
 
-    let local_header_offset =
-        u32::from_le_bytes(bytes[offset + 42..offset + 46].try_into().unwrap());
+    let local_header_offset = u32_at(bytes, offset + 42, "Central Directory entry")?;
 
     let next_offset = filename_end + extra_len + comment_len;
 
-    (
+    Ok((
         CentralDirectoryEntry {
             filename,
             general_purpose_flag,
@@ -177,22 +211,22 @@ fn read_central_directory_entry(bytes: &[u8], offset: usize) -> (CentralDirector
             local_header_offset,
         },
         next_offset,
-    )
+    ))
 }
 
 // Read the file content
-fn read_file_content<'a>(bytes: &'a [u8], cde: &'a CentralDirectoryEntry) -> &'a [u8] {
+fn read_file_content<'a>(
+    bytes: &'a [u8],
+    cde: &'a CentralDirectoryEntry,
+) -> Result<&'a [u8], ZipError> {
     let offset = cde.local_header_offset as usize;
 
-    let filename_len =
-        u16::from_le_bytes(bytes[offset + 26..offset + 28].try_into().unwrap()) as usize;
-    let extra_len =
-        u16::from_le_bytes(bytes[offset + 28..offset + 30].try_into().unwrap()) as usize;
+    let filename_len = u16_at(bytes, offset + 26, "local file header")? as usize;
+    let extra_len = u16_at(bytes, offset + 28, "local file header")? as usize;
 
     let data_start = offset + 30 + filename_len + extra_len;
-    let data_end = data_start + cde.compressed_size as usize;
 
-    return &bytes[data_start..data_end];
+    return slice(bytes, data_start, cde.compressed_size as usize, "file data");
 }
 
 // Check if the file is encrypted
@@ -202,8 +236,8 @@ pub fn is_encrypted(general_purpose_flag: u16) -> bool {
 }
 
 // Check if the file is a zip file
-pub fn check_if_zip(bytes: &Vec<u8>) -> bool {
-    return &bytes[0..4] == ZIP_FILE_SIGNATURE;
+pub fn check_if_zip(bytes: &[u8]) -> bool {
+    return bytes.starts_with(ZIP_FILE_SIGNATURE);
 }
 
 // Helper functions for ZipCrypto algorithm
@@ -219,6 +253,15 @@ fn crc32_update(mut crc: u32, byte: u8) -> u32 {
     crc
 }
 
+/// CRC-32 (IEEE) of `bytes`, the checksum ZIP stores for each entry
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in bytes {
+        crc = crc32_update(crc, byte);
+    }
+    crc ^ 0xFFFFFFFF
+}
+
 fn update_keys(keys: &mut (u32, u32, u32), byte: u8) {
     keys.0 = crc32_update(keys.0, byte);
     keys.1 = keys.1.wrapping_add(keys.0 & 0xff);
@@ -286,33 +329,26 @@ pub fn verify_zip_crypto_password(
     // Skip the 12-byte header and calculate CRC32 of the actual file content
     let file_content = &decrypted[ZIP_CRYPTO_HEADER_SIZE..];
 
-    // Calculate CRC32 of decrypted content
-    let mut crc = 0xFFFFFFFFu32;
-    for &byte in file_content {
-        crc = crc32_update(crc, byte);
-    }
-    crc ^= 0xFFFFFFFF;
-
     // Check if CRC32 matches
-    crc == expected_crc32
+    crc32(file_content) == expected_crc32
 }
 
 // Extract all files from the zip file, and return a vector of (filename, content, crc32)
 // If a file is encrypted, it will be returned as is
-pub fn extract_all_files(bytes: &[u8]) -> Vec<(String, Vec<u8>, u32)> {
-    let eocd = read_eocd(&bytes);
+pub fn extract_all_files(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>, u32)>, ZipError> {
+    let eocd = read_eocd(&bytes)?;
     let mut offset = eocd.central_directory_offset as usize;
     let mut result = Vec::new();
 
     for _ in 0..eocd.total_entries {
-        let (entry, next_offset) = read_central_directory_entry(&bytes, offset);
+        let (entry, next_offset) = read_central_directory_entry(&bytes, offset)?;
         let filename = entry.filename.clone();
-        let file_content = read_file_content(&bytes, &entry).to_vec();
+        let file_content = read_file_content(&bytes, &entry)?.to_vec();
 
         result.push((filename, file_content, entry.crc32));
 
         offset = next_offset
     }
 
-    return result;
+    return Ok(result);
 }
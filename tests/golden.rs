@@ -0,0 +1,77 @@
+// Solves the problems in tests/fixtures/<challenge>/problem.json and compares the payload the
+// client would send, byte for byte, to solution.json next to it, so a renamed field or a change
+// in serialization order fails here instead of on hackattic.
+
+use std::path::{Path, PathBuf};
+
+use hackattic::challenges::{
+    backup_restore, brute_force_zip, help_me_unpack, mini_miner, password_hashing,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+fn fixtures(challenge: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(challenge)
+}
+
+fn problem<T: DeserializeOwned>(challenge: &str) -> T {
+    let path = fixtures(challenge).join("problem.json");
+    let text = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    serde_json::from_str(&text)
+        .unwrap_or_else(|e| panic!("unexpected problem in {}: {}", path.display(), e))
+}
+
+/// The submission as the client sends it: the solution converted to a JSON value, then
+/// serialized
+fn payload<T: Serialize>(solution: &T) -> String {
+    let value = serde_json::to_value(solution).expect("solution isn't JSON");
+    serde_json::to_string(&value).expect("solution isn't JSON")
+}
+
+fn assert_payload(challenge: &str, payload: &str) {
+    let path = fixtures(challenge).join("solution.json");
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    assert_eq!(payload, expected, "{} payload", challenge);
+}
+
+#[test]
+fn help_me_unpack() {
+    let problem: help_me_unpack::Problem = problem("help_me_unpack");
+    let solution = help_me_unpack::solve(&problem).unwrap();
+    assert_payload("help_me_unpack", &payload(&solution));
+}
+
+#[test]
+fn password_hashing() {
+    let problem: password_hashing::Problem = problem("password_hashing");
+    let solution = password_hashing::solve(&problem).unwrap();
+    assert_payload("password_hashing", &payload(&solution));
+}
+
+#[test]
+fn backup_restore() {
+    let problem: backup_restore::Problem = problem("backup_restore");
+    let solution = backup_restore::solve(&problem).unwrap();
+    assert_payload("backup_restore", &payload(&solution));
+}
+
+#[test]
+fn brute_force_zip() {
+    let problem: brute_force_zip::Problem = problem("brute_force_zip");
+    let zip = std::fs::read(fixtures("brute_force_zip").join(&problem.zip_url)).unwrap();
+    let solution = brute_force_zip::solve(&zip).unwrap();
+    assert_eq!(solution.password, "abcd");
+    assert_payload("brute_force_zip", &payload(&solution));
+}
+
+#[test]
+fn mini_miner() {
+    let problem: mini_miner::Problem = problem("mini_miner");
+    // the lowest nonce with enough leading zero bits, whatever the core count
+    let solution = mini_miner::solve(&problem).unwrap();
+    assert_payload("mini_miner", &payload(&solution));
+}
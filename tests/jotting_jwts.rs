@@ -0,0 +1,89 @@
+// Runs `hackattic jotting_jwts --simulate`, which plays hackattic's role against the local
+// server: valid tokens, one signed with the wrong secret, one not valid before an hour from now,
+// an expired one, an unsigned one and a malformed one, then the final token. Only the valid
+// tokens may be appended, so the solution returned for the final token is "simulation".
+
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const EXPECTED_SOLUTION: &str = "simulation";
+
+fn data_dir(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("jotting_jwts")
+        .join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("failed to create the data directory");
+    dir
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+        .port()
+}
+
+/// Runs the simulation with `flags` and returns its logs, asserting it passed
+fn simulate(name: &str, flags: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_hackattic"))
+        .args(["jotting_jwts", "--simulate", "--bind", "127.0.0.1"])
+        .args(["--port", &free_port().to_string()])
+        .args(["--seed", "2130", "--timeout", "60s"])
+        .args(flags)
+        .arg("--data-dir")
+        .arg(data_dir(name))
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("failed to run hackattic");
+    let logs = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.status.success(), "simulation failed:\n{}", logs);
+    logs
+}
+
+/// The solution the server answered the simulated `kind` token with, the last one sent
+fn answer<'a>(logs: &'a str, kind: &str) -> Option<&'a str> {
+    let prefix = format!("[simulate] {} token -> ", kind);
+    logs.lines()
+        .filter_map(|line| line.split_once(prefix.as_str()))
+        .map(|(_, solution)| solution.trim())
+        .next_back()
+}
+
+fn assert_solution(logs: &str) {
+    assert_eq!(answer(logs, "final"), Some(EXPECTED_SOLUTION), "{}", logs);
+    assert!(logs.contains("Challenge passed!"), "{}", logs);
+}
+
+#[test]
+fn simulation_over_http() {
+    let logs = simulate("http", &[]);
+    assert_solution(&logs);
+    // the invalid tokens in between appended nothing
+    assert_eq!(answer(&logs, "valid with exp"), Some("simulat"), "{}", logs);
+    for kind in [
+        "wrong secret",
+        "not yet valid",
+        "expired",
+        "alg none",
+        "malformed",
+    ] {
+        assert!(
+            answer(&logs, kind).is_some(),
+            "no {} token in:\n{}",
+            kind,
+            logs
+        );
+    }
+}
+
+#[test]
+fn simulation_over_tls() {
+    let logs = simulate("tls", &["--tls"]);
+    assert_solution(&logs);
+}
@@ -0,0 +1,134 @@
+// Runs the built-in solvers end to end against the mock server: each test starts its own
+// `hackattic mock_server`, points HACKATTIC_URL at it and checks the run recorded in the data
+// directory's history.jsonl passed. The fixed problems' payloads must also match
+// data/golden/ byte for byte.
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct MockServer {
+    child: Child,
+    url: String,
+}
+
+impl MockServer {
+    fn start(data_dir: &Path) -> MockServer {
+        // a free port, released just before the server binds it
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("no free port")
+            .port();
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/golden");
+        let child = Command::new(env!("CARGO_BIN_EXE_hackattic"))
+            .args(["mock_server", "--port", &port.to_string()])
+            .arg("--golden")
+            .arg(golden)
+            .arg("--data-dir")
+            .arg(data_dir)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start the mock server");
+        let server = MockServer {
+            child,
+            url: format!("http://127.0.0.1:{}", port),
+        };
+
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        let started = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            assert!(
+                started.elapsed() < STARTUP_TIMEOUT,
+                "mock server didn't listen on {} in {:?}",
+                address,
+                STARTUP_TIMEOUT
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        server
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn data_dir(challenge: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("mock_server")
+        .join(challenge);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("failed to create the data directory");
+    dir
+}
+
+/// Solves `challenge` against its own mock server and asserts the submission was accepted
+fn assert_accepted(challenge: &str) {
+    let data_dir = data_dir(challenge);
+    let server = MockServer::start(&data_dir.join("server"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hackattic"))
+        .arg(challenge)
+        .arg("--data-dir")
+        .arg(&data_dir)
+        .env("HACKATTIC_URL", &server.url)
+        .env("ACCESS_TOKEN", "mock")
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("failed to run hackattic");
+    let logs = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.status.success(), "{} failed:\n{}", challenge, logs);
+
+    let history = std::fs::read_to_string(data_dir.join("history.jsonl"))
+        .unwrap_or_else(|e| panic!("{} recorded no run: {}\n{}", challenge, e, logs));
+    let run: serde_json::Value = history
+        .lines()
+        .last()
+        .and_then(|line| serde_json::from_str(line).ok())
+        .unwrap_or_else(|| panic!("{} recorded no run:\n{}", challenge, logs));
+    assert_eq!(
+        run["outcome"], "passed",
+        "{} wasn't accepted: {}\n{}",
+        challenge, run["response"], logs
+    );
+}
+
+#[test]
+fn help_me_unpack() {
+    assert_accepted("help_me_unpack");
+}
+
+#[test]
+fn password_hashing() {
+    assert_accepted("password_hashing");
+}
+
+#[test]
+fn mini_miner() {
+    assert_accepted("mini_miner");
+}
+
+#[test]
+fn backup_restore() {
+    assert_accepted("backup_restore");
+}
+
+#[test]
+fn collision_course() {
+    assert_accepted("collision_course");
+}
+
+#[test]
+fn tales_of_ssl() {
+    assert_accepted("tales_of_ssl");
+}